@@ -1,21 +1,32 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use byteorder::{LittleEndian, WriteBytesExt};
 use colored::Colorize;
-use log::{debug, error, info};
+use log::{debug, error, info, log_enabled, warn, Level};
 use nom::error::{VerboseError, VerboseErrorKind};
+use pest::error::LineColLocation;
 use pest::Parser;
 use crate::assembler_errors::AssemblerError;
-use crate::header_utils::{get_lumi_header, LUMI_HEADER_LENGTH};
+use crate::debug_info::{DebugInfo, LineEntry};
+use crate::diagnostics::{Diagnostic, Span};
+use crate::expressions::Expression;
+use crate::header_utils::code_start_offset;
+use crate::linker::ExecutableImage;
+use crate::listing::{Listing, ListingEntry};
+use crate::object::{ObjectFile, Relocation};
 use crate::instruction::Opcode;
-use crate::file_disassembler::{disassemble, visualize_program};
-use crate::parsers::assembler_instruction::AssemblerInstruction;
+use crate::file_disassembler::disassemble;
+use crate::parsers::assembler_instruction::{parse_instruction, AssemblerInstruction};
 use crate::parsers::lumi_asm_parser::{LumiAsmParser, Rule};
 use crate::parsers::program_parser::Program;
+use crate::sections::SectionKind;
 // use crate::parser_combinators::instruction_parser::AssemblerInstruction;
 // use crate::parser_combinators::program_parser::{parse_program, Program};
 use crate::symbols::{Symbol, SymbolTable, SymbolType};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
   Op { code: Opcode },
   Register { reg_num: u8 },
@@ -25,18 +36,30 @@ pub enum Token {
   LabelUsage { name: String },
   Directive { directive_type: DirectiveType },
   LString { value: String },
+  /// `\name` inside a macro body, replaced by the argument when the macro is expanded.
+  MacroParameter { name: String },
+  /// A constant expression, evaluated once the symbols it uses are known.
+  Expression { expression: Expression },
   Separator,
   Comment,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum DirectiveType {
+  ReadOnly,
   Data,
   Bss,
   Text,
   Asciiz,
   Integer,
+  /// A single unsigned byte of data.
+  Byte,
   Float,
+  Space,
+  Equ,
+  Include,
+  Global,
+  Extern,
   Unknown,
 }
 
@@ -45,12 +68,20 @@ impl FromStr for DirectiveType {
 
   fn from_str(input: &str) -> Result<Self, Self::Err> {
     match input.to_lowercase().as_str() {
+      ".rodata" => Ok(DirectiveType::ReadOnly),
       ".data" => Ok(DirectiveType::Data),
       ".bss" => Ok(DirectiveType::Bss),
       ".text" => Ok(DirectiveType::Text),
+      ".code" => Ok(DirectiveType::Text),
       ".asciiz" => Ok(DirectiveType::Asciiz),
       ".integer" => Ok(DirectiveType::Integer),
+      ".byte" => Ok(DirectiveType::Byte),
       ".float" => Ok(DirectiveType::Float),
+      ".space" => Ok(DirectiveType::Space),
+      ".equ" => Ok(DirectiveType::Equ),
+      ".include" => Ok(DirectiveType::Include),
+      ".global" => Ok(DirectiveType::Global),
+      ".extern" => Ok(DirectiveType::Extern),
       _ => Ok(DirectiveType::Unknown),
     }
   }
@@ -64,7 +95,9 @@ pub enum AssemblerPhase {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum AssemblerSection {
+  ReadOnly { starting_instruction: Option<u32> },
   Data { starting_instruction: Option<u32> },
+  Bss { starting_instruction: Option<u32> },
   Code { starting_instruction: Option<u32> },
   Unknown,
 }
@@ -78,7 +111,9 @@ impl Default for AssemblerSection {
 impl<'a> From<&'a str> for AssemblerSection {
   fn from(s: &'a str) -> Self {
     match s {
+      "rodata" => AssemblerSection::ReadOnly { starting_instruction: None },
       "data" => AssemblerSection::Data { starting_instruction: None },
+      "bss" => AssemblerSection::Bss { starting_instruction: None },
       "text" => AssemblerSection::Code { starting_instruction: None },
       _ => AssemblerSection::Unknown,
     }
@@ -92,18 +127,42 @@ pub struct Assembler {
   pub symbols: SymbolTable,
   /// Read-only section where constants are placed
   pub ro: Vec<u8>,
+  /// Writable data section, copied to the start of the heap on load
+  pub data: Vec<u8>,
   /// Compiled bytecode generated from the assembly code
   pub bytecode: Vec<u8>,
-  /// Offset of the read-only section
-  ro_offset: u32,
+  /// Bytes of zeroed heap reserved by the .bss section
+  bss_size: u32,
   /// List of all the sections in code
   sections: Vec<AssemblerSection>,
   /// Current section being processed
   current_section: Option<AssemblerSection>,
   /// Current instruction being processed
   current_instruction: u32,
+  /// Byte offset of the current instruction from the start of the code section
+  code_offset: u32,
+  /// Line table and symbols for the .debug section, `None` unless requested
+  debug_info: Option<DebugInfo>,
+  /// Path of the file being assembled, relative `.include`s are resolved from its directory
+  source_path: Option<PathBuf>,
+  /// Directories searched for `.include` files that are not next to the including file
+  include_paths: Vec<PathBuf>,
+  /// Files spliced in by `.include`, instruction file `n` is `included_files[n - 1]`
+  included_files: Vec<PathBuf>,
+  /// Names declared with `.global`, exported from objects
+  globals: Vec<String>,
+  /// Names declared with `.extern`, expected to be defined by another object
+  externs: Vec<String>,
   /// Errors encountered during assembly
   errors: Vec<AssemblerError>,
+  /// Errors and warnings with their source location, in the order they were found
+  diagnostics: Vec<Diagnostic>,
+  /// Name and text of every file read, indexed like `AssemblerInstruction::file`
+  sources: Vec<(String, String)>,
+  /// File, line and column of the instruction being processed, where errors are reported
+  location: (u32, usize, usize),
+  /// Address and bytes of every line that emitted something, `None` unless a listing was requested
+  listing: Option<Vec<ListingEntry>>,
   /// Scratch buffer
   buf: [u8; 4],
 }
@@ -114,115 +173,360 @@ impl Assembler {
       phase: AssemblerPhase::First,
       symbols: SymbolTable::new(),
       ro: Vec::new(),
+      data: Vec::new(),
       bytecode: Vec::new(),
-      ro_offset: 0,
+      bss_size: 0,
       sections: Vec::new(),
       current_section: None,
       current_instruction: 0,
+      code_offset: 0,
+      debug_info: None,
+      source_path: None,
+      include_paths: Vec::new(),
+      included_files: Vec::new(),
+      globals: Vec::new(),
+      externs: Vec::new(),
       errors: Vec::new(),
+      diagnostics: Vec::new(),
+      sources: Vec::new(),
+      location: (0, 0, 0),
+      listing: None,
       buf: [0; 4],
     }
   }
 
+  /// Emit a .debug section mapping every instruction back to its line in `file_name`.
+  pub fn with_debug_info(mut self, file_name: &str) -> Self {
+    self.debug_info = Some(DebugInfo::new(file_name));
+    self
+  }
+
+  /// Resolve relative `.include`s against the directory of `path`, the file being assembled.
+  pub fn with_source_path(mut self, path: impl Into<PathBuf>) -> Self {
+    self.source_path = Some(path.into());
+    self
+  }
+
+  /// Also search `path` for `.include` files, after the including file's directory.
+  pub fn with_include_path(mut self, path: impl Into<PathBuf>) -> Self {
+    self.include_paths.push(path.into());
+    self
+  }
+
+  /// Record what every source line assembles to, see `listing`.
+  pub fn with_listing(mut self) -> Self {
+    self.listing = Some(Vec::new());
+    self
+  }
+
   pub fn assemble(&mut self, raw: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
-    let parse_result = LumiAsmParser::parse(Rule::program, raw);
+    let program = self.process_source(raw)?;
 
-    match LumiAsmParser::parse(Rule::program, raw) {
-        Ok(pairs) => {
-            println!("Parsed successfully!");
-            for pair in pairs {
-                // Print out the parse tree for debugging.
-                LumiAsmParser::print_pair(pair, 0);
-            }
-        },
-        Err(e) => {
-            eprintln!("Parsing error:\n{}", e);
-        }
+    // there is nothing to link against, so every .extern must be defined by an included file
+    for name in self.externs.clone() {
+      if !self.symbols.has_symbol(&name) {
+        self.report_declaration(&program, AssemblerError::UndefinedSymbol { symbol: name });
+      }
+    }
+
+    // code labels were recorded relative to the code section, which starts after the RO data,
+    // and .bss is reserved on the heap right after .data
+    let code_start = code_start_offset(self.ro.len());
+    self.symbols.relocate(SymbolType::Label, code_start as u32);
+    self.symbols.relocate(SymbolType::Bss, self.data.len() as u32);
+    for entry in self.listing.iter_mut().flatten().filter(|entry| entry.section == SectionKind::Bss) {
+      entry.address += self.data.len() as u32;
+    }
+
+    let (code, _) = self.process_second_phase(&program, code_start);
+    if !self.errors.is_empty() {
+      return Err(self.errors.clone());
+    }
+    let assembled_program = self.write_binary(code);
+
+    info!("Assembled program length: {}", assembled_program.len());
+    if log_enabled!(Level::Debug) {
+      match disassemble(&assembled_program) {
+        Ok(disassembly) => debug!("Disassembled program:\n{}", disassembly),
+        Err(e) => error!("Error disassembling program: {:?}", e),
+      }
     }
 
-    let pairs = match parse_result {
-      Ok(p) => p,
-      Err(e) => {
-        let error_message = e.to_string();
-        error!("There was an error parsing the code: {}", error_message);
-        return Err(vec![AssemblerError::ParseError { error: error_message }]);
+    Ok(assembled_program)
+  }
+
+  /// Assemble `raw` into a relocatable object for `Linker`. Symbols declared `.extern` and
+  /// not defined in `raw` become imports, resolved against the `.global` symbols of the other
+  /// objects at link time.
+  pub fn assemble_object(&mut self, raw: &str, source: &str) -> Result<ObjectFile, Vec<AssemblerError>> {
+    let program = self.process_source(raw)?;
+
+    let mut imports = vec![];
+    for name in &self.externs {
+      if !self.symbols.has_symbol(name) {
+        self.symbols.add_symbol(Symbol::new_with_offset(name.clone(), SymbolType::Import, 0));
+        imports.push(name.clone());
       }
+    }
+    // undefined symbols are reported by the second phase, like for executables
+    for instruction in program.get_instructions() {
+      self.location = (instruction.file, instruction.line, instruction.column);
+      for expression in instruction.unrelocatable_expressions(&self.symbols) {
+        self.report(AssemblerError::InvalidExpression {
+          error: format!("`{}` cannot be relocated, objects can only use labels as `label ± constant`", expression),
+          line: instruction.line,
+        });
+      }
+    }
+
+    let (code, relocations) = self.process_second_phase(&program, 0);
+    if !self.errors.is_empty() {
+      return Err(self.errors.clone());
+    }
+    info!("Assembled object {} with {} bytes of code and {} relocations", source, code.len(), relocations.len());
+    Ok(self.write_object(source, code, relocations, imports))
+  }
+
+  /// Errors and warnings of the last assembly, with their source spans.
+  pub fn diagnostics(&self) -> &[Diagnostic] {
+    &self.diagnostics
+  }
+
+  /// Listing of the last assembly, if requested with `with_listing`.
+  pub fn listing(&self) -> Option<Listing> {
+    self.listing.as_ref().map(|entries| Listing {
+      files: self.sources.clone(),
+      entries: entries.clone(),
+      symbols: self.symbols.listing_symbols(),
+      ro: self.ro.clone(),
+    })
+  }
+
+  /// Parse `raw` and run the first phase, collecting the sections and symbols. Errors that
+  /// leave the rest of the program usable are only reported, so one run finds as many as it can.
+  fn process_source(&mut self, raw: &str) -> Result<Program, Vec<AssemblerError>> {
+    let name = match (&self.source_path, &self.debug_info) {
+      (Some(path), _) => path.display().to_string(),
+      (None, Some(debug_info)) => debug_info.files[0].clone(),
+      (None, None) => "<input>".to_string(),
     };
+    self.sources.push((name, raw.to_string()));
+    let program = self.parse_program(raw, 0).unwrap_or_else(|| Program::new(vec![]));
 
-    // Convert the Pest parse tree into our internal Program representation.
-    let program = match Program::from_pairs(pairs) {
-      Ok(prog) => prog,
-      Err(err) => return Err(vec![err]),
+    let mut include_stack = vec![];
+    if let Some(path) = &self.source_path {
+      include_stack.push(fs::canonicalize(path).unwrap_or_else(|_| path.clone()));
+    }
+    let directory = self.source_path.as_deref().and_then(Path::parent).map(Path::to_path_buf);
+    let program = match self.expand_includes(program, directory, &mut include_stack) {
+      Ok(instructions) => Program::new(instructions),
+      Err(err) => {
+        self.report(err);
+        return Err(self.errors.clone());
+      }
     };
 
-    // Now you can continue with your two-phase assembly as before.
     self.process_first_phase(&program);
 
-    if !self.errors.is_empty() {
-      error!("Errors during first phase: {:?}", self.errors);
-      return Err(self.errors.clone());
+    for name in self.globals.clone() {
+      if !self.symbols.set_global(&name) {
+        self.report_declaration(&program, AssemblerError::UndefinedSymbol { symbol: name });
+      }
     }
 
-    if self.sections.len() != 2 {
-      error!("Expected exactly two sections: .data and .code");
-      self.errors.push(AssemblerError::InsufficientSections);
+    if !self.sections.iter().any(|section| matches!(section, AssemblerSection::Code { .. })) {
+      // a file that did not parse has no sections either, that error is enough
+      if self.errors.is_empty() {
+        error!("Expected a .code section");
+        self.location = (0, 0, 0);
+        self.report(AssemblerError::InsufficientSections);
+      }
       return Err(self.errors.clone());
     }
 
-    let mut body = self.process_second_phase(&program);
-    let mut assembled_program = get_lumi_header(self.ro.len());
-    assembled_program.append(&mut self.ro);
-    assembled_program.append(&mut body);
+    self.check_warnings(&program);
+    Ok(program)
+  }
 
-    info!("Assembled program length: {}", assembled_program.len());
-    
-    visualize_program(&assembled_program, None);
-    let disassembled_program = disassemble(&assembled_program);
-    match disassembled_program {
-      Ok(disassembly) => {
-        info!("Disassembled program:\n{}", disassembly);
+  /// Parse `raw`, the text of file `file`. A syntax error is reported and its line blanked
+  /// out before parsing again, so one run reports every syntax error.
+  fn parse_program(&mut self, raw: &str, file: u32) -> Option<Program> {
+    let mut text = raw.to_string();
+    let mut failed_lines = HashSet::new();
+    loop {
+      let error = match LumiAsmParser::parse(Rule::program, &text) {
+        Ok(pairs) => {
+          if log::log_enabled!(log::Level::Debug) {
+            for pair in pairs.clone() {
+              LumiAsmParser::print_pair(pair, 0);
+            }
+          }
+          return match Program::from_pairs(pairs) {
+            Ok(program) => Some(program),
+            Err(err) => {
+              self.location = (file, 0, 0);
+              self.report(err);
+              None
+            }
+          };
+        }
+        Err(error) => error,
+      };
+
+      let (line, column) = match error.line_col {
+        LineColLocation::Pos(position) => position,
+        LineColLocation::Span(start, _) => start,
+      };
+      self.location = (file, line, column);
+      self.report(AssemblerError::ParseError { error: syntax_error_message(&error, &text, line, column) });
+      // blanking the line did not help, the error is not confined to it
+      if !failed_lines.insert(line) {
+        return None;
+      }
+      text = text
+        .split('\n')
+        .enumerate()
+        .map(|(index, content)| if index + 1 == line { "" } else { content })
+        .collect::<Vec<_>>()
+        .join("\n");
+    }
+  }
+
+  /// Replace every `.include` in `program` with the instructions of the included file,
+  /// recursively. `stack` holds the files currently being included, to detect cycles, and a
+  /// file that was already included once is skipped.
+  fn expand_includes(
+    &mut self,
+    program: Program,
+    directory: Option<PathBuf>,
+    stack: &mut Vec<PathBuf>,
+  ) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    let mut instructions = vec![];
+    for instruction in program.into_instructions() {
+      if !matches!(instruction.directive, Some(Token::Directive { directive_type: DirectiveType::Include })) {
+        instructions.push(instruction);
+        continue;
+      }
+
+      let name = instruction.get_string_constant().unwrap_or_default();
+      let path = self.resolve_include(&name, directory.as_deref())?;
+      if stack.contains(&path) {
+        let mut chain: Vec<String> = stack.iter().map(|path| path.display().to_string()).collect();
+        chain.push(path.display().to_string());
+        return Err(AssemblerError::IncludeCycle { chain });
+      }
+      if self.included_files.contains(&path) {
+        debug!("{} was already included, skipping", path.display());
+        continue;
+      }
+
+      info!("Including {}", path.display());
+      let raw = fs::read_to_string(&path).map_err(|err| AssemblerError::FailedToReadFile {
+        error: format!("{}: {}", path.display(), err),
+      })?;
+      self.included_files.push(path.clone());
+      let file = self.included_files.len() as u32;
+      if let Some(debug_info) = &mut self.debug_info {
+        debug_info.files.push(path.display().to_string());
       }
-      Err(e) => {
-        error!("Error disassembling program: {:?}", e);
+      self.sources.push((path.display().to_string(), raw.clone()));
+
+      // syntax errors were reported, the rest of the program is still checked
+      let mut included = match self.parse_program(&raw, file) {
+        Some(program) => program.into_instructions(),
+        None => continue,
+      };
+      for instruction in &mut included {
+        instruction.file = file;
       }
+      stack.push(path.clone());
+      let included = self.expand_includes(Program::new(included), path.parent().map(Path::to_path_buf), stack)?;
+      stack.pop();
+      instructions.extend(included);
     }
-    
-    Ok(assembled_program)
+    Ok(instructions)
+  }
+
+  /// Find the file named by `.include "name"`: next to the including file (or in the working
+  /// directory), then in each include path in order.
+  fn resolve_include(&self, name: &str, directory: Option<&Path>) -> Result<PathBuf, AssemblerError> {
+    let candidates = std::iter::once(directory.map_or_else(|| PathBuf::from(name), |directory| directory.join(name)))
+      .chain(self.include_paths.iter().map(|include_path| include_path.join(name)));
+    for candidate in candidates {
+      if candidate.is_file() {
+        return Ok(fs::canonicalize(&candidate).unwrap_or(candidate));
+      }
+    }
+    Err(AssemblerError::IncludeNotFound { path: name.to_string() })
+  }
+
+  /// Assemble a single instruction line into bytecode, without a header.
+  /// Labels are resolved against the symbols collected by previous assemblies.
+  pub fn assemble_instruction(&self, raw: &str) -> Result<Vec<u8>, AssemblerError> {
+    let instruction = parse_instruction(raw.trim())?;
+    if !instruction.is_opcode() {
+      return Err(AssemblerError::NonOpcodeInOpcodeField);
+    }
+    Ok(instruction.to_bytes(&self.symbols))
   }
 
   /// Run the first pass assembly process.
   /// This will look for label declarations and store them in the symbol table.
   fn process_first_phase(&mut self, program: &Program) {
     for instruction in program.get_instructions() {
+      self.location = (instruction.file, instruction.line, instruction.column);
       // debug!("Processing instruction: {:?}", instruction);
+      let emitted = (self.ro.len(), self.data.len(), self.bss_size);
 
-      if let Some(AssemblerSection::Data { .. }) = self.current_section {
+      if self.in_data_section() {
+        let label_type = match self.current_section {
+          Some(AssemblerSection::Bss { .. }) => SymbolType::Bss,
+          _ => SymbolType::Data,
+        };
         if instruction.is_label() {
           debug!("Instruction is a label in DATA section: {:?}", instruction);
           // If a directive is present, then this label defines a constant.
           if let Some(Token::Directive { directive_type }) = &instruction.directive {
             match directive_type {
               DirectiveType::Integer => {
-                self.process_label_declaration(instruction);
+                self.process_label_declaration(instruction, SymbolType::Integer);
                 self.handle_integer(instruction);
               }
+              DirectiveType::Byte => {
+                self.process_label_declaration(instruction, SymbolType::Integer);
+                self.handle_byte(instruction);
+              }
               DirectiveType::Float => {
-                self.process_label_declaration(instruction);
+                self.process_label_declaration(instruction, SymbolType::Float);
                 self.handle_float(instruction);
               }
               DirectiveType::Asciiz => {
-                self.process_label_declaration(instruction);
+                self.process_label_declaration(instruction, SymbolType::LString);
                 self.handle_asciiz(instruction);
               }
+              DirectiveType::Space => {
+                self.process_label_declaration(instruction, label_type);
+                self.handle_space(instruction);
+              }
               _ => {
                 // If it's not one of these, process as a label declaration.
-                self.process_label_declaration(instruction);
+                self.process_label_declaration(instruction, label_type);
               }
             }
+            if matches!(
+              directive_type,
+              DirectiveType::Integer
+                | DirectiveType::Byte
+                | DirectiveType::Float
+                | DirectiveType::Asciiz
+                | DirectiveType::Space
+            ) {
+              self.record_data_size(instruction);
+            }
           } else {
             // No directive found – process as a label declaration.
             debug!("No directive found, processing as label declaration: {:?}", instruction);
-            self.process_label_declaration(instruction);
+            self.process_label_declaration(instruction, label_type);
           }
         } else if instruction.is_directive() {
           debug!("Instruction is a directive in DATA section: {:?}", instruction);
@@ -233,9 +537,9 @@ impl Assembler {
         if instruction.is_label() {
           debug!("Instruction is a label in NON-DATA section: {:?}", instruction);
           if self.current_section.is_some() {
-            self.process_label_declaration(instruction);
+            self.process_label_declaration(instruction, SymbolType::Label);
           } else {
-            self.errors.push(AssemblerError::NoSegmentDeclarationFound {
+            self.report(AssemblerError::NoSegmentDeclarationFound {
               instruction: self.current_instruction,
             });
           }
@@ -244,32 +548,68 @@ impl Assembler {
           debug!("Instruction is a directive in NON-DATA section: {:?}", instruction);
           self.process_directive(instruction);
         }
+        self.code_offset += instruction.encoded_length() as u32;
       }
 
+      self.record_data_listing(instruction, emitted);
       self.current_instruction += 1;
     }
+
     self.phase = AssemblerPhase::Second;
   }
 
 
   /// Run the second pass assembly process.
-  /// This will generate the bytecode for the program.
-  fn process_second_phase(&mut self, program: &Program) -> Vec<u8> {
+  /// This will generate the bytecode for the program, placed at `code_start`, and a
+  /// relocation for every label operand.
+  fn process_second_phase(&mut self, program: &Program, code_start: usize) -> (Vec<u8>, Vec<Relocation>) {
     self.current_instruction = 0;
     let mut bytecode = vec![];
+    let mut relocations = vec![];
     debug!("Symbol table: {:?}", self.symbols.get_symbols());
     debug!("Read-Only data: {:?}", self.ro);
-    debug!("Read-Only offset: {}", self.ro_offset);
+    debug!("Data: {:?}", self.data);
 
     for instruction in program.get_instructions() {
       // debug!("Processing instruction: {:?}", instruction);
+      self.location = (instruction.file, instruction.line, instruction.column);
       if instruction.is_directive() {
         debug!("Found a directive in second phase: {:?}, skipping...", instruction.directive);
         continue;
       }
 
       if instruction.is_opcode() {
+        if let Some(debug_info) = &mut self.debug_info {
+          debug_info.lines.push(LineEntry {
+            offset: (code_start + bytecode.len()) as u32,
+            file: instruction.file,
+            line: instruction.line as u32,
+            column: instruction.column as u32,
+          });
+        }
+        for error in instruction.validate(&self.symbols) {
+          // report each undefined symbol once, at its first use
+          let reported = matches!(&error, AssemblerError::UndefinedSymbol { symbol } if self.errors.iter().any(
+            |previous| matches!(previous, AssemblerError::UndefinedSymbol { symbol: name } if name == symbol)
+          ));
+          if !reported {
+            self.report(error);
+          }
+        }
+        for (offset, kind, symbol, addend) in instruction.label_references(&self.symbols) {
+          relocations.push(Relocation { offset: (bytecode.len() + offset) as u32, kind, symbol, addend: addend as i32 });
+        }
         let mut bytes = instruction.to_bytes(&self.symbols);
+        if let Some(listing) = &mut self.listing {
+          listing.push(ListingEntry {
+            file: instruction.file,
+            line: instruction.line,
+            section: SectionKind::Code,
+            address: (code_start + bytecode.len()) as u32,
+            bytes: bytes.clone(),
+            reserved: 0,
+          });
+        }
         bytecode.append(&mut bytes);
         // debug!("Instruction: {:?}", instruction);
         debug!("Instruction [{:?}]: {:?}", instruction.to_bytes(&self.symbols), instruction);
@@ -278,14 +618,118 @@ impl Assembler {
       self.current_instruction += 1;
     }
 
-    bytecode
+    (bytecode, relocations)
+  }
+
+  /// Record `error` at the instruction being processed, or at the line the error names.
+  fn report(&mut self, error: AssemblerError) {
+    let (file, mut line, mut column) = self.location;
+    if let Some(error_line) = error.line() {
+      if error_line != line {
+        line = error_line;
+        column = 1;
+      }
+    }
+    let symbol = match &error {
+      AssemblerError::UndefinedSymbol { symbol } | AssemblerError::SymbolAlreadyDeclared { symbol } => Some(symbol.clone()),
+      // the span of a syntax error starts at the unexpected text
+      AssemblerError::ParseError { error } => error.split('`').nth(1).map(str::to_string),
+      _ => None,
+    };
+    let diagnostic = self.locate(Diagnostic::error(error.to_string()), file, line, column, symbol.as_deref());
+    self.diagnostics.push(diagnostic);
+    self.errors.push(error);
+  }
+
+  /// Report an error about a symbol at its `.global` or `.extern` declaration.
+  fn report_declaration(&mut self, program: &Program, error: AssemblerError) {
+    let declaration = program.get_instructions().iter().find(|instruction| {
+      matches!(
+        (&instruction.directive, &error, &instruction.operand_1),
+        (
+          Some(Token::Directive { directive_type: DirectiveType::Global | DirectiveType::Extern }),
+          AssemblerError::UndefinedSymbol { symbol },
+          Some(Token::LabelUsage { name }),
+        ) if name == symbol
+      )
+    });
+    self.location = declaration.map_or((0, 0, 0), |instruction| (instruction.file, instruction.line, instruction.column));
+    self.report(error);
+  }
+
+  fn warn(&mut self, message: String, instruction: &AssemblerInstruction, needle: Option<&str>) {
+    warn!("{} on line {}", message, instruction.line);
+    let diagnostic =
+      self.locate(Diagnostic::warning(message), instruction.file, instruction.line, instruction.column, needle);
+    self.diagnostics.push(diagnostic);
+  }
+
+  /// Attach the span of `line:column` in `file` to `diagnostic`. The span covers `needle` if
+  /// it appears on the line after `column`, otherwise the rest of the line before any comment.
+  fn locate(&self, diagnostic: Diagnostic, file: u32, line: usize, column: usize, needle: Option<&str>) -> Diagnostic {
+    let (name, text) = match self.sources.get(file as usize) {
+      Some((name, text)) if line > 0 => (name.clone(), text.lines().nth(line - 1).unwrap_or_default()),
+      _ => return diagnostic,
+    };
+    let column = column.max(1);
+    let rest: String = text.chars().skip(column - 1).collect();
+    let (column, length) = match needle.and_then(|needle| rest.find(needle).map(|index| (needle, index))) {
+      Some((needle, index)) => (column + rest[..index].chars().count(), needle.chars().count()),
+      None => (column, rest.split(';').next().unwrap_or_default().trim_end().chars().count()),
+    };
+    let span = Span { file: name, line, column, length: length.max(1) };
+    diagnostic.with_span(span, Some(text.to_string()))
+  }
+
+  /// Warn about labels nothing refers to, and about instructions that can never run because
+  /// they follow a `hlt`, an unconditional jump or a `ret` with no label in between.
+  fn check_warnings(&mut self, program: &Program) {
+    let mut referenced = HashSet::new();
+    for instruction in program.get_instructions() {
+      let visibility = matches!(
+        instruction.directive,
+        Some(Token::Directive { directive_type: DirectiveType::Global | DirectiveType::Extern })
+      );
+      if !visibility {
+        referenced.extend(instruction.referenced_symbols().into_iter().map(str::to_string));
+      }
+    }
+
+    let mut unreachable_after = None;
+    let mut reported = false;
+    for instruction in program.get_instructions() {
+      if let Some(name) = instruction.get_label_name() {
+        // macro expansions rename their local labels to `macro.n.label`
+        if !referenced.contains(&name) && !self.globals.contains(&name) && !name.contains('.') {
+          self.warn(format!("Label `{}` is never used", name), instruction, Some(&name));
+        }
+      }
+      if instruction.is_label() || instruction.is_directive() {
+        unreachable_after = None;
+        reported = false;
+      }
+      if let Some(Token::Op { code }) = &instruction.opcode {
+        if let Some(terminator) = unreachable_after {
+          if !reported {
+            let message = format!("Unreachable instruction after `{}`", format!("{:?}", terminator).to_lowercase());
+            self.warn(message, instruction, None);
+            reported = true;
+          }
+        } else if matches!(code, Opcode::HLT | Opcode::JMP | Opcode::JMPF | Opcode::JMPB | Opcode::DJMP | Opcode::RET) {
+          unreachable_after = Some(*code);
+        }
+      }
+    }
   }
 
-  fn process_label_declaration(&mut self, instruction: &AssemblerInstruction) {
+  /// Declare the label of `instruction`. Code labels point at the current code offset
+  /// and are relocated once the RO section size is known, `.bss` labels are relocated once
+  /// the `.data` size is known, other labels point at the end of their section's data.
+  fn process_label_declaration(&mut self, instruction: &AssemblerInstruction, symbol_type: SymbolType) {
     let name = match instruction.get_label_name() {
       Some(name) => name,
       None => {
-        self.errors.push(AssemblerError::StringConstantDeclaredWithoutLabel {
+        self.report(AssemblerError::StringConstantDeclaredWithoutLabel {
           instruction: self.current_instruction,
         });
         return;
@@ -294,20 +738,21 @@ impl Assembler {
 
     debug!("Processing label declaration: {} on line {}", name, self.current_instruction);
     if self.symbols.has_symbol(&name) {
-      self.errors.push(AssemblerError::SymbolAlreadyDeclared {
+      self.report(AssemblerError::SymbolAlreadyDeclared {
         symbol: name.to_string(),
       });
     }
 
-    let symbol = Symbol::new_with_offset(
-      name,
-      SymbolType::Label,
-      (self.current_instruction * 4) + LUMI_HEADER_LENGTH as u32 + 1,
-    );
-    info!(
-      "Added a new symbol to table: {:?} with offset: {}",
-      symbol, (self.current_instruction * 4) + LUMI_HEADER_LENGTH as u32 + 1
-    ); // TODO: this was set to + 60 for some reason
+    let offset = match symbol_type {
+      SymbolType::Label => self.code_offset,
+      SymbolType::Bss => self.bss_size,
+      _ => self.data_offset(),
+    };
+    let mut symbol = Symbol::new_with_offset(name, symbol_type, offset);
+    if let Some(AssemblerSection::Data { .. }) | Some(AssemblerSection::Bss { .. }) = self.current_section {
+      symbol = symbol.writable();
+    }
+    info!("Added a new symbol to table: {:?}", symbol);
     self.symbols.add_symbol(symbol);
   }
 
@@ -317,9 +762,15 @@ impl Assembler {
   fn process_directive(&mut self, instruction: &AssemblerInstruction) {
     if let Some(Token::Directive { directive_type }) = &instruction.directive {
       match directive_type {
+        DirectiveType::ReadOnly => {
+          self.process_section_header("rodata");
+        }
         DirectiveType::Data => {
           self.process_section_header("data");
         }
+        DirectiveType::Bss => {
+          self.process_section_header("bss");
+        }
         DirectiveType::Text => {
           self.process_section_header("text");
         }
@@ -329,11 +780,23 @@ impl Assembler {
         DirectiveType::Integer => {
           self.handle_integer(instruction);
         }
+        DirectiveType::Byte => {
+          self.handle_byte(instruction);
+        }
         DirectiveType::Float => {
           self.handle_float(instruction);
         }
+        DirectiveType::Space => {
+          self.handle_space(instruction);
+        }
+        DirectiveType::Equ => {
+          self.handle_equ(instruction);
+        }
+        DirectiveType::Global | DirectiveType::Extern => {
+          self.handle_visibility(instruction, directive_type);
+        }
         _ => {
-          self.errors.push(AssemblerError::UnknownDirectiveFound {
+          self.report(AssemblerError::UnknownDirectiveFound {
             directive: format!("{:?}", directive_type),
           });
         }
@@ -343,6 +806,83 @@ impl Assembler {
     }
   }
 
+  /// Define the constant of an `.equ NAME expr` line. The expression may use constants
+  /// defined before it.
+  fn handle_equ(&mut self, instruction: &AssemblerInstruction) {
+    let (name, expression) = match (&instruction.operand_1, &instruction.operand_2) {
+      (Some(Token::LabelDeclaration { name }), Some(Token::Expression { expression })) => (name, expression),
+      _ => {
+        error!("Malformed .equ directive: {:?}", instruction);
+        return;
+      }
+    };
+
+    let value = match expression.evaluate_constant(&self.symbols) {
+      Ok(value) => value,
+      Err(error) => {
+        self.report(AssemblerError::InvalidExpression { error, line: instruction.line });
+        return;
+      }
+    };
+    if self.symbols.has_symbol(name) {
+      self.report(AssemblerError::SymbolAlreadyDeclared { symbol: name.clone() });
+      return;
+    }
+    debug!("Defined constant {} = {}", name, value);
+    self.symbols.add_symbol(Symbol::new(name.clone(), SymbolType::Constant { value }));
+  }
+
+  /// Integer operand of a data directive, either a literal or a constant expression.
+  fn integer_operand(&mut self, instruction: &AssemblerInstruction) -> Option<i32> {
+    match &instruction.operand_1 {
+      Some(Token::IntegerOperand { value }) => Some(*value),
+      Some(Token::Expression { expression }) => {
+        let value = expression
+          .evaluate_constant(&self.symbols)
+          .map_err(|error| AssemblerError::InvalidExpression { error, line: instruction.line })
+          .and_then(|value| {
+            i32::try_from(value).map_err(|_| AssemblerError::ImmediateOutOfRange {
+              value,
+              min: i32::MIN as i64,
+              max: i32::MAX as i64,
+              line: instruction.line,
+            })
+          });
+        match value {
+          Ok(value) => Some(value),
+          Err(error) => {
+            self.report(error);
+            None
+          }
+        }
+      }
+      _ => None,
+    }
+  }
+
+  /// Remember how many bytes the data directive of `instruction` declared, for `sizeof`.
+  fn record_data_size(&mut self, instruction: &AssemblerInstruction) {
+    if let Some(name) = instruction.get_label_name() {
+      let end = match self.current_section {
+        Some(AssemblerSection::Bss { .. }) => self.bss_size,
+        _ => self.data_offset(),
+      };
+      if let Some(start) = self.symbols.symbol_value(&name) {
+        self.symbols.set_symbol_size(&name, end.saturating_sub(start));
+      }
+    }
+  }
+
+  /// Record a `.global` or `.extern` declaration, applied once every symbol is known.
+  fn handle_visibility(&mut self, instruction: &AssemblerInstruction, directive_type: &DirectiveType) {
+    if let Some(Token::LabelUsage { name }) = &instruction.operand_1 {
+      match directive_type {
+        DirectiveType::Global => self.globals.push(name.clone()),
+        _ => self.externs.push(name.clone()),
+      }
+    }
+  }
+
   fn handle_asciiz(&mut self, instruction: &AssemblerInstruction) {
     if self.phase != AssemblerPhase::First {
       return;
@@ -350,23 +890,14 @@ impl Assembler {
 
     match instruction.get_string_constant() {
       Some(s) => {
-        match instruction.get_label_name() {
-          Some(name) => {
-            self.symbols.set_symbol_offset(&name, self.ro_offset);
-          }
-          None => {
-            error!("Foudn a string constant with no associated label");
-            return;
-          }
-        };
-
-        for byte in s.as_bytes() {
-          self.ro.push(*byte);
-          self.ro_offset += 1;
+        if instruction.get_label_name().is_none() {
+          error!("Foudn a string constant with no associated label");
+          return;
         }
 
-        self.ro.push(0);
-        self.ro_offset += 1;
+        let mut bytes = s.into_bytes();
+        bytes.push(0);
+        self.emit_data(&bytes);
       }
       None => {
         error!("String constant following a .asciiz directive is missing");
@@ -377,8 +908,6 @@ impl Assembler {
   fn handle_integer(&mut self, instruction: &AssemblerInstruction) {
     debug!("Handling integer constant: {:?}", instruction);
     debug!("Current phase: {:?}", self.phase);
-    debug!("Current RO offset: {}", self.ro_offset);
-    debug!("Current RO data: {:?}", self.ro);
     debug!("Current instruction: {}", self.current_instruction);
     debug!("Current section: {:?}", self.current_section);
     debug!("Current symbols: {:?}", self.symbols.get_symbols());
@@ -386,25 +915,17 @@ impl Assembler {
       return;
     }
 
-    match instruction.get_i32_constant() {
+    match self.integer_operand(instruction) {
       Some(s) => {
-        match instruction.get_label_name() {
-          Some(name) => {
-            self.symbols.set_symbol_offset(&name, self.ro_offset);
-          }
-          None => {
-            // e.g. someone types .integer 50
-            error!("Found an integer constant with no associated label");
-            return;
-          }
-        };
+        if instruction.get_label_name().is_none() {
+          // e.g. someone types .integer 50
+          error!("Found an integer constant with no associated label");
+          return;
+        }
 
         let mut wtr = vec![];
         wtr.write_i32::<LittleEndian>(s).unwrap();
-        for byte in &wtr {
-          self.ro.push(*byte);
-          self.ro_offset += 1;
-        }
+        self.emit_data(&wtr);
       }
       None => {
         // someone types .integer
@@ -413,6 +934,23 @@ impl Assembler {
     }
   }
 
+  fn handle_byte(&mut self, instruction: &AssemblerInstruction) {
+    if self.phase != AssemblerPhase::First {
+      return;
+    }
+
+    match self.integer_operand(instruction) {
+      Some(value) if (0..=u8::MAX as i32).contains(&value) => self.emit_data(&[value as u8]),
+      Some(value) => self.report(AssemblerError::ImmediateOutOfRange {
+        value: value as i64,
+        min: 0,
+        max: u8::MAX as i64,
+        line: instruction.line,
+      }),
+      None => error!("Value following a .byte directive is missing"),
+    }
+  }
+
   fn handle_float(&mut self, instruction: &AssemblerInstruction) {
     if self.phase != AssemblerPhase::First {
       return;
@@ -420,21 +958,16 @@ impl Assembler {
 
     match instruction.get_float_value() {
       Some(f) => {
-        if let Some(name) = instruction.get_label_name() {
-          self.symbols.set_symbol_offset(&name, self.ro_offset);
-        } else {
+        if instruction.get_label_name().is_none() {
           error!("Found a float constant with no associated label");
           return;
-        };
+        }
 
         let mut wtr = vec![];
         // Here, you might want to decide whether to write as f32 or f64.
         // This example uses f32.
-        wtr.write_f32::<LittleEndian>(f as f32).unwrap();
-        for byte in &wtr {
-          self.ro.push(*byte);
-          self.ro_offset += 1;
-        }
+        wtr.write_f32::<LittleEndian>(f).unwrap();
+        self.emit_data(&wtr);
       }
       None => {
         error!("Float constant following a .float directive is missing");
@@ -442,29 +975,130 @@ impl Assembler {
     }
   }
 
-  fn process_section_header(&mut self, header_name: &str) {
-    let mut new_section: AssemblerSection = header_name.into();
+  /// Reserve `n` bytes for a `.space #n` directive: zeroes in `.rodata` and `.data`,
+  /// heap memory in `.bss`.
+  fn handle_space(&mut self, instruction: &AssemblerInstruction) {
+    if self.phase != AssemblerPhase::First {
+      return;
+    }
+
+    let size = match self.integer_operand(instruction) {
+      Some(size) if size >= 0 => size,
+      Some(size) => {
+        self.report(AssemblerError::InvalidSpaceSize { size });
+        return;
+      }
+      None => {
+        error!("Size following a .space directive is missing");
+        return;
+      }
+    };
+
+    if let Some(AssemblerSection::Bss { .. }) = self.current_section {
+      self.bss_size += size as u32;
+    } else {
+      self.emit_data(&vec![0; size as usize]);
+    }
+  }
 
-    if new_section == AssemblerSection::Unknown {
-      error!("Found a section header that is unknown: {:#?}", header_name);
+  /// Add a listing entry for the data `instruction` emitted, given the sizes of `.rodata`,
+  /// `.data` and `.bss` before it.
+  fn record_data_listing(&mut self, instruction: &AssemblerInstruction, (ro, data, bss): (usize, usize, u32)) {
+    let entry = if self.ro.len() > ro {
+      (SectionKind::ReadOnly, ro as u32, self.ro[ro..].to_vec(), 0)
+    } else if self.data.len() > data {
+      (SectionKind::Data, data as u32, self.data[data..].to_vec(), 0)
+    } else if self.bss_size > bss {
+      (SectionKind::Bss, bss, vec![], self.bss_size - bss)
+    } else {
       return;
+    };
+    if let Some(listing) = &mut self.listing {
+      let (section, address, bytes, reserved) = entry;
+      listing.push(ListingEntry { file: instruction.file, line: instruction.line, section, address, bytes, reserved });
+    }
+  }
+
+  fn in_data_section(&self) -> bool {
+    matches!(
+      self.current_section,
+      Some(AssemblerSection::ReadOnly { .. }) | Some(AssemblerSection::Data { .. }) | Some(AssemblerSection::Bss { .. })
+    )
+  }
+
+  /// Offset of the next byte of data in the current section: `.data` is addressed from the
+  /// start of the heap, everything else from the start of the RO section.
+  fn data_offset(&self) -> u32 {
+    match self.current_section {
+      Some(AssemblerSection::Data { .. }) => self.data.len() as u32,
+      _ => self.ro.len() as u32,
     }
+  }
+
+  /// Append initialized data to the current section. `.bss` only reserves memory, so
+  /// initialized data there is an error.
+  fn emit_data(&mut self, bytes: &[u8]) {
+    match self.current_section {
+      Some(AssemblerSection::Data { .. }) => self.data.extend_from_slice(bytes),
+      Some(AssemblerSection::Bss { .. }) => self.report(AssemblerError::InitializedDataInBss {
+        instruction: self.current_instruction,
+      }),
+      _ => self.ro.extend_from_slice(bytes),
+    }
+  }
+
+  /// Build the executable from the assembled sections.
+  fn write_binary(&mut self, code: Vec<u8>) -> Vec<u8> {
+    let debug_info = self.debug_info.take().map(|mut debug_info| {
+      debug_info.symbols = self.symbols.debug_symbols();
+      debug_info
+    });
+    let image = ExecutableImage {
+      ro: self.ro.clone(),
+      code,
+      data: self.data.clone(),
+      bss_size: self.bss_size as usize,
+      debug_info,
+      entry: 0,
+    };
+    image.write()
+  }
+
+  /// Package the assembled sections as an object. Symbol offsets stay relative to their
+  /// section and every label operand gets a relocation.
+  fn write_object(&mut self, source: &str, code: Vec<u8>, relocations: Vec<Relocation>, imports: Vec<String>) -> ObjectFile {
+    ObjectFile {
+      source: source.to_string(),
+      ro: self.ro.clone(),
+      code,
+      data: self.data.clone(),
+      bss_size: self.bss_size,
+      symbols: self.symbols.object_symbols(),
+      imports,
+      relocations,
+      // objects only name their own source, so lines from included files are left out
+      lines: self
+        .debug_info
+        .take()
+        .map(|debug_info| debug_info.lines.into_iter().filter(|entry| entry.file == 0).collect())
+        .unwrap_or_default(),
+    }
+  }
+
+  fn process_section_header(&mut self, header_name: &str) {
+    let mut new_section: AssemblerSection = header_name.into();
 
     match new_section {
-      AssemblerSection::Code {
-        ref mut starting_instruction,
-      } => {
-        debug!("Code section starts at: {}", self.current_instruction);
-        *starting_instruction = Some(self.current_instruction)
-      }
-      AssemblerSection::Data {
-        ref mut starting_instruction,
-      } => {
-        debug!("Data section starts at: {}", self.current_instruction);
+      AssemblerSection::ReadOnly { ref mut starting_instruction }
+      | AssemblerSection::Data { ref mut starting_instruction }
+      | AssemblerSection::Bss { ref mut starting_instruction }
+      | AssemblerSection::Code { ref mut starting_instruction } => {
+        debug!("{} section starts at: {}", header_name, self.current_instruction);
         *starting_instruction = Some(self.current_instruction)
       }
       AssemblerSection::Unknown => {
-        error!("Found an unknown section header: {:#?}", header_name);
+        error!("Found a section header that is unknown: {:#?}", header_name);
+        return;
       }
     }
 
@@ -512,9 +1146,30 @@ impl Assembler {
   }
 }
 
+/// Describe a syntax error by the text it was found at, and what was expected there when
+/// that is short enough to be useful.
+fn syntax_error_message(error: &pest::error::Error<Rule>, text: &str, line: usize, column: usize) -> String {
+  let found = match text.split('\n').nth(line - 1) {
+    Some(content) => {
+      let token: String = content.chars().skip(column - 1).take_while(|c| !c.is_whitespace()).collect();
+      if token.is_empty() { "end of line".to_string() } else { format!("`{}`", token) }
+    }
+    None => "end of file".to_string(),
+  };
+  match &error.variant {
+    pest::error::ErrorVariant::ParsingError { positives, .. } if !positives.is_empty() && positives.len() <= 3 => {
+      let expected: Vec<String> = positives.iter().map(|rule| format!("{:?}", rule).replace('_', " ")).collect();
+      format!("Unexpected {}, expected {}", found, expected.join(" or "))
+    }
+    _ => format!("Unexpected {}", found),
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::debug_info::DebugSymbolKind;
+  use crate::header_utils::{code_start_offset, LumiHeader};
 
   #[test]
   fn test_symbol_table() {
@@ -543,17 +1198,17 @@ mod tests {
         load $2 #0
         test: inc $0
         neq $0 $2
-        jeq @test
+        djmpe @test
         hlt
         ";
     let program = asm.assemble(test_string).unwrap();
-    assert_eq!(program.len(), 97);
+    assert_eq!(program.len(), 128);
   }
 
   #[test]
   fn test_assemble_program_with_start_offset_written() {
     let mut asm = Assembler::new();
-    let test_string = r".data
+    let test_string = r".rodata
         test1: .asciiz 'Hello'
         .code
         load $0 #100
@@ -561,11 +1216,182 @@ mod tests {
         load $2 #0
         test: inc $0
         neq $0 $2
-        jmpe @test
+        djmpe @test
+        hlt
+        ";
+    let program = asm.assemble(test_string).unwrap();
+    assert_eq!(program.len(), 134);
+  }
+
+  #[test]
+  fn test_label_offsets_use_encoded_sizes() {
+    let mut asm = Assembler::new();
+    let test_string = r".rodata
+        msg: .asciiz 'Hi'
+        num: .integer #7
+        .code
+        load $0 #1
+        first: loadi $1 #70000
+        second:
+        loadf64 $0 #1.5
+        third: hlt
+        ";
+    let program = asm.assemble(test_string).unwrap();
+    let code_start = code_start_offset(7) as u32;
+    assert_eq!(asm.symbols.symbol_value("msg"), Some(0));
+    assert_eq!(asm.symbols.symbol_value("num"), Some(3));
+    assert_eq!(asm.symbols.symbol_value("first"), Some(code_start + 4));
+    assert_eq!(asm.symbols.symbol_value("second"), Some(code_start + 10));
+    assert_eq!(asm.symbols.symbol_value("third"), Some(code_start + 20));
+    assert_eq!(program[asm.symbols.symbol_value("third").unwrap() as usize], u8::from(Opcode::HLT));
+  }
+
+  #[test]
+  fn test_listing_records_addresses_and_bytes() {
+    let mut asm = Assembler::new().with_listing();
+    let test_string = r".rodata
+        msg: .asciiz 'Hi'
+        .data
+        counter: .integer #5
+        .bss
+        scratch: .space #16
+        .code
+        load $0 #1
+        prts @msg
         hlt
         ";
     let program = asm.assemble(test_string).unwrap();
-    assert_eq!(program.len(), 103);
+    let listing = asm.listing().unwrap();
+    let code_start = code_start_offset(3) as u32;
+    let lines: Vec<(usize, SectionKind, u32, usize)> = listing
+      .entries
+      .iter()
+      .map(|entry| (entry.line, entry.section, entry.address, entry.bytes.len()))
+      .collect();
+    assert_eq!(
+      lines,
+      vec![
+        (2, SectionKind::ReadOnly, 0, 3),
+        (4, SectionKind::Data, 0, 4),
+        (6, SectionKind::Bss, 4, 0),
+        (8, SectionKind::Code, code_start, 4),
+        (9, SectionKind::Code, code_start + 4, 5),
+        (10, SectionKind::Code, code_start + 9, 1),
+      ]
+    );
+    for entry in listing.entries.iter().filter(|entry| entry.section == SectionKind::Code) {
+      let start = entry.address as usize;
+      assert_eq!(&program[start..start + entry.bytes.len()], entry.bytes.as_slice());
+    }
+    assert_eq!(listing.entries[2].reserved, 16);
+    assert_eq!(listing.ro, b"Hi\0");
+
+    let rendered = listing.render();
+    assert!(rendered.contains(&format!("    9  .code   0x{:06x}", code_start + 4)));
+    assert!(rendered.contains("scratch                  .bss     0x00000004      16"));
+    assert!(rendered.contains("msg                      \"Hi\""));
+  }
+
+  #[test]
+  fn test_data_and_bss_sections() {
+    let mut asm = Assembler::new();
+    let test_string = r".rodata
+        msg: .asciiz 'Hi'
+        .data
+        counter: .integer #5
+        buffer: .space #3
+        .bss
+        scratch: .space #16
+        end:
+        .code
+        load $0 @counter
+        load $1 @scratch
+        prts @msg
+        hlt
+        ";
+    let program = asm.assemble(test_string).unwrap();
+    assert_eq!(asm.symbols.symbol_value("msg"), Some(0));
+    assert_eq!(asm.symbols.symbol_value("counter"), Some(0));
+    assert_eq!(asm.symbols.symbol_value("buffer"), Some(4));
+    assert_eq!(asm.symbols.symbol_value("scratch"), Some(7));
+    assert_eq!(asm.symbols.symbol_value("end"), Some(23));
+
+    let header = LumiHeader::parse(&program).unwrap();
+    assert_eq!(header.validate(&program), Ok(()));
+    let sections = header.sections(&program).unwrap();
+    assert_eq!(sections.sections().len(), 4);
+    assert_eq!(sections.contents(SectionKind::ReadOnly, &program), b"Hi\0");
+    assert_eq!(sections.contents(SectionKind::Code, &program).len(), 4 + 4 + 5 + 1);
+    assert_eq!(sections.contents(SectionKind::Data, &program), &[5, 0, 0, 0, 0, 0, 0]);
+    let bss = sections.get(SectionKind::Bss).unwrap();
+    assert_eq!((bss.address, bss.size), (7, 16));
+    assert_eq!(sections.heap_size(), 23);
+  }
+
+  #[test]
+  fn test_debug_info_section() {
+    let test_string = ".data
+counter: .integer #1
+.code
+main: load $0 #1
+  call @double
+  hlt
+double: add $0 $0 $0
+  ret
+";
+    let plain = Assembler::new().assemble(test_string).unwrap();
+    let mut asm = Assembler::new().with_debug_info("double.lumi");
+    let program = asm.assemble(test_string).unwrap();
+
+    let header = LumiHeader::parse(&program).unwrap();
+    assert!(header.has_debug_info());
+    assert!(!LumiHeader::parse(&plain).unwrap().has_debug_info());
+    let sections = header.sections(&program).unwrap();
+    let plain_sections = LumiHeader::parse(&plain).unwrap().sections(&plain).unwrap();
+    assert_eq!(sections.contents(SectionKind::Code, &program), plain_sections.contents(SectionKind::Code, &plain));
+
+    let debug_info = DebugInfo::parse(sections.contents(SectionKind::Debug, &program)).unwrap();
+    assert_eq!(debug_info.files, vec!["double.lumi".to_string()]);
+    let lines: Vec<_> = debug_info.lines.iter().map(|entry| (entry.line, entry.column)).collect();
+    assert_eq!(lines, vec![(4, 7), (5, 3), (6, 3), (7, 9), (8, 3)]);
+
+    let double = asm.symbols.symbol_value("double").unwrap() as usize;
+    assert_eq!(debug_info.label_at(double), Some("double"));
+    assert_eq!(debug_info.location(double).unwrap().to_string(), "double.lumi:7:9");
+    let counter = debug_info.symbols.iter().find(|symbol| symbol.name == "counter").unwrap();
+    assert_eq!(counter.kind, DebugSymbolKind::Variable);
+
+    let disassembly = disassemble(&program).unwrap();
+    assert!(disassembly.contains("double:\n"));
+    assert!(disassembly.contains("; double.lumi:7:9"));
+  }
+
+  #[test]
+  fn test_initialized_data_in_bss_fails() {
+    let mut asm = Assembler::new();
+    let errors = asm.assemble(".bss
+counter: .integer #1
+.code
+hlt
+").unwrap_err();
+    assert!(matches!(errors[0], AssemblerError::InitializedDataInBss { .. }));
+
+    let mut asm = Assembler::new();
+    let errors = asm.assemble(".data
+buffer: .space #-1
+.code
+hlt
+").unwrap_err();
+    assert!(matches!(errors[0], AssemblerError::InvalidSpaceSize { size: -1 }));
+  }
+
+  #[test]
+  fn test_missing_code_section_fails() {
+    let mut asm = Assembler::new();
+    let errors = asm.assemble(".rodata
+msg: .asciiz 'Hi'
+").unwrap_err();
+    assert!(matches!(errors[0], AssemblerError::InsufficientSections));
   }
 
   #[test]
@@ -631,7 +1457,7 @@ mod tests {
   fn test_assemble_program_all() {
     let _ = env_logger::builder().is_test(true).try_init();
     let mut asm = Assembler::new();
-    let test_string = r".data
+    let test_string = r".rodata
         test1: .asciiz 'Hello'
         .code
         load $0 #100
@@ -670,15 +1496,15 @@ mod tests {
         ltf64 $0 $1
         gtef64 $0 $1
         ltef64 $0 $1
-        shl $0 #123
-        shr $0 #123
+        shl $0 #12
+        shr $0 #12
         and $0 $1 $2
         or $0 $1 $2
         xor $0 $1 $2
         not $0 $1
         lui $0 #100 #200
         cloop #10
-        loopstart: add $0 $0 #1
+        loopstart: add $0 $0 $1
         loop @loopstart
         loadm $0 $1
         setm $0 $1
@@ -690,6 +1516,233 @@ mod tests {
         igl
         ";
     let program = asm.assemble(test_string).unwrap();
-    assert_eq!(program.len(), 175);
+    assert_eq!(program.len(), 299);
+  }
+  #[test]
+  fn test_include_searches_include_paths_and_includes_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let lib = dir.path().join("lib");
+    fs::create_dir(&lib).unwrap();
+    fs::write(lib.join("greeting.lumi"), ".rodata\nhello: .asciiz 'Hi'\n").unwrap();
+    fs::write(lib.join("print.lumi"), ".include \"greeting.lumi\"\n.code\nprint: prts @hello\nret\n").unwrap();
+    let main = dir.path().join("main.lumi");
+    fs::write(&main, "").unwrap();
+
+    let mut asm = Assembler::new()
+      .with_source_path(&main)
+      .with_include_path(&lib)
+      .with_debug_info("main.lumi");
+    let source = ".include \"print.lumi\"\n.include \"greeting.lumi\"\n.code\nstart: call @print\nhlt\n";
+    let program = asm.assemble(source).unwrap();
+
+    let header = LumiHeader::parse(&program).unwrap();
+    let sections = header.sections(&program).unwrap();
+    assert_eq!(sections.contents(SectionKind::ReadOnly, &program), b"Hi\0");
+    let debug_info = DebugInfo::parse(sections.contents(SectionKind::Debug, &program)).unwrap();
+    assert_eq!(debug_info.files.len(), 3);
+    assert!(debug_info.files[1].ends_with("print.lumi"));
+    let start = debug_info.symbols.iter().find(|symbol| symbol.name == "start").unwrap();
+    assert_eq!(debug_info.location(start.value as usize).unwrap().line, 4);
+    let print = debug_info.symbols.iter().find(|symbol| symbol.name == "print").unwrap();
+    let location = debug_info.location(print.value as usize).unwrap();
+    assert!(location.file.ends_with("print.lumi"));
+    assert_eq!(location.line, 3);
+  }
+
+  #[test]
+  fn test_include_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.lumi");
+    fs::write(&a, ".include \"b.lumi\"\n.code\nhlt\n").unwrap();
+    fs::write(dir.path().join("b.lumi"), ".include \"a.lumi\"\n").unwrap();
+
+    let source = fs::read_to_string(&a).unwrap();
+    let errors = Assembler::new().with_source_path(&a).assemble(&source).unwrap_err();
+    match &errors[..] {
+      [AssemblerError::IncludeCycle { chain }] => {
+        assert_eq!(chain.len(), 3);
+        assert!(chain[0].ends_with("a.lumi") && chain[1].ends_with("b.lumi") && chain[2].ends_with("a.lumi"));
+      }
+      _ => panic!("expected an include cycle, got {:?}", errors),
+    }
+
+    let errors = Assembler::new().with_source_path(&a).assemble(".include \"missing.lumi\"\n.code\nhlt\n").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::IncludeNotFound { path }] if path == "missing.lumi"));
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_global_and_extern_symbols() {
+    let source = ".global start\n.extern print\n.code\nstart: call @print\nlocal: hlt\n";
+    let object = Assembler::new().assemble_object(source, "main.lumi").unwrap();
+    assert_eq!(object.imports, vec!["print".to_string()]);
+    assert!(object.symbol("start").unwrap().global);
+    assert!(!object.symbol("local").unwrap().global);
+    assert!(object.symbol("print").is_none());
+
+    let errors = Assembler::new().assemble_object(".code\ncall @print\ncall @print\n", "main.lumi").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::UndefinedSymbol { symbol }] if symbol == "print"));
+
+    let errors = Assembler::new().assemble(".global missing\n.code\nhlt\n").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::UndefinedSymbol { symbol }] if symbol == "missing"));
+
+    let errors = Assembler::new().assemble(".extern print\n.code\ncall @print\n").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::UndefinedSymbol { symbol }] if symbol == "print"));
+  }
+  #[test]
+  fn test_macros_assemble_like_hand_written_code() {
+    let with_macros = r"
+      .macro load32 r, high, low
+      load \r \low
+      lui \r \high \low
+      .endm
+      .macro count_down r
+      top: dec \r
+      djmpe @top
+      .endm
+      .code
+      load32 $1, #1, #2
+      .rept 2
+      count_down $1
+      .endr
+      hlt
+      ";
+    let by_hand = r"
+      .code
+      load $1 #2
+      lui $1 #1 #2
+      a: dec $1
+      djmpe @a
+      b: dec $1
+      djmpe @b
+      hlt
+      ";
+    assert_eq!(Assembler::new().assemble(with_macros).unwrap(), Assembler::new().assemble(by_hand).unwrap());
+  }
+
+  #[test]
+  fn test_constant_expressions() {
+    let with_constants = r"
+      .equ BUF_SIZE 16
+      .equ WORDS, BUF_SIZE / 4
+      .rodata
+      msg: .asciiz 'Hello'
+      .bss
+      buf: .space #(BUF_SIZE * 2)
+      .code
+      load $0 #(BUF_SIZE * 4 + 1)
+      load $1 #(sizeof(msg) - -WORDS)
+      load $2 #(sizeof(buf))
+      hlt
+      ";
+    let by_hand = r"
+      .rodata
+      msg: .asciiz 'Hello'
+      .bss
+      buf: .space #32
+      .code
+      load $0 #65
+      load $1 #10
+      load $2 #32
+      hlt
+      ";
+    assert_eq!(Assembler::new().assemble(with_constants).unwrap(), Assembler::new().assemble(by_hand).unwrap());
+
+    let address = |source: &str| {
+      let bytecode = Assembler::new().assemble(source).unwrap();
+      let start = code_start_offset(13) + 1;
+      u32::from_le_bytes(bytecode[start..start + 4].try_into().unwrap())
+    };
+    let table = ".rodata\ntable: .asciiz 'abcdefghijkl'\n.code\n";
+    assert_eq!(address(&format!("{}prts @table + 8\n", table)), address(&format!("{}prts @table\n", table)) + 8);
+  }
+
+  #[test]
+  fn test_constant_expression_errors() {
+    let errors = Assembler::new().assemble(".equ BIG 0x10000\n.code\nload $0 #BIG\n").unwrap_err();
+    assert!(matches!(
+      &errors[..],
+      [AssemblerError::ImmediateOutOfRange { value: 65536, min: -32768, max: 32767, line: 3 }]
+    ));
+
+    let errors = Assembler::new().assemble(".code\nload $0 #(1 / 0)\n").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::InvalidExpression { line: 2, .. }]));
+
+    let errors = Assembler::new().assemble(".equ A 1\n.equ A 2\n.code\nhlt\n").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::SymbolAlreadyDeclared { symbol }] if symbol == "A"));
+
+    let errors = Assembler::new().assemble(".code\nstart: hlt\n.equ B start + 1\n").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::InvalidExpression { error, .. }] if error.contains("is a label")));
+  }
+
+  #[test]
+  fn test_object_relocations_carry_expression_addends() {
+    let object = Assembler::new().assemble_object(".extern far\n.code\ncall @far + 4\nhlt\n", "main.lumi").unwrap();
+    assert_eq!(object.relocations.len(), 1);
+    assert_eq!((object.relocations[0].symbol.as_str(), object.relocations[0].addend), ("far", 4));
+
+    let errors = Assembler::new().assemble_object(".code\nstart: call #(start * 2)\n", "main.lumi").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::InvalidExpression { line: 2, .. }]));
+  }
+
+  #[test]
+  fn test_reports_every_error_with_its_span() {
+    let mut asm = Assembler::new().with_source_path("main.lumi");
+    let errors = asm.assemble(".code\nload $0 #1 !\nload $1 #(1 / 0)\nload $2 %\nhlt\n").unwrap_err();
+    assert_eq!(errors.len(), 3);
+
+    let spans: Vec<_> = asm.diagnostics().iter().map(|diagnostic| diagnostic.span.clone().unwrap()).collect();
+    assert_eq!(
+      spans.iter().map(|span| (span.line, span.column, span.length)).collect::<Vec<_>>(),
+      vec![(2, 12, 1), (4, 9, 1), (3, 1, 16)]
+    );
+    assert_eq!(spans[0].file, "main.lumi");
+    assert_eq!(
+      asm.diagnostics()[0].to_string(),
+      "error: There was an error parsing the code: Unexpected `!`\n --> main.lumi:2:12\n  |\n2 | load $0 #1 !\n  |            ^"
+    );
+
+    let mut asm = Assembler::new();
+    asm.assemble(".global missing\n.code\nhlt\n").unwrap_err();
+    let span = asm.diagnostics()[0].span.clone().unwrap();
+    assert_eq!((span.file.as_str(), span.line, span.column, span.length), ("<input>", 1, 9, 7));
+  }
+
+  #[test]
+  fn test_warnings_do_not_fail_assembly() {
+    let mut asm = Assembler::new();
+    asm.assemble(".code\nstart: load $0 #1\nloop: djmp @loop\nload $1 #2\nhlt\nend: hlt\n").unwrap();
+    let warnings: Vec<_> = asm
+      .diagnostics()
+      .iter()
+      .map(|diagnostic| (diagnostic.is_error(), diagnostic.message.as_str(), diagnostic.span.as_ref().unwrap().line))
+      .collect();
+    assert_eq!(
+      warnings,
+      vec![
+        (false, "Label `start` is never used", 2),
+        (false, "Unreachable instruction after `djmp`", 4),
+        (false, "Label `end` is never used", 6),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_operands_are_validated_against_opcode_metadata() {
+    let source = ".code\nadd $0 $0 #1\nload $0\ninc $32\nshl $0 #123\ndjmp @nowhere\ncall @nowhere\nload $1 @nowhere\nshr $0 #32\nshl $0 #31\nshr $0 #0\nhlt\n";
+    let errors = Assembler::new().assemble(source).unwrap_err();
+    assert_eq!(errors.len(), 7, "{:?}", errors);
+    assert!(matches!(
+      &errors[0],
+      AssemblerError::InvalidOperand { opcode, position: 3, expected, line: 2, .. } if opcode == "add" && expected == "a register"
+    ));
+    assert!(matches!(&errors[1], AssemblerError::WrongOperandCount { expected: 2, found: 1, line: 3, .. }));
+    assert!(matches!(&errors[2], AssemblerError::InvalidRegister { register: 32, line: 4 }));
+    assert!(matches!(&errors[3], AssemblerError::ImmediateOutOfRange { value: 123, min: 1, max: 31, line: 5 }));
+    assert!(matches!(&errors[4], AssemblerError::UndefinedSymbol { symbol } if symbol == "nowhere"));
+    assert!(matches!(&errors[5], AssemblerError::ImmediateOutOfRange { value: 32, min: 1, max: 31, line: 9 }));
+    assert!(matches!(&errors[6], AssemblerError::ImmediateOutOfRange { value: 0, min: 1, max: 31, line: 11 }));
+
+    let errors = Assembler::new().assemble(".code\njeq @top\nhlt\n").unwrap_err();
+    assert!(matches!(&errors[..], [AssemblerError::UnknownOpcode { name, line: 2 }] if name == "jeq"));
+  }
+}
@@ -10,11 +10,43 @@ pub enum AssemblerError {
     UnknownDirectiveFound { directive: String },
     NonOpcodeInOpcodeField,
     InsufficientSections,
+    InitializedDataInBss { instruction: u32 },
+    InvalidSpaceSize { size: i32 },
+    UndefinedSymbol { symbol: String },
+    IncludeNotFound { path: String },
+    IncludeCycle { chain: Vec<String> },
+    UnterminatedBlock { directive: String, line: usize },
+    UnmatchedBlockEnd { directive: String, line: usize },
+    MacroError { error: String, definition_line: usize, call_line: usize },
+    InvalidExpression { error: String, line: usize },
+    ImmediateOutOfRange { value: i64, min: i64, max: i64, line: usize },
+    UnknownOpcode { name: String, line: usize },
+    WrongOperandCount { opcode: String, expected: usize, found: usize, line: usize },
+    InvalidOperand { opcode: String, position: usize, expected: String, found: String, line: usize },
+    InvalidRegister { register: u8, line: usize },
     ParseError { error: String },
     FailedToWriteBinaryFile { error: String },
     FailedToReadFile { error: String },
 }
 
+impl AssemblerError {
+  /// Source line the error was found on, for the variants that record one.
+  pub fn line(&self) -> Option<usize> {
+    match *self {
+      AssemblerError::UnterminatedBlock { line, .. }
+      | AssemblerError::UnmatchedBlockEnd { line, .. }
+      | AssemblerError::InvalidExpression { line, .. }
+      | AssemblerError::ImmediateOutOfRange { line, .. }
+      | AssemblerError::UnknownOpcode { line, .. }
+      | AssemblerError::WrongOperandCount { line, .. }
+      | AssemblerError::InvalidOperand { line, .. }
+      | AssemblerError::InvalidRegister { line, .. } => Some(line),
+      AssemblerError::MacroError { call_line, .. } => Some(call_line),
+      _ => None,
+    }
+  }
+}
+
 impl fmt::Display for AssemblerError {
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
     match *self {
@@ -31,7 +63,48 @@ impl fmt::Display for AssemblerError {
           f.write_str(&format!("Invalid or unknown directive found. Directive name was: {}", directive))
       }
       AssemblerError::NonOpcodeInOpcodeField => f.write_str("A non-opcode was found in an opcode field"),
-      AssemblerError::InsufficientSections => f.write_str("Less than two sections/segments were found in the code"),
+      AssemblerError::InsufficientSections => f.write_str("No .code section was found in the code"),
+      AssemblerError::InitializedDataInBss { instruction } => f.write_str(&format!(
+          "Initialized data found in the .bss section, use .space to reserve memory. Instruction # was: {}",
+          instruction
+      )),
+      AssemblerError::InvalidSpaceSize { size } => f.write_str(&format!("Cannot reserve {} bytes with .space", size)),
+      AssemblerError::UndefinedSymbol { ref symbol } => f.write_str(&format!("Symbol `{}` is used but never defined", symbol)),
+      AssemblerError::IncludeNotFound { ref path } => {
+          f.write_str(&format!("Included file `{}` was not found next to the including file or in any include path", path))
+      }
+      AssemblerError::IncludeCycle { ref chain } => f.write_str(&format!("Include cycle: {}", chain.join(" -> "))),
+      AssemblerError::UnterminatedBlock { ref directive, line } => {
+          f.write_str(&format!("`{}` on line {} is never closed", directive, line))
+      }
+      AssemblerError::UnmatchedBlockEnd { ref directive, line } => {
+          f.write_str(&format!("`{}` on line {} does not close an open block", directive, line))
+      }
+      AssemblerError::MacroError { ref error, definition_line, call_line } => f.write_str(&format!(
+          "{} (macro defined on line {}, expanded on line {})",
+          error, definition_line, call_line
+      )),
+      AssemblerError::InvalidExpression { ref error, line } => {
+          f.write_str(&format!("Invalid expression on line {}: {}", line, error))
+      }
+      AssemblerError::ImmediateOutOfRange { value, min, max, line } => f.write_str(&format!(
+          "Value {} on line {} does not fit in the operand, which takes {} to {}",
+          value, line, min, max
+      )),
+      AssemblerError::UnknownOpcode { ref name, line } => {
+          f.write_str(&format!("Unknown instruction `{}` on line {}", name, line))
+      }
+      AssemblerError::WrongOperandCount { ref opcode, expected, found, line } => f.write_str(&format!(
+          "`{}` on line {} takes {} operand(s) but {} were given",
+          opcode, line, expected, found
+      )),
+      AssemblerError::InvalidOperand { ref opcode, position, ref expected, ref found, line } => f.write_str(&format!(
+          "Operand {} of `{}` on line {} must be {}, found {}",
+          position, opcode, line, expected, found
+      )),
+      AssemblerError::InvalidRegister { register, line } => {
+          f.write_str(&format!("Register ${} on line {} does not exist, registers go from $0 to $31", register, line))
+      }
       AssemblerError::ParseError { ref error } => f.write_str(&format!("There was an error parsing the code: {}", error)),
       AssemblerError::FailedToWriteBinaryFile { ref error } => f.write_str(&format!("Failed to write binary file: {}", error)),
       AssemblerError::FailedToReadFile { ref error } => f.write_str(&format!("Failed to read file: {}", error)),
@@ -47,7 +120,21 @@ impl Error for AssemblerError {
       AssemblerError::SymbolAlreadyDeclared { .. } => "This symbol was previously declared.",
       AssemblerError::UnknownDirectiveFound { .. } => "Invalid or unknown directive found.",
       AssemblerError::NonOpcodeInOpcodeField { .. } => "A non-opcode was found in an opcode field.",
-      AssemblerError::InsufficientSections { .. } => "Less than two sections/segments were found in the code.",
+      AssemblerError::InsufficientSections { .. } => "No .code section was found in the code.",
+      AssemblerError::InitializedDataInBss { .. } => "Initialized data found in the .bss section.",
+      AssemblerError::InvalidSpaceSize { .. } => "Invalid .space size.",
+      AssemblerError::UndefinedSymbol { .. } => "A symbol is used but never defined.",
+      AssemblerError::IncludeNotFound { .. } => "Included file was not found.",
+      AssemblerError::IncludeCycle { .. } => "A file includes itself.",
+      AssemblerError::UnterminatedBlock { .. } => "A .macro or .rept block is never closed.",
+      AssemblerError::UnmatchedBlockEnd { .. } => "A block end does not close an open block.",
+      AssemblerError::MacroError { .. } => "A macro could not be expanded.",
+      AssemblerError::InvalidExpression { .. } => "A constant expression could not be evaluated.",
+      AssemblerError::ImmediateOutOfRange { .. } => "A value does not fit in its operand.",
+      AssemblerError::UnknownOpcode { .. } => "An instruction name is not an opcode or a macro.",
+      AssemblerError::WrongOperandCount { .. } => "An instruction has the wrong number of operands.",
+      AssemblerError::InvalidOperand { .. } => "An operand is not of the kind the instruction takes.",
+      AssemblerError::InvalidRegister { .. } => "A register number is out of range.",
       AssemblerError::ParseError { .. } => "There was an error parsing the code.",
       AssemblerError::FailedToWriteBinaryFile { .. } => "Failed to write binary file.",
       AssemblerError::FailedToReadFile { .. } => "Failed to write binary file.",
@@ -110,10 +197,30 @@ mod tests {
     let error = AssemblerError::InsufficientSections;
     assert_eq!(
       format!("{}", error),
-      "Less than two sections/segments were found in the code"
+      "No .code section was found in the code"
     );
   }
 
+  #[test]
+  fn test_display_include_cycle() {
+    let error = AssemblerError::IncludeCycle {
+      chain: vec!["a.lumi".to_string(), "b.lumi".to_string(), "a.lumi".to_string()],
+    };
+    assert_eq!(format!("{}", error), "Include cycle: a.lumi -> b.lumi -> a.lumi");
+  }
+
+  #[test]
+  fn test_display_invalid_operand() {
+    let error = AssemblerError::InvalidOperand {
+      opcode: "add".to_string(),
+      position: 3,
+      expected: "a register".to_string(),
+      found: "integer #1".to_string(),
+      line: 4,
+    };
+    assert_eq!(format!("{}", error), "Operand 3 of `add` on line 4 must be a register, found integer #1");
+  }
+
   #[test]
   fn test_display_parse_error() {
     let error = AssemblerError::ParseError {
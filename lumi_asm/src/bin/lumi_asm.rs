@@ -2,17 +2,79 @@ use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
 use std::sync::mpsc::sync_channel;
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use env_logger::Env;
 use log::{error, info};
 use pest::Parser;
 use lumi_asm::Assembler;
+use lumi_asm::cfg::ControlFlowGraph;
+use lumi_asm::file_disassembler::{disassemble_as, DisassemblyFormat};
 
 pub fn main() -> Result<(), ()> {
     let matches = Command::new("Lumi Assembler")
       .version("2.0.0")
       .about("Assembles Lumi ASM files into bytecode")
       .author("Lumi")
+      .subcommand_negates_reqs(true)
+      .args_conflicts_with_subcommands(true)
+      .subcommand(
+          Command::new("disasm")
+            .about("Disassembles a Lumi binary")
+            .arg(
+                Arg::new("binary")
+                  .value_name("FILE")
+                  .help("Lumi binary to disassemble")
+                  .required(true),
+            )
+            .arg(
+                Arg::new("format")
+                  .short('f')
+                  .long("format")
+                  .value_name("FORMAT")
+                  .value_parser(["text", "lumi", "json"])
+                  .default_value("text")
+                  .help("Annotated listing, .lumi source that assembles back into the same binary, or JSON records"),
+            )
+            .arg(
+                Arg::new("output")
+                  .short('o')
+                  .long("output")
+                  .value_name("FILE")
+                  .help("File to write the disassembly to instead of stdout"),
+            ),
+      )
+      .subcommand(
+          Command::new("cfg")
+            .about("Exports the control-flow graph or call graph of a Lumi binary")
+            .arg(
+                Arg::new("binary")
+                  .value_name("FILE")
+                  .help("Lumi binary to analyze")
+                  .required(true),
+            )
+            .arg(
+                Arg::new("format")
+                  .short('f')
+                  .long("format")
+                  .value_name("FORMAT")
+                  .value_parser(["dot", "json"])
+                  .default_value("dot")
+                  .help("Graphviz DOT or JSON"),
+            )
+            .arg(
+                Arg::new("call-graph")
+                  .long("call-graph")
+                  .action(ArgAction::SetTrue)
+                  .help("Export the call graph instead of the basic blocks"),
+            )
+            .arg(
+                Arg::new("output")
+                  .short('o')
+                  .long("output")
+                  .value_name("FILE")
+                  .help("File to write the graph to instead of stdout"),
+            ),
+      )
       .arg(
           Arg::new("input")
             .short('i') // Use a char here instead of &str
@@ -39,14 +101,60 @@ pub fn main() -> Result<(), ()> {
         Arg::new("debug")
           .short('d') // Use a char here instead of &str
           .long("debug")
-          .help("Enable debug output"),
+          .action(ArgAction::SetTrue)
+          .help("Emit a debug info section mapping bytecode back to source lines"),
+      )
+      .arg(
+        Arg::new("include")
+          .short('I')
+          .long("include")
+          .value_name("DIR")
+          .action(ArgAction::Append)
+          .help("Directory to search for .include files, may be given several times"),
+      )
+      .arg(
+        Arg::new("object")
+          .short('c')
+          .long("object")
+          .action(ArgAction::SetTrue)
+          .help("Write a relocatable object file for lumi_ld instead of an executable"),
+      )
+      .arg(
+        Arg::new("message-format")
+          .long("message-format")
+          .value_name("FORMAT")
+          .value_parser(["human", "json"])
+          .default_value("human")
+          .help("Print errors and warnings as annotated source snippets or as one JSON object per line"),
+      )
+      .arg(
+        Arg::new("listing")
+          .short('l')
+          .long("listing")
+          .value_name("FILE")
+          .help("Write a listing of every source line with its address and encoded bytes, the symbol table and the RO data layout"),
       )
       .get_matches();
 
+    match matches.subcommand() {
+        Some(("disasm", disasm_matches)) => {
+            env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+            return disasm(disasm_matches);
+        }
+        Some(("cfg", cfg_matches)) => {
+            env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+            return cfg(cfg_matches);
+        }
+        _ => {}
+    }
+
     let input_path = matches.get_one::<String>("input").unwrap(); // Always present because it's required
     let output_path = matches.get_one::<String>("output").unwrap(); // Always present because it's required
     let verbose = matches.contains_id("verbose");
-    let debug = matches.contains_id("debug");
+    let debug = matches.get_flag("debug");
+    let object = matches.get_flag("object");
+    let json = matches.get_one::<String>("message-format").map(String::as_str) == Some("json");
+    let listing_path = matches.get_one::<String>("listing");
 
     if verbose {
         env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
@@ -67,13 +175,32 @@ pub fn main() -> Result<(), ()> {
     //     context.load_grammar()
 
     // Assemble the input code
-    let mut assembler = Assembler::new();
-    let bytecode = match assembler.assemble(&input_code) {
+    let mut assembler = Assembler::new().with_source_path(input_path);
+    if debug {
+        assembler = assembler.with_debug_info(input_path);
+    }
+    for include_path in matches.get_many::<String>("include").unwrap_or_default() {
+        assembler = assembler.with_include_path(include_path);
+    }
+    if listing_path.is_some() {
+        assembler = assembler.with_listing();
+    }
+    let result = if object {
+        assembler.assemble_object(&input_code, input_path).map(|object| object.write())
+    } else {
+        assembler.assemble(&input_code)
+    };
+    for diagnostic in assembler.diagnostics() {
+        if json {
+            println!("{}", diagnostic.to_json());
+        } else {
+            eprintln!("{}\n", diagnostic);
+        }
+    }
+    let bytecode = match result {
         Ok(bytecode) => bytecode,
         Err(errors) => {
-            for err in errors {
-                error!("Assembly error: {:?}", err);
-            }
+            error!("Could not assemble {} due to {} error(s)", input_path, errors.len());
             return Err(());
         }
     };
@@ -88,6 +215,89 @@ pub fn main() -> Result<(), ()> {
     
     info!("Wrote assembled bytecode to {}", output_path);
 
+    if let (Some(listing_path), Some(listing)) = (listing_path, assembler.listing()) {
+        if let Err(err) = write_file(listing_path, listing.render().as_bytes()) {
+            error!("Could not write listing file {}: {}", listing_path, err);
+            return Err(());
+        }
+        info!("Wrote listing to {}", listing_path);
+    }
+
+    Ok(())
+}
+
+// Disassemble a binary in the requested format to stdout or a file
+fn disasm(matches: &ArgMatches) -> Result<(), ()> {
+    let binary_path = matches.get_one::<String>("binary").unwrap(); // Always present because it's required
+    let format = match matches.get_one::<String>("format").map(String::as_str) {
+        Some("lumi") => DisassemblyFormat::Source,
+        Some("json") => DisassemblyFormat::Json,
+        _ => DisassemblyFormat::Text,
+    };
+
+    let bytecode = match std::fs::read(binary_path) {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            error!("Could not read binary {}: {}", binary_path, err);
+            return Err(());
+        }
+    };
+    let disassembly = match disassemble_as(&bytecode, format) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            error!("Could not disassemble {}: {}", binary_path, err);
+            return Err(());
+        }
+    };
+
+    write_output(matches.get_one::<String>("output"), &disassembly)
+}
+
+// Export the CFG or call graph of a binary to stdout or a file
+fn cfg(matches: &ArgMatches) -> Result<(), ()> {
+    let binary_path = matches.get_one::<String>("binary").unwrap(); // Always present because it's required
+    let json = matches.get_one::<String>("format").map(String::as_str) == Some("json");
+    let call_graph = matches.get_flag("call-graph");
+
+    let bytecode = match std::fs::read(binary_path) {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            error!("Could not read binary {}: {}", binary_path, err);
+            return Err(());
+        }
+    };
+    let graph = match ControlFlowGraph::from_binary(&bytecode) {
+        Ok(graph) => graph,
+        Err(err) => {
+            error!("Could not analyze {}: {}", binary_path, err);
+            return Err(());
+        }
+    };
+    for block in graph.unreachable_blocks() {
+        info!("Unreachable block at 0x{:x}{}", block.start, block.label.as_ref().map_or(String::new(), |label| format!(" ({})", label)));
+    }
+
+    let output = match (call_graph, json) {
+        (false, false) => graph.to_dot(),
+        (false, true) => graph.to_json(),
+        (true, false) => graph.call_graph_dot(),
+        (true, true) => graph.call_graph_json(),
+    };
+    write_output(matches.get_one::<String>("output"), &output)
+}
+
+// Write the output of a subcommand to the given file, or to stdout
+fn write_output(path: Option<&String>, output: &str) -> Result<(), ()> {
+    match path {
+        Some(path) => {
+            if let Err(err) = write_file(path, output.as_bytes()) {
+                error!("Could not write output file {}: {}", path, err);
+                return Err(());
+            }
+            info!("Wrote {}", path);
+        }
+        None => print!("{}", output),
+    }
     Ok(())
 }
 
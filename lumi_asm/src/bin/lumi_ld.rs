@@ -0,0 +1,86 @@
+use std::fs;
+use clap::{Arg, ArgAction, Command};
+use env_logger::Env;
+use log::{error, info};
+use lumi_asm::linker::Linker;
+use lumi_asm::object::ObjectFile;
+
+pub fn main() -> Result<(), ()> {
+    let matches = Command::new("Lumi Linker")
+      .version("2.0.0")
+      .about("Links Lumi object files into an executable")
+      .author("Lumi")
+      .arg(
+          Arg::new("objects")
+            .value_name("FILE")
+            .help("Object files produced by lumi_asm --object")
+            .num_args(1..)
+            .required(true),
+      )
+      .arg(
+          Arg::new("output")
+            .short('o')
+            .long("output")
+            .value_name("FILE")
+            .help("Output file to write the executable to")
+            .required(true),
+      )
+      .arg(
+          Arg::new("entry")
+            .short('e')
+            .long("entry")
+            .value_name("SYMBOL")
+            .help("Global code label to start execution at, defaults to the start of the first object"),
+      )
+      .arg(
+          Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::SetTrue)
+            .help("Enable verbose output"),
+      )
+      .get_matches();
+
+    let output_path = matches.get_one::<String>("output").unwrap();
+    let level = if matches.get_flag("verbose") { "debug" } else { "info" };
+    env_logger::Builder::from_env(Env::default().default_filter_or(level)).init();
+
+    let mut linker = Linker::new();
+    for path in matches.get_many::<String>("objects").unwrap() {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                error!("Could not read object file {}: {}", path, err);
+                return Err(());
+            }
+        };
+        match ObjectFile::parse(&bytes) {
+            Ok(object) => linker = linker.object(object),
+            Err(err) => {
+                error!("{}: {}", path, err);
+                return Err(());
+            }
+        }
+    }
+    if let Some(entry) = matches.get_one::<String>("entry") {
+        linker = linker.entry(entry);
+    }
+
+    let executable = match linker.link() {
+        Ok(executable) => executable,
+        Err(errors) => {
+            for err in errors {
+                error!("Link error: {}", err);
+            }
+            return Err(());
+        }
+    };
+
+    if let Err(err) = fs::write(output_path, &executable) {
+        error!("Could not write output file {}: {}", output_path, err);
+        return Err(());
+    }
+    info!("Linked {} bytes into {}", executable.len(), output_path);
+
+    Ok(())
+}
@@ -0,0 +1,413 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use serde::Serialize;
+use crate::file_disassembler::{CodeItem, Disassembly, DisassemblyError};
+use crate::instruction::Opcode;
+
+/// How control gets from one block to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+  /// Execution runs past the end of the block.
+  Fallthrough,
+  /// `DJMP` to a known address.
+  Jump,
+  /// `DJMPE` or `LOOP`, taken depending on the flags or the loop counter.
+  Branch,
+  /// `JMP`, `JMPF`, `JMPB` or `JMPE`, whose destination is only known at runtime.
+  Indirect,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BasicBlock {
+  /// Offset of the first instruction in the binary, which also identifies the block.
+  pub start: usize,
+  /// Offset right after the last instruction.
+  pub end: usize,
+  pub label: Option<String>,
+  pub instructions: Vec<String>,
+  /// Whether the block can be reached from the entry point through direct edges and calls.
+  /// Blocks only reachable through indirect jumps are reported as unreachable too.
+  pub reachable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Edge {
+  pub from: usize,
+  /// Start of the destination block, `None` when it is not known statically.
+  pub to: Option<usize>,
+  pub kind: EdgeKind,
+}
+
+/// The entry point or a `CALL` target, with the blocks reachable from it without following calls.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Function {
+  pub name: String,
+  pub entry: usize,
+  pub blocks: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Call {
+  /// Entry of the calling function.
+  pub caller: usize,
+  /// Entry of the called function, `None` when the target is not an instruction.
+  pub callee: Option<usize>,
+  /// Offset of the `CALL` instruction.
+  pub site: usize,
+}
+
+/// Basic blocks and call graph of a binary's code section.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ControlFlowGraph {
+  pub entry: usize,
+  pub blocks: Vec<BasicBlock>,
+  pub edges: Vec<Edge>,
+  pub functions: Vec<Function>,
+  pub calls: Vec<Call>,
+}
+
+/// How an instruction passes control on.
+enum Flow {
+  Next,
+  Stop,
+  Jump(usize),
+  Branch(usize),
+  Indirect { conditional: bool },
+}
+
+fn flow(item: &CodeItem) -> Flow {
+  let instruction = match item {
+    CodeItem::Instruction(instruction) => instruction,
+    CodeItem::Unknown { .. } => return Flow::Stop,
+  };
+  match instruction.opcode {
+    Opcode::HLT | Opcode::RET | Opcode::IGL => Flow::Stop,
+    Opcode::DJMP => Flow::Jump(instruction.address(0)),
+    Opcode::DJMPE | Opcode::LOOP => Flow::Branch(instruction.address(0)),
+    Opcode::JMP | Opcode::JMPF | Opcode::JMPB => Flow::Indirect { conditional: false },
+    Opcode::JMPE => Flow::Indirect { conditional: true },
+    _ => Flow::Next,
+  }
+}
+
+fn call_target(item: &CodeItem) -> Option<usize> {
+  match item {
+    CodeItem::Instruction(instruction) if instruction.opcode == Opcode::CALL => Some(instruction.address(0)),
+    _ => None,
+  }
+}
+
+impl ControlFlowGraph {
+  pub fn from_binary(bytecode: &[u8]) -> Result<Self, DisassemblyError> {
+    let disassembly = Disassembly::parse(bytecode)?;
+    let items = &disassembly.items;
+    let index: BTreeMap<usize, usize> = items.iter().enumerate().map(|(i, item)| (item.offset(), i)).collect();
+    let entry = disassembly.header.entry_offset as usize;
+
+    // a block starts at the entry, at every known target and after every instruction that
+    // does not simply continue
+    let mut leaders = BTreeSet::new();
+    if let Some(first) = items.first() {
+      leaders.insert(first.offset());
+    }
+    leaders.insert(entry);
+    for (i, item) in items.iter().enumerate() {
+      let target = match flow(item) {
+        Flow::Next => call_target(item),
+        Flow::Jump(target) | Flow::Branch(target) => Some(target),
+        _ => None,
+      };
+      if let Some(target) = target.filter(|target| index.contains_key(target)) {
+        leaders.insert(target);
+      }
+      if !matches!(flow(item), Flow::Next) {
+        if let Some(next) = items.get(i + 1) {
+          leaders.insert(next.offset());
+        }
+      }
+    }
+    leaders.retain(|leader| index.contains_key(leader));
+
+    let mut blocks = vec![];
+    let mut edges = vec![];
+    let mut block_calls: BTreeMap<usize, Vec<(usize, Option<usize>)>> = BTreeMap::new();
+    let starts: Vec<usize> = leaders.iter().copied().collect();
+    for (b, &start) in starts.iter().enumerate() {
+      let first = index[&start];
+      let last = match starts.get(b + 1) {
+        Some(next) => index[next] - 1,
+        None => items.len() - 1,
+      };
+      let block_items = &items[first..=last];
+      let end = match &items[last] {
+        CodeItem::Instruction(instruction) => instruction.next_offset(),
+        CodeItem::Unknown { offset, .. } => offset + 1,
+      };
+      let known = |target: usize| Some(target).filter(|target| leaders.contains(target));
+      let fallthrough = starts.get(b + 1).copied().filter(|next| *next == end);
+
+      let mut successors = vec![];
+      match flow(&items[last]) {
+        Flow::Next => successors.extend(fallthrough.map(|next| (Some(next), EdgeKind::Fallthrough))),
+        Flow::Stop => {}
+        Flow::Jump(target) => successors.push((known(target), EdgeKind::Jump)),
+        Flow::Branch(target) => {
+          successors.push((known(target), EdgeKind::Branch));
+          successors.extend(fallthrough.map(|next| (Some(next), EdgeKind::Fallthrough)));
+        }
+        Flow::Indirect { conditional } => {
+          successors.push((None, EdgeKind::Indirect));
+          if conditional {
+            successors.extend(fallthrough.map(|next| (Some(next), EdgeKind::Fallthrough)));
+          }
+        }
+      }
+      edges.extend(successors.into_iter().map(|(to, kind)| Edge { from: start, to, kind }));
+
+      for item in block_items {
+        if let Some(target) = call_target(item) {
+          block_calls.entry(start).or_default().push((item.offset(), known(target)));
+        }
+      }
+
+      blocks.push(BasicBlock {
+        start,
+        end,
+        label: disassembly.code_labels.get(&start).cloned(),
+        instructions: block_items.iter().map(|item| describe(&disassembly, item)).collect(),
+        reachable: false,
+      });
+    }
+
+    let successors = |block: usize| -> Vec<usize> {
+      edges.iter().filter(|edge| edge.from == block).filter_map(|edge| edge.to).collect()
+    };
+
+    // functions are the entry and every call target, each made of the blocks it reaches
+    // without following calls
+    let mut function_entries: Vec<usize> = vec![entry];
+    for calls in block_calls.values() {
+      for callee in calls.iter().filter_map(|(_, callee)| *callee) {
+        if !function_entries.contains(&callee) {
+          function_entries.push(callee);
+        }
+      }
+    }
+    let mut functions = vec![];
+    let mut calls = vec![];
+    for &function_entry in function_entries.iter().filter(|entry| leaders.contains(entry)) {
+      let function_blocks = walk(function_entry, &successors);
+      for block in &function_blocks {
+        for (site, callee) in block_calls.get(block).into_iter().flatten() {
+          calls.push(Call { caller: function_entry, callee: *callee, site: *site });
+        }
+      }
+      let name = match disassembly.code_labels.get(&function_entry) {
+        Some(label) => label.clone(),
+        None if function_entry == entry => "entry".to_string(),
+        None => format!("sub_{:04x}", function_entry),
+      };
+      functions.push(Function { name, entry: function_entry, blocks: function_blocks.into_iter().collect() });
+    }
+
+    // everything reachable from the entry, following calls into their functions
+    let reachable = walk(entry, &|block| {
+      let mut next = successors(block);
+      next.extend(block_calls.get(&block).into_iter().flatten().filter_map(|(_, callee)| *callee));
+      next
+    });
+    for block in &mut blocks {
+      block.reachable = reachable.contains(&block.start);
+    }
+
+    Ok(ControlFlowGraph { entry, blocks, edges, functions, calls })
+  }
+
+  /// Blocks that cannot be reached from the entry point.
+  pub fn unreachable_blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+    self.blocks.iter().filter(|block| !block.reachable)
+  }
+
+  /// The CFG as a Graphviz digraph, unreachable blocks drawn dashed.
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::from("digraph cfg {\n  node [shape=box, fontname=\"monospace\"];\n");
+    for block in &self.blocks {
+      let mut label: String = block.label.iter().map(|label| format!("{}:\\l", escape(label))).collect();
+      for instruction in &block.instructions {
+        label.push_str(&format!("{}\\l", escape(instruction)));
+      }
+      let style = if block.reachable { "" } else { ", style=dashed, color=gray" };
+      dot.push_str(&format!("  b_{:x} [label=\"{}\"{}];\n", block.start, label, style));
+    }
+    if self.edges.iter().any(|edge| edge.to.is_none()) {
+      dot.push_str("  unknown [shape=diamond, label=\"?\"];\n");
+    }
+    for edge in &self.edges {
+      let (to, style) = match edge.to {
+        Some(to) => (format!("b_{:x}", to), ""),
+        None => ("unknown".to_string(), ", style=dashed"),
+      };
+      dot.push_str(&format!("  b_{:x} -> {} [label=\"{}\"{}];\n", edge.from, to, edge_name(edge.kind), style));
+    }
+    dot.push_str("}\n");
+    dot
+  }
+
+  /// The call graph as a Graphviz digraph, one node per function.
+  pub fn call_graph_dot(&self) -> String {
+    let mut dot = String::from("digraph calls {\n  node [shape=box, fontname=\"monospace\"];\n");
+    for function in &self.functions {
+      dot.push_str(&format!("  f_{:x} [label=\"{}\"];\n", function.entry, escape(&function.name)));
+    }
+    if self.calls.iter().any(|call| call.callee.is_none()) {
+      dot.push_str("  unknown [shape=diamond, label=\"?\"];\n");
+    }
+    let mut drawn = BTreeSet::new();
+    for call in &self.calls {
+      if drawn.insert((call.caller, call.callee)) {
+        let callee = call.callee.map_or("unknown".to_string(), |callee| format!("f_{:x}", callee));
+        dot.push_str(&format!("  f_{:x} -> {};\n", call.caller, callee));
+      }
+    }
+    dot.push_str("}\n");
+    dot
+  }
+
+  /// Blocks, edges, functions and calls as JSON.
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).unwrap_or_default()
+  }
+
+  /// Only the functions and calls as JSON.
+  pub fn call_graph_json(&self) -> String {
+    #[derive(Serialize)]
+    struct CallGraph<'a> {
+      functions: &'a [Function],
+      calls: &'a [Call],
+    }
+    serde_json::to_string_pretty(&CallGraph { functions: &self.functions, calls: &self.calls }).unwrap_or_default()
+  }
+}
+
+/// Blocks reachable from `start`.
+fn walk(start: usize, successors: &dyn Fn(usize) -> Vec<usize>) -> BTreeSet<usize> {
+  let mut seen = BTreeSet::from([start]);
+  let mut queue = VecDeque::from([start]);
+  while let Some(block) = queue.pop_front() {
+    for next in successors(block) {
+      if seen.insert(next) {
+        queue.push_back(next);
+      }
+    }
+  }
+  seen
+}
+
+fn describe(disassembly: &Disassembly, item: &CodeItem) -> String {
+  match item {
+    CodeItem::Instruction(instruction) => match disassembly.target(instruction) {
+      Some(label) => format!("0x{:x}: {} ; {}", instruction.offset, instruction, label),
+      None => format!("0x{:x}: {}", instruction.offset, instruction),
+    },
+    CodeItem::Unknown { offset, byte } => format!("0x{:x}: .byte 0x{:02x} ; unknown opcode", offset, byte),
+  }
+}
+
+fn edge_name(kind: EdgeKind) -> &'static str {
+  match kind {
+    EdgeKind::Fallthrough => "fallthrough",
+    EdgeKind::Jump => "jump",
+    EdgeKind::Branch => "branch",
+    EdgeKind::Indirect => "indirect",
+  }
+}
+
+fn escape(text: &str) -> String {
+  text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assembler::Assembler;
+
+  const PROGRAM: &str = ".code
+main: load $0 #3
+loop: dec $0
+  eq $0 $1
+  djmpe @done
+  call @work
+  djmp @loop
+work: jmp $2
+done: hlt
+orphan: inc $0
+  ret
+";
+
+  fn graph() -> ControlFlowGraph {
+    let program = Assembler::new().with_debug_info("main.lumi").assemble(PROGRAM).unwrap();
+    ControlFlowGraph::from_binary(&program).unwrap()
+  }
+
+  fn start_of(graph: &ControlFlowGraph, label: &str) -> usize {
+    graph.blocks.iter().find(|block| block.label.as_deref() == Some(label)).unwrap().start
+  }
+
+  #[test]
+  fn test_blocks_and_edges() {
+    let graph = graph();
+    let labels: Vec<_> = graph.blocks.iter().map(|block| block.label.clone().unwrap_or_default()).collect();
+    // the call ends nothing, the block after `djmpe` starts at the call
+    assert_eq!(labels, vec!["main", "loop", "", "work", "done", "orphan"]);
+    let (main, looped, work, done) =
+      (start_of(&graph, "main"), start_of(&graph, "loop"), start_of(&graph, "work"), start_of(&graph, "done"));
+    let call_block = graph.blocks[2].start;
+
+    let edges: Vec<_> = graph.edges.iter().map(|edge| (edge.from, edge.to, edge.kind)).collect();
+    assert_eq!(
+      edges,
+      vec![
+        (main, Some(looped), EdgeKind::Fallthrough),
+        (looped, Some(done), EdgeKind::Branch),
+        (looped, Some(call_block), EdgeKind::Fallthrough),
+        (call_block, Some(looped), EdgeKind::Jump),
+        (work, None, EdgeKind::Indirect),
+      ]
+    );
+    assert!(graph.blocks[2].instructions[0].ends_with("; work"));
+  }
+
+  #[test]
+  fn test_dead_code_and_call_graph() {
+    let graph = graph();
+    let unreachable: Vec<_> = graph.unreachable_blocks().map(|block| block.label.as_deref()).collect();
+    assert_eq!(unreachable, vec![Some("orphan")]);
+
+    let names: Vec<_> = graph.functions.iter().map(|function| function.name.as_str()).collect();
+    assert_eq!(names, vec!["main", "work"]);
+    assert_eq!(graph.functions[0].blocks.len(), 4);
+    assert_eq!(graph.calls.len(), 1);
+    assert_eq!(graph.calls[0].caller, start_of(&graph, "main"));
+    assert_eq!(graph.calls[0].callee, Some(start_of(&graph, "work")));
+  }
+
+  #[test]
+  fn test_exports() {
+    let graph = graph();
+    let work = start_of(&graph, "work");
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph cfg {\n"));
+    assert!(dot.contains(&format!("  b_{:x} -> unknown [label=\"indirect\", style=dashed];\n", work)));
+    assert!(dot.contains("style=dashed, color=gray"));
+    let calls = graph.call_graph_dot();
+    assert!(calls.contains(&format!("  f_{:x} [label=\"work\"];\n", work)));
+    assert!(calls.contains(&format!("  f_{:x} -> f_{:x};\n", graph.entry, work)));
+
+    let json: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+    assert_eq!(json["blocks"].as_array().unwrap().len(), 6);
+    assert_eq!(json["edges"][4]["kind"], "indirect");
+    assert_eq!(json["edges"][4]["to"], serde_json::Value::Null);
+    let call_graph: serde_json::Value = serde_json::from_str(&graph.call_graph_json()).unwrap();
+    assert_eq!(call_graph["functions"][1]["name"], "work");
+  }
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::io::{Cursor, Read};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::header_utils::HeaderError;
+
+/// A row of the line table: the instruction at `offset` was assembled from `line:column`
+/// of `files[file]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+  pub offset: u32,
+  pub file: u32,
+  pub line: u32,
+  pub column: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugSymbolKind {
+  /// Offset of an instruction in the binary.
+  Label,
+  /// Offset into the read-only data.
+  Constant,
+  /// Heap address of `.data` or `.bss` memory.
+  Variable,
+}
+
+impl From<DebugSymbolKind> for u8 {
+  fn from(kind: DebugSymbolKind) -> Self {
+    match kind {
+      DebugSymbolKind::Label => 1,
+      DebugSymbolKind::Constant => 2,
+      DebugSymbolKind::Variable => 3,
+    }
+  }
+}
+
+impl TryFrom<u8> for DebugSymbolKind {
+  type Error = HeaderError;
+
+  fn try_from(kind: u8) -> Result<Self, Self::Error> {
+    match kind {
+      1 => Ok(DebugSymbolKind::Label),
+      2 => Ok(DebugSymbolKind::Constant),
+      3 => Ok(DebugSymbolKind::Variable),
+      _ => Err(HeaderError::InvalidDebugInfo),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugSymbol {
+  pub name: String,
+  pub kind: DebugSymbolKind,
+  pub value: u32,
+}
+
+/// Where an instruction came from in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation<'a> {
+  pub file: &'a str,
+  pub line: u32,
+  pub column: u32,
+}
+
+impl fmt::Display for SourceLocation<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}:{}", self.file, self.line, self.column)
+  }
+}
+
+/// Contents of the `.debug` section: source files, a line table sorted by offset and the
+/// assembler's symbol table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+  pub files: Vec<String>,
+  pub lines: Vec<LineEntry>,
+  pub symbols: Vec<DebugSymbol>,
+}
+
+impl DebugInfo {
+  pub fn new(file: impl Into<String>) -> Self {
+    DebugInfo { files: vec![file.into()], lines: vec![], symbols: vec![] }
+  }
+
+  /// Source location of the instruction covering `pc`.
+  pub fn location(&self, pc: usize) -> Option<SourceLocation<'_>> {
+    let index = self.lines.partition_point(|entry| entry.offset as usize <= pc).checked_sub(1)?;
+    let entry = &self.lines[index];
+    Some(SourceLocation {
+      file: self.files.get(entry.file as usize).map_or("?", String::as_str),
+      line: entry.line,
+      column: entry.column,
+    })
+  }
+
+  /// Name of the code label declared exactly at `offset`.
+  pub fn label_at(&self, offset: usize) -> Option<&str> {
+    self
+      .symbols
+      .iter()
+      .find(|symbol| symbol.kind == DebugSymbolKind::Label && symbol.value as usize == offset)
+      .map(|symbol| symbol.name.as_str())
+  }
+
+  /// The closest code label at or before `pc`, usually the routine `pc` is in.
+  pub fn enclosing_label(&self, pc: usize) -> Option<&DebugSymbol> {
+    self
+      .symbols
+      .iter()
+      .filter(|symbol| symbol.kind == DebugSymbolKind::Label && symbol.value as usize <= pc)
+      .max_by_key(|symbol| symbol.value)
+  }
+
+  /// Describe `pc` as `label+0x4 (file:line:column)`, with whatever parts are known.
+  pub fn describe(&self, pc: usize) -> String {
+    let mut description = match self.enclosing_label(pc) {
+      Some(label) if label.value as usize == pc => label.name.clone(),
+      Some(label) => format!("{}+0x{:x}", label.name, pc - label.value as usize),
+      None => format!("0x{:x}", pc),
+    };
+    if let Some(location) = self.location(pc) {
+      description.push_str(&format!(" ({})", location));
+    }
+    description
+  }
+
+  pub fn write(&self) -> Vec<u8> {
+    let mut wtr = vec![];
+    wtr.write_u32::<LittleEndian>(self.files.len() as u32).unwrap();
+    for file in &self.files {
+      write_string(&mut wtr, file);
+    }
+    wtr.write_u32::<LittleEndian>(self.lines.len() as u32).unwrap();
+    for entry in &self.lines {
+      for value in [entry.offset, entry.file, entry.line, entry.column] {
+        wtr.write_u32::<LittleEndian>(value).unwrap();
+      }
+    }
+    wtr.write_u32::<LittleEndian>(self.symbols.len() as u32).unwrap();
+    for symbol in &self.symbols {
+      wtr.push(symbol.kind.into());
+      wtr.write_u32::<LittleEndian>(symbol.value).unwrap();
+      write_string(&mut wtr, &symbol.name);
+    }
+    wtr
+  }
+
+  pub fn parse(bytes: &[u8]) -> Result<Self, HeaderError> {
+    let mut rdr = Cursor::new(bytes);
+    let mut debug_info = DebugInfo::default();
+
+    for _ in 0..read_u32(&mut rdr)? {
+      debug_info.files.push(read_string(&mut rdr)?);
+    }
+    for _ in 0..read_u32(&mut rdr)? {
+      debug_info.lines.push(LineEntry {
+        offset: read_u32(&mut rdr)?,
+        file: read_u32(&mut rdr)?,
+        line: read_u32(&mut rdr)?,
+        column: read_u32(&mut rdr)?,
+      });
+    }
+    for _ in 0..read_u32(&mut rdr)? {
+      let kind = DebugSymbolKind::try_from(rdr.read_u8().map_err(|_| HeaderError::InvalidDebugInfo)?)?;
+      let value = read_u32(&mut rdr)?;
+      debug_info.symbols.push(DebugSymbol { name: read_string(&mut rdr)?, kind, value });
+    }
+
+    if !debug_info.lines.is_sorted_by_key(|entry| entry.offset) {
+      return Err(HeaderError::InvalidDebugInfo);
+    }
+    Ok(debug_info)
+  }
+}
+
+fn write_string(wtr: &mut Vec<u8>, value: &str) {
+  wtr.write_u32::<LittleEndian>(value.len() as u32).unwrap();
+  wtr.extend_from_slice(value.as_bytes());
+}
+
+fn read_u32(rdr: &mut Cursor<&[u8]>) -> Result<u32, HeaderError> {
+  rdr.read_u32::<LittleEndian>().map_err(|_| HeaderError::InvalidDebugInfo)
+}
+
+fn read_string(rdr: &mut Cursor<&[u8]>) -> Result<String, HeaderError> {
+  let length = read_u32(rdr)? as usize;
+  let remaining = rdr.get_ref().len() - rdr.position() as usize;
+  if length > remaining {
+    return Err(HeaderError::InvalidDebugInfo);
+  }
+  let mut bytes = vec![0; length];
+  rdr.read_exact(&mut bytes).map_err(|_| HeaderError::InvalidDebugInfo)?;
+  String::from_utf8(bytes).map_err(|_| HeaderError::InvalidDebugInfo)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn debug_info() -> DebugInfo {
+    let mut debug_info = DebugInfo::new("main.lumi");
+    debug_info.lines = vec![
+      LineEntry { offset: 100, file: 0, line: 3, column: 1 },
+      LineEntry { offset: 104, file: 0, line: 4, column: 5 },
+      LineEntry { offset: 109, file: 0, line: 6, column: 1 },
+    ];
+    debug_info.symbols = vec![
+      DebugSymbol { name: "main".to_string(), kind: DebugSymbolKind::Label, value: 100 },
+      DebugSymbol { name: "double".to_string(), kind: DebugSymbolKind::Label, value: 109 },
+      DebugSymbol { name: "msg".to_string(), kind: DebugSymbolKind::Constant, value: 104 },
+    ];
+    debug_info
+  }
+
+  #[test]
+  fn test_debug_info_write_parse_round_trip() {
+    let debug_info = debug_info();
+    assert_eq!(DebugInfo::parse(&debug_info.write()), Ok(debug_info));
+    assert_eq!(DebugInfo::parse(&DebugInfo::default().write()), Ok(DebugInfo::default()));
+  }
+
+  #[test]
+  fn test_debug_info_parse_rejects_truncated_input() {
+    let bytes = debug_info().write();
+    for length in [0, 3, 10, bytes.len() - 1] {
+      assert_eq!(DebugInfo::parse(&bytes[..length]), Err(HeaderError::InvalidDebugInfo));
+    }
+  }
+
+  #[test]
+  fn test_debug_info_locates_and_describes_pc() {
+    let debug_info = debug_info();
+    assert_eq!(debug_info.location(99), None);
+    assert_eq!(debug_info.location(104).unwrap().to_string(), "main.lumi:4:5");
+    assert_eq!(debug_info.location(106).unwrap().line, 4);
+    assert_eq!(debug_info.label_at(109), Some("double"));
+    assert_eq!(debug_info.label_at(104), None);
+    assert_eq!(debug_info.describe(104), "main+0x4 (main.lumi:4:5)");
+    assert_eq!(debug_info.describe(109), "double (main.lumi:6:1)");
+    assert_eq!(debug_info.describe(50), "0x32");
+  }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::fmt::Formatter;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Severity::Error => f.write_str("error"),
+      Severity::Warning => f.write_str("warning"),
+    }
+  }
+}
+
+/// A range of one source line, with 1-based line and column.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+  pub file: String,
+  pub line: usize,
+  pub column: usize,
+  /// Number of characters covered, at least 1.
+  pub length: usize,
+}
+
+/// An error or warning found while assembling, rendered like rustc's diagnostics or as JSON
+/// for editors.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message: String,
+  pub span: Option<Span>,
+  /// Text of the line `span` points into, used to draw the snippet.
+  #[serde(skip)]
+  pub source_line: Option<String>,
+}
+
+impl Diagnostic {
+  pub fn error(message: impl Into<String>) -> Self {
+    Diagnostic { severity: Severity::Error, message: message.into(), span: None, source_line: None }
+  }
+
+  pub fn warning(message: impl Into<String>) -> Self {
+    Diagnostic { severity: Severity::Warning, message: message.into(), span: None, source_line: None }
+  }
+
+  pub fn with_span(mut self, span: Span, source_line: Option<String>) -> Self {
+    self.span = Some(span);
+    self.source_line = source_line;
+    self
+  }
+
+  pub fn is_error(&self) -> bool {
+    self.severity == Severity::Error
+  }
+
+  /// One line of JSON: severity, message and span.
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).unwrap_or_default()
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  /// ```text
+  /// error: Symbol `print` is used but never defined
+  ///  --> main.lumi:3:7
+  ///   |
+  /// 3 | call @print
+  ///   |       ^^^^^
+  /// ```
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}", self.severity, self.message)?;
+    let span = match &self.span {
+      Some(span) => span,
+      None => return Ok(()),
+    };
+
+    let number = span.line.to_string();
+    let gutter = " ".repeat(number.len());
+    write!(f, "\n{}--> {}:{}:{}", gutter, span.file, span.line, span.column)?;
+    if let Some(text) = &self.source_line {
+      // keep tabs so the carets line up with the text above them
+      let indent: String = text
+        .chars()
+        .take(span.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+      write!(f, "\n{} |", gutter)?;
+      write!(f, "\n{} | {}", number, text)?;
+      write!(f, "\n{} | {}{}", gutter, indent, "^".repeat(span.length.max(1)))?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn diagnostic() -> Diagnostic {
+    Diagnostic::error("Symbol `print` is used but never defined").with_span(
+      Span { file: "main.lumi".to_string(), line: 3, column: 7, length: 5 },
+      Some("\tcall @print".to_string()),
+    )
+  }
+
+  #[test]
+  fn test_render_snippet() {
+    assert_eq!(
+      diagnostic().to_string(),
+      "error: Symbol `print` is used but never defined\n --> main.lumi:3:7\n  |\n3 | \tcall @print\n  | \t     ^^^^^"
+    );
+    assert_eq!(Diagnostic::warning("No span").to_string(), "warning: No span");
+  }
+
+  #[test]
+  fn test_json() {
+    assert_eq!(
+      diagnostic().to_json(),
+      r#"{"severity":"error","message":"Symbol `print` is used but never defined","span":{"file":"main.lumi","line":3,"column":7,"length":5}}"#
+    );
+  }
+}
@@ -0,0 +1,316 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::ops::RangeInclusive;
+use byteorder::{ByteOrder, LittleEndian};
+use serde::Serialize;
+use crate::instruction::{Opcode, OperandType};
+
+/// A single decoded operand value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operand {
+  Register(u8),
+  FloatRegister(u8),
+  Integer(i32),
+  Float(f64),
+  Address(u32),
+  Empty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+  /// The byte at `offset` does not map to any opcode.
+  UnknownOpcode { offset: usize, byte: u8 },
+  /// The instruction at `offset` needs more bytes than are left in the buffer.
+  Truncated { offset: usize, opcode: Opcode, needed: usize, available: usize },
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::UnknownOpcode { offset, byte } => {
+        write!(f, "Unknown opcode 0x{:02x} at offset 0x{:x}", byte, offset)
+      }
+      DecodeError::Truncated { offset, opcode, needed, available } => write!(
+        f,
+        "Truncated {:?} instruction at offset 0x{:x}: needs {} bytes, only {} available",
+        opcode, offset, needed, available
+      ),
+    }
+  }
+}
+
+/// An instruction decoded according to the opcode metadata table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstruction {
+  pub opcode: Opcode,
+  pub operands: [Operand; 3],
+  /// Offset of the opcode byte in the decoded buffer.
+  pub offset: usize,
+  /// Encoded length of the instruction, including the opcode byte.
+  pub length: usize,
+}
+
+impl DecodedInstruction {
+  /// Register number of the operand at `index`, or `0` if it is not a register.
+  pub fn register(&self, index: usize) -> usize {
+    match self.operands[index] {
+      Operand::Register(reg) | Operand::FloatRegister(reg) => reg as usize,
+      _ => 0,
+    }
+  }
+
+  /// Integer value of the operand at `index`, or `0` if it is not an integer.
+  pub fn integer(&self, index: usize) -> i32 {
+    match self.operands[index] {
+      Operand::Integer(value) => value,
+      _ => 0,
+    }
+  }
+
+  /// Float value of the operand at `index`, or `0.0` if it is not a float.
+  pub fn float(&self, index: usize) -> f64 {
+    match self.operands[index] {
+      Operand::Float(value) => value,
+      _ => 0.0,
+    }
+  }
+
+  /// Address of the operand at `index`, or `0` if it is not an address.
+  pub fn address(&self, index: usize) -> usize {
+    match self.operands[index] {
+      Operand::Address(address) => address as usize,
+      _ => 0,
+    }
+  }
+
+  /// Offset of the byte immediately after this instruction.
+  pub fn next_offset(&self) -> usize {
+    self.offset + self.length
+  }
+}
+
+impl fmt::Display for Operand {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Operand::Register(reg) | Operand::FloatRegister(reg) => write!(f, "${}", reg),
+      Operand::Integer(value) => write!(f, "#{}", value),
+      Operand::Float(value) => write!(f, "#{:?}", value),
+      Operand::Address(address) => write!(f, "@0x{:x}", address),
+      Operand::Empty => Ok(()),
+    }
+  }
+}
+
+impl fmt::Display for DecodedInstruction {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let symbol = Opcode::metadata(self.opcode).map(|m| m.str_symbol).unwrap_or("IGL");
+    write!(f, "{}", symbol)?;
+    for operand in self.operands.iter().filter(|o| **o != Operand::Empty) {
+      write!(f, " {}", operand)?;
+    }
+    Ok(())
+  }
+}
+
+/// Encoded length in bytes of an instruction with the given opcode.
+pub fn instruction_length(opcode: Opcode) -> usize {
+  match Opcode::metadata(opcode) {
+    Some(metadata) => 1 + metadata.operand_widths.iter().sum::<usize>(),
+    None => 1,
+  }
+}
+
+/// Range of values an integer immediate of the given width can hold.
+/// Single bytes are unsigned, wider immediates are signed.
+pub fn immediate_range(width: usize) -> RangeInclusive<i64> {
+  match width {
+    1 => 0..=u8::MAX as i64,
+    2 => i16::MIN as i64..=i16::MAX as i64,
+    _ => i32::MIN as i64..=i32::MAX as i64,
+  }
+}
+
+/// Encode an instruction into bytes using the widths from the opcode metadata table.
+/// Operands beyond those the opcode takes are ignored.
+pub fn encode(opcode: Opcode, operands: &[Operand; 3]) -> Vec<u8> {
+  let mut bytes = vec![u8::from(opcode)];
+  let metadata = match Opcode::metadata(opcode) {
+    Some(metadata) => metadata,
+    None => return bytes,
+  };
+
+  for (operand, width) in operands.iter().zip(metadata.operand_widths) {
+    if width == 0 {
+      continue;
+    }
+    let mut buf = [0u8; 8];
+    match *operand {
+      Operand::Register(reg) | Operand::FloatRegister(reg) => buf[0] = reg,
+      Operand::Integer(value) => match width {
+        1 => buf[0] = value as u8,
+        2 => LittleEndian::write_i16(&mut buf, value as i16),
+        _ => LittleEndian::write_i32(&mut buf, value),
+      },
+      Operand::Float(value) => LittleEndian::write_f64(&mut buf, value),
+      Operand::Address(address) => LittleEndian::write_u32(&mut buf, address),
+      Operand::Empty => {}
+    }
+    bytes.extend_from_slice(&buf[..width]);
+  }
+
+  bytes
+}
+
+/// Decode the instruction starting at `offset` using the widths from the opcode metadata table.
+pub fn decode(bytes: &[u8], offset: usize) -> Result<DecodedInstruction, DecodeError> {
+  let byte = match bytes.get(offset) {
+    Some(byte) => *byte,
+    None => {
+      return Err(DecodeError::Truncated {
+        offset,
+        opcode: Opcode::IGL,
+        needed: 1,
+        available: 0,
+      })
+    }
+  };
+
+  let opcode = Opcode::from_byte(byte).ok_or(DecodeError::UnknownOpcode { offset, byte })?;
+  let metadata = Opcode::metadata(opcode).ok_or(DecodeError::UnknownOpcode { offset, byte })?;
+  let length = instruction_length(opcode);
+  let available = bytes.len() - offset;
+  if available < length {
+    return Err(DecodeError::Truncated { offset, opcode, needed: length, available });
+  }
+
+  let mut operands = [Operand::Empty; 3];
+  let mut cursor = offset + 1;
+  for (index, (operand_type, width)) in metadata
+    .operand_types
+    .iter()
+    .zip(metadata.operand_widths)
+    .enumerate()
+  {
+    let raw = &bytes[cursor..cursor + width];
+    operands[index] = match operand_type {
+      OperandType::Register => Operand::Register(raw[0]),
+      OperandType::FloatRegister => Operand::FloatRegister(raw[0]),
+      OperandType::IntegerImmediate => Operand::Integer(match width {
+        1 => raw[0] as i32,
+        2 => LittleEndian::read_i16(raw) as i32,
+        _ => LittleEndian::read_i32(raw),
+      }),
+      OperandType::FloatImmediate => Operand::Float(LittleEndian::read_f64(raw)),
+      OperandType::Address => Operand::Address(LittleEndian::read_u32(raw)),
+      OperandType::Empty => Operand::Empty,
+    };
+    cursor += width;
+  }
+
+  Ok(DecodedInstruction { opcode, operands, offset, length })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assembler::Assembler;
+  use crate::header_utils::LumiHeader;
+  use crate::sections::SectionKind;
+
+  fn sample_operand(operand_type: OperandType, width: usize) -> Operand {
+    match operand_type {
+      OperandType::Register => Operand::Register(7),
+      OperandType::FloatRegister => Operand::FloatRegister(3),
+      OperandType::IntegerImmediate => Operand::Integer(if width == 1 { 200 } else { -1234 }),
+      OperandType::FloatImmediate => Operand::Float(-12.625),
+      OperandType::Address => Operand::Address(0xdead),
+      OperandType::Empty => Operand::Empty,
+    }
+  }
+
+  #[test]
+  fn test_encode_decode_every_opcode() {
+    for opcode in Opcode::all() {
+      let metadata = Opcode::metadata(opcode).unwrap();
+      let mut operands = [Operand::Empty; 3];
+      for i in 0..3 {
+        operands[i] = sample_operand(metadata.operand_types[i], metadata.operand_widths[i]);
+      }
+
+      let bytes = encode(opcode, &operands);
+      assert_eq!(bytes.len(), instruction_length(opcode), "length of {:?}", opcode);
+
+      let decoded = decode(&bytes, 0).unwrap();
+      assert_eq!(decoded.opcode, opcode);
+      assert_eq!(decoded.operands, operands, "operands of {:?}", opcode);
+      assert_eq!(decoded.length, bytes.len());
+    }
+  }
+
+  #[test]
+  fn test_decode_truncated_instruction() {
+    let bytes = encode(Opcode::DJMP, &[Operand::Address(1000), Operand::Empty, Operand::Empty]);
+    let result = decode(&bytes[..3], 0);
+    assert_eq!(
+      result,
+      Err(DecodeError::Truncated { offset: 0, opcode: Opcode::DJMP, needed: 5, available: 3 })
+    );
+  }
+
+  #[test]
+  fn test_decode_unknown_opcode() {
+    assert_eq!(decode(&[0xfe], 0), Err(DecodeError::UnknownOpcode { offset: 0, byte: 0xfe }));
+  }
+
+  #[test]
+  fn test_immediate_ranges() {
+    assert_eq!(immediate_range(1), 0..=255);
+    assert_eq!(immediate_range(2), -32768..=32767);
+    assert!(immediate_range(4).contains(&(i32::MIN as i64)));
+  }
+
+  #[test]
+  fn test_assemble_decode_reassemble_round_trip() {
+    let mut asm = Assembler::new();
+    let program = asm
+      .assemble(
+        r".rodata
+        hello: .asciiz 'Hello'
+        .code
+        load $0 #-100
+        loadi $1 #100000
+        loadf64 $2 #3.25
+        lui $0 #18 #52
+        shl $1 #4
+        cloop #3
+        add $3 $0 $1
+        eqf64 $2 $2
+        prts @hello
+        push $3
+        pop $4
+        hlt
+        ",
+      )
+      .unwrap();
+
+    let sections = LumiHeader::parse(&program).unwrap().sections(&program).unwrap();
+    let code = sections.get(SectionKind::Code).unwrap().range();
+    let mut offset = code.start;
+    let mut reassembled = program[..code.start].to_vec();
+    let mut opcodes = vec![];
+    while offset < code.end {
+      let instruction = decode(&program, offset).unwrap();
+      reassembled.extend(encode(instruction.opcode, &instruction.operands));
+      opcodes.push(instruction.opcode);
+      offset = instruction.next_offset();
+    }
+
+    assert_eq!(offset, code.end);
+    reassembled.extend_from_slice(&program[code.end..]);
+    assert_eq!(reassembled, program);
+    assert_eq!(opcodes.len(), 12);
+    assert_eq!(opcodes[0], Opcode::LOAD);
+    assert_eq!(opcodes[11], Opcode::HLT);
+  }
+}
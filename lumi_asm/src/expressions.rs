@@ -0,0 +1,271 @@
+use std::fmt;
+use std::fmt::Formatter;
+use pest::iterators::Pair;
+use crate::parsers::lumi_asm_parser::Rule;
+use crate::symbols::SymbolTable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+  Remainder,
+}
+
+/// A constant expression in an operand, `.equ` or data directive, evaluated at assembly
+/// time against the symbol table.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+  Integer(i64),
+  /// An `.equ` constant or the address of a label.
+  Symbol(String),
+  /// Size in bytes of the data declared by a label.
+  SizeOf(String),
+  Negate(Box<Expression>),
+  Binary { operator: BinaryOperator, left: Box<Expression>, right: Box<Expression> },
+}
+
+impl Expression {
+  /// Evaluate with labels resolved to their addresses, once every label is known.
+  pub fn evaluate(&self, symbols: &SymbolTable) -> Result<i64, String> {
+    self.evaluate_with(symbols, &|name| match symbols.constant(name) {
+      Some(value) => Ok(value),
+      None => symbols.symbol_value(name).map(i64::from).ok_or_else(|| undefined(name)),
+    })
+  }
+
+  /// Evaluate using only constants and sizes, for `.equ` and data directives which are
+  /// evaluated before the final label addresses are known.
+  pub fn evaluate_constant(&self, symbols: &SymbolTable) -> Result<i64, String> {
+    self.evaluate_with(symbols, &|name| match symbols.constant(name) {
+      Some(value) => Ok(value),
+      None if symbols.has_symbol(name) => Err(format!("`{}` is a label, only constants can be used here", name)),
+      None => Err(undefined(name)),
+    })
+  }
+
+  /// For an expression of the form `label ± constant`, the label and the constant, which a
+  /// linker can resolve as a relocation with an addend.
+  pub fn relocation(&self, symbols: &SymbolTable) -> Option<(String, i64)> {
+    let mut labels = vec![];
+    self.collect_symbols(&mut labels);
+    labels.retain(|name| symbols.constant(name).is_none());
+    labels.dedup();
+    match labels[..] {
+      [label] if self.is_offset_of(label) => {
+        let addend = self
+          .evaluate_with(symbols, &|name| if name == label.as_str() { Ok(0) } else { self::constant(symbols, name) })
+          .ok()?;
+        Some((label.clone(), addend))
+      }
+      _ => None,
+    }
+  }
+
+  /// Whether the expression refers to anything that is not an `.equ` constant.
+  pub fn uses_labels(&self, symbols: &SymbolTable) -> bool {
+    let mut names = vec![];
+    self.collect_symbols(&mut names);
+    names.iter().any(|name| symbols.constant(name).is_none())
+  }
+
+  /// Every symbol the expression mentions, including the labels of `sizeof`.
+  pub fn referenced_symbols(&self) -> Vec<&str> {
+    match self {
+      Expression::Integer(_) => vec![],
+      Expression::Symbol(name) | Expression::SizeOf(name) => vec![name.as_str()],
+      Expression::Negate(operand) => operand.referenced_symbols(),
+      Expression::Binary { left, right, .. } => {
+        let mut names = left.referenced_symbols();
+        names.extend(right.referenced_symbols());
+        names
+      }
+    }
+  }
+
+  fn evaluate_with(&self, symbols: &SymbolTable, resolve: &dyn Fn(&str) -> Result<i64, String>) -> Result<i64, String> {
+    match self {
+      Expression::Integer(value) => Ok(*value),
+      Expression::Symbol(name) => resolve(name),
+      Expression::SizeOf(name) => symbols
+        .symbol_size(name)
+        .map(i64::from)
+        .ok_or_else(|| format!("`{}` has no size, sizeof needs a label declared with a data directive", name)),
+      Expression::Negate(operand) => operand.evaluate_with(symbols, resolve)?.checked_neg().ok_or_else(overflow),
+      Expression::Binary { operator, left, right } => {
+        let left = left.evaluate_with(symbols, resolve)?;
+        let right = right.evaluate_with(symbols, resolve)?;
+        match operator {
+          BinaryOperator::Add => left.checked_add(right).ok_or_else(overflow),
+          BinaryOperator::Subtract => left.checked_sub(right).ok_or_else(overflow),
+          BinaryOperator::Multiply => left.checked_mul(right).ok_or_else(overflow),
+          BinaryOperator::Divide | BinaryOperator::Remainder if right == 0 => Err("Division by zero".to_string()),
+          BinaryOperator::Divide => left.checked_div(right).ok_or_else(overflow),
+          BinaryOperator::Remainder => left.checked_rem(right).ok_or_else(overflow),
+        }
+      }
+    }
+  }
+
+  fn collect_symbols<'a>(&'a self, names: &mut Vec<&'a String>) {
+    match self {
+      Expression::Symbol(name) => names.push(name),
+      Expression::Negate(operand) => operand.collect_symbols(names),
+      Expression::Binary { left, right, .. } => {
+        left.collect_symbols(names);
+        right.collect_symbols(names);
+      }
+      Expression::Integer(_) | Expression::SizeOf(_) => {}
+    }
+  }
+
+  fn mentions(&self, label: &str) -> bool {
+    let mut names = vec![];
+    self.collect_symbols(&mut names);
+    names.iter().any(|name| *name == label)
+  }
+
+  fn is_offset_of(&self, label: &str) -> bool {
+    match self {
+      Expression::Symbol(name) => name == label,
+      Expression::Binary { operator: BinaryOperator::Add, left, right } => {
+        (left.is_offset_of(label) && !right.mentions(label)) || (right.is_offset_of(label) && !left.mentions(label))
+      }
+      Expression::Binary { operator: BinaryOperator::Subtract, left, right } => {
+        left.is_offset_of(label) && !right.mentions(label)
+      }
+      _ => false,
+    }
+  }
+
+  /// Build an expression from an `expression`, `term`, `factor`, `expression_immediate` or
+  /// `label_expression` pair.
+  pub(crate) fn from_pair(pair: Pair<Rule>) -> Result<Self, String> {
+    match pair.as_rule() {
+      Rule::expression | Rule::term | Rule::label_expression => {
+        let mut inner = pair.into_inner();
+        let mut expression = Expression::from_pair(inner.next().ok_or("Empty expression")?)?;
+        while let Some(operator) = inner.next() {
+          let operator = match operator.as_str() {
+            "+" => BinaryOperator::Add,
+            "-" => BinaryOperator::Subtract,
+            "*" => BinaryOperator::Multiply,
+            "/" => BinaryOperator::Divide,
+            _ => BinaryOperator::Remainder,
+          };
+          let right = Expression::from_pair(inner.next().ok_or("Missing operand after operator")?)?;
+          expression = Expression::Binary { operator, left: Box::new(expression), right: Box::new(right) };
+        }
+        Ok(expression)
+      }
+      Rule::factor | Rule::expression_immediate => {
+        Expression::from_pair(pair.into_inner().next().ok_or("Empty expression")?)
+      }
+      Rule::negation => {
+        let operand = Expression::from_pair(pair.into_inner().next().ok_or("Missing operand after `-`")?)?;
+        Ok(Expression::Negate(Box::new(operand)))
+      }
+      Rule::sizeof => {
+        let name = pair.into_inner().next().ok_or("Missing label in sizeof")?;
+        Ok(Expression::SizeOf(name.as_str().to_string()))
+      }
+      Rule::number => {
+        let text = pair.as_str();
+        let value = match text.strip_prefix("0x") {
+          Some(hex) => i64::from_str_radix(hex, 16),
+          None => text.parse::<i64>(),
+        };
+        value.map(Expression::Integer).map_err(|err| format!("Invalid number `{}`: {}", text, err))
+      }
+      Rule::label_usage => Ok(Expression::Symbol(pair.as_str()[1..].to_string())),
+      Rule::identifier => Ok(Expression::Symbol(pair.as_str().to_string())),
+      rule => Err(format!("Unexpected rule in expression: {:?}", rule)),
+    }
+  }
+}
+
+impl fmt::Display for Expression {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Expression::Integer(value) => write!(f, "{}", value),
+      Expression::Symbol(name) => write!(f, "{}", name),
+      Expression::SizeOf(name) => write!(f, "sizeof({})", name),
+      Expression::Negate(operand) => write!(f, "-{}", operand),
+      Expression::Binary { operator, left, right } => {
+        let operator = match operator {
+          BinaryOperator::Add => "+",
+          BinaryOperator::Subtract => "-",
+          BinaryOperator::Multiply => "*",
+          BinaryOperator::Divide => "/",
+          BinaryOperator::Remainder => "%",
+        };
+        write!(f, "({} {} {})", left, operator, right)
+      }
+    }
+  }
+}
+
+fn constant(symbols: &SymbolTable, name: &str) -> Result<i64, String> {
+  symbols.constant(name).ok_or_else(|| undefined(name))
+}
+
+fn undefined(name: &str) -> String {
+  format!("Symbol `{}` is not defined", name)
+}
+
+fn overflow() -> String {
+  "Arithmetic overflow".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pest::Parser;
+  use crate::parsers::lumi_asm_parser::LumiAsmParser;
+  use crate::symbols::{Symbol, SymbolType};
+
+  fn parse(source: &str) -> Expression {
+    let pair = LumiAsmParser::parse(Rule::expression, source).unwrap().next().unwrap();
+    Expression::from_pair(pair).unwrap()
+  }
+
+  fn symbols() -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    symbols.add_symbol(Symbol::new(String::from("BUF_SIZE"), SymbolType::Constant { value: 16 }));
+    symbols.add_symbol(Symbol::new_with_offset(String::from("table"), SymbolType::Label, 100));
+    symbols.add_symbol(Symbol::new_with_offset(String::from("msg"), SymbolType::LString, 0));
+    symbols.set_symbol_size("msg", 6);
+    symbols
+  }
+
+  #[test]
+  fn test_expression_precedence_and_evaluation() {
+    let symbols = symbols();
+    assert_eq!(parse("BUF_SIZE * 4 + 1").evaluate(&symbols), Ok(65));
+    assert_eq!(parse("2 * (3 + 4) - -1").evaluate(&symbols), Ok(15));
+    assert_eq!(parse("0x10 / 3 % 4").evaluate(&symbols), Ok(1));
+    assert_eq!(parse("sizeof(msg) + @table").evaluate(&symbols), Ok(106));
+    assert_eq!(parse("BUF_SIZE * 4 + 1").to_string(), "((BUF_SIZE * 4) + 1)");
+  }
+
+  #[test]
+  fn test_expression_errors() {
+    let symbols = symbols();
+    assert_eq!(parse("1 / (BUF_SIZE - 16)").evaluate(&symbols), Err("Division by zero".to_string()));
+    assert_eq!(parse("missing + 1").evaluate(&symbols), Err("Symbol `missing` is not defined".to_string()));
+    assert!(parse("sizeof(table)").evaluate(&symbols).unwrap_err().contains("has no size"));
+    assert!(parse("table + 1").evaluate_constant(&symbols).unwrap_err().contains("is a label"));
+    assert_eq!(parse("BUF_SIZE + 1").evaluate_constant(&symbols), Ok(17));
+  }
+
+  #[test]
+  fn test_expression_relocation() {
+    let symbols = symbols();
+    assert_eq!(parse("table + BUF_SIZE - 2").relocation(&symbols), Some(("table".to_string(), 14)));
+    assert_eq!(parse("8 + table").relocation(&symbols), Some(("table".to_string(), 8)));
+    assert_eq!(parse("table * 2").relocation(&symbols), None);
+    assert_eq!(parse("BUF_SIZE - table").relocation(&symbols), None);
+    assert!(!parse("BUF_SIZE * 2").uses_labels(&symbols));
+  }
+}
@@ -8,7 +8,7 @@ use crate::assembler_errors::AssemblerError;
 type Error = anyhow::Error;
 
 pub fn assemble_file(file_path: &str) -> Result<(), Vec<AssemblerError>> {
-  let mut assembler: Assembler = Assembler::new();
+  let mut assembler: Assembler = Assembler::new().with_source_path(file_path);
   let raw = read_file(file_path)
     .map_err(|err| vec![
       AssemblerError::FailedToReadFile { error: err.to_string() }
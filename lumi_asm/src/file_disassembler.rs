@@ -1,229 +1,498 @@
-use crate::instruction::{Opcode, OperandType};
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::Cursor;
-use colored::Colorize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::ops::Range;
+use serde::Serialize;
+use crate::debug_info::{DebugInfo, DebugSymbolKind};
+use crate::encoding::{decode, DecodedInstruction, Operand};
 use log::debug;
-use crate::header_utils::LUMI_HEADER_LENGTH;
+use crate::header_utils::{HeaderError, LumiHeader, LUMI_HEADER_SIZE};
+use crate::instruction::Opcode;
+use crate::sections::{SectionKind, SectionTable};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct DisassemblyError {
   message: String,
 }
 
-pub fn to_hex(value: usize) -> String {
-  format!("{:08x}", value)
+impl fmt::Display for DisassemblyError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.message)
+  }
 }
 
-pub fn disassemble(bytecode: &[u8]) -> Result<String, DisassemblyError> {
-  let mut output = String::new();
+/// What `disassemble_as` produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisassemblyFormat {
+  /// Annotated listing with offsets, section layout and source locations.
+  Text,
+  /// `.lumi` source that assembles back into the same binary.
+  Source,
+  /// A JSON array of `InstructionRecord`s.
+  Json,
+}
 
-  // Step 1: Validate bytecode length (header + offset + minimum instruction space)
-  if bytecode.len() < LUMI_HEADER_LENGTH + 5 {
-    return Err(DisassemblyError {
-      message: "Bytecode too short to contain header and starting offset".to_string(),
-    });
-  }
+/// One instruction of the code section, or a byte that does not decode to one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstructionRecord {
+  pub offset: usize,
+  pub bytes: Vec<u8>,
+  /// `None` for a byte that is not a valid opcode.
+  pub mnemonic: Option<String>,
+  pub operands: Vec<Operand>,
+  /// The instruction as it is written in the text disassembly.
+  pub text: String,
+  /// Label declared at `offset`, from the debug info or synthesized for a jump target.
+  pub label: Option<String>,
+  /// Label of the code or string the address operand points at.
+  pub target: Option<String>,
+  /// `file:line:column` the instruction was assembled from, when the binary has debug info.
+  pub source: Option<String>,
+}
 
-  // Step 2: Calculate Program Counter Start
-  const OFFSET_SIZE: usize = 4;
+/// Items of the code section in order.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CodeItem {
+  Instruction(DecodedInstruction),
+  Unknown { offset: usize, byte: u8 },
+}
 
-  // Read the RO offset (size of .data)
-  let mut rdr = Cursor::new(&bytecode[LUMI_HEADER_LENGTH + 1..LUMI_HEADER_LENGTH + 1 + OFFSET_SIZE]);
-  let ro_offset = rdr.read_u32::<LittleEndian>().unwrap() as usize;
+impl CodeItem {
+  pub(crate) fn offset(&self) -> usize {
+    match self {
+      CodeItem::Instruction(instruction) => instruction.offset,
+      CodeItem::Unknown { offset, .. } => *offset,
+    }
+  }
+}
 
-  let ro_data_start = LUMI_HEADER_LENGTH + 1 + OFFSET_SIZE; // Start of RO data section
-  let ro_data_end = ro_data_start + ro_offset; // End of RO data section
-  let mut pc = ro_data_end; // Start of instructions
+/// A binary split into its sections, with the code decoded and every address that is
+/// referenced or named in the debug info given a label.
+pub(crate) struct Disassembly<'a> {
+  bytecode: &'a [u8],
+  pub(crate) header: LumiHeader,
+  sections: SectionTable,
+  pub(crate) code: Range<usize>,
+  pub(crate) items: Vec<CodeItem>,
+  debug_info: Option<DebugInfo>,
+  /// Labels of code offsets, by offset in the binary.
+  pub(crate) code_labels: BTreeMap<usize, String>,
+  /// Labels of RO data, by offset in the RO section.
+  ro_labels: BTreeMap<usize, String>,
+  /// Labels of `.data` and `.bss`, by heap address.
+  heap_labels: BTreeMap<usize, String>,
+}
 
-  debug!("Starting disassembly at PC: {}", pc);
-  output.push_str(&format!("; lumi header <0x{:x}-0x{:x}>\n", ro_data_start, ro_data_end));
+impl<'a> Disassembly<'a> {
+  pub(crate) fn parse(bytecode: &'a [u8]) -> Result<Self, DisassemblyError> {
+    let to_error = |err: HeaderError| DisassemblyError { message: err.to_string() };
+    let header = LumiHeader::parse(bytecode).map_err(to_error)?;
+    let sections = header.sections(bytecode).map_err(to_error)?;
+    let code = sections.get(SectionKind::Code).ok_or(to_error(HeaderError::MissingCodeSection))?.range();
+    let debug_info = match sections.get(SectionKind::Debug) {
+      Some(section) => Some(DebugInfo::parse(&bytecode[section.range()]).map_err(to_error)?),
+      None => None,
+    };
 
-  // Step 3: Process Instructions
-  while pc < bytecode.len() {
-    // Ensure valid opcode
-    if pc >= bytecode.len() {
-      break;
+    // bytes that are not instructions are kept and skipped one at a time, so the rest of the
+    // code can still be read
+    let mut items = vec![];
+    let mut pc = code.start;
+    debug!("Starting disassembly at PC: {}", pc);
+    while pc < code.end {
+      match decode(&bytecode[..code.end], pc) {
+        Ok(instruction) => {
+          pc = instruction.next_offset();
+          items.push(CodeItem::Instruction(instruction));
+        }
+        Err(err) => {
+          debug!("{}", err);
+          items.push(CodeItem::Unknown { offset: pc, byte: bytecode[pc] });
+          pc += 1;
+        }
+      }
     }
 
-    // Step 3.1: Read Opcode
-    let opcode_byte = bytecode[pc];
-    debug!("Opcode byte at 0x{:x}: {:?}", pc, opcode_byte);
-    pc += 1; // Move past opcode byte
+    let mut disassembly = Disassembly {
+      bytecode,
+      header,
+      sections,
+      code,
+      items,
+      debug_info,
+      code_labels: BTreeMap::new(),
+      ro_labels: BTreeMap::new(),
+      heap_labels: BTreeMap::new(),
+    };
+    disassembly.name_labels();
+    Ok(disassembly)
+  }
 
-    // Fetch Opcode Metadata
-    let opcode = Opcode::from(opcode_byte);
-    let metadata = match Opcode::metadata(opcode) {
-      Some(m) => m,
-      None => {
-        return Err(DisassemblyError {
-          message: format!("Unknown opcode at PC 0x{:x}: {}", pc - 1, opcode_byte),
-        });
+  fn name_labels(&mut self) {
+    let boundaries: Vec<usize> = self.items.iter().map(CodeItem::offset).chain([self.code.end]).collect();
+    if let Some(debug_info) = &self.debug_info {
+      for symbol in &debug_info.symbols {
+        let (labels, value) = match symbol.kind {
+          // a label inside an instruction cannot be declared again, operands use the address
+          DebugSymbolKind::Label if boundaries.binary_search(&(symbol.value as usize)).is_err() => continue,
+          DebugSymbolKind::Label => (&mut self.code_labels, symbol.value as usize),
+          DebugSymbolKind::Constant => (&mut self.ro_labels, symbol.value as usize),
+          DebugSymbolKind::Variable => (&mut self.heap_labels, symbol.value as usize),
+        };
+        labels.entry(value).or_insert_with(|| identifier(&symbol.name));
       }
-    };
+    }
 
-    // Append opcode to output
-    output.push_str(&format!("0x{:x}: {}\t", pc - 1, metadata.str_symbol));
-
-    // Step 3.2: Decode Operands
-    for operand_type in &metadata.operand_types {
-      match operand_type {
-        OperandType::Register => {
-          if pc < bytecode.len() {
-            let reg = bytecode[pc];
-            output.push_str(&format!("$r{} ", reg));
-            pc += 1; // Advance past register byte
-          }
+    let ro_length = self.header.ro_length as usize;
+    for item in &self.items {
+      let CodeItem::Instruction(instruction) = item else { continue };
+      let target = instruction.address(0);
+      match target_section(instruction.opcode) {
+        Some(SectionKind::Code) if boundaries.binary_search(&target).is_ok() => {
+          self.code_labels.entry(target).or_insert_with(|| format!("loc_{:04x}", target));
         }
-        OperandType::FloatRegister => {
-          if pc < bytecode.len() {
-            let reg = bytecode[pc];
-            output.push_str(&format!("$r{} ", reg));
-            pc += 1; // Advance past register byte
-          }
+        Some(SectionKind::ReadOnly) if target < ro_length => {
+          self.ro_labels.entry(target).or_insert_with(|| format!("ro_{:04x}", target));
         }
-        OperandType::Address => {
-          if pc + 4 <= bytecode.len() { // Ensure enough bytes for address
-            let mut rdr = Cursor::new(&bytecode[pc..pc + 4]);
-            let address = rdr.read_u32::<LittleEndian>().unwrap();
-            output.push_str(&format!("@0x{:x} ", address));
-            pc += 4; // Advance by address size (4 bytes)
-          }
+        _ => {}
+      }
+    }
+  }
+
+  /// The label an address operand points at, if it has one.
+  pub(crate) fn target(&self, instruction: &DecodedInstruction) -> Option<&String> {
+    match target_section(instruction.opcode)? {
+      SectionKind::Code => self.code_labels.get(&instruction.address(0)),
+      _ => self.ro_labels.get(&instruction.address(0)),
+    }
+  }
+
+  fn records(&self) -> Vec<InstructionRecord> {
+    self
+      .items
+      .iter()
+      .map(|item| {
+        let offset = item.offset();
+        let label = self.code_labels.get(&offset).cloned();
+        let source = self.debug_info.as_ref().and_then(|debug_info| debug_info.location(offset)).map(|l| l.to_string());
+        match item {
+          CodeItem::Instruction(instruction) => InstructionRecord {
+            offset,
+            bytes: self.bytecode[offset..instruction.next_offset()].to_vec(),
+            mnemonic: Some(mnemonic(instruction.opcode).to_string()),
+            operands: instruction.operands.iter().filter(|operand| **operand != Operand::Empty).copied().collect(),
+            text: instruction.to_string(),
+            label,
+            target: self.target(instruction).cloned(),
+            source,
+          },
+          CodeItem::Unknown { byte, .. } => InstructionRecord {
+            offset,
+            bytes: vec![*byte],
+            mnemonic: None,
+            operands: vec![],
+            text: format!(".byte 0x{:02x}", byte),
+            label,
+            target: None,
+            source,
+          },
         }
-        OperandType::IntegerImmediate => {
-          if pc + 4 <= bytecode.len() { // Ensure enough bytes for integer
-            let mut rdr = Cursor::new(&bytecode[pc..pc + 4]);
-            let value = rdr.read_i32::<LittleEndian>().unwrap();
-            output.push_str(&format!("#{} ", value));
-            pc += 4; // Advance by integer size (4 bytes)
+      })
+      .collect()
+  }
+
+  fn text(&self) -> String {
+    let mut output = String::new();
+    output.push_str(&format!("; lumi header <0x0-0x{:x}>\n", LUMI_HEADER_SIZE));
+    for section in self.sections.sections() {
+      output.push_str(&format!("; section {}\n", section));
+    }
+
+    for record in self.records() {
+      if let Some(label) = &record.label {
+        output.push_str(&format!("{}:\n", label));
+      }
+      let mut line = format!("0x{:x}: {}", record.offset, record.text);
+      if record.mnemonic.is_none() {
+        line.push_str(" ; unknown opcode");
+      }
+      match &record.source {
+        Some(location) => output.push_str(&format!("{:<32} ; {}\n", line, location)),
+        None => output.push_str(&format!("{}\n", line)),
+      }
+    }
+
+    // initial contents of the writable data
+    let data = self.sections.contents(SectionKind::Data, self.bytecode);
+    for (row, chunk) in data.chunks(16).enumerate() {
+      let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+      output.push_str(&format!("; .data 0x{:04x}: {}\n", row * 16, bytes.join(" ")));
+    }
+    output
+  }
+
+  fn source(&self) -> String {
+    let mut output = String::from("; disassembled by lumi_asm\n");
+    if self.header.entry_offset as usize != self.code.start {
+      output.push_str(&format!("; the original entry point was 0x{:x}\n", self.header.entry_offset));
+    }
+
+    let ro = self.sections.contents(SectionKind::ReadOnly, self.bytecode);
+    if !ro.is_empty() {
+      output.push_str(".rodata\n");
+      write_data(&mut output, ro, 0, &self.ro_labels, "ro");
+    }
+    if let Some(data) = self.sections.get(SectionKind::Data).filter(|section| section.size > 0) {
+      output.push_str(".data\n");
+      write_data(&mut output, &self.bytecode[data.range()], data.address as usize, &self.heap_labels, "data");
+    }
+    if let Some(bss) = self.sections.get(SectionKind::Bss).filter(|section| section.size > 0) {
+      output.push_str(".bss\n");
+      let range = bss.heap_range();
+      for (start, end, label) in chunks(range.clone(), &self.heap_labels, "bss") {
+        output.push_str(&format!("{}: .space #{}\n", label, end - start));
+      }
+    }
+
+    output.push_str(".code\n");
+    for item in &self.items {
+      if let Some(label) = self.code_labels.get(&item.offset()) {
+        output.push_str(&format!("{}:\n", label));
+      }
+      match item {
+        CodeItem::Instruction(instruction) => {
+          let mut line = format!("  {}", mnemonic(instruction.opcode).to_lowercase());
+          let mut comment = None;
+          for operand in instruction.operands.iter().filter(|operand| **operand != Operand::Empty) {
+            let text = match operand {
+              Operand::Address(address) => match self.target(instruction) {
+                Some(label) => format!("@{}", label),
+                None => format!("#{}", *address as i32),
+              },
+              Operand::Float(value) if !value.is_finite() => {
+                comment = Some(format!("{} cannot be written as a literal", value));
+                "#0.0".to_string()
+              }
+              Operand::Float(value) => format!("#{}", float_literal(*value)),
+              operand => operand.to_string(),
+            };
+            line.push(' ');
+            line.push_str(&text);
           }
-        }
-        OperandType::FloatImmediate => {
-          if pc + 4 <= bytecode.len() { // Ensure enough bytes for integer
-            let mut rdr = Cursor::new(&bytecode[pc..pc + 4]);
-            let value = rdr.read_f32::<LittleEndian>().unwrap();
-            output.push_str(&format!("#{} ", value));
-            pc += 4; // Advance by integer size (4 bytes)
+          if let Some(comment) = comment {
+            line.push_str(&format!(" ; {}", comment));
           }
+          output.push_str(&line);
         }
-        OperandType::Empty => {
-          // No operand - do nothing
-        }
+        // keep the byte's place so every other offset stays the same
+        CodeItem::Unknown { byte, .. } => output.push_str(&format!("  igl ; unknown opcode 0x{:02x}", byte)),
       }
+      output.push('\n');
+    }
+    if let Some(label) = self.code_labels.get(&self.code.end) {
+      output.push_str(&format!("{}:\n", label));
+    }
+    output
+  }
+}
+
+/// Disassemble into an annotated listing with offsets, section layout and, when the binary
+/// has debug info, labels and source locations.
+pub fn disassemble(bytecode: &[u8]) -> Result<String, DisassemblyError> {
+  Ok(Disassembly::parse(bytecode)?.text())
+}
+
+pub fn disassemble_as(bytecode: &[u8], format: DisassemblyFormat) -> Result<String, DisassemblyError> {
+  let disassembly = Disassembly::parse(bytecode)?;
+  Ok(match format {
+    DisassemblyFormat::Text => disassembly.text(),
+    DisassemblyFormat::Source => disassembly.source(),
+    DisassemblyFormat::Json => {
+      // one record per line keeps large programs readable and easy to grep
+      let records: Vec<String> =
+        disassembly.records().iter().map(|record| serde_json::to_string(record).unwrap_or_default()).collect();
+      format!("[\n{}\n]\n", records.join(",\n"))
     }
+  })
+}
+
+/// Every instruction of the code section, with the labels of its offset and target.
+pub fn instruction_records(bytecode: &[u8]) -> Result<Vec<InstructionRecord>, DisassemblyError> {
+  Ok(Disassembly::parse(bytecode)?.records())
+}
+
+/// Section an address operand of `opcode` points into: string printing reads RO data,
+/// everything else jumps.
+fn target_section(opcode: Opcode) -> Option<SectionKind> {
+  match opcode {
+    Opcode::PRTS => Some(SectionKind::ReadOnly),
+    Opcode::DJMP | Opcode::DJMPE | Opcode::CALL | Opcode::LOOP => Some(SectionKind::Code),
+    _ => None,
+  }
+}
 
-    // Append newline after each instruction
-    output.push_str("\n");
-  }
-
-  Ok(output)
-}
-
-fn get_byte_color(
-  index: usize,
-  current_instruction: Option<usize>,
-  bytecode: &Vec<u8>,
-) -> colored::Color {
-  let mut highlight_current = false;
-  let mut current_instruction_pos = 0;
-  if current_instruction.is_some() {
-    highlight_current = true;
-    current_instruction_pos = current_instruction.unwrap();
-  }
-
-  const OFFSET_SIZE: usize = 4;
-  let start_index = LUMI_HEADER_LENGTH + OFFSET_SIZE + 1;
-  let mut rdr = Cursor::new(&bytecode[LUMI_HEADER_LENGTH + 1..LUMI_HEADER_LENGTH + 1 + 4]);
-  let offset = rdr.read_u32::<LittleEndian>().unwrap() as usize;
-  let end_index = start_index + offset - 1;
-
-  // highlight_current = true;
-  // current_instruction_pos = LUMI_HEADER_LENGTH + OFFSET_SIZE + 12;
-
-  if index <= LUMI_HEADER_LENGTH {
-    // highlight the header
-    colored::Color::BrightBlue
-  } else if index >= LUMI_HEADER_LENGTH + 1 && index <= LUMI_HEADER_LENGTH + OFFSET_SIZE {
-    // highlight the RO offset
-    colored::Color::BrightMagenta
-  } else if index >= start_index && index <= end_index {
-    colored::Color::BrightCyan
-  } else if highlight_current && index == current_instruction_pos {
-    // highlight current byte that PC is pointing to
-    colored::Color::Red
-  } else if highlight_current
-    && index > current_instruction_pos
-    && index <= current_instruction_pos + 3
-  {
-    // highlight the next 3 bytes (instruction is 32 bits long)
-    colored::Color::Yellow
+fn mnemonic(opcode: Opcode) -> &'static str {
+  Opcode::metadata(opcode).map_or("IGL", |metadata| metadata.str_symbol)
+}
+
+/// `name` with every character an identifier cannot have replaced by `_`.
+fn identifier(name: &str) -> String {
+  let mut identifier: String =
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect();
+  if !identifier.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+    identifier.insert(0, '_');
+  }
+  identifier
+}
+
+/// A float the grammar accepts, which always has digits on both sides of the point.
+fn float_literal(value: f64) -> String {
+  let text = value.to_string();
+  if text.contains('.') {
+    text
   } else {
-    colored::Color::White
+    format!("{}.0", text)
   }
 }
 
-fn print_ascii_representation(
-  line: &[u8],
-  line_start_index: usize,
-  current_instruction: Option<usize>,
-  bytecode: &Vec<u8>,
-) {
-  print!("  ");
-  for (i, &byte) in line.iter().enumerate() {
-    let index = line_start_index + i;
-    let color = get_byte_color(index, current_instruction, bytecode);
+/// Split `range` at every label in it, naming the pieces without one after `prefix`.
+fn chunks(range: Range<usize>, labels: &BTreeMap<usize, String>, prefix: &str) -> Vec<(usize, usize, String)> {
+  let mut starts: Vec<usize> = labels.range(range.clone()).map(|(address, _)| *address).collect();
+  if starts.first() != Some(&range.start) {
+    starts.insert(0, range.start);
+  }
+  let ends = starts.iter().skip(1).copied().chain([range.end]);
+  starts
+    .iter()
+    .zip(ends)
+    .map(|(&start, end)| {
+      let label = labels.get(&start).cloned().unwrap_or_else(|| format!("{}_{:04x}", prefix, start));
+      (start, end, label)
+    })
+    .collect()
+}
 
-    if byte >= 32 && byte <= 126 {
-      print!("{}", (byte as char).to_string().color(color));
-    } else {
-      print!("{}", ".".color(color));
+/// Write `bytes`, loaded at `base`, as data declarations: strings where the bytes read as
+/// one, runs of zeros as `.space` and everything else as integers and single bytes.
+fn write_data(output: &mut String, bytes: &[u8], base: usize, labels: &BTreeMap<usize, String>, prefix: &str) {
+  for (start, end, label) in chunks(base..base + bytes.len(), labels, prefix) {
+    let mut position = start;
+    while position < end {
+      let label = if position == start { label.clone() } else { format!("{}_{:04x}", prefix, position) };
+      let rest = &bytes[position - base..end - base];
+      let zeros = rest.iter().take_while(|byte| **byte == 0).count();
+      let (declaration, length) = if let Some((literal, length)) = string_literal(rest) {
+        (format!(".asciiz {}", literal), length)
+      } else if zeros >= 4 {
+        (format!(".space #{}", zeros), zeros)
+      } else if rest.len() >= 4 {
+        (format!(".integer #{}", i32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]])), 4)
+      } else {
+        (format!(".byte #{}", rest[0]), 1)
+      };
+      output.push_str(&format!("{}: {}\n", label, declaration));
+      position += length;
     }
   }
 }
 
-pub fn visualize_program(bytecode: &Vec<u8>, current_instruction: Option<usize>) {
-  for (index, &byte) in bytecode.iter().enumerate() {
-    // prints the address offset at the start of each line
-    if index % 16 == 0 {
-      if index != 0 {
-        print_ascii_representation(
-          &bytecode[index - 16..index],
-          index - 16,
-          current_instruction,
-          bytecode,
-        );
-        println!();
-      }
-      print!("{} ", format!("{:08x}", index).green());
-    }
+/// The quoted string at the start of `bytes` and the number of bytes it covers, if they are
+/// printable text followed by a NUL.
+fn string_literal(bytes: &[u8]) -> Option<(String, usize)> {
+  let length = bytes.iter().position(|byte| *byte == 0)?;
+  let text = std::str::from_utf8(&bytes[..length]).ok()?;
+  if text.is_empty() || !text.bytes().all(|byte| (b' '..=b'~').contains(&byte)) {
+    return None;
+  }
+  let literal = match (text.contains('\''), text.contains('"')) {
+    (false, _) => format!("'{}'", text),
+    (true, false) => format!("\"{}\"", text),
+    (true, true) => return None,
+  };
+  Some((literal, length + 1))
+}
 
-    let color = get_byte_color(index, current_instruction, bytecode);
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assembler::Assembler;
 
-    print!("{}", format!("{:02X}", byte).color(color));
+  const PROGRAM: &str = ".rodata
+greeting: .asciiz 'Hi there'
+quote: .asciiz \"it's\"
+answer: .integer #-42
+ratio: .float #1.5
+flag: .byte #7
+.data
+counter: .integer #5
+zeros: .space #8
+.bss
+scratch: .space #16
+.code
+main: load $0 #3
+  prts @greeting
+  prts @quote
+loop: dec $0
+  eq $0 $1
+  djmpe @done
+  call @work
+  djmp @loop
+work: loadf64 $1 #-0.25
+  shl $2 #3
+  ret
+done: hlt
+";
 
-    // group 2 bytes together
-    if index % 2 == 1 {
-      print!(" ");
-    }
+  #[test]
+  fn test_source_reassembles_into_the_same_binary() {
+    let program = Assembler::new().assemble(PROGRAM).unwrap();
+    let source = disassemble_as(&program, DisassemblyFormat::Source).unwrap();
+    assert!(source.contains("ro_0000: .asciiz 'Hi there'\n"));
+    assert!(source.contains("ro_0009: .asciiz \"it's\"\n"));
+    assert!(source.contains("  djmpe @loc_"));
+    assert!(source.contains("  loadf64 $1 #-0.25\n"));
+    assert_eq!(Assembler::new().assemble(&source).unwrap(), program);
+
+    // with debug info the original names are used, and the code is still the same
+    let debug = Assembler::new().with_debug_info("main.lumi").assemble(PROGRAM).unwrap();
+    let source = disassemble_as(&debug, DisassemblyFormat::Source).unwrap();
+    assert!(source.contains("greeting: .asciiz 'Hi there'\n"));
+    assert!(source.contains("scratch: .space #16\n"));
+    assert!(source.contains("  call @work\n"));
+    assert_eq!(Assembler::new().assemble(&source).unwrap(), program);
   }
 
-  // handle case where program does not end at 16-byte boundary
-  let remainder = bytecode.len() % 16;
-  if remainder != 0 {
-    let padding = (16 - remainder) * 2 + (16 - remainder) / 2 + 1;
-    print!("{:padding$}", "", padding = padding);
-    print_ascii_representation(
-      &bytecode[bytecode.len() - remainder..],
-      bytecode.len() - remainder,
-      current_instruction,
-      bytecode,
-    );
-  } else {
-    print_ascii_representation(
-      &bytecode[bytecode.len() - 16..],
-      bytecode.len() - 16,
-      current_instruction,
-      bytecode,
-    );
+  #[test]
+  fn test_unknown_opcodes_do_not_stop_disassembly() {
+    let mut program = Assembler::new().assemble(".code\nload $0 #1\nhlt\nhlt\n").unwrap();
+    let header = LumiHeader::parse(&program).unwrap();
+    let code = header.sections(&program).unwrap().get(SectionKind::Code).unwrap().range();
+    // replace the first hlt with a byte that is not an opcode
+    program[code.start + 4] = 0xee;
+
+    let text = disassemble(&program).unwrap();
+    assert!(text.contains(".byte 0xee ; unknown opcode"));
+    assert!(text.trim_end().ends_with("HLT"));
+
+    let records = instruction_records(&program).unwrap();
+    let mnemonics: Vec<_> = records.iter().map(|record| record.mnemonic.as_deref()).collect();
+    assert_eq!(mnemonics, vec![Some("LOAD"), None, Some("HLT")]);
+    assert_eq!(records[1].bytes, vec![0xee]);
+
+    let source = disassemble_as(&program, DisassemblyFormat::Source).unwrap();
+    assert!(source.contains("  igl ; unknown opcode 0xee\n"));
+    assert_eq!(Assembler::new().assemble(&source).unwrap().len(), program.len());
   }
-  println!();
-}
\ No newline at end of file
+
+  #[test]
+  fn test_json_records() {
+    let program = Assembler::new().with_debug_info("main.lumi").assemble(PROGRAM).unwrap();
+    let json = disassemble_as(&program, DisassemblyFormat::Json).unwrap();
+    let records: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+    let call = records.iter().find(|record| record["mnemonic"] == "CALL").unwrap();
+    assert_eq!(call["target"], "work");
+    assert_eq!(call["source"], "main.lumi:19:3");
+    assert_eq!(records[0]["label"], "main");
+    assert_eq!(records[0]["operands"], serde_json::json!([{ "register": 0 }, { "integer": 3 }]));
+  }
+}
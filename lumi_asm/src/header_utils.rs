@@ -1,42 +1,371 @@
-use byteorder::{LittleEndian, WriteBytesExt};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use byteorder::{ByteOrder, LittleEndian};
 use log::debug;
+use crate::sections::{SectionKind, SectionTable};
 
 /// Magic number for LUMI programs.
 pub const LUMI_HEADER_PREFIX: [u8; 4] = [0x4C, 0x55, 0x4D, 0x49];
 /// Length of the LUMI header.
 pub const LUMI_HEADER_LENGTH: usize = 64;
+/// Size of the whole header, including the read-only section length that follows it.
+pub const LUMI_HEADER_SIZE: usize = LUMI_HEADER_LENGTH + 1 + 4;
+/// Version of the binary layout written by this assembler.
+pub const LUMI_FORMAT_VERSION: u16 = 2;
+/// Version of the instruction set written by this assembler.
+pub const LUMI_ISA_VERSION: u16 = 1;
+/// Header flag set when the binary carries a debug info section.
+pub const FLAG_DEBUG_INFO: u32 = 1;
 
-/// Get the header for a LUMI program.
-pub fn get_lumi_header(read_only_data_length: usize) -> Vec<u8> {
-  let mut header = vec![];
-  for byte in LUMI_HEADER_PREFIX.into_iter() {
-    header.push(byte.clone());
-  }
-  
-  while header.len() <= LUMI_HEADER_LENGTH {
-    header.push(0x11u8);
-  }
-  
-  // calculate and write the starting offset for the VM to know where the RO section ends
-  debug!("Header: {:?}", header);
-  debug!("RO Length: {}", read_only_data_length);
-  let mut wtr: Vec<u8> = vec![];
-  wtr.write_u32::<LittleEndian>(read_only_data_length as u32)
-      .unwrap();
-  for byte in &wtr {
-    debug!("Written offset bytes: {:02X}", byte);
-  }
-  
-  header.append(&mut wtr);
-  debug!("Header: {:?}", header);
-  header
+// field offsets inside the header
+const FORMAT_VERSION: usize = 4;
+const ISA_VERSION: usize = 6;
+const ENTRY_OFFSET: usize = 8;
+const SECTION_TABLE_OFFSET: usize = 12;
+const FLAGS: usize = 16;
+const BODY_CRC32: usize = 20;
+const RO_LENGTH: usize = LUMI_HEADER_LENGTH + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+  TooShort { length: usize, expected: usize },
+  BadMagic,
+  UnsupportedFormatVersion { found: u16, supported: u16 },
+  UnsupportedIsaVersion { found: u16, supported: u16 },
+  ReadOnlySectionOutOfBounds { ro_length: usize, program_length: usize },
+  EntryOutOfBounds { entry: usize, program_length: usize },
+  ChecksumMismatch { expected: u32, actual: u32 },
+  SectionTableOutOfBounds { offset: usize, program_length: usize },
+  SectionOutOfBounds { kind: SectionKind, offset: usize, size: usize, program_length: usize },
+  UnknownSectionKind { kind: u8 },
+  HeapSectionTooLarge { kind: SectionKind, address: usize, size: usize, limit: usize },
+  MissingCodeSection,
+  InvalidDebugInfo,
+}
+
+impl fmt::Display for HeaderError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      HeaderError::TooShort { length, expected } => write!(
+        f,
+        "Program is {} bytes long, but a LUMI header needs at least {} bytes",
+        length, expected
+      ),
+      HeaderError::BadMagic => write!(f, "Not a LUMI program, the header magic is missing"),
+      HeaderError::UnsupportedFormatVersion { found, supported } => write!(
+        f,
+        "Binary format version {} is not supported, this VM reads version {}",
+        found, supported
+      ),
+      HeaderError::UnsupportedIsaVersion { found, supported } => write!(
+        f,
+        "Program targets ISA version {}, this VM supports up to version {}",
+        found, supported
+      ),
+      HeaderError::ReadOnlySectionOutOfBounds { ro_length, program_length } => write!(
+        f,
+        "Read-only section of {} bytes does not fit in a program of {} bytes",
+        ro_length, program_length
+      ),
+      HeaderError::EntryOutOfBounds { entry, program_length } => write!(
+        f,
+        "Entry point 0x{:x} is outside of the code in a program of {} bytes",
+        entry, program_length
+      ),
+      HeaderError::ChecksumMismatch { expected, actual } => write!(
+        f,
+        "Body checksum mismatch, header says 0x{:08x} but body is 0x{:08x}",
+        expected, actual
+      ),
+      HeaderError::SectionTableOutOfBounds { offset, program_length } => write!(
+        f,
+        "Section table at 0x{:x} does not fit in a program of {} bytes",
+        offset, program_length
+      ),
+      HeaderError::SectionOutOfBounds { kind, offset, size, program_length } => write!(
+        f,
+        "Section {} of {} bytes at 0x{:x} does not fit in a program of {} bytes",
+        kind.name(), size, offset, program_length
+      ),
+      HeaderError::UnknownSectionKind { kind } => write!(f, "Unknown section kind {}", kind),
+      HeaderError::HeapSectionTooLarge { kind, address, size, limit } => write!(
+        f,
+        "Section {} of {} bytes at heap 0x{:x} extends past the {} byte heap limit",
+        kind.name(), size, address, limit
+      ),
+      HeaderError::MissingCodeSection => write!(f, "Program has no code section"),
+      HeaderError::InvalidDebugInfo => write!(f, "Debug info section is malformed"),
+    }
+  }
+}
+
+impl Error for HeaderError {}
+
+/// The fixed size header at the start of every LUMI binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LumiHeader {
+  pub format_version: u16,
+  pub isa_version: u16,
+  /// Offset of the first instruction to execute.
+  pub entry_offset: u32,
+  /// Offset of the section table, `0` when the binary has none and the code runs to the end.
+  pub section_table_offset: u32,
+  pub flags: u32,
+  /// CRC32 of everything after the header.
+  pub body_crc32: u32,
+  /// Length of the read-only section that directly follows the header.
+  pub ro_length: u32,
+}
+
+impl LumiHeader {
+  /// Header for the current format and ISA, entering at the first byte after the read-only section.
+  pub fn new(read_only_data_length: usize) -> Self {
+    LumiHeader {
+      format_version: LUMI_FORMAT_VERSION,
+      isa_version: LUMI_ISA_VERSION,
+      entry_offset: code_start_offset(read_only_data_length) as u32,
+      section_table_offset: 0,
+      flags: 0,
+      body_crc32: 0,
+      ro_length: read_only_data_length as u32,
+    }
+  }
+
+  pub fn has_debug_info(&self) -> bool {
+    self.flags & FLAG_DEBUG_INFO != 0
+  }
+
+  /// Serialize the header. Unused bytes are reserved and written as zero.
+  pub fn write(&self) -> Vec<u8> {
+    let mut header = vec![0u8; LUMI_HEADER_SIZE];
+    header[..4].copy_from_slice(&LUMI_HEADER_PREFIX);
+    LittleEndian::write_u16(&mut header[FORMAT_VERSION..], self.format_version);
+    LittleEndian::write_u16(&mut header[ISA_VERSION..], self.isa_version);
+    LittleEndian::write_u32(&mut header[ENTRY_OFFSET..], self.entry_offset);
+    LittleEndian::write_u32(&mut header[SECTION_TABLE_OFFSET..], self.section_table_offset);
+    LittleEndian::write_u32(&mut header[FLAGS..], self.flags);
+    LittleEndian::write_u32(&mut header[BODY_CRC32..], self.body_crc32);
+    LittleEndian::write_u32(&mut header[RO_LENGTH..], self.ro_length);
+    debug!("Header: {:?}", self);
+    header
+  }
+
+  /// Compute the body checksum and return the complete binary: header followed by `body`.
+  pub fn wrap(mut self, body: &[u8]) -> Vec<u8> {
+    self.body_crc32 = crc32fast::hash(body);
+    let mut program = self.write();
+    program.extend_from_slice(body);
+    program
+  }
+
+  /// Read the header fields from the start of `program`. Only the magic and size are checked,
+  /// use `validate` before executing anything.
+  pub fn parse(program: &[u8]) -> Result<Self, HeaderError> {
+    if program.len() < LUMI_HEADER_SIZE {
+      return Err(HeaderError::TooShort { length: program.len(), expected: LUMI_HEADER_SIZE });
+    }
+    if !verify_header(program) {
+      return Err(HeaderError::BadMagic);
+    }
+
+    Ok(LumiHeader {
+      format_version: LittleEndian::read_u16(&program[FORMAT_VERSION..]),
+      isa_version: LittleEndian::read_u16(&program[ISA_VERSION..]),
+      entry_offset: LittleEndian::read_u32(&program[ENTRY_OFFSET..]),
+      section_table_offset: LittleEndian::read_u32(&program[SECTION_TABLE_OFFSET..]),
+      flags: LittleEndian::read_u32(&program[FLAGS..]),
+      body_crc32: LittleEndian::read_u32(&program[BODY_CRC32..]),
+      ro_length: LittleEndian::read_u32(&program[RO_LENGTH..]),
+    })
+  }
+
+  /// Check that `program` can be executed by this VM: versions, section bounds,
+  /// entry point and body checksum.
+  pub fn validate(&self, program: &[u8]) -> Result<(), HeaderError> {
+    if self.format_version != LUMI_FORMAT_VERSION {
+      return Err(HeaderError::UnsupportedFormatVersion {
+        found: self.format_version,
+        supported: LUMI_FORMAT_VERSION,
+      });
+    }
+    if self.isa_version > LUMI_ISA_VERSION {
+      return Err(HeaderError::UnsupportedIsaVersion {
+        found: self.isa_version,
+        supported: LUMI_ISA_VERSION,
+      });
+    }
+
+    let code_start = code_start_offset(self.ro_length as usize);
+    if code_start > program.len() {
+      return Err(HeaderError::ReadOnlySectionOutOfBounds {
+        ro_length: self.ro_length as usize,
+        program_length: program.len(),
+      });
+    }
+
+    let sections = self.sections(program)?;
+    let code = sections.get(SectionKind::Code).ok_or(HeaderError::MissingCodeSection)?.range();
+    let entry = self.entry_offset as usize;
+    if entry < code.start || entry > code.end {
+      return Err(HeaderError::EntryOutOfBounds { entry, program_length: program.len() });
+    }
+
+    let actual = crc32fast::hash(&program[LUMI_HEADER_SIZE..]);
+    if actual != self.body_crc32 {
+      return Err(HeaderError::ChecksumMismatch { expected: self.body_crc32, actual });
+    }
+    Ok(())
+  }
+
+  /// The sections of `program`, read from its section table or derived from the read-only
+  /// length when it has none.
+  pub fn sections(&self, program: &[u8]) -> Result<SectionTable, HeaderError> {
+    match self.section_table_offset {
+      0 => Ok(SectionTable::implicit(self.ro_length as usize, program.len())),
+      offset => SectionTable::parse(program, offset as usize),
+    }
+  }
+}
+
+/// Offset of the first instruction in a program with a read-only section of the given length:
+/// the header, the 4-byte read-only length and the read-only data itself.
+pub fn code_start_offset(read_only_data_length: usize) -> usize {
+  LUMI_HEADER_SIZE + read_only_data_length
 }
 
 /// Verify the header of a LUMI program.
-pub fn verify_header(program: &Vec<u8>) -> bool {
-  if program[0..4] != LUMI_HEADER_PREFIX {
-    return false;
+pub fn verify_header(program: &[u8]) -> bool {
+  program.starts_with(&LUMI_HEADER_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sections::Section;
+
+  #[test]
+  fn test_code_start_offset_matches_header_layout() {
+    for ro_length in [0, 1, 17] {
+      let body = vec![0u8; ro_length];
+      assert_eq!(code_start_offset(ro_length), LumiHeader::new(ro_length).wrap(&body).len());
+    }
   }
 
-  true
+  #[test]
+  fn test_header_write_parse_round_trip() {
+    let header = LumiHeader {
+      format_version: LUMI_FORMAT_VERSION,
+      isa_version: LUMI_ISA_VERSION,
+      entry_offset: 0x1234,
+      section_table_offset: 0x5678,
+      flags: FLAG_DEBUG_INFO,
+      body_crc32: 0xdeadbeef,
+      ro_length: 42,
+    };
+    let bytes = header.write();
+    assert_eq!(bytes.len(), LUMI_HEADER_SIZE);
+    assert_eq!(LumiHeader::parse(&bytes), Ok(header));
+    assert!(header.has_debug_info());
+  }
+
+  #[test]
+  fn test_validate_accepts_wrapped_program() {
+    let program = LumiHeader::new(3).wrap(&[b'h', b'i', 0, 0]);
+    let header = LumiHeader::parse(&program).unwrap();
+    assert_eq!(header.entry_offset as usize, code_start_offset(3));
+    assert_eq!(header.validate(&program), Ok(()));
+  }
+
+  #[test]
+  fn test_parse_rejects_short_and_foreign_input() {
+    assert_eq!(
+      LumiHeader::parse(b"LUMI"),
+      Err(HeaderError::TooShort { length: 4, expected: LUMI_HEADER_SIZE })
+    );
+    let mut program = LumiHeader::new(0).wrap(&[]);
+    program[0] = b'X';
+    assert_eq!(LumiHeader::parse(&program), Err(HeaderError::BadMagic));
+  }
+
+  #[test]
+  fn test_validate_rejects_incompatible_versions() {
+    let mut header = LumiHeader::new(0);
+    header.format_version = LUMI_FORMAT_VERSION + 1;
+    let program = header.wrap(&[]);
+    assert_eq!(
+      LumiHeader::parse(&program).unwrap().validate(&program),
+      Err(HeaderError::UnsupportedFormatVersion { found: LUMI_FORMAT_VERSION + 1, supported: LUMI_FORMAT_VERSION })
+    );
+
+    let mut header = LumiHeader::new(0);
+    header.isa_version = LUMI_ISA_VERSION + 1;
+    let program = header.wrap(&[]);
+    assert_eq!(
+      LumiHeader::parse(&program).unwrap().validate(&program),
+      Err(HeaderError::UnsupportedIsaVersion { found: LUMI_ISA_VERSION + 1, supported: LUMI_ISA_VERSION })
+    );
+  }
+
+  #[test]
+  fn test_validate_rejects_legacy_padded_header() {
+    let mut program = LUMI_HEADER_PREFIX.to_vec();
+    program.resize(LUMI_HEADER_LENGTH + 1, 0x11);
+    program.extend_from_slice(&[0, 0, 0, 0]);
+    let header = LumiHeader::parse(&program).unwrap();
+    assert_eq!(
+      header.validate(&program),
+      Err(HeaderError::UnsupportedFormatVersion { found: 0x1111, supported: LUMI_FORMAT_VERSION })
+    );
+  }
+
+  #[test]
+  fn test_validate_rejects_bad_bounds_and_checksum() {
+    let program = LumiHeader::new(50).wrap(&[0; 10]);
+    assert_eq!(
+      LumiHeader::parse(&program).unwrap().validate(&program),
+      Err(HeaderError::ReadOnlySectionOutOfBounds { ro_length: 50, program_length: program.len() })
+    );
+
+    let mut header = LumiHeader::new(0);
+    header.entry_offset = 3;
+    let program = header.wrap(&[]);
+    assert_eq!(
+      LumiHeader::parse(&program).unwrap().validate(&program),
+      Err(HeaderError::EntryOutOfBounds { entry: 3, program_length: LUMI_HEADER_SIZE })
+    );
+
+    let mut program = LumiHeader::new(0).wrap(&[1, 2, 3]);
+    *program.last_mut().unwrap() = 4;
+    assert_eq!(
+      LumiHeader::parse(&program).unwrap().validate(&program),
+      Err(HeaderError::ChecksumMismatch { expected: crc32fast::hash(&[1, 2, 3]), actual: crc32fast::hash(&[1, 2, 4]) })
+    );
+  }
+
+  #[test]
+  fn test_validate_checks_entry_against_section_table() {
+    let mut table = SectionTable::new();
+    table.push(Section::new(SectionKind::Code, LUMI_HEADER_SIZE, 1));
+    table.push(Section::new(SectionKind::Data, LUMI_HEADER_SIZE + 1, 2));
+    let mut body = vec![0, 7, 7];
+    body.extend(table.write());
+
+    let mut header = LumiHeader::new(0);
+    header.section_table_offset = (LUMI_HEADER_SIZE + 3) as u32;
+    let program = header.wrap(&body);
+    assert_eq!(LumiHeader::parse(&program).unwrap().validate(&program), Ok(()));
+
+    header.entry_offset = (LUMI_HEADER_SIZE + 2) as u32;
+    let program = header.wrap(&body);
+    assert_eq!(
+      LumiHeader::parse(&program).unwrap().validate(&program),
+      Err(HeaderError::EntryOutOfBounds { entry: LUMI_HEADER_SIZE + 2, program_length: program.len() })
+    );
+
+    let mut header = LumiHeader::new(0);
+    header.section_table_offset = LUMI_HEADER_SIZE as u32;
+    let program = header.wrap(&SectionTable::new().write());
+    assert_eq!(LumiHeader::parse(&program).unwrap().validate(&program), Err(HeaderError::MissingCodeSection));
+  }
 }
@@ -69,6 +69,8 @@ pub enum OperandType {
 
 pub struct OpcodeMetadata {
   pub operand_types: [OperandType; 3],
+  /// Encoded size in bytes of each operand, `0` for `OperandType::Empty`.
+  pub operand_widths: [usize; 3],
   pub description: &'static str,
   pub str_symbol: &'static str,
   pub bytecode: u8,
@@ -82,10 +84,11 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
   (Opcode::LOAD, OpcodeMetadata {
     operand_types: [
       OperandType::Register,
-      OperandType::Address,
+      OperandType::IntegerImmediate,
       OperandType::Empty,
     ],
-    description: "Loads a value from memory into a register, use: LOAD $<register> @<value>",
+    operand_widths: [1, 2, 0],
+    description: "Loads a 16-bit signed integer immediate into a register, use: LOAD $<register> #<value>",
     str_symbol: "LOAD",
     bytecode: 0,
   }),
@@ -95,6 +98,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Register,
     ],
+    operand_widths: [1, 1, 1],
     description: "Adds 2 registers together and saves in another register, use: ADD $<register> $<register> $<register>",
     str_symbol: "ADD",
     bytecode: 1,
@@ -105,6 +109,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Register,
     ],
+    operand_widths: [1, 1, 1],
     description: "Subtracts 2 registers together and saves in another register, use: SUB $<register> $<register> $<register>",
     str_symbol: "SUB",
     bytecode: 2,
@@ -115,6 +120,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Register,
     ],
+    operand_widths: [1, 1, 1],
     description: "Multiplies 2 registers together and saves in another register, use: MUL $<register> $<register> $<register>",
     str_symbol: "MUL",
     bytecode: 3,
@@ -125,12 +131,14 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Register,
     ],
+    operand_widths: [1, 1, 1],
     description: "Divides 2 registers together and saves in another register, use: DIV $<register> $<register> $<register>",
     str_symbol: "DIV",
     bytecode: 4,
   }),
   (Opcode::HLT, OpcodeMetadata {
     operand_types: [OperandType::Empty, OperandType::Empty, OperandType::Empty],
+    operand_widths: [0, 0, 0],
     description: "Halts the execution, use: HLT",
     str_symbol: "HLT",
     bytecode: 5,
@@ -141,6 +149,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Jump to a memory address stored in a register, use: JMP $<register>",
     str_symbol: "JMP",
     bytecode: 6,
@@ -151,6 +160,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Jump forward by offset stored in a register, use: JMPF $<register>",
     str_symbol: "JMPF",
     bytecode: 7,
@@ -161,6 +171,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Jump backward by offset stored in a register, use: JMPB $<register>",
     str_symbol: "JMPB",
     bytecode: 8,
@@ -171,6 +182,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 registers if they're equal, sets the equal_flag to true if they are, use: EQ $<register> $<register>",
     str_symbol: "EQ",
     bytecode: 9,
@@ -181,6 +193,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 registers if they're not equal, sets the equal_flag to true if the registers are not equal, use: NEQ $<register> $<register>",
     str_symbol: "NEQ",
     bytecode: 10,
@@ -191,6 +204,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 registers if register_1 is greater than register_2, sets the equal_flag to true if the registers are not equal, use: GT $<register> $<register>",
     str_symbol: "GT",
     bytecode: 11,
@@ -201,6 +215,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 registers if register_1 is less than register_2, sets the equal_flag to true if the registers are not equal, use: LT $<register> $<register>",
     str_symbol: "LT",
     bytecode: 12,
@@ -211,6 +226,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 registers if register_1 is greater than or equal to register_2, sets the equal_flag to true if the registers are not equal, use: GTE $<register> $<register>",
     str_symbol: "GTE",
     bytecode: 13,
@@ -221,6 +237,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 registers if register_1 is less than or equal to register_2, sets the equal_flag to true if the registers are not equal, use: LTE $<register> $<register>",
     str_symbol: "LTE",
     bytecode: 14,
@@ -231,12 +248,14 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Conditional jump if equal_flag is true to a memory address defined in register, use: JMPE $<register>",
     str_symbol: "JMPE",
     bytecode: 15,
   }),
   (Opcode::DJMPE, OpcodeMetadata {
     operand_types: [OperandType::Address, OperandType::Empty, OperandType::Empty],
+    operand_widths: [4, 0, 0],
     description: "Conditional direct jump if equal_flag is true to a memory address, use: DJMPE #<integer>",
     str_symbol: "DJMPE",
     bytecode: 16,
@@ -247,6 +266,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Allocate memory to the heap with size defined in register, use: ALOC $<register>",
     str_symbol: "ALOC",
     bytecode: 17,
@@ -257,6 +277,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Increments a register by 1, use: INC $<register>",
     str_symbol: "INC",
     bytecode: 18,
@@ -267,18 +288,21 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Decrements a register by 1, use: DEC $<register>",
     str_symbol: "DEC",
     bytecode: 19,
   }),
   (Opcode::NOP, OpcodeMetadata {
     operand_types: [OperandType::Empty, OperandType::Empty, OperandType::Empty],
+    operand_widths: [0, 0, 0],
     description: "Performs no operation, use: NOP",
     str_symbol: "NOP",
     bytecode: 20,
   }),
   (Opcode::PRTS, OpcodeMetadata {
     operand_types: [OperandType::Address, OperandType::Empty, OperandType::Empty],
+    operand_widths: [4, 0, 0],
     description: "Print string from heap until a null terminator is reached, heap offset address provided as operand, use: PRTS @label",
     str_symbol: "PRTS",
     bytecode: 21,
@@ -289,6 +313,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatImmediate,
       OperandType::Empty,
     ],
+    operand_widths: [1, 8, 0],
     description: "Loads a float value to a float register, use: LOADF64 $<float_register> #1.2345",
     str_symbol: "LOADF64",
     bytecode: 22,
@@ -299,6 +324,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::FloatRegister,
     ],
+    operand_widths: [1, 1, 1],
     description: "Adds 2 float registers together and saves in another float register, use: ADDF64 $<register> $<register> $<register>",
     str_symbol: "ADDF64",
     bytecode: 23,
@@ -309,6 +335,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::FloatRegister,
     ],
+    operand_widths: [1, 1, 1],
     description: "Subtracts 2 float registers together and saves in another float register, use: SUBF64 $<register> $<register> $<register>",
     str_symbol: "SUBF64",
     bytecode: 24,
@@ -319,6 +346,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::FloatRegister,
     ],
+    operand_widths: [1, 1, 1],
     description: "Multiplies 2 float registers together and saves in another float register, use: MULF64 $<register> $<register> $<register>",
     str_symbol: "MULF64",
     bytecode: 25,
@@ -329,6 +357,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::FloatRegister,
     ],
+    operand_widths: [1, 1, 1],
     description: "Divides 2 float registers together and saves in another float register, use: DIVF64 $<register> $<register> $<register>",
     str_symbol: "DIVF64",
     bytecode: 26,
@@ -339,6 +368,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 float registers if they're equal, sets the equal_flag to true if they are, use: EQF64 $<register> $<register>",
     str_symbol: "EQF64",
     bytecode: 27,
@@ -349,6 +379,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 float registers if they're not equal, sets the equal_flag to true if they are, use: NEQF64 $<register> $<register>",
     str_symbol: "NEQF64",
     bytecode: 28,
@@ -359,6 +390,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 float registers if register_1 is greater than register_2, sets the equal_flag to true if they are, use: GTF64 $<register> $<register>",
     str_symbol: "GTF64",
     bytecode: 29,
@@ -369,6 +401,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 float registers if register_1 is greater than or equal to register_2, sets the equal_flag to true if they are, use: GTEF64 $<register> $<register>",
     str_symbol: "GTEF64",
     bytecode: 30,
@@ -379,6 +412,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 float registers if register_1 is less than register_2, sets the equal_flag to true if they are, use: LTF64 $<register> $<register>",
     str_symbol: "LTF64",
     bytecode: 31,
@@ -389,6 +423,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::FloatRegister,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Compare 2 float registers if register_1 is less than or equal to register_2, sets the equal_flag to true if they are, use: LTEF64 $<register> $<register>",
     str_symbol: "LTEF64",
     bytecode: 32,
@@ -399,6 +434,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::IntegerImmediate,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Shift register value left by integer value, use: SHL $<register> #<integer>",
     str_symbol: "SHL",
     bytecode: 33,
@@ -409,6 +445,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::IntegerImmediate,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Shift register value right by integer value, use: SHR $<register> #<integer>",
     str_symbol: "SHR",
     bytecode: 34,
@@ -419,6 +456,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Register,
     ],
+    operand_widths: [1, 1, 1],
     description: "Perform logical AND operation between register_1 and register_2, store output to register_3, use: AND $<register> $<register> $<register>",
     str_symbol: "AND",
     bytecode: 35,
@@ -429,6 +467,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Register,
     ],
+    operand_widths: [1, 1, 1],
     description: "Perform logical OR operation between register_1 and register_2, store output to register_3, use: OR $<register> $<register> $<register>",
     str_symbol: "OR",
     bytecode: 36,
@@ -439,6 +478,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Register,
     ],
+    operand_widths: [1, 1, 1],
     description: "Perform logical EXCLUSIVE OR operation between register_1 and register_2, store output to register_3, use: XOR $<register> $<register> $<register>",
     str_symbol: "XOR",
     bytecode: 37,
@@ -449,6 +489,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Perform logical NOT operation on register_1 and store the output to register_2, use: NOT $<register> $<register>",
     str_symbol: "NOT",
     bytecode: 38,
@@ -459,18 +500,21 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::IntegerImmediate,
       OperandType::IntegerImmediate,
     ],
+    operand_widths: [1, 1, 1],
     description: "Load upper immediate value to register, use: LUI $<register> #<integer> #<integer>",
     str_symbol: "LUI",
     bytecode: 39,
   }),
   (Opcode::CLOOP, OpcodeMetadata {
     operand_types: [OperandType::IntegerImmediate, OperandType::Empty, OperandType::Empty],
+    operand_widths: [4, 0, 0],
     description: "Create loop setting the loop_count to the value provided, use: CLOOP #<integer>",
     str_symbol: "CLOOP",
     bytecode: 40,
   }),
   (Opcode::LOOP, OpcodeMetadata {
     operand_types: [OperandType::Address, OperandType::Empty, OperandType::Empty],
+    operand_widths: [4, 0, 0],
     description: "Loop to provided address until loop_counter reaches 0, use: LOOP @<address>",
     str_symbol: "LOOP",
     bytecode: 41,
@@ -481,6 +525,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Loads heap memory at offset defined in register_1 and stores it to register_2, use: LOADM $<register> $<register>",
     str_symbol: "LOADM",
     bytecode: 42,
@@ -491,6 +536,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Register,
       OperandType::Empty,
     ],
+    operand_widths: [1, 1, 0],
     description: "Sets heap memory value at offset defined with register_1 with the value of register_2, use: SETM $<register> $<register>",
     str_symbol: "SETM",
     bytecode: 43,
@@ -501,6 +547,7 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Pushes registers value to stack memory, use: PUSH $<register>",
     str_symbol: "PUSH",
     bytecode: 44,
@@ -511,30 +558,35 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::Empty,
       OperandType::Empty,
     ],
+    operand_widths: [1, 0, 0],
     description: "Pops registers value to stack memory, use: POP $<register>",
     str_symbol: "POP",
     bytecode: 45,
   }),
   (Opcode::CALL, OpcodeMetadata {
     operand_types: [OperandType::Address, OperandType::Empty, OperandType::Empty],
+    operand_widths: [4, 0, 0],
     description: "Calls a subroutine and creates a return destination, gets the address destination to jump to, pushes the return address to stack, use: CALL @<address>",
     str_symbol: "CALL",
     bytecode: 46,
   }),
   (Opcode::RET, OpcodeMetadata {
     operand_types: [OperandType::Empty, OperandType::Empty, OperandType::Empty],
+    operand_widths: [0, 0, 0],
     description: "Returns from a subroutine, pops the return address, use: RET",
     str_symbol: "RET",
     bytecode: 47,
   }),
   (Opcode::DJMP, OpcodeMetadata {
     operand_types: [OperandType::Address, OperandType::Empty, OperandType::Empty],
+    operand_widths: [4, 0, 0],
     description: "Direct jump to a memory address, use DJMP @<address>",
     str_symbol: "DJMP",
     bytecode: 48,
   }),
   (Opcode::BKPT, OpcodeMetadata {
     operand_types: [OperandType::Empty, OperandType::Empty, OperandType::Empty],
+    operand_widths: [0, 0, 0],
     description: "Creates a breakpoint in the program for debugging, use: BKPT",
     str_symbol: "BKPT",
     bytecode: 49,
@@ -545,12 +597,14 @@ static OPCODE_METADATA: &'static [(Opcode, OpcodeMetadata)] = &[
       OperandType::IntegerImmediate,
       OperandType::Empty,
     ],
+    operand_widths: [1, 4, 0],
     description: "Loads an integer immediate into a register, use: LOADI $<register> #<value>",
     str_symbol: "LOADI",
     bytecode: 50,
   }),
   (Opcode::IGL, OpcodeMetadata {
     operand_types: [OperandType::Empty, OperandType::Empty, OperandType::Empty],
+    operand_widths: [0, 0, 0],
     description: "Invalid opcode, should never be used directly, use: IGL",
     str_symbol: "IGL",
     bytecode: 100,
@@ -584,6 +638,11 @@ impl Opcode {
     STR_TO_OPCODE_MAP.get(s.to_uppercase().as_str()).copied()
   }
 
+  /// Iterate over every opcode defined in the metadata table.
+  pub fn all() -> impl Iterator<Item = Opcode> {
+    OPCODE_METADATA.iter().map(|(opcode, _)| *opcode)
+  }
+
   pub fn metadata(opcode: Opcode) -> Option<&'static OpcodeMetadata> {
     OPCODE_METADATA
       .iter()
@@ -4,12 +4,21 @@ extern crate pest_derive;
 
 
 pub mod instruction;
+pub mod encoding;
 pub mod header_utils;
+pub mod sections;
+pub mod debug_info;
+pub mod expressions;
+pub mod object;
+pub mod linker;
 pub mod assembler;
 mod symbols;
-mod assembler_errors;
-mod file_assembler;
-mod file_disassembler;
+pub mod assembler_errors;
+pub mod diagnostics;
+pub mod listing;
+pub mod file_assembler;
+pub mod file_disassembler;
+pub mod cfg;
 mod parsers;
 
 pub use assembler::Assembler;
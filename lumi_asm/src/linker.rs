@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use log::debug;
+use crate::debug_info::{DebugInfo, DebugSymbol, DebugSymbolKind, LineEntry};
+use crate::header_utils::{code_start_offset, LumiHeader, FLAG_DEBUG_INFO};
+use crate::object::ObjectFile;
+use crate::sections::{Section, SectionKind, SectionTable};
+
+/// The contents of an executable before it is written out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecutableImage {
+  pub ro: Vec<u8>,
+  pub code: Vec<u8>,
+  pub data: Vec<u8>,
+  pub bss_size: usize,
+  pub debug_info: Option<DebugInfo>,
+  /// Offset of the first instruction to execute from the start of the code.
+  pub entry: usize,
+}
+
+impl ExecutableImage {
+  /// Lay out the binary: header, `.rodata`, code, `.data`, `.debug` and the section table.
+  pub fn write(&self) -> Vec<u8> {
+    let code_start = code_start_offset(self.ro.len());
+    let data_start = code_start + self.code.len();
+    let debug_start = data_start + self.data.len();
+    let debug = self.debug_info.as_ref().map(DebugInfo::write).unwrap_or_default();
+
+    let mut sections = SectionTable::new();
+    sections.push(Section::new(SectionKind::ReadOnly, code_start_offset(0), self.ro.len()));
+    sections.push(Section::new(SectionKind::Code, code_start, self.code.len()));
+    if !self.data.is_empty() {
+      sections.push(Section::new(SectionKind::Data, data_start, self.data.len()));
+    }
+    if self.bss_size > 0 {
+      sections.push(Section::new(SectionKind::Bss, 0, self.bss_size).at_address(self.data.len()));
+    }
+    if self.debug_info.is_some() {
+      sections.push(Section::new(SectionKind::Debug, debug_start, debug.len()));
+    }
+
+    let mut header = LumiHeader::new(self.ro.len());
+    header.entry_offset = (code_start + self.entry) as u32;
+    header.section_table_offset = (debug_start + debug.len()) as u32;
+    if self.debug_info.is_some() {
+      header.flags |= FLAG_DEBUG_INFO;
+    }
+
+    let mut body = Vec::with_capacity(header.section_table_offset as usize);
+    body.extend_from_slice(&self.ro);
+    body.extend_from_slice(&self.code);
+    body.extend_from_slice(&self.data);
+    body.extend(debug);
+    body.extend(sections.write());
+    header.wrap(&body)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+  NoObjects,
+  DuplicateSymbol { name: String, first: String, second: String },
+  UndefinedSymbol { name: String, object: String },
+  RelocationOutOfRange { symbol: String, value: i64, object: String },
+  UndefinedEntry { name: String },
+}
+
+impl fmt::Display for LinkError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      LinkError::NoObjects => write!(f, "No object files to link"),
+      LinkError::DuplicateSymbol { name, first, second } => {
+        write!(f, "Symbol `{}` is defined in both {} and {}", name, first, second)
+      }
+      LinkError::UndefinedSymbol { name, object } => {
+        write!(f, "Undefined symbol `{}` referenced in {}", name, object)
+      }
+      LinkError::RelocationOutOfRange { symbol, value, object } => write!(
+        f,
+        "Value {} of `{}` does not fit in the operand that references it in {}",
+        value, symbol, object
+      ),
+      LinkError::UndefinedEntry { name } => write!(f, "Entry point `{}` is not a global code label", name),
+    }
+  }
+}
+
+impl Error for LinkError {}
+
+/// Combines object files into an executable. Sections are concatenated in the order the
+/// objects were added, and execution starts at the first object's code unless an entry
+/// symbol is given.
+#[derive(Debug, Default)]
+pub struct Linker {
+  objects: Vec<ObjectFile>,
+  entry: Option<String>,
+}
+
+/// Where each object's sections start in the linked executable.
+struct ObjectLayout {
+  ro: usize,
+  code: usize,
+  data: usize,
+  bss: usize,
+}
+
+impl Linker {
+  pub fn new() -> Self {
+    Linker::default()
+  }
+
+  pub fn object(mut self, object: ObjectFile) -> Self {
+    self.objects.push(object);
+    self
+  }
+
+  /// Start execution at the global code label `symbol`.
+  pub fn entry(mut self, symbol: &str) -> Self {
+    self.entry = Some(symbol.to_string());
+    self
+  }
+
+  pub fn link(&self) -> Result<Vec<u8>, Vec<LinkError>> {
+    self.link_image().map(|image| image.write())
+  }
+
+  /// Resolve every relocation and lay out the sections without writing the binary.
+  pub fn link_image(&self) -> Result<ExecutableImage, Vec<LinkError>> {
+    if self.objects.is_empty() {
+      return Err(vec![LinkError::NoObjects]);
+    }
+
+    let mut image = ExecutableImage::default();
+    let mut layouts = vec![];
+    for object in &self.objects {
+      layouts.push(ObjectLayout {
+        ro: image.ro.len(),
+        code: image.code.len(),
+        data: image.data.len(),
+        bss: image.bss_size,
+      });
+      image.ro.extend_from_slice(&object.ro);
+      image.code.extend_from_slice(&object.code);
+      image.data.extend_from_slice(&object.data);
+      image.bss_size += object.bss_size as usize;
+    }
+
+    let code_start = code_start_offset(image.ro.len());
+    let data_size = image.data.len();
+    let address = |index: usize, section: SectionKind, offset: u32| -> i64 {
+      let layout = &layouts[index];
+      let base = match section {
+        SectionKind::ReadOnly => layout.ro,
+        SectionKind::Code => code_start + layout.code,
+        SectionKind::Data => layout.data,
+        SectionKind::Bss => data_size + layout.bss,
+        SectionKind::Debug => 0,
+      };
+      (base + offset as usize) as i64
+    };
+
+    let mut errors = vec![];
+    let mut globals: HashMap<&str, (usize, i64)> = HashMap::new();
+    for (index, object) in self.objects.iter().enumerate() {
+      for symbol in object.exports() {
+        let value = address(index, symbol.section, symbol.offset);
+        if let Some((first, _)) = globals.insert(&symbol.name, (index, value)) {
+          errors.push(LinkError::DuplicateSymbol {
+            name: symbol.name.clone(),
+            first: self.objects[first].source.clone(),
+            second: object.source.clone(),
+          });
+        }
+      }
+    }
+
+    for (index, object) in self.objects.iter().enumerate() {
+      for relocation in &object.relocations {
+        // symbols of the referencing object win over globals from other objects
+        let value = match object.symbol(&relocation.symbol) {
+          Some(symbol) => address(index, symbol.section, symbol.offset),
+          None => match globals.get(relocation.symbol.as_str()) {
+            Some((_, value)) => *value,
+            None => {
+              let error = LinkError::UndefinedSymbol { name: relocation.symbol.clone(), object: object.source.clone() };
+              if !errors.contains(&error) {
+                errors.push(error);
+              }
+              continue;
+            }
+          },
+        } + relocation.addend as i64;
+
+        if !relocation.kind.fits(value) {
+          errors.push(LinkError::RelocationOutOfRange {
+            symbol: relocation.symbol.clone(),
+            value,
+            object: object.source.clone(),
+          });
+          continue;
+        }
+        let start = layouts[index].code + relocation.offset as usize;
+        relocation.kind.write(&mut image.code[start..start + relocation.kind.width()], value);
+        debug!("Relocated `{}` in {} at 0x{:x} to {}", relocation.symbol, object.source, start, value);
+      }
+    }
+
+    if let Some(name) = &self.entry {
+      let entry = self.objects.iter().enumerate().find_map(|(index, object)| {
+        object
+          .exports()
+          .find(|symbol| symbol.name == *name && symbol.section == SectionKind::Code)
+          .map(|symbol| layouts[index].code + symbol.offset as usize)
+      });
+      match entry {
+        Some(entry) => image.entry = entry,
+        None => errors.push(LinkError::UndefinedEntry { name: name.clone() }),
+      }
+    }
+
+    if !errors.is_empty() {
+      return Err(errors);
+    }
+
+    if self.objects.iter().any(|object| !object.lines.is_empty()) {
+      let mut debug_info = DebugInfo::default();
+      for (index, object) in self.objects.iter().enumerate() {
+        debug_info.files.push(object.source.clone());
+        debug_info.lines.extend(object.lines.iter().map(|entry| LineEntry {
+          offset: (code_start + layouts[index].code) as u32 + entry.offset,
+          file: index as u32,
+          ..*entry
+        }));
+        debug_info.symbols.extend(object.symbols.iter().map(|symbol| DebugSymbol {
+          name: symbol.name.clone(),
+          kind: match symbol.section {
+            SectionKind::Code => DebugSymbolKind::Label,
+            SectionKind::Data | SectionKind::Bss => DebugSymbolKind::Variable,
+            SectionKind::ReadOnly | SectionKind::Debug => DebugSymbolKind::Constant,
+          },
+          value: address(index, symbol.section, symbol.offset) as u32,
+        }));
+      }
+      image.debug_info = Some(debug_info);
+    }
+
+    Ok(image)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::encoding::{decode, Operand};
+  use crate::header_utils::LumiHeader;
+  use crate::object::ObjectSymbol;
+  use crate::Assembler;
+
+  const MAIN: &str = r"
+    .extern greet
+    .rodata
+    hello: .asciiz 'Hi'
+    .code
+    start: prts @hello
+    call @greet
+    hlt
+  ";
+
+  const GREET: &str = r"
+    .global greet
+    .rodata
+    bye: .asciiz 'Bye'
+    .data
+    count: .integer #1
+    .code
+    greet: prts @bye
+    load $0 @count
+    ret
+  ";
+
+  fn object(source: &str, name: &str) -> ObjectFile {
+    Assembler::new().assemble_object(source, name).unwrap()
+  }
+
+  #[test]
+  fn test_link_single_object_matches_assemble() {
+    let source = ".rodata\nmsg: .asciiz 'Hi'\n.data\nn: .integer #3\n.bss\nbuf: .space #4\n.code\nstart: prts @msg\nload $1 @buf\ndjmp @start\n";
+    let linked = Linker::new().object(object(source, "main.lumi")).link().unwrap();
+    assert_eq!(linked, Assembler::new().assemble(source).unwrap());
+  }
+
+  #[test]
+  fn test_link_resolves_symbols_across_objects() {
+    let main = object(MAIN, "main.lumi");
+    assert_eq!(main.imports, vec!["greet".to_string()]);
+    let greet = object(GREET, "greet.lumi");
+
+    let image = Linker::new().object(main.clone()).object(greet.clone()).link_image().unwrap();
+    assert_eq!(image.ro, b"Hi\0Bye\0");
+    assert_eq!(image.data, &[1, 0, 0, 0]);
+    assert_eq!(image.entry, 0);
+
+    let program = image.write();
+    let code_start = code_start_offset(image.ro.len());
+    let operand = |offset: usize| decode(&program, code_start + offset).unwrap().operands[0];
+    // prts @hello, call @greet, prts @bye
+    assert_eq!(operand(0), Operand::Address(0));
+    assert_eq!(operand(5), Operand::Address((code_start + main.code.len()) as u32));
+    assert_eq!(operand(main.code.len()), Operand::Address(3));
+
+    let image = Linker::new().object(main).object(greet).entry("greet").link_image().unwrap();
+    let header = LumiHeader::parse(&image.write()).unwrap();
+    assert_eq!(header.entry_offset as usize, code_start + image.entry);
+    assert_eq!(image.entry, 11);
+  }
+
+  #[test]
+  fn test_link_reports_errors() {
+    assert_eq!(Linker::new().link(), Err(vec![LinkError::NoObjects]));
+
+    let errors = Linker::new().object(object(MAIN, "main.lumi")).entry("missing").link().unwrap_err();
+    assert_eq!(
+      errors,
+      vec![
+        LinkError::UndefinedSymbol { name: "greet".to_string(), object: "main.lumi".to_string() },
+        LinkError::UndefinedEntry { name: "missing".to_string() },
+      ]
+    );
+
+    let errors = Linker::new()
+      .object(object(GREET, "a.lumi"))
+      .object(object(GREET, "b.lumi"))
+      .link()
+      .unwrap_err();
+    assert!(errors.contains(&LinkError::DuplicateSymbol {
+      name: "greet".to_string(),
+      first: "a.lumi".to_string(),
+      second: "b.lumi".to_string(),
+    }));
+  }
+
+  #[test]
+  fn test_link_checks_immediate_range() {
+    let mut far = object(".extern far\n.code\nload $0 @far\nhlt\n", "main.lumi");
+    far.bss_size = 40000;
+    far.symbols.push(ObjectSymbol { name: "far".to_string(), section: SectionKind::Bss, offset: 39000, global: true });
+    assert_eq!(
+      Linker::new().object(far).link(),
+      Err(vec![LinkError::RelocationOutOfRange { symbol: "far".to_string(), value: 39000, object: "main.lumi".to_string() }])
+    );
+  }
+}
@@ -0,0 +1,167 @@
+use std::fmt::Write;
+use crate::sections::SectionKind;
+
+/// Bytes shown per row, longer encodings continue on the following rows.
+const BYTES_PER_ROW: usize = 8;
+
+/// What one source line assembled to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingEntry {
+  pub file: u32,
+  pub line: usize,
+  pub section: SectionKind,
+  /// Offset of the first byte, with the same meaning as a label declared on the line.
+  pub address: u32,
+  pub bytes: Vec<u8>,
+  /// Bytes reserved in `.bss`, which has no contents.
+  pub reserved: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingSymbol {
+  pub name: String,
+  /// Section the symbol points into, `None` for `.equ` constants.
+  pub section: Option<SectionKind>,
+  pub value: i64,
+  pub size: Option<u32>,
+  pub global: bool,
+}
+
+/// A listing of an assembly: every source line next to its address and encoded bytes,
+/// followed by the symbol table and the layout of the read-only data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+  /// Name and text of each source file, indexed like `ListingEntry::file`.
+  pub files: Vec<(String, String)>,
+  pub entries: Vec<ListingEntry>,
+  pub symbols: Vec<ListingSymbol>,
+  pub ro: Vec<u8>,
+}
+
+impl Listing {
+  pub fn render(&self) -> String {
+    let mut output = String::new();
+    for (index, (name, text)) in self.files.iter().enumerate() {
+      let _ = writeln!(output, "; {}", name);
+      let _ = writeln!(output, "{:>5}  {:<16}  {:<23}  source", "line", "address", "bytes");
+      for (number, source) in text.lines().enumerate() {
+        let entries = self.entries.iter().filter(|entry| entry.file == index as u32 && entry.line == number + 1);
+        let mut rows = vec![];
+        for entry in entries {
+          let address = format!("{:<7} 0x{:06x}", entry.section.name(), entry.address);
+          if entry.bytes.is_empty() {
+            rows.push((address.clone(), format!("({} bytes reserved)", entry.reserved)));
+          }
+          for (chunk_index, chunk) in entry.bytes.chunks(BYTES_PER_ROW).enumerate() {
+            let address = if chunk_index == 0 { address.clone() } else { String::new() };
+            rows.push((address, hex(chunk)));
+          }
+        }
+        if rows.is_empty() {
+          rows.push((String::new(), String::new()));
+        }
+        for (row, (address, bytes)) in rows.iter().enumerate() {
+          let line = if row == 0 { (number + 1).to_string() } else { String::new() };
+          let source = if row == 0 { source } else { "" };
+          let text = format!("{:>5}  {:<16}  {:<23}  {}", line, address, bytes, source);
+          output.push_str(text.trim_end());
+          output.push('\n');
+        }
+      }
+      output.push('\n');
+    }
+
+    output.push_str("; symbols\n");
+    let _ = writeln!(output, "{:<24} {:<8} {:>10}  {:>6}", "name", "section", "value", "size");
+    for symbol in &self.symbols {
+      let (section, value) = match symbol.section {
+        Some(section) => (section.name(), format!("0x{:08x}", symbol.value)),
+        None => (".equ", symbol.value.to_string()),
+      };
+      let size = symbol.size.map_or("-".to_string(), |size| size.to_string());
+      let global = if symbol.global { "  global" } else { "" };
+      let _ = writeln!(output, "{:<24} {:<8} {:>10}  {:>6}{}", symbol.name, section, value, size, global);
+    }
+
+    output.push_str("\n; .rodata layout\n");
+    let mut ro_symbols: Vec<_> =
+      self.symbols.iter().filter(|symbol| symbol.section == Some(SectionKind::ReadOnly)).collect();
+    ro_symbols.sort_by_key(|symbol| symbol.value);
+    for symbol in ro_symbols {
+      let start = (symbol.value as usize).min(self.ro.len());
+      let end = (start + symbol.size.unwrap_or(0) as usize).min(self.ro.len());
+      let _ = writeln!(
+        output,
+        "0x{:06x}  {:>6}  {:<24} {}",
+        start,
+        end - start,
+        symbol.name,
+        preview(&self.ro[start..end])
+      );
+    }
+    let _ = writeln!(output, "; {} bytes", self.ro.len());
+    output
+  }
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+/// Strings as quoted text, anything else as its first bytes.
+fn preview(bytes: &[u8]) -> String {
+  match bytes.split_last() {
+    Some((0, text)) if text.iter().all(|byte| (32..127).contains(byte)) => {
+      format!("{:?}", String::from_utf8_lossy(text))
+    }
+    _ if bytes.len() > BYTES_PER_ROW => format!("{} ..", hex(&bytes[..BYTES_PER_ROW])),
+    _ => hex(bytes),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_render_listing() {
+    let listing = Listing {
+      files: vec![("main.lumi".to_string(), ".rodata\nmsg: .asciiz 'Hi'\n.code\nload $0 #1 ; one\n".to_string())],
+      entries: vec![
+        ListingEntry { file: 0, line: 2, section: SectionKind::ReadOnly, address: 0, bytes: b"Hi\0".to_vec(), reserved: 0 },
+        ListingEntry {
+          file: 0,
+          line: 4,
+          section: SectionKind::Code,
+          address: 0x48,
+          bytes: vec![0, 0, 1, 0, 0, 0, 0, 0, 9],
+          reserved: 0,
+        },
+      ],
+      symbols: vec![
+        ListingSymbol { name: "msg".to_string(), section: Some(SectionKind::ReadOnly), value: 0, size: Some(3), global: false },
+        ListingSymbol { name: "ONE".to_string(), section: None, value: 1, size: None, global: false },
+      ],
+      ro: b"Hi\0".to_vec(),
+    };
+    let expected = "\
+; main.lumi
+ line  address           bytes                    source
+    1                                             .rodata
+    2  .rodata 0x000000  48 69 00                 msg: .asciiz 'Hi'
+    3                                             .code
+    4  .code   0x000048  00 00 01 00 00 00 00 00  load $0 #1 ; one
+                         09
+
+; symbols
+name                     section       value    size
+msg                      .rodata  0x00000000       3
+ONE                      .equ              1       -
+
+; .rodata layout
+0x000000       3  msg                      \"Hi\"
+; 3 bytes
+";
+    assert_eq!(listing.render(), expected);
+  }
+}
@@ -0,0 +1,325 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::io::{Cursor, Read};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::debug_info::LineEntry;
+use crate::encoding::immediate_range;
+use crate::sections::SectionKind;
+
+/// Magic number for LUMI object files.
+pub const LUMI_OBJECT_PREFIX: [u8; 4] = [0x4C, 0x55, 0x4D, 0x4F];
+/// Version of the object file layout written by this assembler.
+pub const LUMI_OBJECT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectError {
+  BadMagic,
+  UnsupportedVersion { found: u16, supported: u16 },
+  /// The file ends in the middle of a record or holds an invalid value.
+  Malformed,
+}
+
+impl fmt::Display for ObjectError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      ObjectError::BadMagic => write!(f, "Not a LUMI object file, the magic is missing"),
+      ObjectError::UnsupportedVersion { found, supported } => write!(
+        f,
+        "Object file version {} is not supported, this linker reads version {}",
+        found, supported
+      ),
+      ObjectError::Malformed => write!(f, "Object file is truncated or malformed"),
+    }
+  }
+}
+
+impl Error for ObjectError {}
+
+/// A symbol defined by an object, at `offset` bytes into its `section`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSymbol {
+  pub name: String,
+  pub section: SectionKind,
+  pub offset: u32,
+  /// Visible to other objects when linking.
+  pub global: bool,
+}
+
+/// How a resolved symbol value is written into an instruction operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+  /// An unsigned 32-bit address operand.
+  Address,
+  /// An integer immediate of the given width in bytes.
+  Immediate { width: u8 },
+}
+
+impl RelocationKind {
+  pub fn width(&self) -> usize {
+    match self {
+      RelocationKind::Address => 4,
+      RelocationKind::Immediate { width } => *width as usize,
+    }
+  }
+
+  /// Whether `value` can be stored in the operand.
+  pub fn fits(&self, value: i64) -> bool {
+    match self {
+      RelocationKind::Address => (0..=u32::MAX as i64).contains(&value),
+      RelocationKind::Immediate { width } => immediate_range(*width as usize).contains(&value),
+    }
+  }
+
+  /// Write `value` into `field`, which must be `width()` bytes long.
+  pub fn write(&self, field: &mut [u8], value: i64) {
+    match self.width() {
+      1 => field[0] = value as u8,
+      2 => field.copy_from_slice(&(value as i16).to_le_bytes()),
+      _ => field.copy_from_slice(&(value as u32).to_le_bytes()),
+    }
+  }
+}
+
+/// A reference from the code section to a symbol, patched in by the linker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+  /// Offset of the operand field from the start of the object's code.
+  pub offset: u32,
+  pub kind: RelocationKind,
+  pub symbol: String,
+  pub addend: i32,
+}
+
+/// A relocatable unit produced by `Assembler::assemble_object`: section contents with
+/// symbol offsets relative to their own section, and unresolved symbol references.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectFile {
+  /// Source file the object was assembled from, used in link errors and debug info.
+  pub source: String,
+  pub ro: Vec<u8>,
+  pub code: Vec<u8>,
+  pub data: Vec<u8>,
+  pub bss_size: u32,
+  pub symbols: Vec<ObjectSymbol>,
+  /// Symbols referenced but not defined by this object.
+  pub imports: Vec<String>,
+  pub relocations: Vec<Relocation>,
+  /// Line table with offsets relative to the object's code, empty without debug info.
+  pub lines: Vec<LineEntry>,
+}
+
+impl ObjectFile {
+  pub fn symbol(&self, name: &str) -> Option<&ObjectSymbol> {
+    self.symbols.iter().find(|symbol| symbol.name == name)
+  }
+
+  /// Symbols other objects can link against.
+  pub fn exports(&self) -> impl Iterator<Item = &ObjectSymbol> {
+    self.symbols.iter().filter(|symbol| symbol.global)
+  }
+
+  pub fn write(&self) -> Vec<u8> {
+    let mut wtr = LUMI_OBJECT_PREFIX.to_vec();
+    wtr.write_u16::<LittleEndian>(LUMI_OBJECT_VERSION).unwrap();
+    write_string(&mut wtr, &self.source);
+    for section in [&self.ro, &self.code, &self.data] {
+      write_bytes(&mut wtr, section);
+    }
+    wtr.write_u32::<LittleEndian>(self.bss_size).unwrap();
+
+    wtr.write_u32::<LittleEndian>(self.symbols.len() as u32).unwrap();
+    for symbol in &self.symbols {
+      write_string(&mut wtr, &symbol.name);
+      wtr.push(symbol.section.into());
+      wtr.push(symbol.global as u8);
+      wtr.write_u32::<LittleEndian>(symbol.offset).unwrap();
+    }
+
+    wtr.write_u32::<LittleEndian>(self.imports.len() as u32).unwrap();
+    for import in &self.imports {
+      write_string(&mut wtr, import);
+    }
+
+    wtr.write_u32::<LittleEndian>(self.relocations.len() as u32).unwrap();
+    for relocation in &self.relocations {
+      wtr.write_u32::<LittleEndian>(relocation.offset).unwrap();
+      wtr.push(match relocation.kind {
+        RelocationKind::Address => 0,
+        RelocationKind::Immediate { width } => width,
+      });
+      write_string(&mut wtr, &relocation.symbol);
+      wtr.write_i32::<LittleEndian>(relocation.addend).unwrap();
+    }
+
+    wtr.write_u32::<LittleEndian>(self.lines.len() as u32).unwrap();
+    for entry in &self.lines {
+      for value in [entry.offset, entry.file, entry.line, entry.column] {
+        wtr.write_u32::<LittleEndian>(value).unwrap();
+      }
+    }
+    wtr
+  }
+
+  pub fn parse(bytes: &[u8]) -> Result<Self, ObjectError> {
+    if !bytes.starts_with(&LUMI_OBJECT_PREFIX) {
+      return Err(ObjectError::BadMagic);
+    }
+    let mut rdr = Cursor::new(bytes);
+    rdr.set_position(LUMI_OBJECT_PREFIX.len() as u64);
+    let version = rdr.read_u16::<LittleEndian>().map_err(|_| ObjectError::Malformed)?;
+    if version != LUMI_OBJECT_VERSION {
+      return Err(ObjectError::UnsupportedVersion { found: version, supported: LUMI_OBJECT_VERSION });
+    }
+
+    let mut object = ObjectFile {
+      source: read_string(&mut rdr)?,
+      ro: read_bytes(&mut rdr)?,
+      code: read_bytes(&mut rdr)?,
+      data: read_bytes(&mut rdr)?,
+      bss_size: read_u32(&mut rdr)?,
+      ..ObjectFile::default()
+    };
+
+    for _ in 0..read_u32(&mut rdr)? {
+      let name = read_string(&mut rdr)?;
+      let section = SectionKind::try_from(read_u8(&mut rdr)?).map_err(|_| ObjectError::Malformed)?;
+      let global = read_u8(&mut rdr)? != 0;
+      object.symbols.push(ObjectSymbol { name, section, offset: read_u32(&mut rdr)?, global });
+    }
+
+    for _ in 0..read_u32(&mut rdr)? {
+      object.imports.push(read_string(&mut rdr)?);
+    }
+
+    for _ in 0..read_u32(&mut rdr)? {
+      let offset = read_u32(&mut rdr)?;
+      let kind = match read_u8(&mut rdr)? {
+        0 => RelocationKind::Address,
+        width @ (1 | 2 | 4) => RelocationKind::Immediate { width },
+        _ => return Err(ObjectError::Malformed),
+      };
+      let symbol = read_string(&mut rdr)?;
+      let addend = rdr.read_i32::<LittleEndian>().map_err(|_| ObjectError::Malformed)?;
+      object.relocations.push(Relocation { offset, kind, symbol, addend });
+    }
+
+    for _ in 0..read_u32(&mut rdr)? {
+      object.lines.push(LineEntry {
+        offset: read_u32(&mut rdr)?,
+        file: read_u32(&mut rdr)?,
+        line: read_u32(&mut rdr)?,
+        column: read_u32(&mut rdr)?,
+      });
+    }
+
+    let in_bounds = object
+      .relocations
+      .iter()
+      .all(|relocation| relocation.offset as usize + relocation.kind.width() <= object.code.len());
+    if !in_bounds {
+      return Err(ObjectError::Malformed);
+    }
+    Ok(object)
+  }
+}
+
+fn write_bytes(wtr: &mut Vec<u8>, value: &[u8]) {
+  wtr.write_u32::<LittleEndian>(value.len() as u32).unwrap();
+  wtr.extend_from_slice(value);
+}
+
+fn write_string(wtr: &mut Vec<u8>, value: &str) {
+  write_bytes(wtr, value.as_bytes());
+}
+
+fn read_u8(rdr: &mut Cursor<&[u8]>) -> Result<u8, ObjectError> {
+  rdr.read_u8().map_err(|_| ObjectError::Malformed)
+}
+
+fn read_u32(rdr: &mut Cursor<&[u8]>) -> Result<u32, ObjectError> {
+  rdr.read_u32::<LittleEndian>().map_err(|_| ObjectError::Malformed)
+}
+
+fn read_bytes(rdr: &mut Cursor<&[u8]>) -> Result<Vec<u8>, ObjectError> {
+  let length = read_u32(rdr)? as usize;
+  let remaining = rdr.get_ref().len() - rdr.position() as usize;
+  if length > remaining {
+    return Err(ObjectError::Malformed);
+  }
+  let mut bytes = vec![0; length];
+  rdr.read_exact(&mut bytes).map_err(|_| ObjectError::Malformed)?;
+  Ok(bytes)
+}
+
+fn read_string(rdr: &mut Cursor<&[u8]>) -> Result<String, ObjectError> {
+  String::from_utf8(read_bytes(rdr)?).map_err(|_| ObjectError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn object() -> ObjectFile {
+    ObjectFile {
+      source: "lib.lumi".to_string(),
+      ro: b"Hi\0".to_vec(),
+      code: vec![0x02, 0, 0, 0, 0, 0],
+      data: vec![1, 0, 0, 0],
+      bss_size: 8,
+      symbols: vec![
+        ObjectSymbol { name: "print".to_string(), section: SectionKind::Code, offset: 0, global: true },
+        ObjectSymbol { name: "msg".to_string(), section: SectionKind::ReadOnly, offset: 0, global: false },
+      ],
+      imports: vec!["main".to_string()],
+      relocations: vec![Relocation {
+        offset: 1,
+        kind: RelocationKind::Address,
+        symbol: "main".to_string(),
+        addend: 0,
+      }],
+      lines: vec![LineEntry { offset: 0, file: 0, line: 3, column: 1 }],
+    }
+  }
+
+  #[test]
+  fn test_object_write_parse_round_trip() {
+    let object = object();
+    let parsed = ObjectFile::parse(&object.write()).unwrap();
+    assert_eq!(parsed, object);
+    assert_eq!(parsed.exports().map(|symbol| symbol.name.as_str()).collect::<Vec<_>>(), vec!["print"]);
+    assert_eq!(parsed.symbol("msg").unwrap().section, SectionKind::ReadOnly);
+  }
+
+  #[test]
+  fn test_object_parse_rejects_bad_input() {
+    assert_eq!(ObjectFile::parse(b"LUMI\x01\x00"), Err(ObjectError::BadMagic));
+
+    let mut bytes = object().write();
+    bytes[4] = 9;
+    assert_eq!(
+      ObjectFile::parse(&bytes),
+      Err(ObjectError::UnsupportedVersion { found: 9, supported: LUMI_OBJECT_VERSION })
+    );
+
+    let bytes = object().write();
+    assert_eq!(ObjectFile::parse(&bytes[..bytes.len() - 1]), Err(ObjectError::Malformed));
+
+    let mut object = object();
+    object.relocations[0].offset = 4;
+    assert_eq!(ObjectFile::parse(&object.write()), Err(ObjectError::Malformed));
+  }
+
+  #[test]
+  fn test_relocation_kind_ranges() {
+    assert!(RelocationKind::Address.fits(u32::MAX as i64));
+    assert!(!RelocationKind::Address.fits(-1));
+    assert!(RelocationKind::Immediate { width: 2 }.fits(i16::MAX as i64));
+    assert!(!RelocationKind::Immediate { width: 2 }.fits(40_000));
+    assert!(!RelocationKind::Immediate { width: 1 }.fits(256));
+
+    let mut field = [0u8; 2];
+    RelocationKind::Immediate { width: 2 }.write(&mut field, 300);
+    assert_eq!(field, 300i16.to_le_bytes());
+  }
+}
@@ -1,17 +1,21 @@
 use std::fmt;
 use std::fmt::Formatter;
-use byteorder::{LittleEndian, WriteBytesExt};
 use log::{debug, error, info, warn};
 use pest::iterators::Pair;
 use pest::Parser;
 use crate::assembler::{DirectiveType, Token};
-use crate::instruction::Opcode;
+use crate::encoding::{encode, immediate_range, instruction_length, Operand};
+use crate::expressions::Expression;
+use crate::instruction::{Opcode, OperandType};
+use crate::object::RelocationKind;
 use crate::assembler_errors::AssemblerError;
 use crate::parsers::lumi_asm_parser::{LumiAsmParser, Rule};
 use crate::symbols::SymbolTable;
 
 const MAX_I16: i32 = 32767;
 const MIN_I16: i32 = -32768;
+/// Highest register number, the VM has 32 integer and 32 float registers.
+const MAX_REGISTER: u8 = 31;
 
 #[derive(Debug, PartialEq)]
 pub struct AssemblerInstruction {
@@ -21,89 +25,240 @@ pub struct AssemblerInstruction {
   pub(crate) operand_3: Option<Token>,
   pub(crate) label: Option<Token>,
   pub(crate) directive: Option<Token>,
+  /// 1-based source position of the line, `0` when unknown.
+  pub(crate) line: usize,
+  pub(crate) column: usize,
+  /// Index of the source file the line came from, `0` for the file being assembled.
+  pub(crate) file: u32,
 }
 
 impl AssemblerInstruction {
   /// Converts this instruction into its binary representation.
+  /// Operand sizes come from the opcode metadata table, see `encoding::encode`.
   pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
-    let mut results = vec![];
-
-    // 1) Write the opcode as 1 byte
-    if let Some(Token::Op { code }) = &self.opcode {
-      let opcode_byte: u8 = (*code).into(); // from(Opcode)->u8
-      results.push(opcode_byte);
-    } else {
+    let code = match &self.opcode {
+      Some(Token::Op { code }) => *code,
       // Might be a directive line or label-only line => no bytes
-      return results;
+      _ => return vec![],
+    };
+
+    let metadata = match Opcode::metadata(code) {
+      Some(metadata) => metadata,
+      None => return vec![code.into()],
+    };
+
+    let tokens = [&self.operand_1, &self.operand_2, &self.operand_3];
+    let mut operands = [Operand::Empty; 3];
+    for (index, operand_type) in metadata.operand_types.iter().enumerate() {
+      operands[index] = Self::extract_operand(tokens[index].as_ref(), *operand_type, symbols);
     }
 
-    // 2) For each operand, push the correct number of bytes
-    for operand in [&self.operand_1, &self.operand_2, &self.operand_3] {
-      if let Some(token) = operand {
-        Self::extract_operand(token, &mut results, symbols);
+    encode(code, &operands)
+  }
+
+  /// Label operands of this instruction: the offset of the operand field from the start of
+  /// the instruction, how the label's value is encoded there, the label name and the constant
+  /// added to it by a `@label + n` expression.
+  pub fn label_references(&self, symbols: &SymbolTable) -> Vec<(usize, RelocationKind, String, i64)> {
+    let metadata = match &self.opcode {
+      Some(Token::Op { code }) => match Opcode::metadata(*code) {
+        Some(metadata) => metadata,
+        None => return vec![],
+      },
+      _ => return vec![],
+    };
+
+    let tokens = [&self.operand_1, &self.operand_2, &self.operand_3];
+    let mut references = vec![];
+    let mut offset = 1;
+    for (index, operand_type) in metadata.operand_types.iter().enumerate() {
+      let width = metadata.operand_widths[index];
+      let reference = match tokens[index] {
+        Some(Token::LabelUsage { name }) => Some((name.clone(), 0)),
+        Some(Token::Expression { expression }) => expression.relocation(symbols),
+        _ => None,
+      };
+      let kind = match operand_type {
+        OperandType::Address => Some(RelocationKind::Address),
+        OperandType::IntegerImmediate => Some(RelocationKind::Immediate { width: width as u8 }),
+        _ => None,
+      };
+      if let (Some((name, addend)), Some(kind)) = (reference, kind) {
+        references.push((offset, kind, name, addend));
       }
+      offset += width;
+    }
+    references
+  }
+
+  /// Check the operands against the opcode's metadata: their number and kind, that the
+  /// symbols they use are defined, that registers exist and that values fit their field.
+  pub fn validate(&self, symbols: &SymbolTable) -> Vec<AssemblerError> {
+    let metadata = match &self.opcode {
+      Some(Token::Op { code }) => match Opcode::metadata(*code) {
+        Some(metadata) => metadata,
+        None => return vec![AssemblerError::NonOpcodeInOpcodeField],
+      },
+      _ => return vec![],
+    };
+    let opcode = metadata.str_symbol.to_lowercase();
+
+    let tokens = [&self.operand_1, &self.operand_2, &self.operand_3];
+    let expected = metadata.operand_types.iter().filter(|operand_type| **operand_type != OperandType::Empty).count();
+    let found = tokens.iter().filter(|token| token.is_some()).count();
+    if found != expected {
+      return vec![AssemblerError::WrongOperandCount { opcode, expected, found, line: self.line }];
     }
 
-    // 3) No forced padding. Return exactly what's needed.
-    results
+    let mut errors = vec![];
+    for (index, operand_type) in metadata.operand_types.iter().enumerate() {
+      if let Some(token) = tokens[index] {
+        let width = metadata.operand_widths[index];
+        if let Err(error) = self.validate_operand(token, *operand_type, width, symbols) {
+          errors.push(match error {
+            // filled in here, where the opcode and position are known
+            AssemblerError::InvalidOperand { expected, found, .. } => AssemblerError::InvalidOperand {
+              opcode: opcode.clone(),
+              position: index + 1,
+              expected,
+              found,
+              line: self.line,
+            },
+            error => error,
+          });
+        }
+      }
+    }
+    errors
   }
-  // pub fn to_bytes(&self, symbol_table: &SymbolTable) -> Vec<u8> {
-  //   let mut results = vec![];
-  //   if let Some(ref token) = self.opcode {
-  //     match token {
-  //       Token::Op { code } => {
-  //         let b: u8 = (*code).into();
-  //         results.push(b);
-  //       }
-  //       _ => {
-  //         error!("Non-opcode token found in opcode field");
-  //       }
-  //     }
-  //   }
-  //   // Process operands 1, 2 and 3.
-  //   for operand in vec![&self.operand_1, &self.operand_2, &self.operand_3] {
-  //     if let Some(token) = operand {
-  //       AssemblerInstruction::extract_operand(token, &mut results, symbol_table);
-  //     }
-  //   }
-  //   // Pad the instruction bytes to at least 4 bytes.
-  //   // while results.len() < 4 {
-  //   //   results.push(0);
-  //   // }
-  //   results
-  // }
-
-  fn extract_operand(token: &Token, results: &mut Vec<u8>, symbols: &SymbolTable) {
-    match token {
-      Token::Register { reg_num } => {
-        results.push(*reg_num);
+
+  fn validate_operand(
+    &self,
+    token: &Token,
+    operand_type: OperandType,
+    width: usize,
+    symbols: &SymbolTable,
+  ) -> Result<(), AssemblerError> {
+    let value = match (operand_type, token) {
+      (OperandType::Register | OperandType::FloatRegister, Token::Register { reg_num }) => {
+        if *reg_num > MAX_REGISTER {
+          return Err(AssemblerError::InvalidRegister { register: *reg_num, line: self.line });
+        }
+        return Ok(());
+      }
+      (OperandType::FloatImmediate, Token::FloatOperand { .. } | Token::IntegerOperand { .. }) => return Ok(()),
+      (OperandType::IntegerImmediate | OperandType::Address, Token::IntegerOperand { value }) => *value as i64,
+      (OperandType::IntegerImmediate | OperandType::Address, Token::LabelUsage { name }) => {
+        match symbols.symbol_value(name) {
+          Some(value) => value as i64,
+          None => return Err(AssemblerError::UndefinedSymbol { symbol: name.clone() }),
+        }
+      }
+      (OperandType::IntegerImmediate | OperandType::Address, Token::Expression { expression }) => {
+        if let Some(name) = expression.referenced_symbols().into_iter().find(|name| !symbols.has_symbol(name)) {
+          return Err(AssemblerError::UndefinedSymbol { symbol: name.to_string() });
+        }
+        expression
+          .evaluate(symbols)
+          .map_err(|error| AssemblerError::InvalidExpression { error, line: self.line })?
       }
-      Token::IntegerOperand { value } => {
-        let mut wtr = vec![];
-        wtr.write_i32::<LittleEndian>(*value as i32).unwrap();
-        results.extend_from_slice(&wtr);
-        // results.push(wtr[0]);
-        // results.push(wtr[1]);
+      _ => {
+        return Err(AssemblerError::InvalidOperand {
+          opcode: String::new(),
+          position: 0,
+          expected: describe_operand_type(operand_type).to_string(),
+          found: describe_token(token),
+          line: self.line,
+        })
+      }
+    };
+
+    let shift = matches!(self.opcode, Some(Token::Op { code: Opcode::SHL | Opcode::SHR }));
+    let range = match operand_type {
+      OperandType::Address => 0..=u32::MAX as i64,
+      // the VM shifts an i32 and reads a count of 0 as 16
+      OperandType::IntegerImmediate if shift => 1..=31,
+      _ => immediate_range(width),
+    };
+    if !range.contains(&value) {
+      return Err(AssemblerError::ImmediateOutOfRange { value, min: *range.start(), max: *range.end(), line: self.line });
+    }
+    Ok(())
+  }
+
+  /// Expression operands that refer to labels in a way a linker cannot relocate, anything
+  /// other than `label ± constant`.
+  pub fn unrelocatable_expressions<'a>(&'a self, symbols: &SymbolTable) -> Vec<&'a Expression> {
+    [&self.operand_1, &self.operand_2, &self.operand_3]
+      .into_iter()
+      .filter_map(|token| match token {
+        Some(Token::Expression { expression })
+          if expression.uses_labels(symbols) && expression.relocation(symbols).is_none() =>
+        {
+          Some(expression)
+        }
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Names of the symbols the operands of this line refer to, as labels or in expressions.
+  pub fn referenced_symbols(&self) -> Vec<&str> {
+    let mut names = vec![];
+    for token in [&self.operand_1, &self.operand_2, &self.operand_3].into_iter().flatten() {
+      match token {
+        Token::LabelUsage { name } => names.push(name.as_str()),
+        Token::Expression { expression } => names.extend(expression.referenced_symbols()),
+        _ => {}
       }
-      Token::FloatOperand { value } => {
-        let mut wtr = vec![];
-        wtr.write_f32::<LittleEndian>(*value as f32).unwrap();
-        results.extend_from_slice(&wtr);
-        // results.push(wtr[0]);
-        // results.push(wtr[1]);
+    }
+    names
+  }
+
+  /// Number of bytes `to_bytes` will produce for this line, `0` for label-only and directive lines.
+  pub fn encoded_length(&self) -> usize {
+    match &self.opcode {
+      Some(Token::Op { code }) => instruction_length(*code),
+      _ => 0,
+    }
+  }
+
+  /// Converts an operand token into the operand kind the opcode expects at that position.
+  /// Missing or mismatched operands are encoded as zero so the instruction keeps its size.
+  fn extract_operand(token: Option<&Token>, operand_type: OperandType, symbols: &SymbolTable) -> Operand {
+    let value = match token {
+      Some(Token::Register { reg_num }) => *reg_num as i64,
+      Some(Token::IntegerOperand { value }) => *value as i64,
+      Some(Token::FloatOperand { value }) if operand_type == OperandType::FloatImmediate => {
+        return Operand::Float(*value);
       }
-      Token::LabelUsage { name } => {
-        if let Some(value) = symbols.symbol_value(name) {
-          let mut wtr = vec![];
-          wtr.write_u32::<LittleEndian>(value).unwrap();
-          results.extend_from_slice(&wtr);
-        } else {
+      // errors were reported by `validate`
+      Some(Token::Expression { expression }) => expression.evaluate(symbols).unwrap_or(0),
+      Some(Token::LabelUsage { name }) => match symbols.symbol_value(name) {
+        Some(value) => value as i64,
+        None => {
           error!("Label {} not found in symbol table", name);
+          0
         }
+      },
+      Some(token) => {
+        error!("Invalid token found for {:?} operand: {:?}", operand_type, token);
+        0
       }
-      _ => {
-        error!("Invalid token found");
+      None if operand_type != OperandType::Empty => {
+        error!("Missing {:?} operand", operand_type);
+        0
       }
+      None => 0,
+    };
+
+    match operand_type {
+      OperandType::Register => Operand::Register(value as u8),
+      OperandType::FloatRegister => Operand::FloatRegister(value as u8),
+      OperandType::IntegerImmediate => Operand::Integer(value as i32),
+      OperandType::FloatImmediate => Operand::Float(value as f64),
+      OperandType::Address => Operand::Address(value as u32),
+      OperandType::Empty => Operand::Empty,
     }
   }
 
@@ -245,6 +400,7 @@ impl AssemblerInstruction {
   /// Converts a Pest parse tree (for a single instruction) into an AssemblerInstruction.
   /// Now it supports both plain directives and directive definitions.
   pub fn from_pair(pair: Pair<Rule>) -> Result<Self, String> {
+    let (mut line, mut column) = pair.line_col();
     let tokens: Vec<_> = pair.into_inner().collect();
     let mut label = None;
     let mut directive = None;
@@ -310,7 +466,9 @@ impl AssemblerInstruction {
             }
             let dt = match dir_text.to_lowercase().as_str() {
               "data"    => DirectiveType::Data,
+              "bss"     => DirectiveType::Bss,
               "text"    => DirectiveType::Text,
+              "code"    => DirectiveType::Text,
               "asciiz"  => DirectiveType::Asciiz,
               "integer" => DirectiveType::Integer,
               "float"   => DirectiveType::Float,
@@ -330,7 +488,9 @@ impl AssemblerInstruction {
             }
             let dt = match text.to_lowercase().as_str() {
               "data"    => DirectiveType::Data,
+              "bss"     => DirectiveType::Bss,
               "text"    => DirectiveType::Text,
+              "code"    => DirectiveType::Text,
               "asciiz"  => DirectiveType::Asciiz,
               "integer" => DirectiveType::Integer,
               "float"   => DirectiveType::Float,
@@ -341,6 +501,8 @@ impl AssemblerInstruction {
           }
         }
         Rule::opcode => {
+          // point at the opcode rather than a label in front of it
+          (line, column) = t.line_col();
           let text = t.as_str().trim();
           // If the opcode text is numeric, treat it as a constant.
           if let Ok(num) = text.parse::<i32>() {
@@ -379,10 +541,36 @@ impl AssemblerInstruction {
       operand_3,
       label,
       directive,
+      line,
+      column,
+      file: 0,
     })
   }
 }
 
+fn describe_operand_type(operand_type: OperandType) -> &'static str {
+  match operand_type {
+    OperandType::Register => "a register",
+    OperandType::FloatRegister => "a float register",
+    OperandType::IntegerImmediate => "an integer",
+    OperandType::FloatImmediate => "a float",
+    OperandType::Address => "an address or label",
+    OperandType::Empty => "nothing",
+  }
+}
+
+fn describe_token(token: &Token) -> String {
+  match token {
+    Token::Register { reg_num } => format!("register ${}", reg_num),
+    Token::IntegerOperand { value } => format!("integer #{}", value),
+    Token::FloatOperand { value } => format!("float #{}", value),
+    Token::LString { value } => format!("string '{}'", value),
+    Token::LabelUsage { name } => format!("label @{}", name),
+    Token::Expression { expression } => format!("expression `{}`", expression),
+    token => format!("{:?}", token),
+  }
+}
+
 pub fn convert_operand(pair: Pair<Rule>) -> Result<Token, String> {
   debug!("convert_operand({:?})", pair.as_rule());
   match pair.as_rule() {
@@ -401,6 +589,7 @@ pub fn convert_operand(pair: Pair<Rule>) -> Result<Token, String> {
       if s.len() < 1 {
         return Err("Integer immediate token too short".to_string());
       }
+      let s = s.trim_start_matches('#');
       let value = s.parse::<i32>().map_err(|e| format!("Invalid integer immediate: {}", e))?;
       Ok(Token::IntegerOperand { value })
     }
@@ -409,9 +598,18 @@ pub fn convert_operand(pair: Pair<Rule>) -> Result<Token, String> {
       if s.len() < 1 {
         return Err("Float immediate token too short".to_string());
       }
+      let s = s.trim_start_matches('#');
       let value = s.parse::<f64>().map_err(|e| format!("Invalid float immediate: {}", e))?;
       Ok(Token::FloatOperand { value })
     }
+    Rule::string_immediate => {
+      let s = pair.as_str();
+      if s.len() < 2 {
+        return Err("String immediate token too short".to_string());
+      }
+      let s = &s[1..s.len() - 1]; // Strip the surrounding quotes.
+      Ok(Token::LString { value: s.to_string() })
+    }
     Rule::label_usage => {
       let s = pair.as_str();
       if s.len() < 1 {
@@ -420,6 +618,13 @@ pub fn convert_operand(pair: Pair<Rule>) -> Result<Token, String> {
       let s = &s[1..]; // Skip the '@' prefix.
       Ok(Token::LabelUsage { name: s.parse().unwrap() })
     }
+    Rule::expression_immediate | Rule::label_expression | Rule::sizeof => {
+      Ok(Token::Expression { expression: Expression::from_pair(pair)? })
+    }
+    Rule::macro_param => {
+      let s = pair.as_str();
+      Ok(Token::MacroParameter { name: s[1..].to_string() })
+    }
     // In case an operand wraps another operand, drill down.
     Rule::operand => {
       let mut inner = pair.into_inner();
@@ -23,6 +23,8 @@ mod tests {
   use pest::Parser;
   use std::sync::Once;
   use log::LevelFilter;
+  use crate::assembler::Token;
+  use crate::parsers::program_parser::Program;
 
   static INIT: Once = Once::new();
 
@@ -71,4 +73,51 @@ mod tests {
       LumiAsmParser::print_pair(pair, 0);
     }
   }
+
+  fn operand_rules(input: &str) -> Vec<(Rule, &str)> {
+    LumiAsmParser::parse(Rule::instruction, input)
+      .unwrap()
+      .flatten()
+      .filter(|pair| pair.as_rule() == Rule::operand)
+      .map(|pair| {
+        let operand = pair.into_inner().next().unwrap();
+        (operand.as_rule(), operand.as_str())
+      })
+      .collect()
+  }
+
+  #[test]
+  fn parse_immediates_with_hash_prefix() {
+    init_logger();
+    assert_eq!(
+      operand_rules("lui $0 #100 -7"),
+      vec![(Rule::register, "$0"), (Rule::int_immediate, "#100"), (Rule::int_immediate, "-7")]
+    );
+    // floats are tried first, so the integer part is not taken on its own
+    assert_eq!(operand_rules("loadf64 $1 #-1.5"), vec![(Rule::register, "$1"), (Rule::float_immediate, "#-1.5")]);
+  }
+
+  #[test]
+  fn parse_single_and_double_quoted_strings() {
+    init_logger();
+    for input in ["greeting: .asciiz 'Hello'", "greeting: .asciiz \"Hello\""] {
+      let result = LumiAsmParser::parse(Rule::data_declaration, input);
+      assert!(result.is_ok(), "Expected {} to parse, but got: {:?}", input, result.err());
+      let string = result.unwrap().flatten().find(|pair| pair.as_rule() == Rule::string_immediate).unwrap();
+      assert_eq!(string.as_str().len(), 7);
+    }
+    assert!(LumiAsmParser::parse(Rule::string_immediate, "'Hello\"").is_err());
+  }
+
+  #[test]
+  fn parse_instruction_line_into_one_instruction() {
+    init_logger();
+    let pairs = LumiAsmParser::parse(Rule::program, ".code\nload $0 #100\n").unwrap();
+    let program = Program::from_pairs(pairs).unwrap();
+    // the `load` line makes one instruction, no empty one follows it
+    let on_line_2: Vec<_> = program.get_instructions().iter().filter(|instruction| instruction.line == 2).collect();
+    assert_eq!(on_line_2.len(), 1, "{:?}", on_line_2);
+    assert!(on_line_2[0].is_opcode());
+    assert_eq!(on_line_2[0].operand_2, Some(Token::IntegerOperand { value: 100 }));
+  }
 }
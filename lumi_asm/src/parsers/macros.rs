@@ -0,0 +1,422 @@
+use std::collections::{HashMap, HashSet};
+use log::debug;
+use pest::iterators::Pair;
+use crate::assembler::Token;
+use crate::assembler_errors::AssemblerError;
+use crate::instruction::Opcode;
+use crate::parsers::assembler_instruction::{convert_operand, AssemblerInstruction};
+use crate::parsers::lumi_asm_parser::Rule;
+use crate::parsers::program_parser::Program;
+
+/// Deepest chain of macro calls expanded before a macro is considered infinitely recursive.
+const MAX_EXPANSION_DEPTH: usize = 64;
+/// Most lines, macro calls and `.rept` repetitions a program may expand to, so a huge count or
+/// a chain of macros that each call the previous one twice fails instead of exhausting memory.
+const MAX_EXPANDED_LINES: usize = 100_000;
+
+/// A `.macro` definition, kept as unexpanded lines of the parse tree.
+#[derive(Debug, Clone)]
+struct MacroDefinition<'i> {
+  parameters: Vec<String>,
+  body: Vec<Pair<'i, Rule>>,
+  line: usize,
+}
+
+/// The macro call whose body is being expanded.
+struct CallSite {
+  name: String,
+  line: usize,
+  arguments: HashMap<String, Token>,
+  depth: usize,
+}
+
+/// Expands macro calls and `.rept` blocks into plain instructions. Macros must be defined
+/// before they are called, and are only visible in the file that defines them.
+pub struct MacroExpander<'i> {
+  macros: HashMap<String, MacroDefinition<'i>>,
+  /// Number of bodies expanded so far, used to give the labels of each expansion unique names
+  expansions: usize,
+  /// Lines produced by expansions plus macro calls and `.rept` repetitions so far, checked
+  /// against `MAX_EXPANDED_LINES`
+  expanded_lines: usize,
+  /// Number of `.rept` blocks being expanded
+  repeating: usize,
+}
+
+impl<'i> MacroExpander<'i> {
+  pub fn new() -> Self {
+    MacroExpander { macros: HashMap::new(), expansions: 0, expanded_lines: 0, repeating: 0 }
+  }
+
+  /// Expand the lines of a program.
+  pub fn expand(&mut self, lines: &[Pair<'i, Rule>]) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    self.expand_lines(lines, None)
+  }
+
+  fn expand_lines(
+    &mut self,
+    lines: &[Pair<'i, Rule>],
+    call: Option<&CallSite>,
+  ) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    let mut instructions = vec![];
+    let mut index = 0;
+    while index < lines.len() {
+      let pair = &lines[index];
+      let (line, _) = pair.line_col();
+      match pair.as_rule() {
+        Rule::macro_start => {
+          let end = block_end(lines, index, Rule::macro_start, Rule::macro_end)
+            .ok_or(AssemblerError::UnterminatedBlock { directive: ".macro".to_string(), line })?;
+          if call.is_some() {
+            return Err(AssemblerError::ParseError {
+              error: format!("Macros cannot be defined inside another macro, found `.macro` on line {}", line),
+            });
+          }
+          self.define(pair.clone(), lines[index + 1..end].to_vec())?;
+          index = end;
+        }
+        Rule::rept_start => {
+          let end = block_end(lines, index, Rule::rept_start, Rule::rept_end)
+            .ok_or(AssemblerError::UnterminatedBlock { directive: ".rept".to_string(), line })?;
+          let origin = || format!("`.rept` on line {}", line);
+          let count = repeat_count(pair)?;
+          // every repetition counts as a line, so an empty body cannot repeat forever either
+          if self.expanded_lines + count > MAX_EXPANDED_LINES {
+            return Err(too_many_lines(origin()));
+          }
+          self.repeating += 1;
+          for _ in 0..count {
+            let body = self.expand_lines(&lines[index + 1..end], call)?;
+            self.count_expanded(origin)?;
+            instructions.extend(self.uniquify(body, "rept"));
+          }
+          self.repeating -= 1;
+          index = end;
+        }
+        Rule::macro_end | Rule::rept_end => {
+          return Err(AssemblerError::UnmatchedBlockEnd { directive: pair.as_str().trim().to_string(), line });
+        }
+        Rule::instruction if self.macros.contains_key(instruction_name(pair)) => {
+          // the call counts as a line, so macros with empty bodies cannot multiply forever
+          self.count_expanded(|| format!("Macro `{}` on line {}", instruction_name(pair), line))?;
+          instructions.extend(self.expand_call(pair.clone(), call)?);
+        }
+        _ => {
+          for mut instruction in Program::convert_line(pair.clone())? {
+            for token in [&mut instruction.operand_1, &mut instruction.operand_2, &mut instruction.operand_3]
+              .into_iter()
+              .flatten()
+            {
+              substitute(token, call, line)?;
+            }
+            // lines written in the source do not count, only the ones macros and `.rept` produce
+            match call {
+              Some(call) => self.count_expanded(|| format!("Macro `{}` on line {}", call.name, call.line))?,
+              None if self.repeating > 0 => self.expanded_lines += 1,
+              None => {}
+            }
+            instructions.push(instruction);
+          }
+        }
+      }
+      index += 1;
+    }
+    Ok(instructions)
+  }
+
+  /// Count one more expanded line against `MAX_EXPANDED_LINES`, `origin` names the expansion
+  /// it came from.
+  fn count_expanded(&mut self, origin: impl FnOnce() -> String) -> Result<(), AssemblerError> {
+    self.expanded_lines += 1;
+    if self.expanded_lines > MAX_EXPANDED_LINES {
+      return Err(too_many_lines(origin()));
+    }
+    Ok(())
+  }
+
+  /// Record the macro started by `header` with the lines up to its `.endm`.
+  fn define(&mut self, header: Pair<'i, Rule>, body: Vec<Pair<'i, Rule>>) -> Result<(), AssemblerError> {
+    let (line, _) = header.line_col();
+    let mut inner = header.into_inner();
+    let name = inner.next().unwrap().as_str().to_string();
+    let parameters: Vec<String> = inner.map(|parameter| parameter.as_str().to_string()).collect();
+
+    if Opcode::from(name.as_str()) != Opcode::IGL || name.eq_ignore_ascii_case("igl") {
+      return Err(AssemblerError::ParseError {
+        error: format!("Macro `{}` on line {} has the name of an instruction", name, line),
+      });
+    }
+    if self.macros.contains_key(&name) {
+      return Err(AssemblerError::SymbolAlreadyDeclared { symbol: name });
+    }
+
+    debug!("Defined macro {}({}) on line {}", name, parameters.join(", "), line);
+    self.macros.insert(name, MacroDefinition { parameters, body, line });
+    Ok(())
+  }
+
+  /// Expand a call of a defined macro. The expanded instructions are placed at the call
+  /// site, so debug info and errors point at the line the macro was used on.
+  fn expand_call(
+    &mut self,
+    pair: Pair<'i, Rule>,
+    caller: Option<&CallSite>,
+  ) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    let (line, column) = pair.line_col();
+    let mut label = None;
+    let mut name = "";
+    let mut arguments = vec![];
+    for inner in pair.into_inner() {
+      match inner.as_rule() {
+        Rule::label_declaration => label = inner.into_inner().next().map(|identifier| identifier.as_str().to_string()),
+        Rule::opcode => name = inner.as_str(),
+        Rule::operand => {
+          let mut token = convert_operand(inner).map_err(|error| AssemblerError::ParseError { error })?;
+          substitute(&mut token, caller, line)?;
+          arguments.push(token);
+        }
+        _ => {}
+      }
+    }
+
+    let definition = self.macros[name].clone();
+    let located = |error: String| AssemblerError::MacroError { error, definition_line: definition.line, call_line: line };
+    if arguments.len() != definition.parameters.len() {
+      return Err(located(format!(
+        "Macro `{}` takes {} arguments but {} were given",
+        name,
+        definition.parameters.len(),
+        arguments.len()
+      )));
+    }
+    let depth = caller.map_or(0, |caller| caller.depth) + 1;
+    if depth > MAX_EXPANSION_DEPTH {
+      return Err(located(format!("Macro `{}` is nested more than {} calls deep", name, MAX_EXPANSION_DEPTH)));
+    }
+
+    debug!("Expanding macro {} on line {}", name, line);
+    let call = CallSite {
+      name: name.to_string(),
+      line,
+      arguments: definition.parameters.iter().cloned().zip(arguments).collect(),
+      depth,
+    };
+    let body = self.expand_lines(&definition.body, Some(&call)).map_err(|error| match error {
+      AssemblerError::MacroError { .. } => error,
+      error => located(error.to_string()),
+    })?;
+
+    let mut instructions = vec![];
+    if let Some(label) = label {
+      instructions.push(AssemblerInstruction {
+        label: Some(Token::LabelDeclaration { name: label }),
+        directive: None,
+        opcode: None,
+        operand_1: None,
+        operand_2: None,
+        operand_3: None,
+        line,
+        column,
+        file: 0,
+      });
+    }
+    for mut instruction in self.uniquify(body, name) {
+      instruction.line = line;
+      instruction.column = column;
+      instructions.push(instruction);
+    }
+    Ok(instructions)
+  }
+
+  /// Rename the labels declared in an expanded body to `prefix.n.label`, which cannot clash
+  /// with labels written in the source since identifiers have no dots.
+  fn uniquify(&mut self, mut instructions: Vec<AssemblerInstruction>, prefix: &str) -> Vec<AssemblerInstruction> {
+    self.expansions += 1;
+    let locals: HashSet<String> = instructions
+      .iter()
+      .filter_map(|instruction| instruction.get_label_name())
+      .filter(|name| !name.contains('.'))
+      .collect();
+
+    for instruction in &mut instructions {
+      let tokens = [
+        &mut instruction.label,
+        &mut instruction.operand_1,
+        &mut instruction.operand_2,
+        &mut instruction.operand_3,
+      ];
+      for token in tokens.into_iter().flatten() {
+        if let Token::LabelDeclaration { name } | Token::LabelUsage { name } = token {
+          if locals.contains(name.as_str()) {
+            *name = format!("{}.{}.{}", prefix, self.expansions, name);
+          }
+        }
+      }
+    }
+    instructions
+  }
+}
+
+/// Index of the line closing the block opened at `start`, allowing nested blocks.
+fn block_end(lines: &[Pair<Rule>], start: usize, open: Rule, close: Rule) -> Option<usize> {
+  let mut depth = 0;
+  for (index, pair) in lines.iter().enumerate().skip(start + 1) {
+    if pair.as_rule() == open {
+      depth += 1;
+    } else if pair.as_rule() == close {
+      if depth == 0 {
+        return Some(index);
+      }
+      depth -= 1;
+    }
+  }
+  None
+}
+
+fn instruction_name<'a>(pair: &Pair<'a, Rule>) -> &'a str {
+  pair
+    .clone()
+    .into_inner()
+    .find(|inner| inner.as_rule() == Rule::opcode)
+    .map_or("", |opcode| opcode.as_str())
+}
+
+fn too_many_lines(origin: String) -> AssemblerError {
+  AssemblerError::ParseError { error: format!("{} expands to more than {} lines", origin, MAX_EXPANDED_LINES) }
+}
+
+fn repeat_count(pair: &Pair<Rule>) -> Result<usize, AssemblerError> {
+  let (line, _) = pair.line_col();
+  let count = pair.clone().into_inner().next().map(convert_operand);
+  match count {
+    Some(Ok(Token::IntegerOperand { value })) if value >= 0 => Ok(value as usize),
+    _ => Err(AssemblerError::ParseError {
+      error: format!("`.rept` on line {} needs a count of zero or more", line),
+    }),
+  }
+}
+
+/// Replace a macro parameter with the argument passed for it.
+fn substitute(token: &mut Token, call: Option<&CallSite>, line: usize) -> Result<(), AssemblerError> {
+  if let Token::MacroParameter { name } = token {
+    *token = match call {
+      Some(call) => call.arguments.get(name.as_str()).cloned().ok_or_else(|| AssemblerError::ParseError {
+        error: format!("Unknown macro parameter `\\{}` on line {}", name, line),
+      })?,
+      None => {
+        return Err(AssemblerError::ParseError {
+          error: format!("Macro parameter `\\{}` used outside of a macro on line {}", name, line),
+        })
+      }
+    };
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pest::Parser;
+  use crate::parsers::lumi_asm_parser::LumiAsmParser;
+
+  fn expand(source: &str) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    let pairs = LumiAsmParser::parse(Rule::program, source).unwrap();
+    Program::from_pairs(pairs).map(|program| {
+      program.into_instructions().into_iter().filter(|instruction| instruction.is_opcode() || instruction.is_label()).collect()
+    })
+  }
+
+  #[test]
+  fn test_macro_substitutes_arguments_and_uniquifies_labels() {
+    let instructions = expand(
+      ".macro save a, b\npush \\a\npush \\b\n.endm\n\
+       .macro spin n\ncloop \\n\ntop: inc $0\nloop @top\n.endm\n\
+       .code\nsave $1, $2\nspin #3\nspin #4\n",
+    )
+    .unwrap();
+
+    // the label of `top: inc $0` is a line of its own
+    assert_eq!(instructions.len(), 10);
+    assert_eq!(instructions[0].operand_1, Some(Token::Register { reg_num: 1 }));
+    assert_eq!(instructions[1].operand_1, Some(Token::Register { reg_num: 2 }));
+    assert_eq!((instructions[1].line, instructions[1].column), (11, 1));
+    assert_eq!(instructions[2].operand_1, Some(Token::IntegerOperand { value: 3 }));
+
+    let first = instructions[3].get_label_name().unwrap();
+    let second = instructions[7].get_label_name().unwrap();
+    assert!(first.starts_with("spin.") && first.ends_with(".top"));
+    assert_ne!(first, second);
+    assert_eq!(instructions[5].operand_1, Some(Token::LabelUsage { name: first }));
+    assert_eq!(instructions[9].operand_1, Some(Token::LabelUsage { name: second }));
+  }
+
+  #[test]
+  fn test_rept_and_nested_macros() {
+    let instructions = expand(".macro inner r\npush \\r\n.endm\n.macro outer r\n.rept 2\ninner \\r\n.endr\n.endm\n.code\nhere: outer $7\n").unwrap();
+    assert_eq!(instructions.len(), 3);
+    assert_eq!(instructions[0].get_label_name(), Some("here".to_string()));
+    for instruction in &instructions[1..] {
+      assert_eq!(instruction.operand_1, Some(Token::Register { reg_num: 7 }));
+      assert_eq!(instruction.line, 10);
+    }
+
+    assert_eq!(expand(".code\n.rept 0\npush $0\n.endr\n").unwrap().len(), 0);
+  }
+
+  #[test]
+  fn test_rept_expansion_is_limited() {
+    let error = expand(".code\n.rept 2147483647\npush $0\n.endr\n").unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "There was an error parsing the code: `.rept` on line 2 expands to more than 100000 lines"
+    );
+    let error = expand(".code\n.rept 400\n.rept 400\npush $0\n.endr\n.endr\n").unwrap_err();
+    assert!(error.to_string().contains("`.rept` on line 3 expands to more than"), "{}", error);
+  }
+
+  #[test]
+  fn test_macro_doubling_chain_is_limited() {
+    for body in ["push $0\n", ""] {
+      let mut source = format!(".macro m0\n{}.endm\n", body);
+      for level in 1..=26 {
+        source.push_str(&format!(".macro m{}\nm{}\nm{}\n.endm\n", level, level - 1, level - 1));
+      }
+      source.push_str(".code\nm26\n");
+      let error = expand(&source).unwrap_err();
+      assert!(matches!(error, AssemblerError::MacroError { .. }), "{:?}", error);
+      assert!(error.to_string().contains("expands to more than 100000 lines"), "{}", error);
+    }
+  }
+
+  #[test]
+  fn test_macro_errors_point_at_definition_and_call() {
+    let error = expand(".macro save a, b\npush \\a\n.endm\n.code\nsave $1\n").unwrap_err();
+    assert!(matches!(error, AssemblerError::MacroError { definition_line: 1, call_line: 5, .. }));
+    assert_eq!(
+      error.to_string(),
+      "Macro `save` takes 2 arguments but 1 were given (macro defined on line 1, expanded on line 5)"
+    );
+
+    let error = expand(".macro save a\npush \\b\n.endm\n.code\nsave $1\n").unwrap_err();
+    assert!(error.to_string().starts_with("There was an error parsing the code: Unknown macro parameter `\\b` on line 2"));
+    assert!(matches!(error, AssemblerError::MacroError { definition_line: 1, call_line: 5, .. }));
+
+    let error = expand(".macro forever\nforever\n.endm\n.code\nforever\n").unwrap_err();
+    assert!(error.to_string().contains("nested more than 64 calls deep"));
+  }
+
+  #[test]
+  fn test_malformed_blocks() {
+    assert!(matches!(
+      expand(".code\n.macro save a\npush \\a\n"),
+      Err(AssemblerError::UnterminatedBlock { line: 2, .. })
+    ));
+    assert!(matches!(expand(".code\npush $0\n.endr\n"), Err(AssemblerError::UnmatchedBlockEnd { line: 3, .. })));
+    assert!(matches!(expand(".code\npush \\a\n"), Err(AssemblerError::ParseError { .. })));
+    assert!(matches!(expand(".macro push a\n.endm\n"), Err(AssemblerError::ParseError { .. })));
+    assert!(matches!(
+      expand(".macro twice\n.endm\n.macro twice\n.endm\n"),
+      Err(AssemblerError::SymbolAlreadyDeclared { .. })
+    ));
+  }
+}
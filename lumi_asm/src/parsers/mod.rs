@@ -1,3 +1,4 @@
 pub mod assembler_instruction;
 pub mod lumi_asm_parser;
+pub mod macros;
 pub mod program_parser;
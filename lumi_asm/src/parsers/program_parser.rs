@@ -6,11 +6,14 @@ use nom::error::{context, VerboseError};
 use nom::multi::many0;
 use nom::sequence::terminated;
 use nom::IResult;
-use pest::iterators::Pairs;
+use pest::iterators::{Pair, Pairs};
 use crate::assembler::{DirectiveType, Token};
 use crate::assembler_errors::AssemblerError;
+use crate::expressions::Expression;
+use crate::instruction::Opcode;
 use crate::parsers::assembler_instruction::{convert_operand, AssemblerInstruction};
 use crate::parsers::lumi_asm_parser::Rule;
+use crate::parsers::macros::MacroExpander;
 use crate::symbols::SymbolTable;
 
 #[derive(Debug, PartialEq)]
@@ -27,6 +30,10 @@ impl Program {
     &self.instructions
   }
 
+  pub fn into_instructions(self) -> Vec<AssemblerInstruction> {
+    self.instructions
+  }
+
   pub fn from_pairs(mut pairs: Pairs<Rule>) -> Result<Self, AssemblerError> {
     // The parse result is a top-level pair with rule `program`.
     // Get the single top-level pair.
@@ -34,110 +41,163 @@ impl Program {
       .next()
       .ok_or(AssemblerError::ParseError { error: "No top-level program found".to_string() })?;
 
-    let mut instructions = Vec::new();
+    // Iterate over the inner pairs of the top-level program, expanding macros and `.rept` blocks.
+    let lines: Vec<_> = program_pair.into_inner().collect();
+    let instructions = MacroExpander::new().expand(&lines)?;
+
+    Ok(Program::new(instructions))
+  }
 
-    // Iterate over the inner pairs of the top-level program.
-    for pair in program_pair.into_inner() {
-      
-      let mut label: Option<Token> = None;
-      let mut directive: Option<Token> = None;
-      let mut opcode: Option<Token> = None;
-      let mut operand_1: Option<Token> = None;
-      let mut operand_2: Option<Token> = None;
-      let mut operand_3: Option<Token> = None;
-      
-      match pair.as_rule() {
-        Rule::directive => {
-          let directive_type = pair.as_str();
-          directive = Some(Token::Directive { directive_type: DirectiveType::from_str(directive_type).unwrap() });
-          debug!("Found directive - {}: {:?}", directive_type, directive);
-        },
-        Rule::data_declaration => {
-          let mut inner_pairs = pair.into_inner();
-          while let Some(inner_pair) = inner_pairs.next() {
-            if inner_pair.as_rule() == Rule::label_declaration {
-              let identifier_pair = inner_pair.into_inner().next().unwrap();
-              let label_name = identifier_pair.as_str();
-              label = Some(Token::LabelDeclaration { name: label_name.to_string() });
-              debug!("Found label: {:?}", label);
-            } else if inner_pair.as_rule() == Rule::directive {
-              let directive_type = inner_pair.as_str();
-              directive = Some(Token::Directive { directive_type: DirectiveType::from_str(directive_type).unwrap() });
-              debug!("Found directive - {}: {:?}", directive_type, directive);
-            } else if inner_pair.as_rule() == Rule::operand {
-              debug!("Found operand: {:?}", inner_pair);
-              let operand_pair = inner_pair.into_inner().next().unwrap();
-              debug!("Found operand pair: {:?}", operand_pair);
-              let operand = match operand_pair.as_rule() {
-                Rule::register => convert_operand(operand_pair),
-                Rule::int_immediate => convert_operand(operand_pair),
-                Rule::float_immediate => convert_operand(operand_pair),
-                Rule::string_immediate => convert_operand(operand_pair),
-                Rule::label_usage => convert_operand(operand_pair),
-                _ => {
-                  error!("Unexpected rule for operand: {:?}", operand_pair.as_rule());
-                  return Result::Err(AssemblerError::ParseError { error: "Unexpected rule for operand".to_string() });
-                }
-              };
-              
-              if operand.is_ok() {
-                let operand_token = operand.unwrap();
-                if operand_1.is_none() {
-                  operand_1 = Some(operand_token);
-                  debug!("Found operand 1: {:?}", operand_1);
-                } else if operand_2.is_none() {
-                  operand_2 = Some(operand_token);
-                  debug!("Found operand 2: {:?}", operand_2);
-                } else if operand_3.is_none() {
-                  operand_3 = Some(operand_token);
-                  debug!("Found operand 3: {:?}", operand_3);
-                } else {
-                  error!("Too many operands found in data declaration");
-                }
+  /// Convert a single line of the parse tree into instructions. Most lines make one
+  /// instruction, a `.global` or `.extern` list makes one per name.
+  pub(crate) fn convert_line(pair: Pair<Rule>) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    let mut label: Option<Token> = None;
+    let mut directive: Option<Token> = None;
+    let mut opcode: Option<Token> = None;
+    let mut operand_1: Option<Token> = None;
+    let mut operand_2: Option<Token> = None;
+    let mut operand_3: Option<Token> = None;
+    let (line, column) = pair.line_col();
+    
+    match pair.as_rule() {
+      Rule::directive => {
+        let directive_type = pair.as_str();
+        directive = Some(Token::Directive { directive_type: DirectiveType::from_str(directive_type).unwrap() });
+        debug!("Found directive - {}: {:?}", directive_type, directive);
+      },
+      Rule::equ_directive => {
+        let mut inner_pairs = pair.into_inner();
+        let name = inner_pairs.next().unwrap().as_str().to_string();
+        let expression = Expression::from_pair(inner_pairs.next().unwrap())
+          .map_err(|error| AssemblerError::ParseError { error })?;
+        directive = Some(Token::Directive { directive_type: DirectiveType::Equ });
+        operand_1 = Some(Token::LabelDeclaration { name });
+        operand_2 = Some(Token::Expression { expression });
+        debug!("Found constant: {:?} = {:?}", operand_1, operand_2);
+      }
+      Rule::include_directive => {
+        let path_pair = pair.into_inner().next().unwrap();
+        directive = Some(Token::Directive { directive_type: DirectiveType::Include });
+        operand_1 = Some(convert_operand(path_pair).map_err(|error| AssemblerError::ParseError { error })?);
+        debug!("Found include: {:?}", operand_1);
+      }
+      Rule::visibility_directive => {
+        let mut inner_pairs = pair.into_inner();
+        let visibility = inner_pairs.next().unwrap().as_str();
+        // one instruction per declared name, all at the position of the directive
+        let mut instructions = vec![];
+        for identifier_pair in inner_pairs {
+          let directive_type = DirectiveType::from_str(&format!(".{}", visibility)).unwrap();
+          debug!("Found {:?} directive for {}", directive_type, identifier_pair.as_str());
+          instructions.push(AssemblerInstruction {
+            label: None,
+            directive: Some(Token::Directive { directive_type }),
+            opcode: None,
+            operand_1: Some(Token::LabelUsage { name: identifier_pair.as_str().to_string() }),
+            operand_2: None,
+            operand_3: None,
+            line,
+            column,
+            file: 0,
+          });
+        }
+        return Ok(instructions);
+      }
+      Rule::data_declaration => {
+        let mut inner_pairs = pair.into_inner();
+        while let Some(inner_pair) = inner_pairs.next() {
+          if inner_pair.as_rule() == Rule::label_declaration {
+            let identifier_pair = inner_pair.into_inner().next().unwrap();
+            let label_name = identifier_pair.as_str();
+            label = Some(Token::LabelDeclaration { name: label_name.to_string() });
+            debug!("Found label: {:?}", label);
+          } else if inner_pair.as_rule() == Rule::directive {
+            let directive_type = inner_pair.as_str();
+            directive = Some(Token::Directive { directive_type: DirectiveType::from_str(directive_type).unwrap() });
+            debug!("Found directive - {}: {:?}", directive_type, directive);
+          } else if inner_pair.as_rule() == Rule::operand {
+            debug!("Found operand: {:?}", inner_pair);
+            let operand_pair = inner_pair.into_inner().next().unwrap();
+            debug!("Found operand pair: {:?}", operand_pair);
+            let operand = match operand_pair.as_rule() {
+              Rule::register => convert_operand(operand_pair),
+              Rule::int_immediate => convert_operand(operand_pair),
+              Rule::float_immediate => convert_operand(operand_pair),
+              Rule::string_immediate => convert_operand(operand_pair),
+              Rule::label_usage => convert_operand(operand_pair),
+              Rule::macro_param => convert_operand(operand_pair),
+              Rule::expression_immediate | Rule::label_expression | Rule::sizeof => convert_operand(operand_pair),
+              _ => {
+                error!("Unexpected rule for operand: {:?}", operand_pair.as_rule());
+                return Result::Err(AssemblerError::ParseError { error: "Unexpected rule for operand".to_string() });
+              }
+            };
+            
+            if operand.is_ok() {
+              let operand_token = operand.unwrap();
+              if operand_1.is_none() {
+                operand_1 = Some(operand_token);
+                debug!("Found operand 1: {:?}", operand_1);
+              } else if operand_2.is_none() {
+                operand_2 = Some(operand_token);
+                debug!("Found operand 2: {:?}", operand_2);
+              } else if operand_3.is_none() {
+                operand_3 = Some(operand_token);
+                debug!("Found operand 3: {:?}", operand_3);
               } else {
-                error!("Error converting operand: {:?}", operand);
+                error!("Too many operands found in data declaration");
               }
+            } else {
+              error!("Error converting operand: {:?}", operand);
             }
           }
         }
-        Rule::label_declaration => {
-          if let Some(identifier_pair) = pair.into_inner().next() {
-            if identifier_pair.as_rule() == Rule::identifier {
-              let label_name = identifier_pair.as_str();
-              label = Some(Token::LabelDeclaration { name: label_name.to_string() });
-              debug!("Found label: {:?}", label);
-            } else {
-              error!("Expected an identifier for label declaration but found: {:?} - `{:?}`", identifier_pair.as_rule(), identifier_pair.as_str())
-            }
+      }
+      Rule::label_declaration => {
+        if let Some(identifier_pair) = pair.into_inner().next() {
+          if identifier_pair.as_rule() == Rule::identifier {
+            let label_name = identifier_pair.as_str();
+            label = Some(Token::LabelDeclaration { name: label_name.to_string() });
+            debug!("Found label: {:?}", label);
           } else {
-            error!("No inner rules found in label_declaration");
+            error!("Expected an identifier for label declaration but found: {:?} - `{:?}`", identifier_pair.as_rule(), identifier_pair.as_str())
           }
+        } else {
+          error!("No inner rules found in label_declaration");
         }
-        Rule::instruction => {
-          // Convert each line into an instruction.
-          let instruction = AssemblerInstruction::from_pair(pair)
-            .map_err(|e| AssemblerError::ParseError { error: e })?;
-          debug!("instruction: {:?}", instruction);
-          instructions.push(instruction);
-        }
-        _ => {
-          // Skip or log any unexpected rule.
-          debug!("Skipping unexpected rule: {:?}", pair.as_rule());
+      }
+      Rule::instruction => {
+        let opcode = pair.clone().into_inner().find(|inner| inner.as_rule() == Rule::opcode);
+        if let Some(name) = opcode.map(|opcode| opcode.as_str().trim()) {
+          // anything that is not a mnemonic or a number becomes IGL, which only `igl` should
+          if Opcode::from(name) == Opcode::IGL && !name.eq_ignore_ascii_case("igl") && name.parse::<i32>().is_err() {
+            return Err(AssemblerError::UnknownOpcode { name: name.to_string(), line });
+          }
         }
+        // Convert each line into an instruction.
+        let instruction = AssemblerInstruction::from_pair(pair)
+          .map_err(|e| AssemblerError::ParseError { error: e })?;
+        debug!("instruction: {:?}", instruction);
+        return Ok(vec![instruction]);
+      }
+      _ => {
+        // Skip or log any unexpected rule.
+        debug!("Skipping unexpected rule: {:?}", pair.as_rule());
       }
-      
-      let instruction = AssemblerInstruction {
-        label,
-        directive,
-        opcode,
-        operand_1,
-        operand_2,
-        operand_3,
-      };
-      instructions.push(instruction);
     }
-
-    Ok(Program::new(instructions))
+    
+    let instruction = AssemblerInstruction {
+      label,
+      directive,
+      opcode,
+      operand_1,
+      operand_2,
+      operand_3,
+      line,
+      column,
+      file: 0,
+    };
+    Ok(vec![instruction])
   }
   
   pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
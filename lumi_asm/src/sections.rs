@@ -0,0 +1,365 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::ops::Range;
+use byteorder::{ByteOrder, LittleEndian};
+use crate::header_utils::{code_start_offset, HeaderError, LUMI_HEADER_SIZE};
+
+/// The section may be read by the program.
+pub const PERMISSION_READ: u8 = 1;
+/// The section may be written by the program.
+pub const PERMISSION_WRITE: u8 = 2;
+/// The section contains instructions.
+pub const PERMISSION_EXECUTE: u8 = 4;
+
+/// Size of the section count that starts the section table.
+const SECTION_COUNT_SIZE: usize = 4;
+/// Size of a single serialized section table entry.
+pub const SECTION_ENTRY_SIZE: usize = 16;
+/// Highest heap address `.data` and `.bss` may extend to. The VM reserves the heap they cover
+/// on load, so without a bound a small binary could claim gigabytes.
+pub const MAX_LOADED_HEAP: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SectionKind {
+  /// Constants declared in `.rodata`, readable by `PRTS`.
+  ReadOnly,
+  /// Instructions.
+  Code,
+  /// Initialized writable data, copied into the heap on load.
+  Data,
+  /// Zeroed writable data, reserved on the heap on load. Takes no space in the binary.
+  Bss,
+  /// Debug information, never loaded.
+  Debug,
+}
+
+impl SectionKind {
+  pub fn name(&self) -> &'static str {
+    match self {
+      SectionKind::ReadOnly => ".rodata",
+      SectionKind::Code => ".code",
+      SectionKind::Data => ".data",
+      SectionKind::Bss => ".bss",
+      SectionKind::Debug => ".debug",
+    }
+  }
+
+  pub fn default_permissions(&self) -> u8 {
+    match self {
+      SectionKind::ReadOnly | SectionKind::Debug => PERMISSION_READ,
+      SectionKind::Code => PERMISSION_READ | PERMISSION_EXECUTE,
+      SectionKind::Data | SectionKind::Bss => PERMISSION_READ | PERMISSION_WRITE,
+    }
+  }
+}
+
+impl From<SectionKind> for u8 {
+  fn from(kind: SectionKind) -> Self {
+    match kind {
+      SectionKind::ReadOnly => 1,
+      SectionKind::Code => 2,
+      SectionKind::Data => 3,
+      SectionKind::Bss => 4,
+      SectionKind::Debug => 5,
+    }
+  }
+}
+
+impl TryFrom<u8> for SectionKind {
+  type Error = HeaderError;
+
+  fn try_from(kind: u8) -> Result<Self, Self::Error> {
+    match kind {
+      1 => Ok(SectionKind::ReadOnly),
+      2 => Ok(SectionKind::Code),
+      3 => Ok(SectionKind::Data),
+      4 => Ok(SectionKind::Bss),
+      5 => Ok(SectionKind::Debug),
+      _ => Err(HeaderError::UnknownSectionKind { kind }),
+    }
+  }
+}
+
+/// An entry of the section table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+  pub kind: SectionKind,
+  pub permissions: u8,
+  /// Offset of the section contents in the binary, `0` for `.bss`.
+  pub offset: u32,
+  /// Size in bytes. For `.bss` this is the amount of zeroed heap to reserve.
+  pub size: u32,
+  /// Heap address the section is loaded at, only used by `.data` and `.bss`.
+  pub address: u32,
+}
+
+impl Section {
+  /// Section with the default permissions for its kind, loaded at address `0`.
+  pub fn new(kind: SectionKind, offset: usize, size: usize) -> Self {
+    Section {
+      kind,
+      permissions: kind.default_permissions(),
+      offset: offset as u32,
+      size: size as u32,
+      address: 0,
+    }
+  }
+
+  pub fn at_address(mut self, address: usize) -> Self {
+    self.address = address as u32;
+    self
+  }
+
+  pub fn is_writable(&self) -> bool {
+    self.permissions & PERMISSION_WRITE != 0
+  }
+
+  pub fn is_executable(&self) -> bool {
+    self.permissions & PERMISSION_EXECUTE != 0
+  }
+
+  /// Whether the section has contents stored in the binary.
+  pub fn is_stored(&self) -> bool {
+    self.kind != SectionKind::Bss
+  }
+
+  /// Byte range of the section in the binary, empty for `.bss`.
+  pub fn range(&self) -> Range<usize> {
+    if self.is_stored() {
+      self.offset as usize..self.offset as usize + self.size as usize
+    } else {
+      0..0
+    }
+  }
+
+  /// Heap addresses covered by the section once loaded.
+  pub fn heap_range(&self) -> Range<usize> {
+    self.address as usize..self.address as usize + self.size as usize
+  }
+}
+
+impl fmt::Display for Section {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let flag = |permission: u8, c: char| if self.permissions & permission != 0 { c } else { '-' };
+    write!(
+      f,
+      "{:<8} {}{}{} offset 0x{:x} size {}",
+      self.kind.name(),
+      flag(PERMISSION_READ, 'r'),
+      flag(PERMISSION_WRITE, 'w'),
+      flag(PERMISSION_EXECUTE, 'x'),
+      self.offset,
+      self.size
+    )?;
+    if matches!(self.kind, SectionKind::Data | SectionKind::Bss) {
+      write!(f, " at heap 0x{:x}", self.address)?;
+    }
+    Ok(())
+  }
+}
+
+/// The section table of a binary: a `u32` entry count followed by one entry per section.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SectionTable {
+  sections: Vec<Section>,
+}
+
+impl SectionTable {
+  pub fn new() -> Self {
+    SectionTable { sections: vec![] }
+  }
+
+  /// The layout of a binary without a section table: read-only data directly after the header
+  /// and code up to the end of the program.
+  pub fn implicit(ro_length: usize, program_length: usize) -> Self {
+    let code_start = code_start_offset(ro_length);
+    SectionTable {
+      sections: vec![
+        Section::new(SectionKind::ReadOnly, LUMI_HEADER_SIZE, ro_length),
+        Section::new(SectionKind::Code, code_start, program_length.saturating_sub(code_start)),
+      ],
+    }
+  }
+
+  pub fn push(&mut self, section: Section) {
+    self.sections.push(section);
+  }
+
+  pub fn sections(&self) -> &[Section] {
+    &self.sections
+  }
+
+  /// First section of the given kind.
+  pub fn get(&self, kind: SectionKind) -> Option<&Section> {
+    self.sections.iter().find(|section| section.kind == kind)
+  }
+
+  /// Contents of the first section of the given kind, empty if there is none.
+  pub fn contents<'a>(&self, kind: SectionKind, program: &'a [u8]) -> &'a [u8] {
+    match self.get(kind) {
+      Some(section) => &program[section.range()],
+      None => &[],
+    }
+  }
+
+  /// Number of heap bytes that `.data` and `.bss` occupy once loaded.
+  pub fn heap_size(&self) -> usize {
+    self
+      .sections
+      .iter()
+      .filter(|section| matches!(section.kind, SectionKind::Data | SectionKind::Bss))
+      .map(|section| section.heap_range().end)
+      .max()
+      .unwrap_or(0)
+  }
+
+  pub fn write(&self) -> Vec<u8> {
+    let mut table = vec![0u8; SECTION_COUNT_SIZE + self.sections.len() * SECTION_ENTRY_SIZE];
+    LittleEndian::write_u32(&mut table, self.sections.len() as u32);
+    for (section, entry) in self.sections.iter().zip(table[SECTION_COUNT_SIZE..].chunks_mut(SECTION_ENTRY_SIZE)) {
+      entry[0] = section.kind.into();
+      entry[1] = section.permissions;
+      LittleEndian::write_u32(&mut entry[4..], section.offset);
+      LittleEndian::write_u32(&mut entry[8..], section.size);
+      LittleEndian::write_u32(&mut entry[12..], section.address);
+    }
+    table
+  }
+
+  /// Read the section table at `offset` and check that every stored section lies inside the
+  /// body of `program` and every heap section below `MAX_LOADED_HEAP`.
+  pub fn parse(program: &[u8], offset: usize) -> Result<Self, HeaderError> {
+    let out_of_bounds = HeaderError::SectionTableOutOfBounds { offset, program_length: program.len() };
+    let count_end = offset.checked_add(SECTION_COUNT_SIZE).ok_or(out_of_bounds)?;
+    if offset < LUMI_HEADER_SIZE || count_end > program.len() {
+      return Err(out_of_bounds);
+    }
+
+    let count = LittleEndian::read_u32(&program[offset..]) as usize;
+    let end = count
+      .checked_mul(SECTION_ENTRY_SIZE)
+      .and_then(|size| size.checked_add(count_end))
+      .ok_or(out_of_bounds)?;
+    if end > program.len() {
+      return Err(out_of_bounds);
+    }
+
+    let mut table = SectionTable::new();
+    for entry in program[count_end..end].chunks(SECTION_ENTRY_SIZE) {
+      let section = Section {
+        kind: SectionKind::try_from(entry[0])?,
+        permissions: entry[1],
+        offset: LittleEndian::read_u32(&entry[4..]),
+        size: LittleEndian::read_u32(&entry[8..]),
+        address: LittleEndian::read_u32(&entry[12..]),
+      };
+
+      let start = section.offset as usize;
+      let in_bounds = start
+        .checked_add(section.size as usize)
+        .is_some_and(|section_end| start >= LUMI_HEADER_SIZE && section_end <= program.len());
+      if section.is_stored() && !in_bounds {
+        return Err(HeaderError::SectionOutOfBounds {
+          kind: section.kind,
+          offset: start,
+          size: section.size as usize,
+          program_length: program.len(),
+        });
+      }
+      let heap_end = section.address as usize + section.size as usize;
+      if matches!(section.kind, SectionKind::Data | SectionKind::Bss) && heap_end > MAX_LOADED_HEAP {
+        return Err(HeaderError::HeapSectionTooLarge {
+          kind: section.kind,
+          address: section.address as usize,
+          size: section.size as usize,
+          limit: MAX_LOADED_HEAP,
+        });
+      }
+      table.push(section);
+    }
+    Ok(table)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::header_utils::LumiHeader;
+
+  fn table() -> SectionTable {
+    let mut table = SectionTable::new();
+    table.push(Section::new(SectionKind::ReadOnly, LUMI_HEADER_SIZE, 3));
+    table.push(Section::new(SectionKind::Code, LUMI_HEADER_SIZE + 3, 1));
+    table.push(Section::new(SectionKind::Data, LUMI_HEADER_SIZE + 4, 2));
+    table.push(Section::new(SectionKind::Bss, 0, 8).at_address(2));
+    table
+  }
+
+  #[test]
+  fn test_section_table_write_parse_round_trip() {
+    let table = table();
+    let mut body = vec![0u8; 6];
+    body.extend(table.write());
+    let program = LumiHeader::new(3).wrap(&body);
+
+    let parsed = SectionTable::parse(&program, LUMI_HEADER_SIZE + 6).unwrap();
+    assert_eq!(parsed, table);
+    assert_eq!(parsed.heap_size(), 10);
+    assert!(parsed.get(SectionKind::Code).unwrap().is_executable());
+    assert!(parsed.get(SectionKind::Data).unwrap().is_writable());
+    assert!(!parsed.get(SectionKind::ReadOnly).unwrap().is_writable());
+    assert_eq!(parsed.get(SectionKind::Bss).unwrap().range(), 0..0);
+    assert_eq!(parsed.contents(SectionKind::Data, &program), &[0, 0]);
+    assert_eq!(parsed.contents(SectionKind::Debug, &program), &[] as &[u8]);
+  }
+
+  #[test]
+  fn test_section_table_parse_rejects_bad_tables() {
+    let program = LumiHeader::new(0).wrap(&[1, 0, 0]);
+    assert_eq!(
+      SectionTable::parse(&program, LUMI_HEADER_SIZE),
+      Err(HeaderError::SectionTableOutOfBounds { offset: LUMI_HEADER_SIZE, program_length: program.len() })
+    );
+
+    let mut body = vec![];
+    let mut table = SectionTable::new();
+    table.push(Section::new(SectionKind::Data, LUMI_HEADER_SIZE, 100));
+    body.extend(table.write());
+    let program = LumiHeader::new(0).wrap(&body);
+    assert_eq!(
+      SectionTable::parse(&program, LUMI_HEADER_SIZE),
+      Err(HeaderError::SectionOutOfBounds {
+        kind: SectionKind::Data,
+        offset: LUMI_HEADER_SIZE,
+        size: 100,
+        program_length: program.len(),
+      })
+    );
+
+    let mut body = table.write();
+    body[SECTION_COUNT_SIZE] = 42;
+    let program = LumiHeader::new(0).wrap(&body);
+    assert_eq!(SectionTable::parse(&program, LUMI_HEADER_SIZE), Err(HeaderError::UnknownSectionKind { kind: 42 }));
+
+    let mut table = SectionTable::new();
+    table.push(Section::new(SectionKind::Bss, 0, u32::MAX as usize).at_address(16));
+    let program = LumiHeader::new(0).wrap(&table.write());
+    assert_eq!(
+      SectionTable::parse(&program, LUMI_HEADER_SIZE),
+      Err(HeaderError::HeapSectionTooLarge {
+        kind: SectionKind::Bss,
+        address: 16,
+        size: u32::MAX as usize,
+        limit: MAX_LOADED_HEAP,
+      })
+    );
+  }
+
+  #[test]
+  fn test_section_display() {
+    let section = Section::new(SectionKind::Bss, 0, 8).at_address(2);
+    assert_eq!(section.to_string(), ".bss     rw- offset 0x0 size 8 at heap 0x2");
+    let section = Section::new(SectionKind::Code, 0x45, 10);
+    assert_eq!(section.to_string(), ".code    r-x offset 0x45 size 10");
+  }
+}
@@ -1,8 +1,23 @@
-#[derive(Debug)]
+use crate::debug_info::{DebugSymbol, DebugSymbolKind};
+use crate::listing::ListingSymbol;
+use crate::object::ObjectSymbol;
+use crate::sections::SectionKind;
+
+#[derive(Debug, PartialEq)]
 pub enum SymbolType {
+  /// Address of an instruction in the code section.
   Label,
   Integer,
+  Float,
   LString,
+  /// Data label without a typed directive.
+  Data,
+  /// Address in the heap of memory reserved in `.bss`, which follows `.data`.
+  Bss,
+  /// Referenced but defined in another object, resolved by the linker.
+  Import,
+  /// A value defined with `.equ`, which takes no space.
+  Constant { value: i64 },
 }
 
 #[derive(Debug)]
@@ -10,6 +25,12 @@ pub struct Symbol {
   name: String,
   offset: Option<u32>,
   symbol_type: SymbolType,
+  /// Declared in `.data` or `.bss`, so the offset is a heap address.
+  writable: bool,
+  /// Declared `.global`, so other objects can link against it.
+  global: bool,
+  /// Bytes declared by a data directive, for `sizeof`.
+  size: Option<u32>,
 }
 
 impl Symbol {
@@ -18,6 +39,9 @@ impl Symbol {
       name,
       symbol_type,
       offset: None,
+      writable: false,
+      global: false,
+      size: None,
     }
   }
   
@@ -26,6 +50,31 @@ impl Symbol {
       name,
       symbol_type,
       offset: Some(offset),
+      writable: false,
+      global: false,
+      size: None,
+    }
+  }
+
+  pub fn writable(mut self) -> Symbol {
+    self.writable = true;
+    self
+  }
+
+  fn debug_kind(&self) -> DebugSymbolKind {
+    match self.symbol_type {
+      SymbolType::Label => DebugSymbolKind::Label,
+      _ if self.writable => DebugSymbolKind::Variable,
+      _ => DebugSymbolKind::Constant,
+    }
+  }
+
+  fn section(&self) -> SectionKind {
+    match self.symbol_type {
+      SymbolType::Label => SectionKind::Code,
+      SymbolType::Bss => SectionKind::Bss,
+      _ if self.writable => SectionKind::Data,
+      _ => SectionKind::ReadOnly,
     }
   }
 }
@@ -67,6 +116,102 @@ impl SymbolTable {
     false
   }
   
+  /// Value of the `.equ` constant `s`.
+  pub fn constant(&self, s: &str) -> Option<i64> {
+    self.symbols.iter().find(|symbol| symbol.name == s).and_then(|symbol| match symbol.symbol_type {
+      SymbolType::Constant { value } => Some(value),
+      _ => None,
+    })
+  }
+
+  pub fn set_symbol_size(&mut self, s: &str, size: u32) -> bool {
+    for symbol in &mut self.symbols {
+      if symbol.name == s {
+        symbol.size = Some(size);
+        return true;
+      }
+    }
+    false
+  }
+
+  pub fn symbol_size(&self, s: &str) -> Option<u32> {
+    self.symbols.iter().find(|symbol| symbol.name == s).and_then(|symbol| symbol.size)
+  }
+
+  /// Mark `s` as visible to other objects, `false` if it is not declared.
+  pub fn set_global(&mut self, s: &str) -> bool {
+    for symbol in &mut self.symbols {
+      if symbol.name == s {
+        symbol.global = true;
+        return true;
+      }
+    }
+    false
+  }
+
+  /// Shift the offset of every symbol of the given type by `by` bytes.
+  pub fn relocate(&mut self, symbol_type: SymbolType, by: u32) {
+    for symbol in &mut self.symbols {
+      if symbol.symbol_type == symbol_type {
+        symbol.offset = symbol.offset.map(|offset| offset + by);
+      }
+    }
+  }
+  
+  /// Symbols with a known offset, as they are stored in the debug info section.
+  pub fn debug_symbols(&self) -> Vec<DebugSymbol> {
+    self
+      .symbols
+      .iter()
+      .filter_map(|symbol| {
+        symbol.offset.map(|value| DebugSymbol { name: symbol.name.clone(), kind: symbol.debug_kind(), value })
+      })
+      .collect()
+  }
+
+  /// Symbols defined by this unit with offsets relative to their section, as they are stored
+  /// in an object file.
+  pub fn object_symbols(&self) -> Vec<ObjectSymbol> {
+    self
+      .symbols
+      .iter()
+      .filter(|symbol| symbol.symbol_type != SymbolType::Import)
+      .filter_map(|symbol| {
+        symbol.offset.map(|offset| ObjectSymbol {
+          name: symbol.name.clone(),
+          section: symbol.section(),
+          offset,
+          global: symbol.global,
+        })
+      })
+      .collect()
+  }
+
+  /// Every symbol with its resolved value, `.equ` constants included, for listings.
+  pub fn listing_symbols(&self) -> Vec<ListingSymbol> {
+    self
+      .symbols
+      .iter()
+      .filter_map(|symbol| match symbol.symbol_type {
+        SymbolType::Import => None,
+        SymbolType::Constant { value } => Some(ListingSymbol {
+          name: symbol.name.clone(),
+          section: None,
+          value,
+          size: None,
+          global: symbol.global,
+        }),
+        _ => symbol.offset.map(|offset| ListingSymbol {
+          name: symbol.name.clone(),
+          section: Some(symbol.section()),
+          value: offset as i64,
+          size: symbol.size,
+          global: symbol.global,
+        }),
+      })
+      .collect()
+  }
+
   pub fn symbol_value(&self, s: &str) -> Option<u32> {
     for symbol in &self.symbols {
       if symbol.name == s {
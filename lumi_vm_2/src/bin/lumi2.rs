@@ -1,34 +1,154 @@
-#![feature(async_closure)]
-#![feature(ascii_char)]
-
 extern crate clap;
 
+use std::fs::File;
+use std::io::BufReader;
 use clap::Parser;
-use log::info;
-use lumi2::{utils::logging::setup_logging, cli::Args};
+use log::{error, info};
+use lumi2::{utils::logging::{init_lumi_home, setup_logging, setup_logging_to_stderr}, cli::Args};
+use lumi2::debugger::{gdb, DebugConsole, Debugger};
+use lumi2::repl::REPL;
+use lumi2::vm::config::VmConfig;
+use lumi2::vm::program::Program;
+use lumi2::vm::trace::{diff_traces, Tracer};
+use lumi2::vm::virtual_machine::VirtualMachine;
+use lumi_asm::file_assembler::assemble_file;
 
 pub const VM_VERSION: &str = "2.0.0";
 
 #[tokio::main]
 async fn main() {
-  if let Err(e) = setup_logging() {
+  init_lumi_home();
+  let args = Args::parse();
+
+  // the GDB protocol on stdio needs stdout to itself
+  let stdio_protocol = matches!(&args.command, lumi2::cli::Commands::Debug { gdb: Some(gdb), .. } if gdb == "stdio");
+  let logging = if stdio_protocol { setup_logging_to_stderr() } else { setup_logging() };
+  if let Err(e) = logging {
     eprintln!("Error setting logging: {}", e);
     return;
   }
 
-  let args = Args::parse();
-
   info!("Lumi GPL VM v{}", VM_VERSION);
 
   match args.command {
     lumi2::cli::Commands::Assemble { input_file } => {
-      info!("assembling {} file...", input_file.unwrap_or("".to_string()));
+      let Some(input_file) = input_file else {
+        error!("No input file provided to assemble");
+        std::process::exit(1);
+      };
+
+      info!("assembling {} file...", input_file);
+      if let Err(errors) = assemble_file(&input_file) {
+        for err in errors {
+          error!("Assembly error: {}", err);
+        }
+        std::process::exit(1);
+      }
+      info!("assembled {} successfully", input_file);
     }
-    lumi2::cli::Commands::Run { input_file } => {
+    lumi2::cli::Commands::Run { input_file, max_heap_bytes, max_stack_depth, fuel, extensions_dir, trace } => {
       info!("running {} executable...", input_file);
+      let program = match Program::from_file(&input_file) {
+        Ok(program) => program,
+        Err(err) => {
+          error!("Could not load executable {}: {}", input_file, err);
+          std::process::exit(1);
+        }
+      };
+
+      let default_config = VmConfig::default();
+      let config = VmConfig {
+        max_heap_bytes,
+        max_stack_depth,
+        fuel,
+        journal: None,
+        extensions_dir: extensions_dir.map(Into::into).unwrap_or(default_config.extensions_dir),
+      };
+
+      let mut vm = VirtualMachine::builder().config(config).build();
+      vm.load(program);
+      if let Some(trace) = trace {
+        match Tracer::create(&trace) {
+          Ok(tracer) => vm.tracer = Some(tracer),
+          Err(err) => {
+            error!("Could not create trace file {}: {}", trace, err);
+            std::process::exit(1);
+          }
+        }
+      }
+      let events = vm.run();
+      for event in &events {
+        info!("{:?}", event);
+      }
+      // the process exits without dropping the VM, so flush the trace by hand
+      if let Some(Err(err)) = vm.tracer.as_mut().map(Tracer::flush) {
+        error!("Could not write trace: {}", err);
+      }
+
+      let exit_code = events
+        .last()
+        .map(|event| event.event_type.stop_code())
+        .unwrap_or(1);
+      std::process::exit(exit_code as i32);
+    }
+    lumi2::cli::Commands::Debug { input_file, max_heap_bytes, max_stack_depth, extensions_dir, gdb, journal } => {
+      let program = match Program::from_file(&input_file) {
+        Ok(program) => program,
+        Err(err) => {
+          error!("Could not load executable {}: {}", input_file, err);
+          std::process::exit(1);
+        }
+      };
+
+      let default_config = VmConfig::default();
+      let config = VmConfig {
+        max_heap_bytes,
+        max_stack_depth,
+        fuel: None,
+        journal,
+        extensions_dir: extensions_dir.map(Into::into).unwrap_or(default_config.extensions_dir),
+      };
+
+      let mut vm = VirtualMachine::builder().config(config).build();
+      vm.load(program);
+      let mut debugger = Debugger::new(vm);
+      let served = match gdb.as_deref() {
+        Some("stdio") => gdb::serve_stdio(&mut debugger),
+        Some(address) => gdb::listen(&mut debugger, address),
+        None => {
+          DebugConsole::new(debugger).run();
+          Ok(())
+        }
+      };
+      if let Err(err) = served {
+        error!("GDB connection failed: {}", err);
+        std::process::exit(1);
+      }
+    }
+    lumi2::cli::Commands::TraceDiff { left, right } => {
+      let open = |path: &str| match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => {
+          error!("Could not open trace {}: {}", path, err);
+          std::process::exit(2);
+        }
+      };
+      match diff_traces(open(&left), open(&right)) {
+        Ok(None) => println!("Traces are identical"),
+        Ok(Some(divergence)) => {
+          println!("{}", divergence);
+          std::process::exit(1);
+        }
+        Err(err) => {
+          error!("{}", err);
+          std::process::exit(2);
+        }
+      }
     }
     lumi2::cli::Commands::Console {} => {
       info!("launching REPL console...");
+      let mut repl = REPL::new();
+      repl.run();
     }
   }
 }
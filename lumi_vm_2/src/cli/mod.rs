@@ -27,6 +27,50 @@ pub enum Commands {
         /// Path to the assembled file to run
         #[arg(short, long)]
         input_file: String,
+        /// Maximum heap size in bytes
+        #[arg(long)]
+        max_heap_bytes: Option<usize>,
+        /// Maximum number of values on the stack
+        #[arg(long)]
+        max_stack_depth: Option<usize>,
+        /// Maximum number of instructions to execute
+        #[arg(long)]
+        fuel: Option<u64>,
+        /// Directory to load VM extensions from
+        #[arg(long)]
+        extensions_dir: Option<String>,
+        /// Write a JSON line per executed instruction to this file
+        #[arg(long, value_name = "FILE")]
+        trace: Option<String>,
+    },
+    /// Debug an assembled program with breakpoints and stepping
+    Debug {
+        /// Path to the assembled file to debug
+        #[arg(short, long)]
+        input_file: String,
+        /// Maximum heap size in bytes
+        #[arg(long)]
+        max_heap_bytes: Option<usize>,
+        /// Maximum number of values on the stack
+        #[arg(long)]
+        max_stack_depth: Option<usize>,
+        /// Directory to load VM extensions from
+        #[arg(long)]
+        extensions_dir: Option<String>,
+        /// Serve the GDB remote protocol on this TCP address, or on `stdio`, instead of opening the console
+        #[arg(long, value_name = "ADDRESS")]
+        gdb: Option<String>,
+        /// Keep this many recent instructions in a journal so the debugger can step backwards
+        #[arg(long, value_name = "INSTRUCTIONS")]
+        journal: Option<usize>,
+    },
+    /// Compare two traces written by `run --trace` and report where they first differ.
+    /// Exits with 0 when they match, 1 when they differ and 2 when a trace cannot be read
+    TraceDiff {
+        /// The first trace
+        left: String,
+        /// The second trace
+        right: String,
     },
     /// Open a REPL console
     Console {
@@ -0,0 +1,353 @@
+use std::fmt::Write as _;
+use std::io;
+use std::io::Write;
+use log::error;
+use crate::vm::watch::{WatchCondition, WatchMode, WatchTarget};
+use super::{Debugger, StopReason};
+
+const HELP: &str = "\
+break <addr|label>       set a breakpoint (b)
+delete <addr|label>      remove a breakpoint (d)
+breakpoints              list breakpoints
+watch <target> [if <condition>] [log]
+                         stop or log when a location changes, targets are
+                         reg <n>, freg <n>, heap <addr> [len], stack <slot>
+                         and flag, conditions look like `value > 10`
+unwatch <id>             remove a watchpoint
+watches                  list watchpoints
+step                     execute one instruction (s)
+next                     execute one instruction, stepping over calls (n)
+continue                 run to the next breakpoint (c)
+finish                   run until the current routine returns
+reverse-step             undo one instruction (rs)
+reverse-continue         run backwards to a breakpoint or the last write to a
+                         watched location (rc), both need `--journal` and
+                         stop at the last set, fset or write, which clear it
+where                    show the call stack (bt)
+registers                show the integer registers (regs)
+fregisters               show the float registers (fregs)
+stack                    show the stack
+x <addr> [len]           show heap bytes
+set $<reg> <value>       write an integer register
+fset $<reg> <value>      write a float register
+write <addr> <byte>...   write heap bytes
+quit                     leave the debugger (q)";
+
+/// Terminal front-end for a `Debugger`, reading one command per line.
+pub struct DebugConsole {
+  debugger: Debugger,
+}
+
+impl DebugConsole {
+  pub fn new(debugger: Debugger) -> Self {
+    DebugConsole { debugger }
+  }
+
+  pub fn debugger(&self) -> &Debugger {
+    &self.debugger
+  }
+
+  /// Run a command and return what to print, or `None` when the session should end.
+  pub fn execute_command(&mut self, line: &str) -> Option<String> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let Some((command, args)) = tokens.split_first() else {
+      return Some(String::new());
+    };
+
+    let output = match *command {
+      "break" | "b" => self.command_break(args),
+      "delete" | "d" => self.command_delete(args),
+      "breakpoints" => Ok(self.command_breakpoints()),
+      "watch" | "w" => self.command_watch(args),
+      "unwatch" => self.command_unwatch(args),
+      "watches" => Ok(self.command_watches()),
+      "step" | "s" => Ok(self.stop(Debugger::step)),
+      "next" | "n" => Ok(self.stop(Debugger::step_over)),
+      "continue" | "c" => Ok(self.stop(Debugger::resume)),
+      "finish" => Ok(self.stop(Debugger::finish)),
+      "reverse-step" | "rs" => Ok(self.stop(Debugger::reverse_step)),
+      "reverse-continue" | "rc" => Ok(self.stop(Debugger::reverse_resume)),
+      "where" | "bt" => Ok(self.command_where()),
+      "registers" | "regs" => Ok(self.command_registers()),
+      "fregisters" | "fregs" => Ok(self.command_float_registers()),
+      "stack" => Ok(format!("{:?}", self.debugger.stack())),
+      "x" => self.command_examine(args),
+      "set" => self.command_set(args, false),
+      "fset" => self.command_set(args, true),
+      "write" => self.command_write(args),
+      "help" | "h" => Ok(HELP.to_string()),
+      "quit" | "q" => return None,
+      _ => Err(format!("Unrecognized command: {}, try help", command)),
+    };
+    Some(output.unwrap_or_else(|message| message))
+  }
+
+  /// Print where the program is and read commands from stdin until `quit` or end of input.
+  pub fn run(&mut self) {
+    println!("{}", self.describe_pc());
+    loop {
+      print!("(lumi) ");
+      io::stdout().flush().expect("Unable to flush stdout");
+
+      let mut buffer = String::new();
+      match io::stdin().read_line(&mut buffer) {
+        Ok(0) => return,
+        Ok(_) => {}
+        Err(err) => {
+          error!("Unable to read line: {}", err);
+          return;
+        }
+      }
+
+      match self.execute_command(&buffer) {
+        Some(output) if output.is_empty() => {}
+        Some(output) => println!("{}", output),
+        None => return,
+      }
+    }
+  }
+
+  fn command_break(&mut self, args: &[&str]) -> Result<String, String> {
+    let location = args.first().ok_or("Usage: break <addr|label>")?;
+    let address = self.debugger.add_breakpoint(location).map_err(|err| err.to_string())?;
+    Ok(format!("Breakpoint at {}", self.debugger.vm().describe_pc(address)))
+  }
+
+  fn command_delete(&mut self, args: &[&str]) -> Result<String, String> {
+    let location = args.first().ok_or("Usage: delete <addr|label>")?;
+    match self.debugger.remove_breakpoint(location) {
+      Ok(true) => Ok(format!("Deleted breakpoint at {}", location)),
+      Ok(false) => Err(format!("No breakpoint at {}", location)),
+      Err(err) => Err(err.to_string()),
+    }
+  }
+
+  fn command_breakpoints(&self) -> String {
+    let breakpoints: Vec<String> =
+      self.debugger.breakpoints().map(|address| self.debugger.vm().describe_pc(address)).collect();
+    if breakpoints.is_empty() {
+      "No breakpoints".to_string()
+    } else {
+      breakpoints.join("\n")
+    }
+  }
+
+  fn command_watch(&mut self, args: &[&str]) -> Result<String, String> {
+    let usage = "Usage: watch <reg <n>|freg <n>|heap <addr> [len]|stack <slot>|flag> [if <condition>] [log]";
+    let (args, mode) = match args.split_last() {
+      Some((&"log", rest)) => (rest, WatchMode::Log),
+      _ => (args, WatchMode::Break),
+    };
+    let (target, condition) = match args.iter().position(|arg| *arg == "if") {
+      Some(index) => {
+        let text = args[index + 1..].join(" ");
+        let condition = WatchCondition::parse(&text).ok_or(format!("Invalid condition {}", text))?;
+        (&args[..index], Some(condition))
+      }
+      None => (args, None),
+    };
+
+    let number = |index: usize| target.get(index).ok_or(usage.to_string()).and_then(|text| parse_number(text));
+    let target = match target.first() {
+      Some(&"reg") => WatchTarget::Register(number(1)?),
+      Some(&"freg") => WatchTarget::FloatRegister(number(1)?),
+      Some(&"heap") => WatchTarget::Heap { addr: number(1)?, len: if target.len() > 2 { number(2)? } else { 4 } },
+      Some(&"stack") => WatchTarget::Stack(number(1)?),
+      Some(&"flag") => WatchTarget::EqualFlag,
+      _ => return Err(usage.to_string()),
+    };
+    if let WatchTarget::Register(register) | WatchTarget::FloatRegister(register) = target {
+      if register >= self.debugger.registers().len() {
+        return Err(format!("Invalid register ${}", register));
+      }
+    }
+
+    let id = self.debugger.add_watchpoint(target, condition, mode).map_err(|err| err.to_string())?;
+    Ok(format!("Watchpoint {}: {}", id, target))
+  }
+
+  fn command_unwatch(&mut self, args: &[&str]) -> Result<String, String> {
+    let id = parse_number(args.first().ok_or("Usage: unwatch <id>")?)?;
+    if self.debugger.remove_watchpoint(id) {
+      Ok(format!("Deleted watchpoint {}", id))
+    } else {
+      Err(format!("No watchpoint {}", id))
+    }
+  }
+
+  fn command_watches(&self) -> String {
+    let mut output = String::new();
+    for watchpoint in self.debugger.watchpoints() {
+      let _ = write!(output, "{}: {} = {}", watchpoint.id, watchpoint.target, watchpoint.value);
+      if let Some(condition) = watchpoint.condition {
+        let _ = write!(output, " if {}", condition);
+      }
+      if watchpoint.mode == WatchMode::Log {
+        output.push_str(" (log)");
+      }
+      output.push('\n');
+    }
+    if output.is_empty() {
+      "No watchpoints".to_string()
+    } else {
+      output.trim_end().to_string()
+    }
+  }
+
+  fn command_where(&self) -> String {
+    let frames = self.debugger.backtrace();
+    let mut output = String::new();
+    for (depth, frame) in frames.iter().enumerate() {
+      let _ = writeln!(output, "#{} {}", depth, frame);
+    }
+    output.trim_end().to_string()
+  }
+
+  fn command_registers(&self) -> String {
+    let mut output = String::new();
+    for (index, value) in self.debugger.registers().iter().enumerate() {
+      let _ = write!(output, "${:<2} {:>11}", index, value);
+      output.push(if index % 4 == 3 { '\n' } else { ' ' });
+    }
+    let _ = write!(output, "pc  0x{:x}  equal_flag {}", self.debugger.pc(), self.debugger.equal_flag());
+    output
+  }
+
+  fn command_float_registers(&self) -> String {
+    let mut output = String::new();
+    for (index, value) in self.debugger.float_registers().iter().enumerate() {
+      let _ = write!(output, "${:<2} {:>14?}", index, value);
+      output.push(if index % 4 == 3 { '\n' } else { ' ' });
+    }
+    output.trim_end().to_string()
+  }
+
+  fn command_examine(&self, args: &[&str]) -> Result<String, String> {
+    let addr = parse_number(args.first().ok_or("Usage: x <addr> [len]")?)?;
+    let len = args.get(1).map_or(Ok(16), |len| parse_number(len))?;
+    let bytes = self.debugger.read_memory(addr, len).map_err(|err| err.to_string())?;
+    let mut output = String::new();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+      let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+      let _ = writeln!(output, "0x{:06x}  {}", addr + row * 16, hex.join(" "));
+    }
+    Ok(output.trim_end().to_string())
+  }
+
+  fn command_set(&mut self, args: &[&str], float: bool) -> Result<String, String> {
+    let usage = if float { "Usage: fset $<reg> <value>" } else { "Usage: set $<reg> <value>" };
+    let [register, value] = args else {
+      return Err(usage.to_string());
+    };
+    let register = register.strip_prefix('$').and_then(|reg| reg.parse().ok()).ok_or(usage)?;
+    let result = if float {
+      let value = value.parse().map_err(|_| format!("Invalid float {}", value))?;
+      self.debugger.set_float_register(register, value)
+    } else {
+      let value = value.parse().map_err(|_| format!("Invalid integer {}", value))?;
+      self.debugger.set_register(register, value)
+    };
+    result.map(|()| String::new()).map_err(|err| err.to_string())
+  }
+
+  fn command_write(&mut self, args: &[&str]) -> Result<String, String> {
+    let usage = "Usage: write <addr> <byte>...";
+    let (addr, bytes) = args.split_first().filter(|(_, bytes)| !bytes.is_empty()).ok_or(usage)?;
+    let addr = parse_number(addr)?;
+    let bytes = bytes
+      .iter()
+      .map(|byte| {
+        let byte = byte.trim_start_matches("0x");
+        u8::from_str_radix(byte, 16).map_err(|_| format!("Invalid byte {}", byte))
+      })
+      .collect::<Result<Vec<u8>, String>>()?;
+    self.debugger.write_memory(addr, &bytes).map_err(|err| err.to_string())?;
+    Ok(String::new())
+  }
+
+  /// Run one of the debugger's execution commands and describe where it stopped.
+  fn stop(&mut self, command: fn(&mut Debugger) -> StopReason) -> String {
+    match command(&mut self.debugger) {
+      StopReason::Breakpoint(_) => format!("Breakpoint hit\n{}", self.describe_pc()),
+      StopReason::BreakpointInstruction(pc) => {
+        format!("BKPT at {}\n{}", self.debugger.vm().describe_pc(pc), self.describe_pc())
+      }
+      StopReason::Watchpoint { id, pc } => {
+        let watchpoint = self.debugger.watchpoints().iter().find(|watchpoint| watchpoint.id == id);
+        let change = watchpoint.map_or(String::new(), |watchpoint| format!(": {} = {}", watchpoint.target, watchpoint.value));
+        format!("Watchpoint {} hit at {}{}\n{}", id, self.debugger.vm().describe_pc(pc), change, self.describe_pc())
+      }
+      StopReason::Stepped => self.describe_pc(),
+      StopReason::Halted(code) => format!("Program finished with exit code {}", code),
+      StopReason::Trapped(trap) => format!("Program trapped at {}: {}", self.debugger.location(), trap),
+      StopReason::HistoryStart => format!("No earlier instructions in the journal\n{}", self.describe_pc()),
+      StopReason::Interrupted => format!("Interrupted\n{}", self.describe_pc()),
+    }
+  }
+
+  /// The pc, its source location and the instruction there.
+  fn describe_pc(&self) -> String {
+    let instruction = self.debugger.current_instruction().map_or("??".to_string(), |instruction| instruction.to_string());
+    format!("=> 0x{:x}  {}  {}", self.debugger.pc(), instruction, self.debugger.location())
+  }
+}
+
+/// Parse a `0x` hex or decimal number.
+fn parse_number(text: &str) -> Result<usize, String> {
+  let parsed = match text.strip_prefix("0x") {
+    Some(hex) => usize::from_str_radix(hex, 16),
+    None => text.parse(),
+  };
+  parsed.map_err(|_| format!("Invalid number {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lumi_asm::Assembler;
+  use crate::vm::program::Program;
+  use crate::vm::virtual_machine::VirtualMachine;
+
+  #[test]
+  fn test_console_commands() {
+    let source = ".data\nbuffer: .space #4\n.code\nload $0 #3\ncall @work\nhlt\nwork: inc $0\nret\n";
+    let bytecode = Assembler::new().with_debug_info("main.lumi").assemble(source).unwrap();
+    let mut vm = VirtualMachine::initialize();
+    vm.load(Program::from_bytes(bytecode).unwrap());
+    let mut console = DebugConsole::new(Debugger::new(vm));
+
+    assert_eq!(console.execute_command("b work"), Some("Breakpoint at work (main.lumi:7:7)".to_string()));
+    assert_eq!(console.execute_command("breakpoints"), Some("work (main.lumi:7:7)".to_string()));
+    let stop = console.execute_command("c").unwrap();
+    assert!(stop.starts_with("Breakpoint hit\n=> 0x"), "{}", stop);
+    assert!(stop.ends_with("INC $0  work (main.lumi:7:7)"), "{}", stop);
+    assert_eq!(console.execute_command("bt").unwrap().lines().count(), 2);
+    assert!(console.execute_command("regs").unwrap().starts_with("$0            3 $1            0"));
+
+    assert_eq!(console.execute_command("set $0 40"), Some(String::new()));
+    assert_eq!(console.execute_command("write 0x1 ff 0x10"), Some(String::new()));
+    assert_eq!(console.execute_command("x 0 4"), Some("0x000000  00 ff 10 00".to_string()));
+    assert_eq!(console.execute_command("x 2 4"), Some("4 bytes at 0x2 are outside of the heap".to_string()));
+    assert_eq!(console.execute_command("set 0 1"), Some("Usage: set $<reg> <value>".to_string()));
+
+    assert_eq!(console.execute_command("watch heap 0 2 if value != 0"), Some("Watchpoint 1: heap 0x0..0x2".to_string()));
+    assert_eq!(console.execute_command("watch reg 0 log"), Some("Watchpoint 2: register $0".to_string()));
+    assert_eq!(console.execute_command("watch reg 40"), Some("Invalid register $40".to_string()));
+    assert_eq!(
+      console.execute_command("watch heap 0xffffffffffffffff 2"),
+      Some("2 bytes at 0xffffffffffffffff are outside of the heap".to_string())
+    );
+    assert_eq!(console.execute_command("watch flag if ~"), Some("Invalid condition ~".to_string()));
+    assert_eq!(
+      console.execute_command("watches"),
+      Some("1: heap 0x0..0x2 = [00 ff] if value != 0\n2: register $0 = 40 (log)".to_string())
+    );
+    assert_eq!(console.execute_command("unwatch 1"), Some("Deleted watchpoint 1".to_string()));
+    assert!(console.execute_command("finish").unwrap().contains("HLT"));
+    assert_eq!(console.debugger().registers()[0], 41);
+    assert_eq!(console.execute_command("c"), Some("Program finished with exit code 0".to_string()));
+    assert!(console.execute_command("rc").unwrap().starts_with("No earlier instructions in the journal\n"));
+    assert_eq!(console.execute_command("jump"), Some("Unrecognized command: jump, try help".to_string()));
+    assert_eq!(console.execute_command("q"), None);
+  }
+}
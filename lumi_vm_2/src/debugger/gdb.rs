@@ -0,0 +1,671 @@
+//! A GDB Remote Serial Protocol server for the `Debugger`.
+//!
+//! Registers are numbered `r0`-`r31` (0-31, 32 bit), `f0`-`f31` (32-63, 64 bit float), `pc`
+//! (64) and `eq` (65, the equal flag). The program image is mapped at address 0 and is read
+//! only, the heap is mapped at `HEAP_BASE`. `bs` and `bc` step and continue backwards when
+//! the VM keeps a journal. The client is read on a separate thread, so a running program
+//! notices an interrupt (`0x03`) within `INTERRUPT_CHECK_INTERVAL` instructions.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+use log::{debug, info};
+use crate::vm::trap::VmTrap;
+use crate::vm::watch::{WatchMode, WatchTarget};
+use super::{Debugger, StopReason};
+
+/// Address the heap is mapped at in the debugger's address space.
+pub const HEAP_BASE: usize = 0x1000_0000;
+
+const REGISTER_COUNT: usize = 32;
+const PC_REGISTER: usize = 2 * REGISTER_COUNT;
+const EQUAL_FLAG_REGISTER: usize = PC_REGISTER + 1;
+
+const TARGET_XML_HEADER: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.lumi.core">
+"#;
+
+/// Wait for one client on `address` and serve it until it detaches or kills the program.
+pub fn listen(debugger: &mut Debugger, address: impl ToSocketAddrs) -> io::Result<()> {
+  let listener = TcpListener::bind(address)?;
+  info!("Waiting for a GDB connection on {}", listener.local_addr()?);
+  let (stream, peer) = listener.accept()?;
+  info!("GDB connected from {}", peer);
+  // replies are small and sent one at a time, do not hold them back
+  stream.set_nodelay(true)?;
+  serve(debugger, stream.try_clone()?, stream)
+}
+
+/// Serve the protocol on standard input and output, for `target remote | lumi2 debug --gdb stdio`.
+pub fn serve_stdio(debugger: &mut Debugger) -> io::Result<()> {
+  serve(debugger, io::stdin(), io::stdout().lock())
+}
+
+/// Answer packets read from `reader` until the client detaches, kills the program or hangs up.
+pub fn serve(debugger: &mut Debugger, reader: impl Read + Send + 'static, writer: impl Write) -> io::Result<()> {
+  let mut session = Session {
+    debugger,
+    reader: Incoming::spawn(reader),
+    writer,
+    no_ack: false,
+    last_stop: StopReason::Stepped,
+    done: false,
+  };
+  while let Some(packet) = session.read_packet()? {
+    debug!("gdb <- {}", packet);
+    if let Some(reply) = session.handle(&packet) {
+      debug!("gdb -> {}", reply);
+      session.write_packet(&reply)?;
+    }
+    if session.done {
+      break;
+    }
+  }
+  Ok(())
+}
+
+/// Bytes from the client, read on a thread of their own so they can be polled while the
+/// program runs. The thread exits when the client hangs up.
+struct Incoming {
+  chunks: Receiver<io::Result<Vec<u8>>>,
+  pending: VecDeque<u8>,
+  /// A read error seen while polling, reported once the pending bytes are consumed.
+  error: Option<io::Error>,
+}
+
+impl Incoming {
+  fn spawn(mut reader: impl Read + Send + 'static) -> Self {
+    let (sender, chunks) = mpsc::channel();
+    thread::spawn(move || {
+      let mut buffer = [0; 4096];
+      loop {
+        let chunk = match reader.read(&mut buffer) {
+          Ok(0) => break,
+          Ok(length) => Ok(buffer[..length].to_vec()),
+          Err(err) => Err(err),
+        };
+        let failed = chunk.is_err();
+        if sender.send(chunk).is_err() || failed {
+          break;
+        }
+      }
+    });
+    Incoming { chunks, pending: VecDeque::new(), error: None }
+  }
+
+  /// Take an interrupt byte out of what the client sent so far, without blocking.
+  fn interrupted(&mut self) -> bool {
+    while self.error.is_none() {
+      match self.chunks.try_recv() {
+        Ok(Ok(chunk)) => self.pending.extend(chunk),
+        Ok(Err(err)) => self.error = Some(err),
+        // a hang-up is seen by the next read
+        Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+      }
+    }
+    match self.pending.iter().position(|byte| *byte == 0x03) {
+      Some(index) => {
+        self.pending.remove(index);
+        true
+      }
+      None => false,
+    }
+  }
+}
+
+impl Read for Incoming {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let available = self.fill_buf()?;
+    let length = buf.len().min(available.len());
+    buf[..length].copy_from_slice(&available[..length]);
+    self.consume(length);
+    Ok(length)
+  }
+}
+
+impl BufRead for Incoming {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    if self.pending.is_empty() {
+      if let Some(err) = self.error.take() {
+        return Err(err);
+      }
+      // an empty buffer tells the caller the client hung up
+      if let Ok(chunk) = self.chunks.recv() {
+        self.pending.extend(chunk?);
+      }
+    }
+    Ok(self.pending.make_contiguous())
+  }
+
+  fn consume(&mut self, amount: usize) {
+    self.pending.drain(..amount);
+  }
+}
+
+struct Session<'a, W> {
+  debugger: &'a mut Debugger,
+  reader: Incoming,
+  writer: W,
+  no_ack: bool,
+  last_stop: StopReason,
+  done: bool,
+}
+
+impl<W: Write> Session<'_, W> {
+  /// Read the next packet, acknowledging it unless no-ack mode is on. An interrupt byte that
+  /// arrives while the program is stopped is answered like `?`.
+  fn read_packet(&mut self) -> io::Result<Option<String>> {
+    loop {
+      let Some(byte) = self.read_byte()? else {
+        return Ok(None);
+      };
+      match byte {
+        b'$' => {}
+        0x03 => return Ok(Some("?".to_string())),
+        _ => continue,
+      }
+
+      let mut data = vec![];
+      self.reader.read_until(b'#', &mut data)?;
+      if data.pop() != Some(b'#') {
+        return Ok(None);
+      }
+      let mut checksum = [0; 2];
+      self.reader.read_exact(&mut checksum)?;
+      let expected = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+      if expected != Some(checksum_of(&data)) && !self.no_ack {
+        self.writer.write_all(b"-")?;
+        self.writer.flush()?;
+        continue;
+      }
+      if !self.no_ack {
+        self.writer.write_all(b"+")?;
+      }
+      return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+    }
+  }
+
+  fn read_byte(&mut self) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match self.reader.read(&mut byte)? {
+      0 => Ok(None),
+      _ => Ok(Some(byte[0])),
+    }
+  }
+
+  fn write_packet(&mut self, data: &str) -> io::Result<()> {
+    let data = escape(data.as_bytes());
+    write!(self.writer, "$")?;
+    self.writer.write_all(&data)?;
+    write!(self.writer, "#{:02x}", checksum_of(&data))?;
+    self.writer.flush()
+  }
+
+  /// Reply to a packet, `None` sends nothing.
+  fn handle(&mut self, packet: &str) -> Option<String> {
+    let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+    let reply = match command {
+      "?" => self.stop_reply(self.last_stop),
+      "g" => self.read_registers(),
+      "G" => ok_or_error(self.write_registers(args)),
+      "p" => self.read_register(args).unwrap_or_else(|| "E01".to_string()),
+      "P" => ok_or_error(self.write_register(args)),
+      "m" => self.read_memory(args).unwrap_or_else(|| "E01".to_string()),
+      "M" => ok_or_error(self.write_memory(args)),
+      "Z" | "z" => self.breakpoint(command == "Z", args),
+      "c" => self.resume(),
+      "s" => self.run(Debugger::step),
+      "b" => match args {
+        "s" => self.run(Debugger::reverse_step),
+        "c" => self.run(Debugger::reverse_resume),
+        _ => String::new(),
+      },
+      "v" => self.handle_v(packet),
+      "q" | "Q" => self.handle_query(packet),
+      "H" | "T" => "OK".to_string(),
+      "D" => {
+        self.done = true;
+        "OK".to_string()
+      }
+      "k" => {
+        self.done = true;
+        return None;
+      }
+      _ => String::new(),
+    };
+    Some(reply)
+  }
+
+  fn handle_query(&mut self, packet: &str) -> String {
+    match packet.split(':').next().unwrap_or_default() {
+      "qSupported" => "PacketSize=4000;QStartNoAckMode+;qXfer:features:read+;swbreak+;ReverseStep+;ReverseContinue+".to_string(),
+      "QStartNoAckMode" => {
+        self.no_ack = true;
+        "OK".to_string()
+      }
+      "qAttached" => "1".to_string(),
+      "qC" => "QC1".to_string(),
+      "qfThreadInfo" => "m1".to_string(),
+      "qsThreadInfo" => "l".to_string(),
+      "qXfer" => {
+        let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") else {
+          return "E00".to_string();
+        };
+        let Some((offset, length)) = parse_pair(range) else {
+          return "E01".to_string();
+        };
+        let xml = target_xml();
+        let end = offset.saturating_add(length).min(xml.len());
+        let chunk = xml.get(offset.min(end)..end).unwrap_or_default();
+        let more = end < xml.len();
+        format!("{}{}", if more { "m" } else { "l" }, chunk)
+      }
+      _ => String::new(),
+    }
+  }
+
+  fn handle_v(&mut self, packet: &str) -> String {
+    match packet {
+      "vCont?" => "vCont;c;s".to_string(),
+      _ if packet.starts_with("vCont;c") => self.resume(),
+      _ if packet.starts_with("vCont;s") => self.run(Debugger::step),
+      "vKill;1" => {
+        self.done = true;
+        "OK".to_string()
+      }
+      _ => String::new(),
+    }
+  }
+
+  fn run(&mut self, command: fn(&mut Debugger) -> StopReason) -> String {
+    self.last_stop = command(self.debugger);
+    self.stop_reply(self.last_stop)
+  }
+
+  /// Continue until the program stops or the client sends an interrupt.
+  fn resume(&mut self) -> String {
+    let reader = &mut self.reader;
+    self.last_stop = self.debugger.resume_interruptible(|| reader.interrupted());
+    self.stop_reply(self.last_stop)
+  }
+
+  fn stop_reply(&self, reason: StopReason) -> String {
+    match reason {
+      StopReason::Breakpoint(_) => "T05swbreak:;".to_string(),
+      // the pc is already past a compiled-in `BKPT`, `swbreak` would make GDB run it again
+      StopReason::BreakpointInstruction(_) => "S05".to_string(),
+      StopReason::Watchpoint { id, .. } => {
+        let watchpoint = self.debugger.watchpoints().iter().find(|watchpoint| watchpoint.id == id);
+        match watchpoint.map(|watchpoint| watchpoint.target) {
+          Some(WatchTarget::Heap { addr, .. }) => format!("T05watch:{:x};", HEAP_BASE + addr),
+          _ => "S05".to_string(),
+        }
+      }
+      StopReason::Stepped => "S05".to_string(),
+      StopReason::Halted(code) => format!("W{:02x}", code & 0xff),
+      StopReason::Trapped(trap) => format!("X{:02x}", signal(trap)),
+      StopReason::HistoryStart => "T05replaylog:begin;".to_string(),
+      // SIGINT
+      StopReason::Interrupted => "S02".to_string(),
+    }
+  }
+
+  fn read_registers(&self) -> String {
+    let mut reply = String::new();
+    for register in 0..=EQUAL_FLAG_REGISTER {
+      reply.push_str(&self.read_register_value(register));
+    }
+    reply
+  }
+
+  fn read_register(&self, args: &str) -> Option<String> {
+    let register = usize::from_str_radix(args, 16).ok()?;
+    (register <= EQUAL_FLAG_REGISTER).then(|| self.read_register_value(register))
+  }
+
+  fn read_register_value(&self, register: usize) -> String {
+    let bytes = match register {
+      0..=31 => self.debugger.registers()[register].to_le_bytes().to_vec(),
+      32..=63 => self.debugger.float_registers()[register - REGISTER_COUNT].to_le_bytes().to_vec(),
+      PC_REGISTER => (self.debugger.pc() as u32).to_le_bytes().to_vec(),
+      _ => (self.debugger.equal_flag() as u32).to_le_bytes().to_vec(),
+    };
+    hex(&bytes)
+  }
+
+  fn write_registers(&mut self, args: &str) -> Option<()> {
+    let bytes = parse_hex(args)?;
+    let mut offset = 0;
+    for register in 0..=EQUAL_FLAG_REGISTER {
+      let width = register_width(register);
+      self.write_register_value(register, bytes.get(offset..offset + width)?)?;
+      offset += width;
+    }
+    Some(())
+  }
+
+  fn write_register(&mut self, args: &str) -> Option<()> {
+    let (register, value) = args.split_once('=')?;
+    let register = usize::from_str_radix(register, 16).ok()?;
+    let value = parse_hex(value)?;
+    (register <= EQUAL_FLAG_REGISTER && value.len() == register_width(register)).then_some(())?;
+    self.write_register_value(register, &value)
+  }
+
+  fn write_register_value(&mut self, register: usize, bytes: &[u8]) -> Option<()> {
+    match register {
+      0..=31 => self.debugger.set_register(register, i32::from_le_bytes(bytes.try_into().ok()?)).ok(),
+      32..=63 => self.debugger.set_float_register(register - REGISTER_COUNT, f64::from_le_bytes(bytes.try_into().ok()?)).ok(),
+      PC_REGISTER => {
+        self.debugger.set_pc(u32::from_le_bytes(bytes.try_into().ok()?) as usize);
+        Some(())
+      }
+      _ => {
+        self.debugger.set_equal_flag(u32::from_le_bytes(bytes.try_into().ok()?) != 0);
+        Some(())
+      }
+    }
+  }
+
+  fn read_memory(&self, args: &str) -> Option<String> {
+    let (addr, len) = parse_pair(args)?;
+    let bytes = if addr >= HEAP_BASE {
+      self.debugger.read_memory(addr - HEAP_BASE, len).ok()?
+    } else {
+      self.debugger.vm().program.get(addr..addr.checked_add(len)?)?
+    };
+    Some(hex(bytes))
+  }
+
+  fn write_memory(&mut self, args: &str) -> Option<()> {
+    let (range, data) = args.split_once(':')?;
+    let (addr, len) = parse_pair(range)?;
+    let bytes = parse_hex(data)?;
+    // the program image is read only
+    if addr < HEAP_BASE || bytes.len() != len {
+      return None;
+    }
+    self.debugger.write_memory(addr - HEAP_BASE, &bytes).ok()
+  }
+
+  /// `Z0`/`z0` set and clear software breakpoints, `Z2`/`z2` write watchpoints on the heap.
+  fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+    let mut fields = args.split(',');
+    let (Some(kind), Some(addr), Some(len)) = (fields.next(), fields.next(), fields.next()) else {
+      return "E01".to_string();
+    };
+    let (Ok(addr), Ok(len)) = (usize::from_str_radix(addr, 16), usize::from_str_radix(len, 16)) else {
+      return "E01".to_string();
+    };
+    match kind {
+      "0" if insert => ok_or_error(self.debugger.set_breakpoint(addr).ok()),
+      "0" => {
+        self.debugger.clear_breakpoint(addr);
+        "OK".to_string()
+      }
+      "2" if addr >= HEAP_BASE => {
+        let target = WatchTarget::Heap { addr: addr - HEAP_BASE, len };
+        if insert {
+          return ok_or_error(self.debugger.add_watchpoint(target, None, WatchMode::Break).ok().map(|_| ()));
+        }
+        let ids: Vec<usize> =
+          self.debugger.watchpoints().iter().filter(|watchpoint| watchpoint.target == target).map(|watchpoint| watchpoint.id).collect();
+        for id in ids {
+          self.debugger.remove_watchpoint(id);
+        }
+        "OK".to_string()
+      }
+      _ => String::new(),
+    }
+  }
+}
+
+/// Description of the register file, so GDB knows the names, sizes and order of `g` packets.
+fn target_xml() -> String {
+  let mut xml = TARGET_XML_HEADER.to_string();
+  for index in 0..REGISTER_COUNT {
+    let _ = writeln!(xml, r#"    <reg name="r{}" bitsize="32" type="int32" regnum="{}"/>"#, index, index);
+  }
+  for index in 0..REGISTER_COUNT {
+    let _ = writeln!(xml, r#"    <reg name="f{}" bitsize="64" type="ieee_double" regnum="{}"/>"#, index, REGISTER_COUNT + index);
+  }
+  let _ = writeln!(xml, r#"    <reg name="pc" bitsize="32" type="code_ptr" regnum="{}"/>"#, PC_REGISTER);
+  let _ = writeln!(xml, r#"    <reg name="eq" bitsize="32" type="int32" regnum="{}"/>"#, EQUAL_FLAG_REGISTER);
+  xml.push_str("  </feature>\n</target>\n");
+  xml
+}
+
+fn register_width(register: usize) -> usize {
+  match register {
+    32..=63 => 8,
+    _ => 4,
+  }
+}
+
+/// Signal reported to GDB for a program stopped by a trap.
+fn signal(trap: VmTrap) -> u8 {
+  const SIGILL: u8 = 4;
+  const SIGABRT: u8 = 6;
+  const SIGFPE: u8 = 8;
+  const SIGSEGV: u8 = 11;
+  match trap {
+    VmTrap::IllegalOpcode { .. } | VmTrap::UnimplementedOpcode { .. } | VmTrap::TruncatedInstruction { .. } => SIGILL,
+    VmTrap::InvalidRegister { .. } | VmTrap::InvalidHeader { .. } => SIGILL,
+    VmTrap::DivideByZero => SIGFPE,
+    VmTrap::PcOutOfBounds { .. } | VmTrap::MemoryFault { .. } | VmTrap::StackOverflow { .. } => SIGSEGV,
+    _ => SIGABRT,
+  }
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+  match result {
+    Some(()) => "OK".to_string(),
+    None => "E01".to_string(),
+  }
+}
+
+/// Parse `addr,len` in hex.
+fn parse_pair(text: &str) -> Option<(usize, usize)> {
+  let (first, second) = text.split_once(',')?;
+  Some((usize::from_str_radix(first, 16).ok()?, usize::from_str_radix(second, 16).ok()?))
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+  if !text.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+  data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn escape(data: &[u8]) -> Vec<u8> {
+  let mut escaped = Vec::with_capacity(data.len());
+  for byte in data {
+    if let b'#' | b'$' | b'}' | b'*' = byte {
+      escaped.extend([b'}', byte ^ 0x20]);
+    } else {
+      escaped.push(*byte);
+    }
+  }
+  escaped
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+  let mut unescaped = Vec::with_capacity(data.len());
+  let mut bytes = data.iter();
+  while let Some(byte) = bytes.next() {
+    match byte {
+      b'}' => unescaped.extend(bytes.next().map(|byte| byte ^ 0x20)),
+      _ => unescaped.push(*byte),
+    }
+  }
+  unescaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::net::TcpStream;
+  use std::thread;
+  use lumi_asm::header_utils::code_start_offset;
+  use lumi_asm::Assembler;
+  use crate::vm::program::Program;
+  use crate::vm::virtual_machine::VirtualMachine;
+
+  const PROGRAM: &str = r".data
+    counter: .integer #5
+    .code
+    load $0 #1
+    load $1 #0
+    setm $1 $0
+    inc $0
+    hlt
+    ";
+
+  struct Client {
+    stream: TcpStream,
+  }
+
+  impl Client {
+    /// Send a packet and return the reply, checking the acknowledgement and checksum.
+    fn request(&mut self, packet: &str) -> String {
+      self.send(packet);
+      let mut ack = [0];
+      self.stream.read_exact(&mut ack).unwrap();
+      assert_eq!(ack[0], b'+');
+      self.reply()
+    }
+
+    fn send(&mut self, packet: &str) {
+      write!(self.stream, "${}#{:02x}", packet, checksum_of(packet.as_bytes())).unwrap();
+    }
+
+    fn reply(&mut self) -> String {
+      let mut reply = vec![];
+      let mut byte = [0];
+      self.stream.read_exact(&mut byte).unwrap();
+      assert_eq!(byte[0], b'$');
+      loop {
+        self.stream.read_exact(&mut byte).unwrap();
+        if byte[0] == b'#' {
+          break;
+        }
+        reply.push(byte[0]);
+      }
+      let mut checksum = [0; 2];
+      self.stream.read_exact(&mut checksum).unwrap();
+      assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{:02x}", checksum_of(&reply)));
+      self.stream.write_all(b"+").unwrap();
+      String::from_utf8(unescape(&reply)).unwrap()
+    }
+  }
+
+  fn connect(source: &str) -> (Client, thread::JoinHandle<io::Result<()>>) {
+    let bytecode = Assembler::new().assemble(source).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+      let mut vm = VirtualMachine::initialize();
+      vm.load(Program::from_bytes(bytecode).unwrap());
+      let mut debugger = Debugger::new(vm);
+      let (stream, _) = listener.accept()?;
+      stream.set_nodelay(true)?;
+      serve(&mut debugger, stream.try_clone()?, stream)
+    });
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+    (Client { stream }, server)
+  }
+
+  #[test]
+  fn test_gdb_session_over_tcp() {
+    let (mut client, server) = connect(PROGRAM);
+    let start = code_start_offset(0);
+
+    assert!(client.request("qSupported:swbreak+").contains("qXfer:features:read+"));
+    assert_eq!(client.request("?"), "S05");
+    let xml = client.request("qXfer:features:read:target.xml:0,4000");
+    assert!(xml.starts_with("l<?xml"));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="32" type="code_ptr" regnum="64"/>"#));
+    assert_eq!(client.request(&format!("qXfer:features:read:target.xml:{:x},1", usize::MAX)), "l");
+    assert!(client.request(&format!("qXfer:features:read:target.xml:10,{:x}", usize::MAX)).starts_with("l"));
+
+    let registers = client.request("g");
+    assert_eq!(registers.len(), 2 * (32 * 4 + 32 * 8 + 4 + 4));
+    assert_eq!(client.request("p40"), hex(&(start as u32).to_le_bytes()));
+
+    assert_eq!(client.request(&format!("Z0,{:x},1", start + 13)), "OK");
+    assert_eq!(client.request(&format!("Z0,{:x},1", start + 1)), "E01");
+    assert_eq!(client.request(&format!("Z2,{:x},{:x}", usize::MAX, u32::MAX)), "E01");
+    assert_eq!(client.request(&format!("Z2,{:x},4", HEAP_BASE)), "OK");
+    assert_eq!(client.request("c"), format!("T05watch:{:x};", HEAP_BASE));
+    assert_eq!(client.request(&format!("m{:x},4", HEAP_BASE)), "01000000");
+    assert_eq!(client.request(&format!("z2,{:x},4", HEAP_BASE)), "OK");
+
+    assert_eq!(client.request("vCont;c"), "T05swbreak:;");
+    assert_eq!(client.request("p40"), hex(&(start as u32 + 13).to_le_bytes()));
+    assert_eq!(client.request("p0"), "02000000");
+
+    assert_eq!(client.request("P0=2a000000"), "OK");
+    assert_eq!(client.request(&format!("M{:x},2:ffee", HEAP_BASE + 2)), "OK");
+    assert_eq!(client.request(&format!("m{:x},4", HEAP_BASE)), "0100ffee");
+    assert_eq!(client.request("M0,1:00"), "E01");
+    assert_eq!(client.request("m0,2"), "4c55");
+
+    assert_eq!(client.request("s"), "W00");
+    assert_eq!(client.request("?"), "W00");
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap().unwrap();
+  }
+
+  #[test]
+  fn test_gdb_reports_traps_and_no_ack_mode() {
+    let (mut client, server) = connect(".data\n.code\nload $0 #1\nload $1 #0\ndiv $0 $1 $2\nhlt\n");
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.send("c");
+    assert_eq!(client.reply(), "X08");
+    client.send("vMustReplyEmpty");
+    assert_eq!(client.reply(), "");
+    client.send("k");
+    server.join().unwrap().unwrap();
+  }
+
+  #[test]
+  fn test_gdb_bkpt_and_interrupt() {
+    let (mut client, server) = connect(".data\n.code\nbkpt\nspin: djmp @spin\n");
+    // the pc is already past a compiled-in BKPT, so it is not reported as a swbreak
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p40"), hex(&(code_start_offset(0) as u32 + 1).to_le_bytes()));
+
+    client.send("c");
+    let mut ack = [0];
+    client.stream.read_exact(&mut ack).unwrap();
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.reply(), "S02");
+    assert_eq!(client.request("?"), "S02");
+    client.send("k");
+    server.join().unwrap().unwrap();
+  }
+
+  #[test]
+  fn test_packet_escaping() {
+    assert_eq!(escape(b"a#b}"), b"a}\x03b}]");
+    assert_eq!(unescape(&escape(b"$*#}")), b"$*#}");
+    assert_eq!(parse_hex("0aFf"), Some(vec![0x0a, 0xff]));
+    assert_eq!(parse_hex("0a0"), None);
+  }
+}
@@ -0,0 +1,515 @@
+mod console;
+pub mod gdb;
+
+pub use console::DebugConsole;
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Formatter;
+use lumi_asm::debug_info::DebugSymbolKind;
+use lumi_asm::encoding::{decode, DecodedInstruction};
+use lumi_asm::instruction::Opcode;
+use crate::vm::trap::VmTrap;
+use crate::vm::virtual_machine::{RunOutcome, VirtualMachine};
+use crate::vm::watch::{WatchCondition, WatchMode, WatchTarget, Watchpoint};
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+  /// The pc reached a breakpoint, the instruction there has not run yet.
+  Breakpoint(usize),
+  /// A `BKPT` instruction at the given offset was executed.
+  BreakpointInstruction(usize),
+  /// The instruction at `pc` changed the location of a breaking watchpoint.
+  Watchpoint { id: usize, pc: usize },
+  /// A step, next or finish command completed.
+  Stepped,
+  /// The program finished with the given exit code.
+  Halted(u32),
+  /// The program was stopped by a trap.
+  Trapped(VmTrap),
+  /// Stepping backwards ran out of journal, or the VM has no journal.
+  HistoryStart,
+  /// The client asked a running program to stop.
+  Interrupted,
+}
+
+/// Instructions `resume_interruptible` runs between checks for an interrupt.
+const INTERRUPT_CHECK_INTERVAL: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebuggerError {
+  /// The location is neither an address nor a code label from the debug info.
+  UnknownLocation { location: String },
+  /// No instruction starts at the address.
+  NotAnInstruction { address: usize },
+  /// The register does not exist.
+  InvalidRegister { register: usize },
+  /// Access to `len` bytes at `addr` falls outside of the heap.
+  MemoryFault { addr: usize, len: usize },
+  /// The VM refused to watch the target.
+  InvalidWatchpoint { target: WatchTarget },
+}
+
+impl fmt::Display for DebuggerError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      DebuggerError::UnknownLocation { location } => write!(f, "No address or label named {}", location),
+      DebuggerError::NotAnInstruction { address } => write!(f, "No instruction starts at 0x{:x}", address),
+      DebuggerError::InvalidRegister { register } => write!(f, "Invalid register ${}", register),
+      DebuggerError::MemoryFault { addr, len } => {
+        write!(f, "{} bytes at 0x{:x} are outside of the heap", len, addr)
+      }
+      DebuggerError::InvalidWatchpoint { target } => write!(f, "Cannot watch {}", target),
+    }
+  }
+}
+
+/// Drives a `VirtualMachine` one instruction at a time, stopping on breakpoints and after
+/// stepping commands, and gives access to its registers, stack and heap in between.
+pub struct Debugger {
+  vm: VirtualMachine,
+  breakpoints: BTreeSet<usize>,
+}
+
+impl Debugger {
+  /// Take over a VM with a program in it and move the pc to the entry point without running
+  /// any instructions.
+  pub fn new(mut vm: VirtualMachine) -> Self {
+    vm.run_for(0);
+    Debugger { vm, breakpoints: BTreeSet::new() }
+  }
+
+  pub fn vm(&self) -> &VirtualMachine {
+    &self.vm
+  }
+
+  /// Resolve an address, either `0x` hex or decimal, or the name of a code label.
+  pub fn resolve(&self, location: &str) -> Result<usize, DebuggerError> {
+    let location = location.trim_start_matches('@');
+    let address = match location.strip_prefix("0x") {
+      Some(hex) => usize::from_str_radix(hex, 16).ok(),
+      None => location.parse().ok(),
+    };
+    address
+      .or_else(|| {
+        let debug_info = self.vm.debug_info.as_ref()?;
+        let symbol = debug_info
+          .symbols
+          .iter()
+          .find(|symbol| symbol.kind == DebugSymbolKind::Label && symbol.name == location)?;
+        Some(symbol.value as usize)
+      })
+      .ok_or_else(|| DebuggerError::UnknownLocation { location: location.to_string() })
+  }
+
+  /// Set a breakpoint at an address or label and return the address it was set at.
+  pub fn add_breakpoint(&mut self, location: &str) -> Result<usize, DebuggerError> {
+    let address = self.resolve(location)?;
+    self.set_breakpoint(address)?;
+    Ok(address)
+  }
+
+  /// Set a breakpoint at an address, which has to be the start of an instruction.
+  pub fn set_breakpoint(&mut self, address: usize) -> Result<(), DebuggerError> {
+    if let Some(code) = self.vm.verified_code() {
+      if !code.is_instruction(address) {
+        return Err(DebuggerError::NotAnInstruction { address });
+      }
+    }
+    self.breakpoints.insert(address);
+    Ok(())
+  }
+
+  /// Remove the breakpoint at an address or label, returns whether there was one.
+  pub fn remove_breakpoint(&mut self, location: &str) -> Result<bool, DebuggerError> {
+    let address = self.resolve(location)?;
+    Ok(self.clear_breakpoint(address))
+  }
+
+  /// Remove the breakpoint at an address, returns whether there was one.
+  pub fn clear_breakpoint(&mut self, address: usize) -> bool {
+    self.breakpoints.remove(&address)
+  }
+
+  pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+    self.breakpoints.iter().copied()
+  }
+
+  /// Watch a location for changes and return the watchpoint's id.
+  pub fn add_watchpoint(&mut self, target: WatchTarget, condition: Option<WatchCondition>, mode: WatchMode) -> Result<usize, DebuggerError> {
+    self.vm.add_watchpoint(target, condition, mode).ok_or(match target {
+      // the only heap range the VM refuses is one past the end of the address space
+      WatchTarget::Heap { addr, len } => DebuggerError::MemoryFault { addr, len },
+      _ => DebuggerError::InvalidWatchpoint { target },
+    })
+  }
+
+  /// Remove a watchpoint, returns whether it existed.
+  pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+    self.vm.remove_watchpoint(id)
+  }
+
+  pub fn watchpoints(&self) -> &[Watchpoint] {
+    &self.vm.watchpoints
+  }
+
+  /// Execute a single instruction.
+  pub fn step(&mut self) -> StopReason {
+    self.run_until(|_| true)
+  }
+
+  /// Execute a single instruction, running a `CALL` through to its return.
+  pub fn step_over(&mut self) -> StopReason {
+    match self.current_instruction() {
+      Some(instruction) if instruction.opcode == Opcode::CALL => {
+        let return_address = instruction.next_offset();
+        let depth = self.vm.stack.len();
+        self.run_until(|vm| vm.pc == return_address && vm.stack.len() == depth)
+      }
+      _ => self.step(),
+    }
+  }
+
+  /// Run until the current routine returns to its caller. Outside of any call this runs
+  /// the program to the end.
+  pub fn finish(&mut self) -> StopReason {
+    let bp = self.vm.bp;
+    if bp < 2 || bp > self.vm.stack.len() {
+      return self.resume();
+    }
+    let return_address = self.vm.stack[bp - 2] as usize;
+    self.run_until(|vm| vm.pc == return_address && vm.stack.len() == bp - 2)
+  }
+
+  /// Run until a breakpoint is hit or the program stops.
+  pub fn resume(&mut self) -> StopReason {
+    self.run_until(|_| false)
+  }
+
+  /// Like `resume`, but also stops when `interrupted` returns true. It is asked every
+  /// `INTERRUPT_CHECK_INTERVAL` instructions, so it may do I/O.
+  pub fn resume_interruptible(&mut self, mut interrupted: impl FnMut() -> bool) -> StopReason {
+    let mut executed = 0u64;
+    let reason = self.run_until(|_| {
+      executed += 1;
+      executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && interrupted()
+    });
+    match reason {
+      StopReason::Stepped => StopReason::Interrupted,
+      other => other,
+    }
+  }
+
+  /// Undo a single instruction from the journal. Writes through the debugger clear the journal,
+  /// so this never steps back past one.
+  pub fn reverse_step(&mut self) -> StopReason {
+    if self.vm.undo_instruction().is_none() {
+      return StopReason::HistoryStart;
+    }
+    self.vm.refresh_watchpoints();
+    StopReason::Stepped
+  }
+
+  /// Undo instructions until the pc is back on a breakpoint or an undone instruction was the
+  /// last write to a breaking watchpoint's location, which leaves the pc on that write.
+  pub fn reverse_resume(&mut self) -> StopReason {
+    while let Some(pc) = self.vm.undo_instruction() {
+      if let Some(id) = self.vm.check_watchpoints(pc) {
+        return StopReason::Watchpoint { id, pc };
+      }
+      if self.breakpoints.contains(&pc) {
+        return StopReason::Breakpoint(pc);
+      }
+    }
+    StopReason::HistoryStart
+  }
+
+  /// Execute instructions until `done` holds after one of them, a breakpoint is reached or
+  /// the program stops. The breakpoint the pc is on does not fire again, so resuming from a
+  /// breakpoint moves past it.
+  fn run_until(&mut self, mut done: impl FnMut(&VirtualMachine) -> bool) -> StopReason {
+    let mut first = true;
+    loop {
+      let pc = self.vm.pc;
+      if !first && self.breakpoints.contains(&pc) {
+        return StopReason::Breakpoint(pc);
+      }
+      first = false;
+
+      match self.vm.run_once() {
+        RunOutcome::Yielded => {}
+        RunOutcome::Breakpoint(pc) => return StopReason::BreakpointInstruction(pc),
+        RunOutcome::Watchpoint { id, pc } => return StopReason::Watchpoint { id, pc },
+        RunOutcome::Halted(code) => return StopReason::Halted(code),
+        RunOutcome::Trapped(trap) => return StopReason::Trapped(trap),
+      }
+      if done(&self.vm) {
+        return StopReason::Stepped;
+      }
+    }
+  }
+
+  pub fn pc(&self) -> usize {
+    self.vm.pc
+  }
+
+  /// The pc by label and source line when the program has debug info.
+  pub fn location(&self) -> String {
+    self.vm.describe_pc(self.vm.pc)
+  }
+
+  /// The instruction that runs next, `None` when the bytes at the pc do not decode.
+  pub fn current_instruction(&self) -> Option<DecodedInstruction> {
+    decode(&self.vm.program, self.vm.pc).ok()
+  }
+
+  /// The pc and the `CALL` of every active frame, innermost first, described like `location`.
+  pub fn backtrace(&self) -> Vec<String> {
+    self.vm.backtrace().into_iter().map(|pc| self.vm.describe_pc(pc)).collect()
+  }
+
+  pub fn registers(&self) -> &[i32; 32] {
+    &self.vm.registers
+  }
+
+  pub fn float_registers(&self) -> &[f64; 32] {
+    &self.vm.float_registers
+  }
+
+  pub fn stack(&self) -> &[i32] {
+    &self.vm.stack
+  }
+
+  pub fn equal_flag(&self) -> bool {
+    self.vm.equal_flag
+  }
+
+  /// Move the pc, the next instruction runs from `pc`.
+  pub fn set_pc(&mut self, pc: usize) {
+    self.vm.pc = pc;
+    self.written();
+  }
+
+  pub fn set_equal_flag(&mut self, value: bool) {
+    self.vm.equal_flag = value;
+    self.written();
+  }
+
+  pub fn set_register(&mut self, register: usize, value: i32) -> Result<(), DebuggerError> {
+    let slot = self.vm.registers.get_mut(register).ok_or(DebuggerError::InvalidRegister { register })?;
+    *slot = value;
+    self.written();
+    Ok(())
+  }
+
+  pub fn set_float_register(&mut self, register: usize, value: f64) -> Result<(), DebuggerError> {
+    let slot = self.vm.float_registers.get_mut(register).ok_or(DebuggerError::InvalidRegister { register })?;
+    *slot = value;
+    self.written();
+    Ok(())
+  }
+
+  /// Read `len` bytes of the heap at `addr`.
+  pub fn read_memory(&self, addr: usize, len: usize) -> Result<&[u8], DebuggerError> {
+    addr
+      .checked_add(len)
+      .and_then(|end| self.vm.heap.get(addr..end))
+      .ok_or(DebuggerError::MemoryFault { addr, len })
+  }
+
+  /// Overwrite heap bytes at `addr`.
+  pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), DebuggerError> {
+    let len = bytes.len();
+    let memory = addr
+      .checked_add(len)
+      .and_then(|end| self.vm.heap.get_mut(addr..end))
+      .ok_or(DebuggerError::MemoryFault { addr, len })?;
+    memory.copy_from_slice(bytes);
+    self.written();
+    Ok(())
+  }
+
+  /// Take the debugger's own writes into the watchpoints' last values, so the next instruction
+  /// only reports what it changed itself. The journal cannot undo these writes, so it is
+  /// cleared and reverse execution stops here.
+  fn written(&mut self) {
+    self.vm.refresh_watchpoints();
+    if let Some(journal) = self.vm.journal.as_mut() {
+      journal.clear();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lumi_asm::header_utils::code_start_offset;
+  use lumi_asm::Assembler;
+  use crate::vm::program::Program;
+  use crate::vm::watch::WatchValue;
+
+  const PROGRAM: &str = r".data
+    counter: .integer #5
+    .code
+    load $0 #1
+    call @double
+    load $1 #7
+    bkpt
+    hlt
+    double: add $0 $0 $0
+    ret
+    ";
+
+  fn debugger(source: &str) -> Debugger {
+    let bytecode = Assembler::new().with_debug_info("main.lumi").assemble(source).unwrap();
+    let mut vm = VirtualMachine::builder().journal(64).build();
+    vm.load(Program::from_bytes(bytecode).unwrap());
+    Debugger::new(vm)
+  }
+
+  #[test]
+  fn test_breakpoints_by_label_and_address() {
+    let mut debugger = debugger(PROGRAM);
+    let start = code_start_offset(0);
+    assert_eq!(debugger.pc(), start);
+
+    let double = debugger.add_breakpoint("double").unwrap();
+    assert_eq!(double, start + 15);
+    assert_eq!(debugger.add_breakpoint(&format!("0x{:x}", start + 1)), Err(DebuggerError::NotAnInstruction { address: start + 1 }));
+    assert_eq!(debugger.add_breakpoint("nowhere"), Err(DebuggerError::UnknownLocation { location: "nowhere".to_string() }));
+
+    assert_eq!(debugger.resume(), StopReason::Breakpoint(double));
+    assert_eq!(debugger.registers()[0], 1);
+    assert_eq!(debugger.location(), "double (main.lumi:9:13)");
+    assert_eq!(debugger.backtrace().len(), 2);
+
+    assert_eq!(debugger.resume(), StopReason::BreakpointInstruction(start + 13));
+    assert_eq!(debugger.registers()[..2], [2, 7]);
+    assert_eq!(debugger.resume(), StopReason::Halted(0));
+    assert_eq!(debugger.resume(), StopReason::Halted(0));
+  }
+
+  #[test]
+  fn test_resume_interruptible_stops_a_loop() {
+    let mut debugger = debugger(".data\n.code\nspin: djmp @spin\n");
+    let mut polls = 0;
+    let reason = debugger.resume_interruptible(|| {
+      polls += 1;
+      polls == 3
+    });
+    assert_eq!(reason, StopReason::Interrupted);
+    assert_eq!(debugger.vm().instructions_executed, 3 * INTERRUPT_CHECK_INTERVAL);
+
+    let mut debugger = self::debugger(PROGRAM);
+    assert_eq!(debugger.resume_interruptible(|| true), StopReason::BreakpointInstruction(code_start_offset(0) + 13));
+  }
+
+  #[test]
+  fn test_step_next_and_finish() {
+    let mut debugger = debugger(PROGRAM);
+    let start = code_start_offset(0);
+    assert_eq!(debugger.step(), StopReason::Stepped);
+    assert_eq!(debugger.step_over(), StopReason::Stepped);
+    assert_eq!(debugger.pc(), start + 9);
+    assert_eq!(debugger.registers()[0], 2);
+    assert!(debugger.stack().is_empty());
+
+    let mut debugger = self::debugger(PROGRAM);
+    debugger.step();
+    debugger.step();
+    assert_eq!(debugger.pc(), start + 15);
+    assert_eq!(debugger.stack().len(), 2);
+    assert_eq!(debugger.finish(), StopReason::Stepped);
+    assert_eq!(debugger.pc(), start + 9);
+    assert_eq!(debugger.registers()[0], 2);
+  }
+
+  #[test]
+  fn test_reverse_continue_to_the_last_write() {
+    let mut debugger = debugger(r".data
+      counter: .integer #5
+      .code
+      load $0 #0
+      load $1 #6
+      setm $0 $1
+      load $1 #7
+      setm $0 $1
+      load $2 #1
+      hlt
+      ");
+    let start = code_start_offset(0);
+    assert_eq!(debugger.reverse_step(), StopReason::HistoryStart);
+    debugger.add_watchpoint(WatchTarget::Heap { addr: 0, len: 4 }, None, WatchMode::Log).unwrap();
+    assert_eq!(debugger.resume(), StopReason::Halted(0));
+
+    let watch = debugger.add_watchpoint(WatchTarget::Heap { addr: 0, len: 4 }, None, WatchMode::Break).unwrap();
+    assert_eq!(debugger.reverse_resume(), StopReason::Watchpoint { id: watch, pc: start + 15 });
+    assert_eq!(debugger.read_memory(0, 4), Ok(&[6, 0, 0, 0][..]));
+    assert_eq!(debugger.registers()[..3], [0, 7, 0]);
+    assert_eq!(debugger.reverse_resume(), StopReason::Watchpoint { id: watch, pc: start + 8 });
+    assert_eq!(debugger.read_memory(0, 4), Ok(&[5, 0, 0, 0][..]));
+
+    assert_eq!(debugger.reverse_step(), StopReason::Stepped);
+    assert_eq!(debugger.pc(), start + 4);
+    assert_eq!(debugger.reverse_resume(), StopReason::HistoryStart);
+    assert_eq!(debugger.pc(), start);
+    assert_eq!(debugger.resume(), StopReason::Watchpoint { id: watch, pc: start + 8 });
+    assert_eq!(debugger.resume(), StopReason::Watchpoint { id: watch, pc: start + 15 });
+    assert_eq!(debugger.resume(), StopReason::Halted(0));
+  }
+
+  #[test]
+  fn test_write_registers_and_memory() {
+    let mut debugger = debugger(PROGRAM);
+    assert_eq!(debugger.read_memory(0, 4), Ok(&[5, 0, 0, 0][..]));
+    debugger.write_memory(1, &[9, 9]).unwrap();
+    assert_eq!(debugger.read_memory(0, 4), Ok(&[5, 9, 9, 0][..]));
+    assert_eq!(debugger.write_memory(3, &[1, 2]), Err(DebuggerError::MemoryFault { addr: 3, len: 2 }));
+    assert_eq!(debugger.read_memory(usize::MAX, 2), Err(DebuggerError::MemoryFault { addr: usize::MAX, len: 2 }));
+    let overflowing = WatchTarget::Heap { addr: usize::MAX, len: 2 };
+    let error = debugger.add_watchpoint(overflowing, None, WatchMode::Break);
+    assert_eq!(error, Err(DebuggerError::MemoryFault { addr: usize::MAX, len: 2 }));
+    let error = DebuggerError::InvalidWatchpoint { target: WatchTarget::Register(40) };
+    assert_eq!(error.to_string(), "Cannot watch register $40");
+
+    debugger.set_register(0, 20).unwrap();
+    debugger.set_float_register(3, 1.5).unwrap();
+    assert_eq!(debugger.set_register(32, 1), Err(DebuggerError::InvalidRegister { register: 32 }));
+    debugger.step();
+    assert_eq!(debugger.registers()[0], 1);
+    assert_eq!(debugger.float_registers()[3], 1.5);
+  }
+
+  #[test]
+  fn test_writes_do_not_trigger_watchpoints() {
+    let mut debugger = debugger(PROGRAM);
+    let register = debugger.add_watchpoint(WatchTarget::Register(1), None, WatchMode::Break).unwrap();
+    debugger.add_watchpoint(WatchTarget::Heap { addr: 0, len: 4 }, None, WatchMode::Break).unwrap();
+    debugger.set_register(1, 9).unwrap();
+    debugger.write_memory(0, &[1]).unwrap();
+    assert_eq!(debugger.step(), StopReason::Stepped);
+    assert_eq!(debugger.watchpoints()[0].value, WatchValue::Integer(9));
+    assert_eq!(debugger.watchpoints()[1].value, WatchValue::Bytes(vec![1, 0, 0, 0]));
+
+    assert_eq!(debugger.resume(), StopReason::Watchpoint { id: register, pc: code_start_offset(0) + 9 });
+  }
+
+  #[test]
+  fn test_writes_clear_the_journal() {
+    let mut debugger = debugger(PROGRAM);
+    debugger.add_watchpoint(WatchTarget::Register(0), None, WatchMode::Break).unwrap();
+    debugger.step();
+    debugger.step();
+    assert_eq!(debugger.step(), StopReason::Watchpoint { id: 1, pc: code_start_offset(0) + 15 });
+    assert_eq!(debugger.registers()[0], 2);
+    debugger.set_register(0, 100).unwrap();
+
+    assert_eq!(debugger.reverse_step(), StopReason::HistoryStart);
+    assert_eq!(debugger.reverse_resume(), StopReason::HistoryStart);
+    assert_eq!(debugger.registers()[0], 100);
+    assert_eq!(debugger.step(), StopReason::Stepped);
+    assert_eq!(debugger.reverse_step(), StopReason::Stepped);
+    assert_eq!(debugger.registers()[0], 100);
+  }
+}
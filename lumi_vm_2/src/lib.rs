@@ -1,5 +1,3 @@
-#![feature(async_closure)]
-
 extern crate clap;
 extern crate clap_derive;
 extern crate byteorder;
@@ -14,5 +12,7 @@ extern crate uuid;
 
 
 pub mod cli;
+pub mod debugger;
+pub mod repl;
 pub mod utils;
-mod vm;
+pub mod vm;
@@ -0,0 +1,99 @@
+mod repl_commands;
+
+use std::io;
+use std::io::Write;
+use log::{error, info};
+use lumi_asm::Assembler;
+use crate::vm::virtual_machine::{ExecutionStatus, VirtualMachine};
+
+/// Core REPL structure
+pub struct REPL {
+  command_buffer: Vec<String>,
+  vm: VirtualMachine,
+  asm: Assembler,
+}
+
+impl REPL {
+  pub fn new() -> REPL {
+    REPL {
+      command_buffer: vec![],
+      vm: VirtualMachine::initialize(),
+      asm: Assembler::new(),
+    }
+  }
+
+  pub fn execute_command(&mut self, buffer: &str) {
+    let tokens: Vec<String> = buffer.split_whitespace().map(|s| s.to_string()).collect();
+
+    if let Some(command) = tokens.first() {
+      let args = tokens[1..].to_vec();
+      match command.as_str() {
+        "!quit" => self.command_quit(args),
+        "!history" => self.command_history(args),
+        "!registers" => self.command_registers(args),
+        "!clear_registers" => self.command_clear_registers(args),
+        "!clear_program" => self.command_clear_program(args),
+        "!assemble" => self.command_assemble(args),
+        "!load" => self.command_load_program(args),
+        "!run" => self.command_run(args),
+        _ => error!("Unrecognized command: {}", command),
+      }
+    }
+  }
+
+  /// Assemble a single instruction, append it to the program and execute it straight away.
+  pub fn execute_instruction(&mut self, buffer: &str) {
+    let bytecode = match self.asm.assemble_instruction(buffer) {
+      Ok(bytecode) => bytecode,
+      Err(err) => {
+        error!("Unable to assemble input: {}", err);
+        return;
+      }
+    };
+
+    self.vm.pc = self.vm.program.len();
+    self.vm.program.extend_from_slice(&bytecode);
+    match self.vm.execute_instruction() {
+      ExecutionStatus::Continue => {}
+      ExecutionStatus::BreakpointHit => info!("Breakpoint hit at {}", self.vm.pc),
+      ExecutionStatus::WatchpointHit(id) => info!("Watchpoint {} hit at {}", id, self.vm.pc),
+      ExecutionStatus::Crash(trap) => error!("Instruction crashed at {}: {}", self.vm.describe_pc(self.vm.pc), trap),
+      ExecutionStatus::Done(code) => info!("Program finished with exit code {}", code),
+    }
+  }
+
+  pub fn run(&mut self) {
+    loop {
+      let mut buffer = String::new();
+      print!(">>> ");
+      io::stdout().flush().expect("Unable to flush stdout");
+
+      match io::stdin().read_line(&mut buffer) {
+        Ok(0) => return,
+        Ok(_) => {}
+        Err(err) => {
+          error!("Unable to read line: {}", err);
+          return;
+        }
+      }
+
+      let buffer = buffer.trim();
+      if buffer.is_empty() {
+        continue;
+      }
+      self.command_buffer.push(buffer.to_string());
+
+      if buffer.starts_with('!') {
+        self.execute_command(buffer);
+      } else {
+        self.execute_instruction(buffer);
+      }
+    }
+  }
+}
+
+impl Default for REPL {
+  fn default() -> Self {
+    Self::new()
+  }
+}
@@ -0,0 +1,77 @@
+use log::{error, info};
+use lumi_asm::file_assembler::assemble_file;
+use crate::vm::program::Program;
+use super::REPL;
+
+impl REPL {
+  pub(crate) fn command_quit(&self, _args: Vec<String>) {
+    info!("Exiting...");
+    std::process::exit(0);
+  }
+
+  pub(crate) fn command_history(&self, _args: Vec<String>) {
+    for command in &self.command_buffer {
+      info!("{}", command);
+    }
+  }
+
+  pub(crate) fn command_registers(&self, _args: Vec<String>) {
+    info!("Registers: {:?}", self.vm.registers);
+    info!("Float Registers: {:?}", self.vm.float_registers);
+  }
+
+  pub(crate) fn command_clear_registers(&mut self, _args: Vec<String>) {
+    info!("Clearing the VMs registers...");
+    self.vm.registers = [0; 32];
+    self.vm.float_registers = [0.0; 32];
+  }
+
+  pub(crate) fn command_clear_program(&mut self, _args: Vec<String>) {
+    info!("Clearing the program vector...");
+    self.vm.program.clear();
+    self.vm.pc = 0;
+  }
+
+  pub(crate) fn command_assemble(&mut self, args: Vec<String>) {
+    let Some(file_to_assemble) = args.first() else {
+      error!("Usage: !assemble <file.lumi>");
+      return;
+    };
+
+    match assemble_file(file_to_assemble) {
+      Ok(()) => info!("File successfully assembled"),
+      Err(errors) => {
+        for err in errors {
+          error!("Assembly error: {}", err);
+        }
+      }
+    }
+  }
+
+  pub(crate) fn command_load_program(&mut self, args: Vec<String>) {
+    let Some(file_to_load) = args.first() else {
+      error!("Usage: !load <file.bin>");
+      return;
+    };
+
+    match Program::from_file(file_to_load) {
+      Ok(program) => {
+        info!("Read in total of {} bytes", program.program_size);
+        self.vm.load(program);
+      }
+      Err(err) => error!("Failed to load file {}: {}", file_to_load, err),
+    }
+  }
+
+  pub(crate) fn command_run(&mut self, args: Vec<String>) {
+    if !args.is_empty() {
+      self.command_load_program(args);
+    }
+
+    self.vm.rewind();
+    let events = self.vm.run();
+    for event in &events {
+      info!("{:?}", event);
+    }
+  }
+}
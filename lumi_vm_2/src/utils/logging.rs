@@ -39,6 +39,15 @@ fn colorize_by_level(level: Level) -> ColoredString {
 }
 
 pub fn setup_logging() -> Result<(), fern::InitError> {
+  setup_logging_with(std::io::stdout())
+}
+
+/// Log to stderr instead of stdout, for when stdout carries a protocol.
+pub fn setup_logging_to_stderr() -> Result<(), fern::InitError> {
+  setup_logging_with(std::io::stderr())
+}
+
+fn setup_logging_with(console: impl Into<fern::Output>) -> Result<(), fern::InitError> {
   let file_config = Dispatch::new()
     .format(|out, message, record| {
       let current_thread = thread::current();
@@ -97,7 +106,7 @@ pub fn setup_logging() -> Result<(), fern::InitError> {
       ))
     })
     .level(LevelFilter::Trace)
-    .chain(std::io::stdout());
+    .chain(console);
 
   Dispatch::new()
     .chain(file_config)
@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use crate::vm::virtual_machine::VirtualMachine;
+
+/// Resource limits and environment for a single VM. `None` means unbounded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmConfig {
+  /// Maximum size of the heap in bytes, checked by `ALOC`.
+  pub max_heap_bytes: Option<usize>,
+  /// Maximum number of values on the stack, checked by `PUSH` and `CALL`.
+  pub max_stack_depth: Option<usize>,
+  /// Maximum number of instructions the VM may execute.
+  pub fuel: Option<u64>,
+  /// Number of recent instructions to keep in the journal for stepping backwards.
+  pub journal: Option<usize>,
+  /// Directory to load extensions from when the VM starts running.
+  pub extensions_dir: PathBuf,
+}
+
+impl Default for VmConfig {
+  fn default() -> Self {
+    VmConfig {
+      max_heap_bytes: None,
+      max_stack_depth: None,
+      fuel: None,
+      journal: None,
+      extensions_dir: PathBuf::from("./extensions"),
+    }
+  }
+}
+
+/// Builds a `VirtualMachine` with a custom `VmConfig`.
+#[derive(Debug, Default)]
+pub struct VirtualMachineBuilder {
+  config: VmConfig,
+}
+
+impl VirtualMachineBuilder {
+  pub fn new() -> Self {
+    VirtualMachineBuilder::default()
+  }
+
+  pub fn max_heap_bytes(mut self, bytes: usize) -> Self {
+    self.config.max_heap_bytes = Some(bytes);
+    self
+  }
+
+  pub fn max_stack_depth(mut self, depth: usize) -> Self {
+    self.config.max_stack_depth = Some(depth);
+    self
+  }
+
+  pub fn fuel(mut self, instructions: u64) -> Self {
+    self.config.fuel = Some(instructions);
+    self
+  }
+
+  pub fn journal(mut self, instructions: usize) -> Self {
+    self.config.journal = Some(instructions);
+    self
+  }
+
+  pub fn extensions_dir(mut self, path: impl Into<PathBuf>) -> Self {
+    self.config.extensions_dir = path.into();
+    self
+  }
+
+  pub fn config(mut self, config: VmConfig) -> Self {
+    self.config = config;
+    self
+  }
+
+  pub fn build(self) -> VirtualMachine {
+    let mut vm = VirtualMachine::initialize();
+    vm.config = self.config;
+    vm
+  }
+}
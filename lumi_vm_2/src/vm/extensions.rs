@@ -1,9 +1,10 @@
 use std::error::Error;
 use std::fs;
+use std::path::Path;
 use libloading::{Library, Symbol};
 use lumi_vm_sdk::LumiVmPlugin;
 
-pub fn load_extensions(path: &str) -> Vec<Box<dyn LumiVmPlugin>> {
+pub fn load_extensions(path: &Path) -> Vec<Box<dyn LumiVmPlugin>> {
   let mut extensions = vec![];
   if let Ok(entries) = fs::read_dir(path) {
     for entry in entries {
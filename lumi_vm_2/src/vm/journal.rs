@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use lumi_asm::encoding::DecodedInstruction;
+use lumi_asm::instruction::Opcode;
+use crate::vm::virtual_machine::VirtualMachine;
+
+/// What one instruction changed, as the values from before it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+  /// Offset of the instruction.
+  pub pc: usize,
+  /// Prior value of every register the instruction wrote.
+  pub registers: Vec<(usize, i32)>,
+  pub float_registers: Vec<(usize, f64)>,
+  pub equal_flag: bool,
+  pub remainder: u32,
+  pub loop_counter: usize,
+  pub sp: usize,
+  pub bp: usize,
+  /// The stack is restored by truncating it to `stack_base` and pushing `stack_tail`, the
+  /// values the instruction popped.
+  pub stack_base: usize,
+  pub stack_tail: Vec<i32>,
+  /// Heap size before `ALOC` grew it.
+  pub heap_length: usize,
+  /// Address and prior contents of heap bytes the instruction overwrote.
+  pub heap_bytes: Option<(usize, Vec<u8>)>,
+}
+
+/// The most recent instructions a VM executed, oldest first, for stepping backwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Journal {
+  entries: VecDeque<JournalEntry>,
+  capacity: usize,
+}
+
+impl Journal {
+  /// A journal that keeps the last `capacity` instructions and forgets older ones.
+  pub fn new(capacity: usize) -> Self {
+    Journal { entries: VecDeque::new(), capacity }
+  }
+
+  pub fn push(&mut self, entry: JournalEntry) {
+    if self.capacity == 0 {
+      return;
+    }
+    if self.entries.len() == self.capacity {
+      self.entries.pop_front();
+    }
+    self.entries.push_back(entry);
+  }
+
+  /// Take the most recent entry.
+  pub fn pop(&mut self) -> Option<JournalEntry> {
+    self.entries.pop_back()
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn entries(&self) -> impl Iterator<Item = &JournalEntry> {
+    self.entries.iter()
+  }
+}
+
+/// The state an instruction may change, copied before it runs.
+pub(crate) struct Snapshot {
+  registers: [i32; 32],
+  float_registers: [f64; 32],
+  entry: JournalEntry,
+}
+
+impl VirtualMachine {
+  /// Copy what `instruction` may change. Only `SETM` writes the heap and only `POP` and
+  /// `RET` take values off the stack, so those are the only ones that copy memory.
+  pub(crate) fn journal_snapshot(&self, instruction: &DecodedInstruction) -> Snapshot {
+    let (stack_base, heap_bytes) = match instruction.opcode {
+      Opcode::POP => (self.stack.len().saturating_sub(1), None),
+      Opcode::RET => (self.bp.saturating_sub(2).min(self.stack.len()), None),
+      Opcode::SETM => {
+        let addr = self.registers[instruction.register(0)] as usize;
+        let bytes = addr.checked_add(4).and_then(|end| self.heap.get(addr..end));
+        (self.stack.len(), bytes.map(|bytes| (addr, bytes.to_vec())))
+      }
+      _ => (self.stack.len(), None),
+    };
+    Snapshot {
+      registers: self.registers,
+      float_registers: self.float_registers,
+      entry: JournalEntry {
+        pc: instruction.offset,
+        registers: vec![],
+        float_registers: vec![],
+        equal_flag: self.equal_flag,
+        remainder: self.remainder,
+        loop_counter: self.loop_counter,
+        sp: self.sp,
+        bp: self.bp,
+        stack_base,
+        stack_tail: self.stack[stack_base..].to_vec(),
+        heap_length: self.heap.len(),
+        heap_bytes,
+      },
+    }
+  }
+
+  /// Record the instruction a snapshot was taken for, keeping only the registers it changed.
+  pub(crate) fn journal_record(&mut self, snapshot: Snapshot) {
+    let Snapshot { registers, float_registers, mut entry } = snapshot;
+    entry.registers = changed(&registers, &self.registers);
+    // compare bits so NaN and -0.0 changes are recorded too
+    entry.float_registers = float_registers
+      .iter()
+      .zip(self.float_registers.iter())
+      .enumerate()
+      .filter(|(_, (before, after))| before.to_bits() != after.to_bits())
+      .map(|(index, (before, _))| (index, *before))
+      .collect();
+    if let Some(journal) = self.journal.as_mut() {
+      journal.push(entry);
+    }
+  }
+}
+
+fn changed(before: &[i32], after: &[i32]) -> Vec<(usize, i32)> {
+  before
+    .iter()
+    .zip(after)
+    .enumerate()
+    .filter(|(_, (before, after))| before != after)
+    .map(|(index, (before, _))| (index, *before))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entry(pc: usize) -> JournalEntry {
+    JournalEntry {
+      pc,
+      registers: vec![],
+      float_registers: vec![],
+      equal_flag: false,
+      remainder: 0,
+      loop_counter: 0,
+      sp: 0,
+      bp: 0,
+      stack_base: 0,
+      stack_tail: vec![],
+      heap_length: 0,
+      heap_bytes: None,
+    }
+  }
+
+  #[test]
+  fn test_journal_keeps_the_most_recent_entries() {
+    let mut journal = Journal::new(2);
+    for pc in 0..3 {
+      journal.push(entry(pc));
+    }
+    assert_eq!(journal.len(), 2);
+    assert_eq!(journal.entries().map(|entry| entry.pc).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(journal.pop().map(|entry| entry.pc), Some(2));
+    assert_eq!(journal.pop().map(|entry| entry.pc), Some(1));
+    assert!(journal.pop().is_none());
+
+    let mut journal = Journal::new(0);
+    journal.push(entry(0));
+    assert!(journal.is_empty());
+  }
+}
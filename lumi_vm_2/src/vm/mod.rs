@@ -1,4 +1,10 @@
 pub mod virtual_machine;
 pub mod program;
+pub mod trap;
+pub mod verifier;
+pub mod watch;
+pub mod journal;
+pub mod trace;
+pub mod config;
 mod operations;
 mod extensions;
\ No newline at end of file
@@ -1,108 +1,111 @@
 use log::debug;
+use lumi_asm::encoding::DecodedInstruction;
+use crate::vm::trap::VmTrap;
 use crate::vm::virtual_machine::{ExecutionStatus, VirtualMachine};
 
 impl VirtualMachine {
-  pub fn arithmetic_execute_add(&mut self) -> ExecutionStatus {
-    let first_byte = self.next_8_bits();
-    let second_byte = self.next_8_bits();
-    let third_byte = self.next_8_bits();
+  pub fn arithmetic_execute_add(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let first_byte = instruction.register(0);
+    let second_byte = instruction.register(1);
+    let third_byte = instruction.register(2);
     
     debug!("ADD ${} ${} ${}", first_byte, second_byte, third_byte);
-    let register_1 = self.registers[first_byte as usize];
-    let register_2 = self.registers[second_byte as usize];
-    self.registers[third_byte as usize] = register_1 + register_2;
+    let register_1 = self.registers[first_byte];
+    let register_2 = self.registers[second_byte];
+    self.registers[third_byte] = register_1.wrapping_add(register_2);
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_sub(&mut self) -> ExecutionStatus {
-    let first_byte = self.next_8_bits();
-    let second_byte = self.next_8_bits();
-    let third_byte = self.next_8_bits();
+  pub fn arithmetic_execute_sub(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let first_byte = instruction.register(0);
+    let second_byte = instruction.register(1);
+    let third_byte = instruction.register(2);
     
     debug!("SUB ${} ${} ${}", first_byte, second_byte, third_byte);
-    let register_1 = self.registers[first_byte as usize];
-    let register_2 = self.registers[second_byte as usize];
-    self.registers[third_byte as usize] = register_1 - register_2;
+    let register_1 = self.registers[first_byte];
+    let register_2 = self.registers[second_byte];
+    self.registers[third_byte] = register_1.wrapping_sub(register_2);
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_mul(&mut self) -> ExecutionStatus {
-    let first_byte = self.next_8_bits();
-    let second_byte = self.next_8_bits();
-    let third_byte = self.next_8_bits();
+  pub fn arithmetic_execute_mul(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let first_byte = instruction.register(0);
+    let second_byte = instruction.register(1);
+    let third_byte = instruction.register(2);
     
     debug!("MUL ${} ${} ${}", first_byte, second_byte, third_byte);
-    let register_1 = self.registers[first_byte as usize];
-    let register_2 = self.registers[second_byte as usize];
-    self.registers[third_byte as usize] = register_1 * register_2;
+    let register_1 = self.registers[first_byte];
+    let register_2 = self.registers[second_byte];
+    self.registers[third_byte] = register_1.wrapping_mul(register_2);
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_div(&mut self) -> ExecutionStatus {
-    let first_byte = self.next_8_bits();
-    let second_byte = self.next_8_bits();
-    let third_byte = self.next_8_bits();
+  pub fn arithmetic_execute_div(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let first_byte = instruction.register(0);
+    let second_byte = instruction.register(1);
+    let third_byte = instruction.register(2);
     
     debug!("DIV ${} ${} ${}", first_byte, second_byte, third_byte);
-    let register_1 = self.registers[first_byte as usize];
-    let register_2 = self.registers[second_byte as usize];
-    self.registers[third_byte as usize] = register_1 / register_2;
-    self.remainder = (register_1 % register_2) as u32;
+    let register_1 = self.registers[first_byte];
+    let register_2 = self.registers[second_byte];
+    if register_2 == 0 {
+      return ExecutionStatus::Crash(VmTrap::DivideByZero);
+    }
+    self.registers[third_byte] = register_1.wrapping_div(register_2);
+    self.remainder = register_1.wrapping_rem(register_2) as u32;
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_add_f64(&mut self) -> ExecutionStatus {
-    let register_1 = self.float_registers[self.next_8_bits() as usize];
-    let register_2 = self.float_registers[self.next_8_bits() as usize];
+  pub fn arithmetic_execute_add_f64(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register_1 = self.float_registers[instruction.register(0)];
+    let register_2 = self.float_registers[instruction.register(1)];
     
     debug!("ADDF64 ${} ${}", register_1, register_2);
-    self.float_registers[self.next_8_bits() as usize] = register_1 + register_2;
+    self.float_registers[instruction.register(2)] = register_1 + register_2;
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_sub_f64(&mut self) -> ExecutionStatus {
-    let register_1 = self.float_registers[self.next_8_bits() as usize];
-    let register_2 = self.float_registers[self.next_8_bits() as usize];
+  pub fn arithmetic_execute_sub_f64(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register_1 = self.float_registers[instruction.register(0)];
+    let register_2 = self.float_registers[instruction.register(1)];
     
     debug!("SUBF64 ${} ${}", register_1, register_2);
-    self.float_registers[self.next_8_bits() as usize] = register_1 - register_2;
+    self.float_registers[instruction.register(2)] = register_1 - register_2;
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_mul_f64(&mut self) -> ExecutionStatus {
-    let register_1 = self.float_registers[self.next_8_bits() as usize];
-    let register_2 = self.float_registers[self.next_8_bits() as usize];
+  pub fn arithmetic_execute_mul_f64(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register_1 = self.float_registers[instruction.register(0)];
+    let register_2 = self.float_registers[instruction.register(1)];
     
     debug!("MULF64 ${} ${}", register_1, register_2);
-    self.float_registers[self.next_8_bits() as usize] = register_1 * register_2;
+    self.float_registers[instruction.register(2)] = register_1 * register_2;
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_div_f64(&mut self) -> ExecutionStatus {
-    let register_1 = self.float_registers[self.next_8_bits() as usize];
-    let register_2 = self.float_registers[self.next_8_bits() as usize];
+  pub fn arithmetic_execute_div_f64(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register_1 = self.float_registers[instruction.register(0)];
+    let register_2 = self.float_registers[instruction.register(1)];
     
     debug!("DIVF64 ${} ${}", register_1, register_2);
-    self.float_registers[self.next_8_bits() as usize] = register_1 / register_2;
+    self.float_registers[instruction.register(2)] = register_1 / register_2;
     self.remainder = (register_1 % register_2) as u32;
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_increment(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
+  pub fn arithmetic_execute_increment(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
     
     debug!("INC ${}", register);
-    self.registers[register] += 1;
-    self.next_16_bits();
+    self.registers[register] = self.registers[register].wrapping_add(1);
     ExecutionStatus::Continue
   }
   
-  pub fn arithmetic_execute_decrement(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
+  pub fn arithmetic_execute_decrement(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
     
     debug!("DEC ${}", register);
-    self.registers[register] -= 1;
-    self.next_16_bits();
+    self.registers[register] = self.registers[register].wrapping_sub(1);
     ExecutionStatus::Continue
   }
-}
\ No newline at end of file
+}
@@ -1,67 +1,61 @@
+use lumi_asm::encoding::DecodedInstruction;
+use crate::vm::trap::VmTrap;
 use crate::vm::virtual_machine::{ExecutionStatus, VirtualMachine};
 
 impl VirtualMachine {
-  pub fn control_execute_jump(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
-    let target = self.registers[register];
+  pub fn control_execute_jump(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let target = self.registers[instruction.register(0)];
     self.pc = target as usize;
     ExecutionStatus::Continue
   }
 
-  pub fn control_execute_jump_forward(&mut self) -> ExecutionStatus {
-    let value = self.registers[self.next_8_bits() as usize] as usize;
-    self.pc += value;
+  pub fn control_execute_jump_forward(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let value = self.registers[instruction.register(0)] as usize;
+    self.pc = self.pc.wrapping_add(value);
     ExecutionStatus::Continue
   }
 
-  pub fn control_execute_jump_backward(&mut self) -> ExecutionStatus {
-    let value = self.registers[self.next_8_bits() as usize] as usize;
-    self.pc -= value;
-    ExecutionStatus::Continue
+  pub fn control_execute_jump_backward(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let value = self.registers[instruction.register(0)] as usize;
+    match self.pc.checked_sub(value) {
+      Some(pc) => {
+        self.pc = pc;
+        ExecutionStatus::Continue
+      }
+      None => ExecutionStatus::Crash(VmTrap::PcOutOfBounds { pc: self.pc.wrapping_sub(value) }),
+    }
   }
 
-  pub fn control_execute_jump_if_equal(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
-    let target = self.registers[register];
+  pub fn control_execute_jump_if_equal(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let target = self.registers[instruction.register(0)];
     if self.equal_flag {
       self.pc = target as usize;
-    } else {
-      self.next_16_bits();
     }
     ExecutionStatus::Continue
   }
 
-  pub fn control_execute_direct_jump(&mut self) -> ExecutionStatus {
-    let destination = self.next_16_bits();
-    self.pc = destination as usize;
+  pub fn control_execute_direct_jump(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    self.pc = instruction.address(0);
     ExecutionStatus::Continue
   }
 
-  pub fn control_execute_direct_jump_if_equal(&mut self) -> ExecutionStatus {
-    let destination = self.next_16_bits();
+  pub fn control_execute_direct_jump_if_equal(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
     if self.equal_flag {
-      self.pc = destination as usize;
-    } else {
-      self.next_8_bits();
+      self.pc = instruction.address(0);
     }
     ExecutionStatus::Continue
   }
   
-  pub fn control_execute_loop(&mut self) -> ExecutionStatus {
+  pub fn control_execute_loop(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
     if self.loop_counter != 0 {
       self.loop_counter -= 1;
-      let target = self.next_16_bits();
-      self.pc = target as usize;
-    } else {
-      self.pc += 3;
+      self.pc = instruction.address(0);
     }
     ExecutionStatus::Continue
   }
   
-  pub fn control_execute_create_loop(&mut self) -> ExecutionStatus{
-    let loop_count = self.next_16_bits();
-    self.loop_counter = loop_count as usize;
-    self.next_8_bits();
+  pub fn control_execute_create_loop(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus{
+    self.loop_counter = instruction.integer(0).max(0) as usize;
     ExecutionStatus::Continue
   }
 }
@@ -1,6 +1,7 @@
-use std::io::Cursor;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian};
 use log::{debug, error};
+use lumi_asm::encoding::DecodedInstruction;
+use crate::vm::trap::VmTrap;
 use crate::vm::virtual_machine::{ExecutionStatus, VirtualMachine};
 
 impl VirtualMachine {
@@ -9,116 +10,121 @@ impl VirtualMachine {
   //   to perform bounds checking on memory access
   pub fn system_safe_memory_access_range(&mut self, start: usize, len: usize) -> Option<&mut [u8]> {
     // TODO: benchmark this to see how much impact this has on performance
-    if start < self.heap.len() && start + len <= self.heap.len() {
-      Some(&mut self.heap[start..start + len])
-    } else {
-      None
-    }
+    let end = start.checked_add(len)?;
+    self.heap.get_mut(start..end)
   }
   
-  pub fn memory_execute_load(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
-    let integer_immediate = self.next_16_bits();
+  pub fn memory_execute_load(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
+    let integer_immediate = instruction.integer(1);
     
     debug!("LOAD ${} #{}", register, integer_immediate);
-    self.registers[register] = integer_immediate as i32;
+    self.registers[register] = integer_immediate;
+    ExecutionStatus::Continue
+  }
+  
+  pub fn memory_execute_load_immediate(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
+    let integer_immediate = instruction.integer(1);
+    
+    debug!("LOADI ${} #{}", register, integer_immediate);
+    self.registers[register] = integer_immediate;
     ExecutionStatus::Continue
   }
   
-  pub fn memory_execute_load_f64(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
-    let float_immediate = f64::from(self.next_16_bits());
+  pub fn memory_execute_load_f64(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
+    let float_immediate = instruction.float(1);
    
     debug!("LOADF64 ${} #{}", register, float_immediate);
     self.float_registers[register] = float_immediate;
     ExecutionStatus::Continue
   }
   
-  pub fn memory_execute_allocate(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
+  pub fn memory_execute_allocate(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
     let bytes = self.registers[register];
     
     debug!("ALOC ${}", register);
-    let new_end = self.heap.len() as i32 + bytes;
-    self.heap.resize(new_end as usize, 0);
-    self.next_8_bits();
-    self.next_8_bits();
+    if bytes < 0 {
+      return ExecutionStatus::Crash(VmTrap::InvalidAllocation { size: bytes });
+    }
+    let new_end = self.heap.len() + bytes as usize;
+    if let Some(limit) = self.config.max_heap_bytes {
+      if new_end > limit {
+        return ExecutionStatus::Crash(VmTrap::HeapLimitExceeded { requested: new_end, limit });
+      }
+    }
+    self.heap.resize(new_end, 0);
     ExecutionStatus::Continue
   }
   
-  pub fn memory_execute_load_upper_immediate(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
+  pub fn memory_execute_load_upper_immediate(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
     let value = self.registers[register];
-    let uv1 = i32::from(self.next_8_bits());
-    let uv2 = i32::from(self.next_8_bits());
-    let mut value = value.checked_shl(8).unwrap();
-    value = value | uv1;
-    value = value.checked_shl(8).unwrap();
-    value = value | uv2;
+    let uv1 = instruction.integer(1);
+    let uv2 = instruction.integer(2);
+    let value = (value << 16) | (uv1 << 8) | uv2;
     
     debug!("LOADUI ${} #{}", register, value);
     self.registers[register] = value;
     ExecutionStatus::Continue
   }
 
-  pub fn memory_execute_load_memory(&mut self) -> ExecutionStatus {
-    let offset = self.registers[self.next_8_bits() as usize] as usize;
+  pub fn memory_execute_load_memory(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let offset = self.registers[instruction.register(0)] as usize;
 
     if let Some(value) = self.system_safe_memory_access_range(offset, 4) {
-      let mut cursor = Cursor::new(value);
-      let data = cursor.read_i32::<LittleEndian>().unwrap();
+      let data = LittleEndian::read_i32(value);
 
       debug!("LOADM ${}", data);
-      self.registers[self.next_8_bits() as usize] = data;
-      self.next_8_bits();
+      self.registers[instruction.register(1)] = data;
       ExecutionStatus::Continue
     } else {
       debug!("Memory access out of bounds for LOADM at offset {}", offset);
-      ExecutionStatus::Crash(10)
+      ExecutionStatus::Crash(VmTrap::MemoryFault { addr: offset, len: 4 })
     }
   }
 
-  pub fn memory_execute_set_memory(&mut self) -> ExecutionStatus {
-    let offset_register = self.next_8_bits();
-    let data_register = self.next_8_bits();
-    let offset = self.registers[offset_register as usize] as usize;
-    let data = self.registers[data_register as usize];
+  pub fn memory_execute_set_memory(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let offset_register = instruction.register(0);
+    let data_register = instruction.register(1);
+    let offset = self.registers[offset_register] as usize;
+    let data = self.registers[data_register];
 
     debug!("SETM ${} ${}", offset_register, data_register);
-    let mut buf: [u8; 4] = [0, 0, 0, 0];
-    buf.as_mut()
-      .write_i32::<LittleEndian>(data)
-      .expect("failed to write to buffer");
-
     if let Some(range) = self.system_safe_memory_access_range(offset, 4) {
-      range.copy_from_slice(&buf);
+      LittleEndian::write_i32(range, data);
     } else {
       error!("Memory access out of bounds: offset={}, size=4", offset);
-      return ExecutionStatus::Crash(10);
+      return ExecutionStatus::Crash(VmTrap::MemoryFault { addr: offset, len: 4 });
     }
 
-    self.next_8_bits();
     ExecutionStatus::Continue
   }
   
-  pub fn memory_execute_push_to_stack(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
+  pub fn memory_execute_push_to_stack(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
     let value = self.registers[register];
     
     debug!("PUSH ${}", register);
+    if let Err(trap) = self.check_stack_capacity(1) {
+      return ExecutionStatus::Crash(trap);
+    }
     self.stack.push(value);
     self.sp += 1;
-    self.next_16_bits();
     ExecutionStatus::Continue
   }
   
-  pub fn memory_execute_pop_from_stack(&mut self) -> ExecutionStatus {
-    let register = self.next_8_bits() as usize;
+  pub fn memory_execute_pop_from_stack(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let register = instruction.register(0);
     
     debug!("POP ${}", register);
-    self.sp -= 1;
-    self.registers[register] = self.stack.pop().unwrap();
-    self.next_16_bits();
+    let Some(value) = self.stack.pop() else {
+      return ExecutionStatus::Crash(VmTrap::StackUnderflow);
+    };
+    self.sp = self.stack.len();
+    self.registers[register] = value;
     ExecutionStatus::Continue
   }
 }
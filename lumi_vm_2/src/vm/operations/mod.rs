@@ -1,3 +1,4 @@
+use lumi_asm::encoding::DecodedInstruction;
 use crate::vm::virtual_machine::{ExecutionStatus, VirtualMachine};
 
 mod memory;
@@ -8,4 +9,4 @@ mod control;
 mod logical;
 mod system;
 
-pub type InstructionHandler = fn(&mut VirtualMachine) -> ExecutionStatus;
+pub type InstructionHandler = fn(&mut VirtualMachine, &DecodedInstruction) -> ExecutionStatus;
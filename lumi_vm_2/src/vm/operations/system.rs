@@ -1,56 +1,33 @@
 use log::{error, info};
-use crate::vm::virtual_machine::{ExecutionStatus, VirtualMachine, WatchType, WatchVariable};
+use lumi_asm::encoding::DecodedInstruction;
+use crate::vm::trap::VmTrap;
+use crate::vm::virtual_machine::{ExecutionStatus, VirtualMachine};
 
 impl VirtualMachine {
   
-  pub fn system_halt(&mut self) -> ExecutionStatus {
+  pub fn system_halt(&mut self, _instruction: &DecodedInstruction) -> ExecutionStatus {
     ExecutionStatus::Done(0)
   }
   
-  pub fn system_breakpoint(&mut self) -> ExecutionStatus {
-    self.next_8_bits();
-    self.next_8_bits();
-    self.next_8_bits();
-    ExecutionStatus::BreakpointHit
-  }
-
-  pub fn system_no_operation(&mut self) -> ExecutionStatus {
-    self.next_8_bits();
-    self.next_8_bits();
-    self.next_8_bits();
+  pub fn system_no_operation(&mut self, _instruction: &DecodedInstruction) -> ExecutionStatus {
     ExecutionStatus::Continue
   }
   
-  pub fn system_call(&mut self) -> ExecutionStatus {
+  pub fn system_call(&mut self, _instruction: &DecodedInstruction) -> ExecutionStatus {
     ExecutionStatus::Continue
   }
   
-  pub fn add_watch_variable(&mut self, watch_type: WatchType) {
-    let initial_value = match watch_type {
-      WatchType::Memory(addr) => self.heap[addr] as f32,
-      WatchType::Register(index) => self.registers[index] as f32,
-      WatchType::FloatRegister(index) => self.float_registers[index] as f32,
+  pub fn system_execute_print_string(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let starting_offset = instruction.address(0);
+    let slice = self.ro_data.get(starting_offset..).unwrap_or_default();
+    let Some(length) = slice.iter().position(|byte| *byte == 0) else {
+      return ExecutionStatus::Crash(VmTrap::MemoryFault {
+        addr: starting_offset,
+        len: slice.len() + 1,
+      });
     };
 
-    self.watch_variables.insert(
-      watch_type.clone(),
-      WatchVariable {
-        watch_type,
-        last_value: initial_value,
-      },
-    );
-  }
-  
-  pub fn system_execute_print_string(&mut self) -> ExecutionStatus {
-    let starting_offset = self.next_24_bits() as usize;
-    let mut ending_offset = starting_offset;
-    let slice = self.ro_data.as_slice();
-
-    while slice[ending_offset] != 0 {
-      ending_offset += 1;
-    }
-
-    let result = std::str::from_utf8(&slice[starting_offset..ending_offset]);
+    let result = std::str::from_utf8(&slice[..length]);
     match result {
       Ok(s) => {
         info!("{}", s);
@@ -62,32 +39,36 @@ impl VirtualMachine {
     ExecutionStatus::Continue
   }
   
-  pub fn system_execute_call(&mut self) -> ExecutionStatus {
-    let return_destination = self.pc + 3;
-    let destination = self.next_16_bits();
+  pub fn system_execute_call(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    let return_destination = self.pc;
+    if let Err(trap) = self.check_stack_capacity(2) {
+      return ExecutionStatus::Crash(trap);
+    }
     self.stack.push(return_destination as i32);
     self.stack.push(self.bp as i32);
+    self.sp = self.stack.len();
     self.bp = self.sp;
-    self.pc = destination as usize;
+    self.pc = instruction.address(0);
     ExecutionStatus::Continue
   }
   
-  pub fn system_execute_return(&mut self) -> ExecutionStatus {
-    let bp = self.bp;
-    self.sp = bp;
-    self.bp = self.stack.pop().unwrap() as usize;
-    self.pc = self.stack.pop().unwrap() as usize;
+  pub fn system_execute_return(&mut self, _instruction: &DecodedInstruction) -> ExecutionStatus {
+    if self.bp < 2 || self.bp > self.stack.len() {
+      return ExecutionStatus::Crash(VmTrap::StackUnderflow);
+    }
+    self.stack.truncate(self.bp);
+    self.bp = self.stack.pop().unwrap_or_default() as usize;
+    self.pc = self.stack.pop().unwrap_or_default() as usize;
+    self.sp = self.stack.len();
     ExecutionStatus::Continue
   }
   
-  pub fn system_execute_breakpoint(&mut self) -> ExecutionStatus {
-    self.next_8_bits();
-    self.next_8_bits();
-    self.next_8_bits();
+  /// Pause a debugger that drives the VM with `run_for`, `run` carries on past it.
+  pub fn system_execute_breakpoint(&mut self, _instruction: &DecodedInstruction) -> ExecutionStatus {
     ExecutionStatus::BreakpointHit
   }
   
-  pub fn system_illegal_instruction(&mut self) -> ExecutionStatus {
-    ExecutionStatus::Done(1)
+  pub fn system_illegal_instruction(&mut self, instruction: &DecodedInstruction) -> ExecutionStatus {
+    ExecutionStatus::Crash(VmTrap::IllegalOpcode { opcode: instruction.opcode.into() })
   }
 }
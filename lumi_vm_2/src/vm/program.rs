@@ -1,5 +1,43 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::path::Path;
+use lumi_asm::debug_info::DebugInfo;
+use lumi_asm::header_utils::{HeaderError, LumiHeader};
+use lumi_asm::sections::{SectionKind, SectionTable};
 use uuid::Uuid;
+use crate::vm::verifier::{verify, VerifiedCode, VerifyError};
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgramError {
+    /// The file could not be read.
+    Io { path: String, error: String },
+    /// The header is malformed or incompatible with this VM.
+    Header { error: HeaderError },
+    /// The code section failed verification, every problem found is listed.
+    Verification { errors: Vec<VerifyError> },
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramError::Io { path, error } => write!(f, "Could not read program {}: {}", path, error),
+            ProgramError::Header { error } => write!(f, "Invalid header: {}", error),
+            ProgramError::Verification { errors } => {
+                write!(f, "Code section failed verification with {} error(s)", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ProgramError {}
+
+/// A LUMI binary whose header and code section have been validated.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub id: Uuid,
@@ -7,6 +45,12 @@ pub struct Program {
 //    pub symbols: HashMap<usize, Symbol>,
     pub program_size: usize,
     pub debug: bool,
+    pub header: LumiHeader,
+    pub sections: SectionTable,
+    /// Source lines and symbols, present when the program was assembled with debug info.
+    pub debug_info: Option<DebugInfo>,
+    /// Instruction boundaries of the verified code section.
+    pub verified: VerifiedCode,
 }
 
 impl Program {
@@ -16,6 +60,10 @@ impl Program {
             bytecode: vec![],
             program_size: 0,
             debug: false,
+            header: LumiHeader::new(0),
+            sections: SectionTable::new(),
+            debug_info: None,
+            verified: VerifiedCode::default(),
         }
     }
 
@@ -23,4 +71,191 @@ impl Program {
         self.program_size = self.bytecode.len();
         self.clone()
     }
-}
\ No newline at end of file
+
+    /// Validate an assembled binary: the header, its versions and checksum, the section table,
+    /// and that the code section passes the verifier.
+    pub fn from_bytes(bytecode: Vec<u8>) -> Result<Program, ProgramError> {
+        let header = LumiHeader::parse(&bytecode).map_err(|error| ProgramError::Header { error })?;
+        header.validate(&bytecode).map_err(|error| ProgramError::Header { error })?;
+        let sections = header.sections(&bytecode).map_err(|error| ProgramError::Header { error })?;
+
+        let verified = verify(&bytecode, &sections).map_err(|errors| ProgramError::Verification { errors })?;
+
+        let debug_info = match sections.get(SectionKind::Debug) {
+            Some(debug) => {
+                let debug_info = DebugInfo::parse(&bytecode[debug.range()]).map_err(|error| ProgramError::Header { error })?;
+                Some(debug_info)
+            }
+            None => None,
+        };
+
+        Ok(Program {
+            id: Uuid::new_v4(),
+            program_size: bytecode.len(),
+            bytecode,
+            debug: header.has_debug_info(),
+            header,
+            sections,
+            debug_info,
+            verified,
+        })
+    }
+
+    /// Read and validate an assembled binary from disk.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Program, ProgramError> {
+        let path = path.as_ref();
+        let bytecode = fs::read(path).map_err(|error| ProgramError::Io {
+            path: path.display().to_string(),
+            error: error.to_string(),
+        })?;
+        Program::from_bytes(bytecode)
+    }
+
+    /// Offset of the first instruction.
+    pub fn code_offset(&self) -> usize {
+        self.sections.get(SectionKind::Code).map_or(0, |code| code.offset as usize)
+    }
+
+    pub fn ro_data(&self) -> &[u8] {
+        self.sections.contents(SectionKind::ReadOnly, &self.bytecode)
+    }
+
+    pub fn code(&self) -> &[u8] {
+        self.sections.contents(SectionKind::Code, &self.bytecode)
+    }
+
+    /// Initial contents of the writable `.data` section.
+    pub fn data(&self) -> &[u8] {
+        self.sections.contents(SectionKind::Data, &self.bytecode)
+    }
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Program::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lumi_asm::encoding::{encode, DecodeError, Operand};
+    use lumi_asm::header_utils::{code_start_offset, LUMI_FORMAT_VERSION, LUMI_ISA_VERSION};
+    use lumi_asm::instruction::Opcode;
+    use lumi_asm::Assembler;
+
+    fn assemble(source: &str) -> Vec<u8> {
+        Assembler::new().assemble(source).unwrap()
+    }
+
+    #[test]
+    fn test_from_bytes_accepts_assembled_program() {
+        let bytecode = assemble(
+            r".rodata
+            hello: .asciiz 'Hi'
+            .data
+            counter: .integer #7
+            .code
+            load $0 #1
+            prts @hello
+            hlt
+            ",
+        );
+        let program = Program::from_bytes(bytecode.clone()).unwrap();
+        assert_eq!(program.program_size, bytecode.len());
+        assert_eq!(program.ro_data(), b"Hi\0");
+        assert_eq!(program.code().len(), 4 + 5 + 1);
+        assert_eq!(program.code_offset(), code_start_offset(3));
+        assert_eq!(program.data(), &[7, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_invalid_header() {
+        assert_eq!(
+            Program::from_bytes(vec![0x4C, 0x55]),
+            Err(ProgramError::Header { error: HeaderError::TooShort { length: 2, expected: code_start_offset(0) } })
+        );
+
+        let mut header = LumiHeader::new(0);
+        header.format_version = 9;
+        let error = Program::from_bytes(header.wrap(&[Opcode::HLT.into()])).unwrap_err();
+        assert!(matches!(error, ProgramError::Header { error: HeaderError::UnsupportedFormatVersion { found: 9, .. } }));
+        assert!(error.to_string().contains("version 9 is not supported"));
+
+        let mut bytecode = LumiHeader::new(0).wrap(&[Opcode::HLT.into()]);
+        bytecode.push(Opcode::HLT.into());
+        assert!(matches!(
+            Program::from_bytes(bytecode),
+            Err(ProgramError::Header { error: HeaderError::ChecksumMismatch { .. } })
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_reads_header_fields() {
+        let program = Program::from_bytes(assemble(".data\n.code\nhlt\n")).unwrap();
+        assert_eq!(program.header.format_version, LUMI_FORMAT_VERSION);
+        assert_eq!(program.header.isa_version, LUMI_ISA_VERSION);
+        assert_eq!(program.header.entry_offset as usize, program.code_offset());
+        assert!(!program.debug);
+    }
+
+    #[test]
+    fn test_from_bytes_reads_debug_info() {
+        let bytecode = Assembler::new().with_debug_info("main.lumi").assemble(".code\nstart: hlt\n").unwrap();
+        let program = Program::from_bytes(bytecode).unwrap();
+        assert!(program.debug);
+        let debug_info = program.debug_info.as_ref().unwrap();
+        assert_eq!(debug_info.describe(program.code_offset()), "start (main.lumi:2:8)");
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_invalid_code() {
+        let bytecode = LumiHeader::new(0).wrap(&[0xfe]);
+        let offset = code_start_offset(0);
+        assert_eq!(
+            Program::from_bytes(bytecode),
+            Err(ProgramError::Verification {
+                errors: vec![VerifyError::Decode { error: DecodeError::UnknownOpcode { offset, byte: 0xfe } }]
+            })
+        );
+
+        let bytecode = LumiHeader::new(0).wrap(&[Opcode::DJMP.into(), 0x10]);
+        assert!(matches!(
+            Program::from_bytes(bytecode),
+            Err(ProgramError::Verification { errors })
+                if matches!(errors[..], [VerifyError::Decode { error: DecodeError::Truncated { opcode: Opcode::DJMP, .. } }])
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_reports_every_verification_error() {
+        let mut body = encode(Opcode::PRTS, &[Operand::Address(8), Operand::Empty, Operand::Empty]);
+        body.extend(encode(Opcode::INC, &[Operand::Register(33), Operand::Empty, Operand::Empty]));
+        let error = Program::from_bytes(LumiHeader::new(0).wrap(&body)).unwrap_err();
+        let offset = code_start_offset(0);
+        assert_eq!(
+            error,
+            ProgramError::Verification {
+                errors: vec![
+                    VerifyError::StringOutOfBounds { offset, address: 8, ro_length: 0 },
+                    VerifyError::InvalidRegister { offset: offset + 5, register: 33 },
+                ]
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Code section failed verification with 2 error(s)\n  PRTS at offset 0x{:x} reads 0x8, past 0 bytes of read-only data\n  Invalid register $33 at offset 0x{:x}",
+                offset,
+                offset + 5
+            )
+        );
+    }
+
+    #[test]
+    fn test_from_file_reports_missing_file() {
+        let error = Program::from_file("/definitely/not/here.bin").unwrap_err();
+        assert!(matches!(error, ProgramError::Io { ref path, .. } if path == "/definitely/not/here.bin"));
+        assert!(error.to_string().starts_with("Could not read program /definitely/not/here.bin"));
+    }
+}
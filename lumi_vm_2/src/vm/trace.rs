@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+use log::error;
+use serde_derive::Serialize;
+use serde_json::Value;
+use lumi_asm::encoding::{DecodedInstruction, Operand};
+use lumi_asm::instruction::Opcode;
+use crate::vm::virtual_machine::{ExecutionStatus, VirtualMachine};
+
+/// One executed instruction and the registers and flags it changed, written as a JSON line.
+/// Only deterministic state goes in, so two runs of the same program produce the same trace.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TraceRecord {
+  /// Number of instructions executed before this one.
+  pub step: u64,
+  pub pc: usize,
+  pub opcode: &'static str,
+  pub operands: Vec<Operand>,
+  /// New value of every register the instruction changed.
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub registers: BTreeMap<usize, i32>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub float_registers: BTreeMap<usize, f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub equal_flag: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub remainder: Option<u32>,
+  /// The trap the instruction raised.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub trap: Option<String>,
+}
+
+/// Writes a `TraceRecord` per line.
+pub struct Tracer {
+  writer: Box<dyn Write + Send>,
+}
+
+impl Tracer {
+  pub fn new(writer: impl Write + Send + 'static) -> Self {
+    Tracer { writer: Box::new(writer) }
+  }
+
+  /// Trace into a new file at `path`, replacing an existing one.
+  pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+    Ok(Tracer::new(BufWriter::new(File::create(path)?)))
+  }
+
+  pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+    serde_json::to_writer(&mut self.writer, record)?;
+    self.writer.write_all(b"\n")
+  }
+
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.writer.flush()
+  }
+}
+
+/// Registers and flags copied before an instruction runs, to find what it changed.
+pub(crate) struct TraceSnapshot {
+  registers: [i32; 32],
+  float_registers: [f64; 32],
+  equal_flag: bool,
+  remainder: u32,
+}
+
+impl VirtualMachine {
+  pub(crate) fn trace_snapshot(&self) -> TraceSnapshot {
+    TraceSnapshot {
+      registers: self.registers,
+      float_registers: self.float_registers,
+      equal_flag: self.equal_flag,
+      remainder: self.remainder,
+    }
+  }
+
+  /// Write the record for an instruction that just ran. Tracing stops at the first write error.
+  pub(crate) fn trace_record(&mut self, instruction: &DecodedInstruction, before: TraceSnapshot, status: &ExecutionStatus) {
+    let record = TraceRecord {
+      step: self.instructions_executed - 1,
+      pc: instruction.offset,
+      opcode: Opcode::metadata(instruction.opcode).map_or("IGL", |metadata| metadata.str_symbol),
+      operands: instruction.operands.iter().copied().filter(|operand| *operand != Operand::Empty).collect(),
+      registers: (0..self.registers.len())
+        .filter(|index| before.registers[*index] != self.registers[*index])
+        .map(|index| (index, self.registers[index]))
+        .collect(),
+      // compare bits so NaN and -0.0 changes are recorded too
+      float_registers: (0..self.float_registers.len())
+        .filter(|index| before.float_registers[*index].to_bits() != self.float_registers[*index].to_bits())
+        .map(|index| (index, self.float_registers[index]))
+        .collect(),
+      equal_flag: (before.equal_flag != self.equal_flag).then_some(self.equal_flag),
+      remainder: (before.remainder != self.remainder).then_some(self.remainder),
+      trap: match status {
+        ExecutionStatus::Crash(trap) => Some(trap.to_string()),
+        _ => None,
+      },
+    };
+    if let Some(tracer) = self.tracer.as_mut() {
+      if let Err(err) = tracer.write(&record) {
+        error!("Could not write trace, tracing stopped: {}", err);
+        self.tracer = None;
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceError {
+  /// Reading trace `trace` (1 or 2) failed.
+  Read { trace: usize, message: String },
+  /// A line of trace `trace` is not a JSON value.
+  InvalidRecord { trace: usize, line: usize, message: String },
+}
+
+impl fmt::Display for TraceError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      TraceError::Read { trace, message } => write!(f, "Could not read trace {}: {}", trace, message),
+      TraceError::InvalidRecord { trace, line, message } => {
+        write!(f, "Invalid record on line {} of trace {}: {}", line, trace, message)
+      }
+    }
+  }
+}
+
+/// The first line two traces disagree on. A side is `None` when its trace ended before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceDivergence {
+  pub line: usize,
+  pub left: Option<Value>,
+  pub right: Option<Value>,
+}
+
+impl TraceDivergence {
+  /// Names of the fields that differ, empty when one trace ended early.
+  pub fn fields(&self) -> Vec<&str> {
+    let (Some(Value::Object(left)), Some(Value::Object(right))) = (&self.left, &self.right) else {
+      return vec![];
+    };
+    let mut fields: Vec<&str> = left
+      .keys()
+      .chain(right.keys().filter(|key| !left.contains_key(*key)))
+      .filter(|key| left.get(*key) != right.get(*key))
+      .map(String::as_str)
+      .collect();
+    fields.sort_unstable();
+    fields
+  }
+}
+
+impl fmt::Display for TraceDivergence {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "Traces diverge at line {}", self.line)?;
+    let fields = self.fields();
+    if !fields.is_empty() {
+      write!(f, " ({})", fields.join(", "))?;
+    }
+    let side = |record: &Option<Value>| record.as_ref().map_or("<end of trace>".to_string(), Value::to_string);
+    write!(f, "\n< {}\n> {}", side(&self.left), side(&self.right))
+  }
+}
+
+/// Compare two traces record by record and return the first line they differ on, `None` when
+/// they are the same. Records are compared as JSON, so traces from older VMs can be compared too.
+pub fn diff_traces(left: impl BufRead, right: impl BufRead) -> Result<Option<TraceDivergence>, TraceError> {
+  let mut left = records(left, 1);
+  let mut right = records(right, 2);
+  let mut line = 0;
+  loop {
+    line += 1;
+    match (left.next().transpose()?, right.next().transpose()?) {
+      (None, None) => return Ok(None),
+      (left, right) if left != right => return Ok(Some(TraceDivergence { line, left, right })),
+      _ => {}
+    }
+  }
+}
+
+fn records(reader: impl BufRead, trace: usize) -> impl Iterator<Item = Result<Value, TraceError>> {
+  reader
+    .lines()
+    .enumerate()
+    .filter(|(_, line)| !matches!(line, Ok(text) if text.trim().is_empty()))
+    .map(move |(index, line)| {
+      let line_text = line.map_err(|err| TraceError::Read { trace, message: err.to_string() })?;
+      serde_json::from_str(&line_text)
+        .map_err(|err| TraceError::InvalidRecord { trace, line: index + 1, message: err.to_string() })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+  use lumi_asm::Assembler;
+  use crate::vm::program::Program;
+
+  /// A writer tests can read back after the VM owns it.
+  #[derive(Clone, Default)]
+  struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+  impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn trace(source: &str) -> String {
+    let buffer = SharedBuffer::default();
+    let mut vm = VirtualMachine::initialize();
+    vm.load(Program::from_bytes(Assembler::new().assemble(source).unwrap()).unwrap());
+    vm.tracer = Some(Tracer::new(buffer.clone()));
+    vm.run();
+    let bytes = buffer.0.lock().unwrap().clone();
+    String::from_utf8(bytes).unwrap()
+  }
+
+  fn lines_of(trace: &str) -> Vec<&str> {
+    trace.lines().collect()
+  }
+
+  #[test]
+  fn test_trace_records_changes() {
+    let trace = trace(".data\n.code\nload $0 #7\nload $1 #7\neq $0 $1\ndiv $0 $0 $1\nhlt\n");
+    let lines = lines_of(&trace);
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], r#"{"step":0,"pc":69,"opcode":"LOAD","operands":[{"register":0},{"integer":7}],"registers":{"0":7}}"#);
+    assert_eq!(lines[2], r#"{"step":2,"pc":77,"opcode":"EQ","operands":[{"register":0},{"register":1}],"equal_flag":true}"#);
+    assert!(lines[3].ends_with(r#""registers":{"1":1}}"#), "{}", lines[3]);
+    assert_eq!(lines[4], r#"{"step":4,"pc":84,"opcode":"HLT","operands":[]}"#);
+  }
+
+  #[test]
+  fn test_diff_traces_reports_first_divergence() {
+    let left = trace(".data\n.code\nload $0 #1\nload $1 #2\nhlt\n");
+    assert_eq!(diff_traces(left.as_bytes(), left.as_bytes()), Ok(None));
+
+    let right = trace(".data\n.code\nload $0 #1\nload $1 #3\nhlt\n");
+    let divergence = diff_traces(left.as_bytes(), right.as_bytes()).unwrap().unwrap();
+    assert_eq!(divergence.line, 2);
+    assert_eq!(divergence.fields(), vec!["operands", "registers"]);
+    assert!(divergence.to_string().starts_with("Traces diverge at line 2 (operands, registers)\n< {"));
+
+    let shorter = lines_of(&left)[..2].join("\n");
+    let divergence = diff_traces(left.as_bytes(), shorter.as_bytes()).unwrap().unwrap();
+    assert_eq!((divergence.line, divergence.right), (3, None));
+
+    assert_eq!(
+      diff_traces(left.as_bytes(), format!("{}\nnot json\n", lines_of(&left)[0]).as_bytes()),
+      Err(TraceError::InvalidRecord { trace: 2, line: 2, message: "expected ident at line 1 column 2".to_string() })
+    );
+  }
+}
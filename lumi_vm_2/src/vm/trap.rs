@@ -0,0 +1,92 @@
+use std::fmt;
+use std::fmt::Formatter;
+use lumi_asm::encoding::DecodeError;
+use lumi_asm::header_utils::HeaderError;
+use lumi_asm::instruction::Opcode;
+
+/// A fault raised by a guest program. Traps stop execution without ever panicking the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmTrap {
+  /// The program header is malformed or was written for an incompatible VM.
+  InvalidHeader { error: HeaderError },
+  /// The byte at the program counter is not a known opcode, or is `IGL`.
+  IllegalOpcode { opcode: u8 },
+  /// The opcode is known but the VM has no handler for it.
+  UnimplementedOpcode { opcode: Opcode },
+  /// The instruction at the program counter runs past the end of the program.
+  TruncatedInstruction { opcode: Opcode },
+  /// The program counter points outside of the program.
+  PcOutOfBounds { pc: usize },
+  /// An instruction names a register that does not exist.
+  InvalidRegister { register: usize },
+  DivideByZero,
+  /// `POP` or `RET` on a stack that holds too few values.
+  StackUnderflow,
+  /// Access to `len` bytes at `addr` falls outside of the heap or read-only data.
+  MemoryFault { addr: usize, len: usize },
+  /// `ALOC` was asked for a negative number of bytes.
+  InvalidAllocation { size: i32 },
+  /// `ALOC` would grow the heap to `requested` bytes, past the configured limit.
+  HeapLimitExceeded { requested: usize, limit: usize },
+  /// The stack would grow past the configured maximum depth.
+  StackOverflow { limit: usize },
+  /// The configured instruction budget has been used up.
+  OutOfFuel { limit: u64 },
+}
+
+impl VmTrap {
+  /// Exit code reported for a program stopped by this trap.
+  pub fn exit_code(&self) -> u32 {
+    match self {
+      VmTrap::InvalidHeader { .. } => 1,
+      VmTrap::IllegalOpcode { .. } => 2,
+      VmTrap::UnimplementedOpcode { .. } => 3,
+      VmTrap::TruncatedInstruction { .. } => 4,
+      VmTrap::PcOutOfBounds { .. } => 5,
+      VmTrap::InvalidRegister { .. } => 6,
+      VmTrap::DivideByZero => 7,
+      VmTrap::StackUnderflow => 8,
+      VmTrap::MemoryFault { .. } => 10,
+      VmTrap::InvalidAllocation { .. } => 11,
+      VmTrap::HeapLimitExceeded { .. } => 12,
+      VmTrap::StackOverflow { .. } => 13,
+      VmTrap::OutOfFuel { .. } => 14,
+    }
+  }
+}
+
+impl From<DecodeError> for VmTrap {
+  fn from(error: DecodeError) -> Self {
+    match error {
+      DecodeError::UnknownOpcode { byte, .. } => VmTrap::IllegalOpcode { opcode: byte },
+      DecodeError::Truncated { offset, available: 0, .. } => VmTrap::PcOutOfBounds { pc: offset },
+      DecodeError::Truncated { opcode, .. } => VmTrap::TruncatedInstruction { opcode },
+    }
+  }
+}
+
+impl fmt::Display for VmTrap {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      VmTrap::InvalidHeader { error } => write!(f, "Invalid LUMI header: {}", error),
+      VmTrap::IllegalOpcode { opcode } => write!(f, "Illegal opcode 0x{:02x}", opcode),
+      VmTrap::UnimplementedOpcode { opcode } => write!(f, "Opcode {:?} is not implemented", opcode),
+      VmTrap::TruncatedInstruction { opcode } => {
+        write!(f, "{:?} instruction runs past the end of the program", opcode)
+      }
+      VmTrap::PcOutOfBounds { pc } => write!(f, "Program counter 0x{:x} is out of bounds", pc),
+      VmTrap::InvalidRegister { register } => write!(f, "Invalid register ${}", register),
+      VmTrap::DivideByZero => write!(f, "Division by zero"),
+      VmTrap::StackUnderflow => write!(f, "Stack underflow"),
+      VmTrap::MemoryFault { addr, len } => {
+        write!(f, "Memory fault accessing {} bytes at 0x{:x}", len, addr)
+      }
+      VmTrap::InvalidAllocation { size } => write!(f, "Invalid allocation of {} bytes", size),
+      VmTrap::HeapLimitExceeded { requested, limit } => {
+        write!(f, "Heap of {} bytes exceeds the limit of {} bytes", requested, limit)
+      }
+      VmTrap::StackOverflow { limit } => write!(f, "Stack overflow, maximum depth is {}", limit),
+      VmTrap::OutOfFuel { limit } => write!(f, "Out of fuel after {} instructions", limit),
+    }
+  }
+}
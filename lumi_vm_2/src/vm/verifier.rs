@@ -0,0 +1,229 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::ops::Range;
+use lumi_asm::encoding::{decode, DecodeError, DecodedInstruction, Operand};
+use lumi_asm::instruction::Opcode;
+use lumi_asm::sections::{SectionKind, SectionTable};
+
+/// Number of integer and float registers in the VM.
+const REGISTER_COUNT: usize = 32;
+
+/// A reason the verifier rejected a program, with the offset of the offending instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+  /// The code section holds an unknown opcode or an instruction cut off by the end of the section.
+  Decode { error: DecodeError },
+  /// An operand names a register that does not exist.
+  InvalidRegister { offset: usize, register: u8 },
+  /// A jump or call target lies outside of the code section or inside another instruction.
+  InvalidTarget { offset: usize, opcode: Opcode, target: usize },
+  /// A `PRTS` operand points past the end of the read-only data.
+  StringOutOfBounds { offset: usize, address: usize, ro_length: usize },
+}
+
+impl VerifyError {
+  /// Offset of the instruction the error was found in.
+  pub fn offset(&self) -> usize {
+    match self {
+      VerifyError::Decode { error: DecodeError::UnknownOpcode { offset, .. } } => *offset,
+      VerifyError::Decode { error: DecodeError::Truncated { offset, .. } } => *offset,
+      VerifyError::InvalidRegister { offset, .. } => *offset,
+      VerifyError::InvalidTarget { offset, .. } => *offset,
+      VerifyError::StringOutOfBounds { offset, .. } => *offset,
+    }
+  }
+}
+
+impl fmt::Display for VerifyError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      VerifyError::Decode { error } => write!(f, "{}", error),
+      VerifyError::InvalidRegister { offset, register } => {
+        write!(f, "Invalid register ${} at offset 0x{:x}", register, offset)
+      }
+      VerifyError::InvalidTarget { offset, opcode, target } => write!(
+        f,
+        "{:?} at offset 0x{:x} targets 0x{:x}, which is not the start of an instruction",
+        opcode, offset, target
+      ),
+      VerifyError::StringOutOfBounds { offset, address, ro_length } => write!(
+        f,
+        "PRTS at offset 0x{:x} reads 0x{:x}, past {} bytes of read-only data",
+        offset, address, ro_length
+      ),
+    }
+  }
+}
+
+/// The instruction boundaries of a code section that passed verification.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VerifiedCode {
+  code: Range<usize>,
+  boundaries: Vec<bool>,
+}
+
+impl VerifiedCode {
+  /// Whether a verified instruction starts at `offset`.
+  pub fn is_instruction(&self, offset: usize) -> bool {
+    offset >= self.code.start && self.boundaries.get(offset - self.code.start).copied().unwrap_or(false)
+  }
+}
+
+/// Walk the code section of `bytecode` and check every instruction: opcodes and lengths,
+/// register numbers, jump and call targets, and `PRTS` offsets. Returns every error found,
+/// not just the first one.
+pub fn verify(bytecode: &[u8], sections: &SectionTable) -> Result<VerifiedCode, Vec<VerifyError>> {
+  let code = sections.get(SectionKind::Code).map(|code| code.range()).unwrap_or_default();
+  let ro_length = sections.get(SectionKind::ReadOnly).map_or(0, |ro| ro.size as usize);
+  let bytecode = &bytecode[..code.end.min(bytecode.len())];
+
+  let mut errors = vec![];
+  let mut instructions = vec![];
+  // one extra slot so jumps to the end of the code, which halt the program, are accepted
+  let mut boundaries = vec![false; code.len() + 1];
+  let mut offset = code.start;
+  while offset < code.end {
+    match decode(bytecode, offset) {
+      Ok(instruction) => {
+        boundaries[offset - code.start] = true;
+        offset = instruction.next_offset();
+        instructions.push(instruction);
+      }
+      Err(error @ DecodeError::UnknownOpcode { .. }) => {
+        errors.push(VerifyError::Decode { error });
+        offset += 1;
+      }
+      Err(error) => {
+        errors.push(VerifyError::Decode { error });
+        break;
+      }
+    }
+  }
+  boundaries[code.len()] = true;
+
+  let verified = VerifiedCode { code, boundaries };
+  for instruction in &instructions {
+    check_instruction(instruction, &verified, ro_length, &mut errors);
+  }
+
+  if errors.is_empty() {
+    Ok(verified)
+  } else {
+    errors.sort_by_key(VerifyError::offset);
+    Err(errors)
+  }
+}
+
+fn check_instruction(instruction: &DecodedInstruction, code: &VerifiedCode, ro_length: usize, errors: &mut Vec<VerifyError>) {
+  let offset = instruction.offset;
+  for operand in &instruction.operands {
+    if let Operand::Register(register) | Operand::FloatRegister(register) = *operand {
+      if register as usize >= REGISTER_COUNT {
+        errors.push(VerifyError::InvalidRegister { offset, register });
+      }
+    }
+  }
+
+  match instruction.opcode {
+    Opcode::DJMP | Opcode::DJMPE | Opcode::CALL | Opcode::LOOP => {
+      let target = instruction.address(0);
+      if !code.is_instruction(target) {
+        errors.push(VerifyError::InvalidTarget { offset, opcode: instruction.opcode, target });
+      }
+    }
+    Opcode::PRTS => {
+      let address = instruction.address(0);
+      if address >= ro_length {
+        errors.push(VerifyError::StringOutOfBounds { offset, address, ro_length });
+      }
+    }
+    _ => {}
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lumi_asm::encoding::encode;
+  use lumi_asm::header_utils::{code_start_offset, LumiHeader};
+  use lumi_asm::Assembler;
+
+  fn verify_bytes(bytecode: &[u8]) -> Result<VerifiedCode, Vec<VerifyError>> {
+    let header = LumiHeader::parse(bytecode).unwrap();
+    verify(bytecode, &header.sections(bytecode).unwrap())
+  }
+
+  fn wrap(ro_data: &[u8], instructions: &[(Opcode, [Operand; 3])]) -> Vec<u8> {
+    let mut body = ro_data.to_vec();
+    for (opcode, operands) in instructions {
+      body.extend(encode(*opcode, operands));
+    }
+    LumiHeader::new(ro_data.len()).wrap(&body)
+  }
+
+  #[test]
+  fn test_verify_accepts_assembled_program() {
+    let bytecode = Assembler::new()
+      .assemble(
+        r".rodata
+        hello: .asciiz 'Hi'
+        .code
+        load $0 #3
+        top: prts @hello
+        dec $0
+        neq $0 $31
+        djmpe @done
+        djmp @top
+        done: hlt
+        ",
+      )
+      .unwrap();
+    let verified = verify_bytes(&bytecode).unwrap();
+    let start = code_start_offset(3);
+    assert!(verified.is_instruction(start));
+    assert!(!verified.is_instruction(start + 1));
+    assert!(verified.is_instruction(start + 4));
+  }
+
+  #[test]
+  fn test_verify_reports_every_error() {
+    let start = code_start_offset(2);
+    let bytecode = wrap(
+      b"hi",
+      &[
+        (Opcode::LOAD, [Operand::Register(40), Operand::Integer(1), Operand::Empty]),
+        (Opcode::DJMP, [Operand::Address(start as u32 + 1), Operand::Empty, Operand::Empty]),
+        (Opcode::CALL, [Operand::Address(30000), Operand::Empty, Operand::Empty]),
+        (Opcode::PRTS, [Operand::Address(2), Operand::Empty, Operand::Empty]),
+        (Opcode::ADDF64, [Operand::FloatRegister(1), Operand::FloatRegister(32), Operand::FloatRegister(2)]),
+      ],
+    );
+    assert_eq!(
+      verify_bytes(&bytecode),
+      Err(vec![
+        VerifyError::InvalidRegister { offset: start, register: 40 },
+        VerifyError::InvalidTarget { offset: start + 4, opcode: Opcode::DJMP, target: start + 1 },
+        VerifyError::InvalidTarget { offset: start + 9, opcode: Opcode::CALL, target: 30000 },
+        VerifyError::StringOutOfBounds { offset: start + 14, address: 2, ro_length: 2 },
+        VerifyError::InvalidRegister { offset: start + 19, register: 32 },
+      ])
+    );
+  }
+
+  #[test]
+  fn test_verify_reports_unknown_and_truncated_instructions() {
+    let load = encode(Opcode::LOADI, &[Operand::Register(0), Operand::Integer(7), Operand::Empty]);
+    let mut body = vec![0xfe, Opcode::HLT.into()];
+    body.extend(&load[..3]);
+    let bytecode = LumiHeader::new(0).wrap(&body);
+    let start = code_start_offset(0);
+    let errors = verify_bytes(&bytecode).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0], VerifyError::Decode { error: DecodeError::UnknownOpcode { offset: start, byte: 0xfe } });
+    assert!(matches!(
+      errors[1],
+      VerifyError::Decode { error: DecodeError::Truncated { opcode: Opcode::LOADI, offset, .. } } if offset == start + 2
+    ));
+    assert_eq!(errors[1].offset(), start + 2);
+  }
+}
@@ -1,16 +1,25 @@
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::Cursor;
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::ops::Range;
 use chrono::{DateTime, Utc};
 use libloading::{Library, Symbol};
 use log::{debug, error, info};
 use uuid::Uuid;
+use lumi_asm::debug_info::DebugInfo;
+use lumi_asm::encoding::{decode, instruction_length, DecodeError, DecodedInstruction, Operand};
 use lumi_asm::instruction::Opcode;
-use lumi_asm::header_utils::{verify_header, LUMI_HEADER_LENGTH};
+use lumi_asm::header_utils::LumiHeader;
+use lumi_asm::sections::{SectionKind, SectionTable};
 use lumi_vm_sdk::{LumiVmContext, LumiVmPlugin};
 use crate::vm::extensions::load_extensions;
 use crate::vm::operations::InstructionHandler;
+use crate::vm::config::{VirtualMachineBuilder, VmConfig};
+use crate::vm::journal::Journal;
+use crate::vm::trace::Tracer;
+use crate::vm::program::Program;
+use crate::vm::trap::VmTrap;
+use crate::vm::verifier::VerifiedCode;
+use crate::vm::watch::Watchpoint;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VMEventType {
@@ -18,13 +27,33 @@ pub enum VMEventType {
   Info,
   GracefulShutdown { exit_code: u32 },
   ForcefulShutdown { exit_code: u32 },
-  Crash { exit_code: u32 },
+  Crash { exit_code: u32, pc: usize, trap: VmTrap },
+}
+
+/// Number of instructions `run` executes per `run_for` slice.
+const RUN_SLICE: usize = 10_000;
+
+/// Why a call to `VirtualMachine::run_for` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+  /// The instruction budget for this slice ran out, call `run_for` again to resume.
+  Yielded,
+  /// The program finished with the given exit code.
+  Halted(u32),
+  /// The program was stopped by a trap.
+  Trapped(VmTrap),
+  /// A breakpoint instruction at the given pc was hit.
+  Breakpoint(usize),
+  /// The instruction at `pc` changed the location of a breaking watchpoint.
+  Watchpoint { id: usize, pc: usize },
 }
 
 pub enum ExecutionStatus {
   Continue,
   BreakpointHit,
-  Crash(u32),
+  /// The instruction changed the location of the breaking watchpoint with this id.
+  WatchpointHit(usize),
+  Crash(VmTrap),
   Done(u32),
 }
 
@@ -36,19 +65,6 @@ pub struct VMEvent {
   message: Option<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum WatchType {
-  Memory(usize),
-  Register(usize),
-  FloatRegister(usize),
-}
-
-#[derive(Debug, Clone)]
-pub struct WatchVariable {
-  pub(crate) watch_type: WatchType,
-  pub(crate) last_value: f32,
-}
-
 impl VMEventType {
   pub fn stop_code(&self) -> u32 {
     match self {
@@ -56,11 +72,17 @@ impl VMEventType {
       VMEventType::Info => 0,
       VMEventType::GracefulShutdown { exit_code } => *exit_code,
       VMEventType::ForcefulShutdown { exit_code } => *exit_code,
-      VMEventType::Crash { exit_code } => *exit_code,
+      VMEventType::Crash { exit_code, .. } => *exit_code,
     }
   }
 }
 
+impl VMEvent {
+  pub fn message(&self) -> Option<&str> {
+    self.message.as_deref()
+  }
+}
+
 pub struct VirtualMachine {
   pub vm_id: Uuid,
   pub logical_cores: usize,
@@ -77,8 +99,27 @@ pub struct VirtualMachine {
   pub loop_counter: usize,
   pub sp: usize,
   pub bp: usize,
-  pub watch_variables: HashMap<WatchType, WatchVariable>,
+  /// Checked after every instruction, an empty list costs a single length check.
+  pub watchpoints: Vec<Watchpoint>,
+  /// Prior state of the most recent instructions when `config.journal` is set, created when
+  /// the program starts.
+  pub journal: Option<Journal>,
+  /// Receives a record of every instruction executed while it is set.
+  pub tracer: Option<Tracer>,
   pub instruction_table: HashMap<Opcode, InstructionHandler>,
+  pub config: VmConfig,
+  /// Number of instructions executed so far, counted against `config.fuel`.
+  pub instructions_executed: u64,
+  /// Source lines and symbols from the program's debug section, if it has one.
+  pub debug_info: Option<DebugInfo>,
+  /// The code section. The program halts when the PC reaches its end and traps when the PC
+  /// lands anywhere else outside it. `None` runs the whole program as code.
+  code: Option<Range<usize>>,
+  /// Instruction boundaries of a program installed with `load`. Instructions that start on one
+  /// skip the register check, anything else is checked as it runs.
+  verified: Option<VerifiedCode>,
+  started: bool,
+  finished: Option<RunOutcome>,
 }
 
 impl VirtualMachine {
@@ -99,11 +140,24 @@ impl VirtualMachine {
       loop_counter: 0,
       sp: 0,
       bp: 0,
-      watch_variables: HashMap::new(),
+      watchpoints: vec![],
+      journal: None,
+      tracer: None,
       instruction_table: HashMap::new(),
+      config: VmConfig::default(),
+      instructions_executed: 0,
+      debug_info: None,
+      code: None,
+      verified: None,
+      started: false,
+      finished: None,
     }
   }
   
+  pub fn builder() -> VirtualMachineBuilder {
+    VirtualMachineBuilder::new()
+  }
+
   pub fn initialize() -> Self {
     let mut vm = VirtualMachine::new();
     vm.initialize_instruction_table();
@@ -112,6 +166,7 @@ impl VirtualMachine {
   
   fn initialize_instruction_table(&mut self) {
     self.instruction_table.insert(Opcode::LOAD, VirtualMachine::memory_execute_load);
+    self.instruction_table.insert(Opcode::LOADI, VirtualMachine::memory_execute_load_immediate);
     self.instruction_table.insert(Opcode::LOADF64, VirtualMachine::memory_execute_load_f64);
     self.instruction_table.insert(Opcode::ALOC, VirtualMachine::memory_execute_allocate);
     self.instruction_table.insert(Opcode::LUI, VirtualMachine::memory_execute_load_upper_immediate);
@@ -168,6 +223,7 @@ impl VirtualMachine {
     self.instruction_table.insert(Opcode::CALL, VirtualMachine::system_execute_call);
     self.instruction_table.insert(Opcode::RET, VirtualMachine::system_execute_return);
     
+    self.instruction_table.insert(Opcode::BKPT, VirtualMachine::system_execute_breakpoint);
     self.instruction_table.insert(Opcode::NOP, VirtualMachine::system_no_operation);
     self.instruction_table.insert(Opcode::HLT, VirtualMachine::system_halt);
     self.instruction_table.insert(Opcode::IGL, VirtualMachine::system_illegal_instruction);
@@ -184,23 +240,95 @@ impl VirtualMachine {
     info!("RO Data: {:?}", self.ro_data);
   }
 
-  // TODO: implement this
-  fn detect_changes(&mut self) {
-    for (watch_key, watch_var) in self.watch_variables.iter_mut() {
-      let current_value = match watch_key {
-        WatchType::Memory(addr) => self.heap[*addr] as f32,
-        WatchType::Register(index) => self.registers[*index] as f32,
-        WatchType::FloatRegister(index) => self.float_registers[*index] as f32,
-      };
+  /// Run the program until it halts or traps and return every event recorded so far.
+  /// Breakpoints and watchpoints are ignored, use `run_for` to stop on them.
+  pub fn run(&mut self) -> Vec<VMEvent> {
+    while let RunOutcome::Yielded | RunOutcome::Breakpoint(_) | RunOutcome::Watchpoint { .. } = self.run_for(RUN_SLICE) {}
+    self.events.clone()
+  }
+
+  /// Execute at most `instructions` instructions and report why execution stopped.
+  /// A `Yielded` or `Breakpoint` VM resumes where it left off on the next call, a halted or
+  /// trapped VM keeps returning the same outcome.
+  pub fn run_for(&mut self, instructions: usize) -> RunOutcome {
+    if let Some(outcome) = self.finished {
+      return outcome;
+    }
+
+    if !self.started {
+      self.started = true;
+      if let Err(trap) = self.start() {
+        self.record_crash(trap);
+        return self.finish(RunOutcome::Trapped(trap));
+      }
+    }
 
-      if (current_value - watch_var.last_value).abs() > f32::EPSILON {
-        info!("Watched variable changed: {:?}", watch_var);
-        watch_var.last_value = current_value;
+    for _ in 0..instructions {
+      let pc = self.pc;
+      match self.execute_instruction() {
+        ExecutionStatus::Continue => {}
+        ExecutionStatus::BreakpointHit => return RunOutcome::Breakpoint(pc),
+        ExecutionStatus::WatchpointHit(id) => return RunOutcome::Watchpoint { id, pc },
+        ExecutionStatus::Crash(trap) => {
+          error!("Program crashed at {}: {}", self.describe_pc(self.pc), trap);
+          self.record_crash(trap);
+          return self.finish(RunOutcome::Trapped(trap));
+        }
+        ExecutionStatus::Done(code) => {
+          self.events.push(VMEvent {
+            event_type: VMEventType::GracefulShutdown { exit_code: code },
+            at: Utc::now(),
+            application_id: self.vm_id,
+            message: None,
+          });
+          return self.finish(RunOutcome::Halted(code));
+        }
       }
     }
+
+    RunOutcome::Yielded
   }
 
-  pub fn run(&mut self) -> Vec<VMEvent> {
+  /// Install a validated program and reset the registers, memory and stack so it runs from the start.
+  pub fn load(&mut self, program: Program) {
+    self.program = program.bytecode;
+    self.pc = 0;
+    self.registers = [0; 32];
+    self.float_registers = [0.0; 32];
+    self.heap.clear();
+    self.stack.clear();
+    self.ro_data.clear();
+    self.remainder = 0;
+    self.equal_flag = false;
+    self.loop_counter = 0;
+    self.sp = 0;
+    self.bp = 0;
+    self.instructions_executed = 0;
+    self.debug_info = program.debug_info;
+    self.code = None;
+    self.verified = Some(program.verified);
+    self.rewind();
+  }
+
+  /// Instruction boundaries of the program installed with `load`.
+  pub fn verified_code(&self) -> Option<&VerifiedCode> {
+    self.verified.as_ref()
+  }
+
+  /// Forget how far the program has run, so the next `run_for` starts again from the header.
+  pub fn rewind(&mut self) {
+    self.started = false;
+    self.finished = None;
+  }
+
+  fn finish(&mut self, outcome: RunOutcome) -> RunOutcome {
+    self.finished = Some(outcome);
+    outcome
+  }
+
+  /// Record the start event, load extensions, check the header, load the sections and move
+  /// the PC to the code.
+  fn start(&mut self) -> Result<(), VmTrap> {
     self.events.push(VMEvent {
       event_type: VMEventType::Start,
       at: Utc::now(),
@@ -208,7 +336,7 @@ impl VirtualMachine {
       message: None,
     });
     
-    let mut extensions: Vec<Box<dyn LumiVmPlugin>> = load_extensions("./extensions");
+    let mut extensions: Vec<Box<dyn LumiVmPlugin>> = load_extensions(&self.config.extensions_dir);
     
     for ext in &mut extensions {
       let context = LumiVmContext {
@@ -220,138 +348,924 @@ impl VirtualMachine {
       ext.on_load().unwrap();
     }
 
-    if !verify_header(&self.program) {
-      self.events.push(VMEvent {
-        event_type: VMEventType::Crash { exit_code: 1 },
-        at: Utc::now(),
-        application_id: self.vm_id,
-        message: Some("Not a LUMI header, skipping execution.".to_string()),
-      });
-      error!("Not a LUMI header, skipping execution.");
-      return self.events.clone();
-    }
+    let header = LumiHeader::parse(&self.program).and_then(|header| {
+      header.validate(&self.program)?;
+      Ok((header, header.sections(&self.program)?))
+    });
+    let (header, sections) = match header {
+      Ok(header) => header,
+      Err(error) => {
+        error!("{}, skipping execution.", error);
+        return Err(VmTrap::InvalidHeader { error });
+      }
+    };
 
-    // move PC past header
-    self.pc = LUMI_HEADER_LENGTH + 1 + 4 + self.get_starting_offset();
+    // move PC to the entry point
+    self.pc = header.entry_offset as usize;
     debug!("code start: {}", self.pc);
 
-    self.read_ro_data();
+    self.load_sections(&sections)?;
+    self.refresh_watchpoints();
+    self.journal = self.config.journal.map(Journal::new);
+    Ok(())
+  }
 
-    let mut is_done = None;
-    let mut in_step_mode = false;
-    while is_done.is_none() {
-      match self.execute_instruction() {
-        ExecutionStatus::Continue => {
-          if in_step_mode {
-            // in_step_mode = self.system_execute_breakpoint();
-          }
-        }
-        ExecutionStatus::BreakpointHit => {
-          // in_step_mode = self.system_execute_breakpoint();
-        }
-        ExecutionStatus::Crash(code) => {
-          // TODO: log crash event
-          is_done = Some(code);
-        }
-        ExecutionStatus::Done(code) => {
-          is_done = Some(code);
-        }
+  /// Copy the read-only data and debug info out of the program and lay out the heap: `.data`
+  /// is copied in and `.bss` is zeroed.
+  fn load_sections(&mut self, sections: &SectionTable) -> Result<(), VmTrap> {
+    self.ro_data = sections.contents(SectionKind::ReadOnly, &self.program).to_vec();
+    self.code = sections.get(SectionKind::Code).map(|code| code.range());
+    if let Some(debug) = sections.get(SectionKind::Debug) {
+      let debug_info = DebugInfo::parse(&self.program[debug.range()])
+        .map_err(|error| VmTrap::InvalidHeader { error })?;
+      self.debug_info = Some(debug_info);
+    }
+
+    let heap_size = sections.heap_size();
+    if let Some(limit) = self.config.max_heap_bytes {
+      if heap_size > limit {
+        return Err(VmTrap::HeapLimitExceeded { requested: heap_size, limit });
       }
     }
 
+    self.heap = vec![0; heap_size];
+    for section in sections.sections().iter().filter(|section| section.kind == SectionKind::Data) {
+      self.heap[section.heap_range()].copy_from_slice(&self.program[section.range()]);
+    }
+    debug!("Loaded {} bytes of read-only data and {} bytes of heap", self.ro_data.len(), heap_size);
+    Ok(())
+  }
+
+  /// Check that the stack can take `count` more values without exceeding the configured depth.
+  pub(crate) fn check_stack_capacity(&self, count: usize) -> Result<(), VmTrap> {
+    match self.config.max_stack_depth {
+      Some(limit) if self.stack.len() + count > limit => Err(VmTrap::StackOverflow { limit }),
+      _ => Ok(()),
+    }
+  }
+
+  /// Record a crash event for a trap raised at the current program counter. The message
+  /// names the source location and the call stack when the program has debug info.
+  fn record_crash(&mut self, trap: VmTrap) {
+    let mut message = format!("{} at {}", trap, self.describe_pc(self.pc));
+    let backtrace = self.backtrace();
+    if backtrace.len() > 1 {
+      for (depth, pc) in backtrace.iter().enumerate() {
+        message.push_str(&format!("\n  #{} {}", depth, self.describe_pc(*pc)));
+      }
+    }
     self.events.push(VMEvent {
-      event_type: VMEventType::GracefulShutdown { exit_code: is_done.unwrap() },
+      event_type: VMEventType::Crash { exit_code: trap.exit_code(), pc: self.pc, trap },
       at: Utc::now(),
       application_id: self.vm_id,
-      message: None,
+      message: Some(message),
     });
+  }
 
-    self.events.clone()
+  /// Describe `pc` by label and source line when debug info is loaded, otherwise as an offset.
+  pub fn describe_pc(&self, pc: usize) -> String {
+    match &self.debug_info {
+      Some(debug_info) => debug_info.describe(pc),
+      None => format!("0x{:x}", pc),
+    }
+  }
+
+  /// The current PC followed by the `CALL` instruction of every active frame, innermost first.
+  pub fn backtrace(&self) -> Vec<usize> {
+    let mut frames = vec![self.pc];
+    let mut bp = self.bp;
+    // every frame holds the return address and the caller's bp just below its own bp
+    while bp >= 2 && bp <= self.stack.len() {
+      let return_address = self.stack[bp - 2] as usize;
+      frames.push(return_address.saturating_sub(instruction_length(Opcode::CALL)));
+      let caller_bp = self.stack[bp - 1] as usize;
+      if caller_bp >= bp {
+        break;
+      }
+      bp = caller_bp;
+    }
+    frames
+  }
+
+  /// Undo the most recent instruction in the journal and return its offset. The PC is left on
+  /// the instruction so it runs again on the next `run_for`. Returns `None` when the journal is
+  /// off or empty.
+  pub fn undo_instruction(&mut self) -> Option<usize> {
+    let entry = self.journal.as_mut()?.pop()?;
+    for (index, value) in entry.registers {
+      self.registers[index] = value;
+    }
+    for (index, value) in entry.float_registers {
+      self.float_registers[index] = value;
+    }
+    self.equal_flag = entry.equal_flag;
+    self.remainder = entry.remainder;
+    self.loop_counter = entry.loop_counter;
+    self.sp = entry.sp;
+    self.bp = entry.bp;
+    self.stack.truncate(entry.stack_base);
+    self.stack.extend(entry.stack_tail);
+    self.heap.truncate(entry.heap_length);
+    if let Some((addr, bytes)) = entry.heap_bytes {
+      self.heap[addr..addr + bytes.len()].copy_from_slice(&bytes);
+    }
+    self.pc = entry.pc;
+    self.instructions_executed = self.instructions_executed.saturating_sub(1);
+    // a halted program runs again from the undone instruction
+    self.finished = None;
+    Some(entry.pc)
   }
 
   /// Run the VM for one instruction.
-  pub fn run_once(&mut self) {
-    self.execute_instruction();
+  pub fn run_once(&mut self) -> RunOutcome {
+    self.run_for(1)
   }
 
   /// Execute the next instruction in the program.
   pub fn execute_instruction(&mut self) -> ExecutionStatus {
-    if self.pc >= self.program.len() {
+    let code = self.code.clone().unwrap_or(0..self.program.len());
+    if self.pc == code.end {
       return ExecutionStatus::Done(1);
     }
-    
-    // check for watch variable changes
-    for watch_var in self.watch_variables.values_mut() {
-      let current_value = match watch_var.watch_type {
-        WatchType::Memory(addr) => self.heap[addr] as f32,
-        WatchType::Register(index) => self.registers[index] as f32,
-        WatchType::FloatRegister(index) => self.float_registers[index] as f32,
-      };
-      
-      if current_value != watch_var.last_value {
-        info!("Watched variable changed: {:?}", watch_var);
-        watch_var.last_value = current_value;
+    if !code.contains(&self.pc) {
+      return ExecutionStatus::Crash(VmTrap::PcOutOfBounds { pc: self.pc });
+    }
+
+    if let Some(limit) = self.config.fuel {
+      if self.instructions_executed >= limit {
+        return ExecutionStatus::Crash(VmTrap::OutOfFuel { limit });
       }
     }
+    self.instructions_executed += 1;
 
-    let opcode = self.decode_opcode();
-    if let Some(handler) = self.instruction_table.get(&opcode) {
-      return handler(self);
-    } else {
-      error!("Illegal instruction: {:?}", opcode);
-      return ExecutionStatus::Done(1);
+    let instruction = match self.decode_instruction() {
+      Ok(instruction) => instruction,
+      Err(e) => {
+        error!("{}", e);
+        return ExecutionStatus::Crash(e.into());
+      }
+    };
+
+    // handlers index the register files directly, so reject bad registers up front unless
+    // the verifier already checked this instruction
+    if !self.verified.as_ref().is_some_and(|code| code.is_instruction(instruction.offset)) {
+      for operand in &instruction.operands {
+        if let Operand::Register(reg) | Operand::FloatRegister(reg) = *operand {
+          if reg as usize >= self.registers.len() {
+            self.pc = instruction.offset;
+            return ExecutionStatus::Crash(VmTrap::InvalidRegister { register: reg as usize });
+          }
+        }
+      }
+    }
+
+    let snapshot = self.journal.is_some().then(|| self.journal_snapshot(&instruction));
+    let traced = self.tracer.is_some().then(|| self.trace_snapshot());
+    let status = match self.instruction_table.get(&instruction.opcode) {
+      Some(handler) => handler(self, &instruction),
+      None => ExecutionStatus::Crash(VmTrap::UnimplementedOpcode { opcode: instruction.opcode }),
+    };
+    // handlers check before they write, so an instruction that crashed changed nothing
+    if let Some(snapshot) = snapshot.filter(|_| !matches!(status, ExecutionStatus::Crash(_))) {
+      self.journal_record(snapshot);
+    }
+    if let Some(before) = traced {
+      self.trace_record(&instruction, before, &status);
+    }
+
+    match status {
+      // leave the program counter on the faulting instruction
+      ExecutionStatus::Crash(_) => self.pc = instruction.offset,
+      ExecutionStatus::Continue if !self.watchpoints.is_empty() => {
+        if let Some(id) = self.check_watchpoints(instruction.offset) {
+          return ExecutionStatus::WatchpointHit(id);
+        }
+      }
+      _ => {}
     }
+    status
   }
 
-  /// Decode the current opcode from the program and increment the program counter.
-  pub fn decode_opcode(&mut self) -> Opcode {
-    let byte = self.program[self.pc];
-    let opcode = Opcode::from(byte);
-    self.pc += 1;
-    opcode
+  /// Decode the instruction at the program counter and move the program counter past it.
+  pub fn decode_instruction(&mut self) -> Result<DecodedInstruction, DecodeError> {
+    let instruction = decode(&self.program, self.pc)?;
+    self.pc = instruction.next_offset();
+    Ok(instruction)
   }
-  
-  /// Read the next 8 bits from the program and increment the program counter.
-  pub fn next_8_bits(&mut self) -> u8 {
-    let result = self.program[self.pc];
-    self.pc += 1;
-    result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lumi_asm::encoding::encode;
+  use lumi_asm::header_utils::{code_start_offset, HeaderError, LUMI_HEADER_SIZE, LUMI_ISA_VERSION};
+  use lumi_asm::Assembler;
+  use crate::vm::watch::{WatchCondition, WatchMode, WatchTarget, WatchValue};
+
+  fn run_source(source: &str) -> (VirtualMachine, Vec<VMEvent>) {
+    let mut asm = Assembler::new();
+    let mut vm = VirtualMachine::initialize();
+    vm.program = asm.assemble(source).unwrap();
+    let events = vm.run();
+    (vm, events)
   }
-  
-  
-  /// Read the next 16 bits from the program and increment the program counter.
-  /// Uses little-endian format.
-  pub fn next_16_bits(&mut self) -> u16 {
-    let result = (self.program[self.pc] as u16) | ((self.program[self.pc + 1] as u16) << 8);
-    self.pc += 2;
-    result
+
+  fn run_bytecode(ro_data: &[u8], instructions: &[(Opcode, [Operand; 3])]) -> Vec<VMEvent> {
+    let mut body = ro_data.to_vec();
+    for (opcode, operands) in instructions {
+      body.extend(encode(*opcode, operands));
+    }
+    let program = LumiHeader::new(ro_data.len()).wrap(&body);
+    let mut vm = VirtualMachine::initialize();
+    vm.program = program;
+    vm.run()
   }
-  
-  /// Read the next 24 bits from the program and increment the program counter.
-  /// Uses little-endian format.
-  pub fn next_24_bits(&mut self) -> u32 {
-    let result = (self.program[self.pc] as u32)
-      | ((self.program[self.pc + 1] as u32) << 8)
-      | ((self.program[self.pc + 2] as u32) << 16);
-    self.pc += 3;
-    result
+
+  fn assert_trap(events: &[VMEvent], expected_pc: usize, expected: VmTrap) {
+    let event = events.last().unwrap();
+    match event.event_type {
+      VMEventType::Crash { exit_code, pc, trap } => {
+        assert_eq!(trap, expected);
+        assert_eq!(pc, expected_pc);
+        assert_eq!(exit_code, expected.exit_code());
+        let message = event.message().unwrap();
+        assert_eq!(message.lines().next(), Some(format!("{} at 0x{:x}", expected, expected_pc).as_str()));
+      }
+      other => panic!("expected a crash event, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_run_arithmetic_program() {
+    let (vm, events) = run_source(
+      r".data
+      .code
+      load $0 #-300
+      loadi $1 #100000
+      add $0 $1 $2
+      sub $1 $0 $3
+      load $4 #7
+      div $1 $4 $5
+      hlt
+      ",
+    );
+    assert_eq!(vm.registers[0], -300);
+    assert_eq!(vm.registers[1], 100000);
+    assert_eq!(vm.registers[2], 99700);
+    assert_eq!(vm.registers[3], 100300);
+    assert_eq!(vm.registers[5], 14285);
+    assert_eq!(vm.remainder, 5);
+    assert_eq!(events.last().unwrap().event_type, VMEventType::GracefulShutdown { exit_code: 0 });
+  }
+
+  #[test]
+  fn test_run_float_and_upper_immediate_program() {
+    let (vm, _) = run_source(
+      r".data
+      .code
+      loadf64 $0 #2.5
+      loadf64 $1 #0.25
+      mulf64 $0 $1 $2
+      load $3 #1
+      lui $3 #2 #3
+      shl $3 #4
+      hlt
+      ",
+    );
+    assert_eq!(vm.float_registers[2], 0.625);
+    assert_eq!(vm.registers[3], 0x010203 << 4);
+  }
+
+  #[test]
+  fn test_run_memory_and_stack_program() {
+    let (vm, _) = run_source(
+      r".data
+      .code
+      load $0 #8
+      aloc $0
+      load $1 #4
+      load $2 #-42
+      setm $1 $2
+      loadm $1 $3
+      push $3
+      pop $4
+      hlt
+      ",
+    );
+    assert_eq!(vm.heap.len(), 8);
+    assert_eq!(vm.registers[3], -42);
+    assert_eq!(vm.registers[4], -42);
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_run_direct_jump_skips_instructions() {
+    let (vm, _) = run_source(
+      r".data
+      greeting: .asciiz 'Hello'
+      .code
+      load $0 #1
+      djmp @skip
+      load $0 #2
+      loadi $1 #99999
+      skip: load $2 #3
+      hlt
+      ",
+    );
+    assert_eq!(vm.registers[0], 1);
+    assert_eq!(vm.registers[1], 0);
+    assert_eq!(vm.registers[2], 3);
+  }
+
+  #[test]
+  fn test_run_loop_program() {
+    let (vm, _) = run_source(
+      r".data
+      .code
+      load $0 #0
+      cloop #4
+      start: inc $0
+      loop @start
+      hlt
+      ",
+    );
+    assert_eq!(vm.registers[0], 5);
+    assert_eq!(vm.loop_counter, 0);
+  }
+
+  #[test]
+  fn test_run_conditional_jump_program() {
+    let (vm, _) = run_source(
+      r".data
+      .code
+      load $0 #0
+      load $1 #10
+      top: inc $0
+      eq $0 $1
+      djmpe @done
+      djmp @top
+      done: hlt
+      ",
+    );
+    assert_eq!(vm.registers[0], 10);
+  }
+
+  #[test]
+  fn test_run_call_and_return() {
+    let (vm, events) = run_source(
+      r".data
+      .code
+      load $0 #5
+      call @double
+      call @double
+      hlt
+      double: add $0 $0 $0
+      ret
+      ",
+    );
+    assert_eq!(vm.registers[0], 20);
+    assert!(vm.stack.is_empty());
+    assert_eq!(events.last().unwrap().event_type, VMEventType::GracefulShutdown { exit_code: 0 });
+  }
+
+  #[test]
+  fn test_run_program_with_data_after_code() {
+    let (vm, _) = run_source(
+      r".code
+      djmp @end
+      load $0 #1
+      end: hlt
+      .data
+      text: .asciiz 'after the code'
+      ",
+    );
+    assert_eq!(vm.registers[0], 0);
+  }
+
+  #[test]
+  fn test_data_and_bss_are_loaded_into_heap() {
+    let (vm, events) = run_source(
+      r".rodata
+      greeting: .asciiz 'Hi'
+      .data
+      counter: .integer #41
+      .bss
+      result: .space #8
+      .code
+      load $0 @counter
+      loadm $0 $1
+      inc $1
+      setm $0 $1
+      load $2 @result
+      setm $2 $1
+      prts @greeting
+      hlt
+      ",
+    );
+    assert_eq!(events.last().unwrap().event_type, VMEventType::GracefulShutdown { exit_code: 0 });
+    assert_eq!(vm.ro_data, b"Hi\0");
+    assert_eq!(vm.heap, vec![42, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_sections_larger_than_heap_limit_trap() {
+    let source = r".bss
+      buffer: .space #64
+      .code
+      hlt
+      ";
+    let (vm, events) = run_source_with(VirtualMachine::builder().max_heap_bytes(16), source);
+    assert_trap(&events, code_start_offset(0), VmTrap::HeapLimitExceeded { requested: 64, limit: 16 });
+    assert!(vm.heap.is_empty());
+  }
+
+  const DIVIDE_IN_SUBROUTINE: &str = ".code
+main: load $0 #1
+  call @divide
+  hlt
+divide: div $0 $1 $2
+  ret
+";
+
+  #[test]
+  fn test_crash_message_names_source_line_and_backtrace() {
+    let mut vm = VirtualMachine::initialize();
+    vm.program = Assembler::new().with_debug_info("divide.lumi").assemble(DIVIDE_IN_SUBROUTINE).unwrap();
+    let events = vm.run();
+    assert_eq!(
+      events.last().unwrap().message(),
+      Some(
+        "Division by zero at divide (divide.lumi:5:9)
+  #0 divide (divide.lumi:5:9)
+  #1 main+0x4 (divide.lumi:3:3)"
+      )
+    );
+    let code_start = code_start_offset(0);
+    assert_eq!(vm.backtrace(), vec![code_start + 10, code_start + 4]);
+  }
+
+  #[test]
+  fn test_crash_message_without_debug_info_uses_offsets() {
+    let (vm, events) = run_source(DIVIDE_IN_SUBROUTINE);
+    assert!(vm.debug_info.is_none());
+    let code_start = code_start_offset(0);
+    let expected = format!(
+      "Division by zero at 0x{:x}\n  #0 0x{:x}\n  #1 0x{:x}",
+      code_start + 10,
+      code_start + 10,
+      code_start + 4
+    );
+    assert_eq!(events.last().unwrap().message(), Some(expected.as_str()));
+  }
+
+  #[test]
+  fn test_divide_by_zero_traps() {
+    let (_, events) = run_source(
+      r".data
+      .code
+      load $0 #10
+      div $0 $1 $2
+      hlt
+      ",
+    );
+    assert_trap(&events, code_start_offset(0) + 4, VmTrap::DivideByZero);
+  }
+
+  #[test]
+  fn test_stack_underflow_traps() {
+    let (_, events) = run_source(
+      r".data
+      .code
+      pop $0
+      ",
+    );
+    assert_trap(&events, code_start_offset(0), VmTrap::StackUnderflow);
+
+    let (_, events) = run_source(
+      r".data
+      .code
+      push $0
+      ret
+      ",
+    );
+    assert_trap(&events, code_start_offset(0) + 2, VmTrap::StackUnderflow);
+  }
+
+  #[test]
+  fn test_memory_fault_traps() {
+    let (_, events) = run_source(
+      r".data
+      .code
+      load $0 #8
+      aloc $0
+      load $1 #6
+      loadm $1 $2
+      ",
+    );
+    assert_trap(&events, code_start_offset(0) + 10, VmTrap::MemoryFault { addr: 6, len: 4 });
+
+    let (_, events) = run_source(
+      r".data
+      .code
+      load $1 #-1
+      setm $1 $2
+      ",
+    );
+    assert_trap(&events, code_start_offset(0) + 4, VmTrap::MemoryFault { addr: usize::MAX, len: 4 });
+  }
+
+  #[test]
+  fn test_negative_allocation_traps() {
+    let (vm, events) = run_source(
+      r".data
+      .code
+      load $0 #-8
+      aloc $0
+      ",
+    );
+    assert_trap(&events, code_start_offset(0) + 4, VmTrap::InvalidAllocation { size: -8 });
+    assert!(vm.heap.is_empty());
+  }
+
+  #[test]
+  fn test_invalid_register_traps() {
+    let events = run_bytecode(
+      &[],
+      &[(Opcode::LOAD, [Operand::Register(40), Operand::Integer(1), Operand::Empty])],
+    );
+    assert_trap(&events, code_start_offset(0), VmTrap::InvalidRegister { register: 40 });
+  }
+
+  #[test]
+  fn test_illegal_and_truncated_instructions_trap() {
+    let program = LumiHeader::new(0).wrap(&[0xfe]);
+    let mut vm = VirtualMachine::initialize();
+    vm.program = program;
+    assert_trap(&vm.run(), code_start_offset(0), VmTrap::IllegalOpcode { opcode: 0xfe });
+
+    let events = run_bytecode(&[], &[(Opcode::IGL, [Operand::Empty; 3])]);
+    assert_trap(&events, code_start_offset(0), VmTrap::IllegalOpcode { opcode: Opcode::IGL.into() });
+
+    let load = encode(Opcode::LOADI, &[Operand::Register(0), Operand::Integer(7), Operand::Empty]);
+    let program = LumiHeader::new(0).wrap(&load[..3]);
+    let mut vm = VirtualMachine::initialize();
+    vm.program = program;
+    assert_trap(&vm.run(), code_start_offset(0), VmTrap::TruncatedInstruction { opcode: Opcode::LOADI });
+  }
+
+  #[test]
+  fn test_jumps_out_of_program_trap() {
+    let (_, events) = run_source(
+      r".data
+      .code
+      load $0 #30000
+      jmp $0
+      ",
+    );
+    assert_trap(&events, 30000, VmTrap::PcOutOfBounds { pc: 30000 });
+
+    let (_, events) = run_source(
+      r".data
+      .code
+      load $0 #30000
+      jmpb $0
+      ",
+    );
+    let target = (code_start_offset(0) + 6).wrapping_sub(30000);
+    assert_trap(&events, code_start_offset(0) + 4, VmTrap::PcOutOfBounds { pc: target });
+
+    // the header and read-only data are inside the program but not code
+    let (_, events) = run_source(
+      r".rodata
+      greeting: .asciiz 'Hello'
+      .code
+      load $0 #69
+      jmp $0
+      ",
+    );
+    assert_trap(&events, 69, VmTrap::PcOutOfBounds { pc: 69 });
+
+    let (_, events) = run_source(
+      r".data
+      .code
+      load $0 #0
+      jmp $0
+      ",
+    );
+    assert_trap(&events, 0, VmTrap::PcOutOfBounds { pc: 0 });
+  }
+
+  #[test]
+  fn test_print_string_without_terminator_traps() {
+    let events = run_bytecode(
+      b"hi",
+      &[(Opcode::PRTS, [Operand::Address(0), Operand::Empty, Operand::Empty])],
+    );
+    assert_trap(&events, code_start_offset(2), VmTrap::MemoryFault { addr: 0, len: 3 });
+  }
+
+  #[test]
+  fn test_invalid_header_traps() {
+    let mut vm = VirtualMachine::initialize();
+    vm.program = vec![0x4C, 0x55];
+    let error = HeaderError::TooShort { length: 2, expected: LUMI_HEADER_SIZE };
+    assert_trap(&vm.run(), 0, VmTrap::InvalidHeader { error });
+
+    let mut vm = VirtualMachine::initialize();
+    vm.program = LumiHeader::new(100).wrap(&[]);
+    let error = HeaderError::ReadOnlySectionOutOfBounds { ro_length: 100, program_length: LUMI_HEADER_SIZE };
+    assert_trap(&vm.run(), 0, VmTrap::InvalidHeader { error });
+  }
+
+  #[test]
+  fn test_incompatible_isa_version_is_refused() {
+    let mut source = Assembler::new().assemble(COUNT_TO_TEN).unwrap();
+    let mut header = LumiHeader::parse(&source).unwrap();
+    header.isa_version = LUMI_ISA_VERSION + 1;
+    source.splice(..LUMI_HEADER_SIZE, header.write());
+
+    let mut vm = VirtualMachine::initialize();
+    vm.program = source;
+    assert_eq!(
+      vm.run_for(100),
+      RunOutcome::Trapped(VmTrap::InvalidHeader {
+        error: HeaderError::UnsupportedIsaVersion { found: LUMI_ISA_VERSION + 1, supported: LUMI_ISA_VERSION },
+      })
+    );
+    assert_eq!(vm.instructions_executed, 0);
+    assert!(vm.events.last().unwrap().message().unwrap().contains("ISA version 2"));
+  }
+
+  fn run_source_with(builder: VirtualMachineBuilder, source: &str) -> (VirtualMachine, Vec<VMEvent>) {
+    let mut vm = builder.build();
+    vm.program = Assembler::new().assemble(source).unwrap();
+    let events = vm.run();
+    (vm, events)
+  }
+
+  #[test]
+  fn test_builder_applies_config() {
+    let vm = VirtualMachine::builder()
+      .max_heap_bytes(64)
+      .max_stack_depth(8)
+      .fuel(1000)
+      .extensions_dir("/tmp/lumi-extensions")
+      .build();
+    assert_eq!(vm.config.max_heap_bytes, Some(64));
+    assert_eq!(vm.config.max_stack_depth, Some(8));
+    assert_eq!(vm.config.fuel, Some(1000));
+    assert_eq!(vm.config.extensions_dir, std::path::PathBuf::from("/tmp/lumi-extensions"));
+    assert!(!vm.instruction_table.is_empty());
+    assert_eq!(VirtualMachine::initialize().config, VmConfig::default());
+  }
+
+  #[test]
+  fn test_heap_limit_traps() {
+    let source = r".data
+      .code
+      load $0 #16
+      aloc $0
+      aloc $0
+      hlt
+      ";
+    let (vm, events) = run_source_with(VirtualMachine::builder().max_heap_bytes(16), source);
+    assert_trap(&events, code_start_offset(0) + 6, VmTrap::HeapLimitExceeded { requested: 32, limit: 16 });
+    assert_eq!(vm.heap.len(), 16);
+
+    let (_, events) = run_source_with(VirtualMachine::builder().max_heap_bytes(32), source);
+    assert_eq!(events.last().unwrap().event_type, VMEventType::GracefulShutdown { exit_code: 0 });
+  }
+
+  #[test]
+  fn test_stack_depth_limit_traps() {
+    let (vm, events) = run_source_with(
+      VirtualMachine::builder().max_stack_depth(2),
+      r".data
+      .code
+      push $0
+      push $0
+      push $0
+      ",
+    );
+    assert_trap(&events, code_start_offset(0) + 4, VmTrap::StackOverflow { limit: 2 });
+    assert_eq!(vm.stack.len(), 2);
+
+    let (_, events) = run_source_with(
+      VirtualMachine::builder().max_stack_depth(10),
+      r".data
+      .code
+      recurse: call @recurse
+      ",
+    );
+    assert_trap(&events, code_start_offset(0), VmTrap::StackOverflow { limit: 10 });
+  }
+
+  #[test]
+  fn test_fuel_limit_traps() {
+    let (vm, events) = run_source_with(
+      VirtualMachine::builder().fuel(100),
+      r".data
+      .code
+      spin: inc $0
+      djmp @spin
+      ",
+    );
+    assert_eq!(vm.instructions_executed, 100);
+    assert_eq!(vm.registers[0], 50);
+    assert_trap(&events, code_start_offset(0), VmTrap::OutOfFuel { limit: 100 });
+  }
+
+  fn load_source(source: &str) -> VirtualMachine {
+    let mut vm = VirtualMachine::initialize();
+    vm.program = Assembler::new().assemble(source).unwrap();
+    vm
   }
 
-  /// Get the programs starting offset.
-  pub fn get_starting_offset(&self) -> usize {
-    let mut rdr =
-      Cursor::new(&self.program[LUMI_HEADER_LENGTH + 1.. LUMI_HEADER_LENGTH + 1 + 4]);
-    rdr.read_u32::<LittleEndian>().unwrap() as usize
+  const COUNT_TO_TEN: &str = r".data
+      .code
+      load $1 #10
+      top: inc $0
+      eq $0 $1
+      djmpe @done
+      djmp @top
+      done: hlt
+      ";
+
+  #[test]
+  fn test_run_for_yields_and_resumes() {
+    let mut vm = load_source(COUNT_TO_TEN);
+    assert_eq!(vm.run_for(5), RunOutcome::Yielded);
+    assert_eq!(vm.instructions_executed, 5);
+    assert_eq!(vm.registers[0], 1);
+
+    assert_eq!(vm.run_for(1000), RunOutcome::Halted(0));
+    assert_eq!(vm.registers[0], 10);
+
+    let events = vm.events.len();
+    assert_eq!(vm.run_for(1000), RunOutcome::Halted(0));
+    assert_eq!(vm.events.len(), events);
+    assert_eq!(vm.events[0].event_type, VMEventType::Start);
+  }
+
+  #[test]
+  fn test_run_for_reports_traps_and_breakpoints() {
+    let mut vm = load_source(
+      r".data
+      .code
+      load $0 #1
+      bkpt
+      load $0 #2
+      pop $1
+      ",
+    );
+    assert_eq!(vm.run_for(10), RunOutcome::Breakpoint(code_start_offset(0) + 4));
+    assert_eq!(vm.registers[0], 1);
+    assert_eq!(vm.run_once(), RunOutcome::Yielded);
+    assert_eq!(vm.registers[0], 2);
+    assert_eq!(vm.run_for(10), RunOutcome::Trapped(VmTrap::StackUnderflow));
+    assert_eq!(vm.run_for(10), RunOutcome::Trapped(VmTrap::StackUnderflow));
+  }
+
+  #[test]
+  fn test_watchpoints_break_on_matching_changes() {
+    let mut vm = load_source(COUNT_TO_TEN);
+    vm.run_for(0);
+    let counter = vm.add_watchpoint(WatchTarget::Register(0), WatchCondition::parse("value >= 3"), WatchMode::Break).unwrap();
+    vm.add_watchpoint(WatchTarget::EqualFlag, None, WatchMode::Log);
+    vm.add_watchpoint(WatchTarget::Heap { addr: 100, len: 4 }, None, WatchMode::Break);
+
+    let inc = code_start_offset(0) + 4;
+    assert_eq!(vm.run_for(1000), RunOutcome::Watchpoint { id: counter, pc: inc });
+    assert_eq!(vm.registers[0], 3);
+    assert_eq!(vm.run_for(1000), RunOutcome::Watchpoint { id: counter, pc: inc });
+    assert_eq!(vm.registers[0], 4);
+    assert_eq!(vm.watchpoints[0].value, WatchValue::Integer(4));
+
+    assert!(vm.remove_watchpoint(counter));
+    assert!(!vm.remove_watchpoint(counter));
+    assert_eq!(vm.run_for(1000), RunOutcome::Halted(0));
+    assert_eq!(vm.watchpoints[0].value, WatchValue::Bool(true));
+    assert_eq!(vm.watchpoints[1].value, WatchValue::Missing);
+  }
+
+  #[test]
+  fn test_watchpoints_on_heap_ranges_and_stack() {
+    let mut vm = load_source(
+      r".data
+      counter: .integer #0
+      .code
+      load $0 #7
+      load $1 #0
+      push $0
+      setm $1 $0
+      hlt
+      ",
+    );
+    vm.run_for(0);
+    let stack = vm.add_watchpoint(WatchTarget::Stack(0), None, WatchMode::Break).unwrap();
+    let heap = vm.add_watchpoint(WatchTarget::Heap { addr: 0, len: 2 }, None, WatchMode::Break).unwrap();
+    assert_eq!(vm.watchpoints[1].value, WatchValue::Bytes(vec![0, 0]));
+
+    let start = code_start_offset(0);
+    assert_eq!(vm.run_for(100), RunOutcome::Watchpoint { id: stack, pc: start + 8 });
+    assert_eq!(vm.watchpoints[0].value, WatchValue::Integer(7));
+    assert_eq!(vm.run_for(100), RunOutcome::Watchpoint { id: heap, pc: start + 10 });
+    assert_eq!(vm.watchpoints[1].value, WatchValue::Bytes(vec![7, 0]));
+    assert_eq!(vm.run_for(100), RunOutcome::Halted(0));
   }
 
+  #[test]
+  fn test_undo_instructions_from_the_journal() {
+    let mut vm = load_source(
+      r".data
+      .code
+      load $0 #8
+      aloc $0
+      push $0
+      call @fill
+      pop $2
+      hlt
+      fill: load $1 #4
+      setm $1 $0
+      ret
+      ",
+    );
+    vm.config.journal = Some(100);
+    vm.run_for(0);
+    let state = |vm: &VirtualMachine| (vm.pc, vm.registers, vm.stack.clone(), vm.heap.clone(), vm.sp, vm.bp);
+    let initial = state(&vm);
+    assert_eq!(vm.run_for(100), RunOutcome::Halted(0));
+    let halted = state(&vm);
+    assert_eq!(vm.heap, vec![0, 0, 0, 0, 8, 0, 0, 0]);
+    assert_eq!(vm.journal.as_ref().map(Journal::len), Some(9));
 
-  /// Read the read-only data from the program.
-  pub fn read_ro_data(&mut self) {
-    const OFFSET_SIZE: usize = 4;
+    let start = code_start_offset(0);
+    assert_eq!(vm.undo_instruction(), Some(start + 15));
+    assert_eq!(vm.undo_instruction(), Some(start + 13));
+    assert_eq!((vm.stack.len(), vm.registers[2]), (1, 0));
+    assert_eq!(vm.undo_instruction(), Some(start + 23));
+    assert_eq!(vm.stack.len(), 3);
+    while vm.undo_instruction().is_some() {}
+    assert_eq!(state(&vm), initial);
+    assert_eq!(vm.instructions_executed, 0);
+
+    assert_eq!(vm.run_for(100), RunOutcome::Halted(0));
+    assert_eq!(state(&vm), halted);
+  }
+
+  #[test]
+  fn test_run_for_interleaves_virtual_machines() {
+    let mut vms = vec![load_source(COUNT_TO_TEN), load_source(COUNT_TO_TEN)];
+    vms[1].registers[0] = 5;
+    let mut outcomes = vec![RunOutcome::Yielded; vms.len()];
+    while outcomes.contains(&RunOutcome::Yielded) {
+      for (vm, outcome) in vms.iter_mut().zip(outcomes.iter_mut()) {
+        *outcome = vm.run_for(3);
+      }
+    }
+
+    assert_eq!(outcomes, vec![RunOutcome::Halted(0), RunOutcome::Halted(0)]);
+    assert_eq!(vms[0].registers[0], 10);
+    assert_eq!(vms[1].registers[0], 10);
+    assert!(vms[0].instructions_executed > vms[1].instructions_executed);
+  }
+
+  #[test]
+  fn test_load_resets_state_and_runs_program() {
+    let mut vm = VirtualMachine::initialize();
+    let program = Program::from_bytes(Assembler::new().assemble(COUNT_TO_TEN).unwrap()).unwrap();
+    vm.load(program.clone());
+    assert_eq!(vm.run_for(1000), RunOutcome::Halted(0));
+    assert_eq!(vm.registers[0], 10);
+
+    vm.load(program);
+    assert_eq!(vm.registers[0], 0);
+    assert_eq!(vm.instructions_executed, 0);
+    assert_eq!(vm.run_for(1000), RunOutcome::Halted(0));
+    assert_eq!(vm.registers[0], 10);
+  }
+
+  #[test]
+  fn test_jump_into_verified_instruction_checks_registers() {
+    let start = code_start_offset(0);
+    // the immediate of the first load decodes as `inc $40` when jumped into
+    let hidden = i32::from_le_bytes([Opcode::INC.into(), 40, Opcode::HLT.into(), 0]);
+    let mut body = encode(Opcode::LOADI, &[Operand::Register(0), Operand::Integer(hidden), Operand::Empty]);
+    body.extend(encode(Opcode::LOADI, &[Operand::Register(1), Operand::Integer(start as i32 + 2), Operand::Empty]));
+    body.extend(encode(Opcode::JMP, &[Operand::Register(1), Operand::Empty, Operand::Empty]));
+    let program = Program::from_bytes(LumiHeader::new(0).wrap(&body)).unwrap();
+    let mut vm = VirtualMachine::initialize();
+    vm.load(program);
+    assert_trap(&vm.run(), start + 2, VmTrap::InvalidRegister { register: 40 });
+  }
 
-    let start_index = LUMI_HEADER_LENGTH + OFFSET_SIZE + 1;
-    let end_index = start_index + self.get_starting_offset();
-    debug!("Reading read-only data from {} to {}", start_index, end_index);
-    self.ro_data = self.program[start_index..end_index].to_vec();
+  #[test]
+  fn test_execute_instruction_stops_at_end_of_program() {
+    let mut vm = VirtualMachine::initialize();
+    vm.program = Assembler::new().assemble_instruction("load $0 #5").unwrap();
+    assert!(matches!(vm.execute_instruction(), ExecutionStatus::Continue));
+    assert_eq!(vm.pc, vm.program.len());
+    assert_eq!(vm.registers[0], 5);
+    assert!(matches!(vm.execute_instruction(), ExecutionStatus::Done(1)));
   }
 }
@@ -0,0 +1,270 @@
+use std::fmt;
+use std::fmt::Formatter;
+use log::info;
+use crate::vm::virtual_machine::VirtualMachine;
+
+/// A location a watchpoint observes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchTarget {
+  /// `len` bytes of the heap starting at `addr`.
+  Heap { addr: usize, len: usize },
+  Register(usize),
+  FloatRegister(usize),
+  /// A stack slot, counted from the bottom of the stack.
+  Stack(usize),
+  EqualFlag,
+}
+
+impl fmt::Display for WatchTarget {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      WatchTarget::Heap { addr, len } => write!(f, "heap 0x{:x}..0x{:x}", addr, addr.saturating_add(*len)),
+      WatchTarget::Register(index) => write!(f, "register ${}", index),
+      WatchTarget::FloatRegister(index) => write!(f, "float register ${}", index),
+      WatchTarget::Stack(index) => write!(f, "stack slot {}", index),
+      WatchTarget::EqualFlag => write!(f, "equal flag"),
+    }
+  }
+}
+
+/// The value of a watched location.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchValue {
+  Bytes(Vec<u8>),
+  Integer(i32),
+  Float(f64),
+  Bool(bool),
+  /// The location does not exist right now, like heap bytes past the end of the heap or an
+  /// empty stack slot.
+  Missing,
+}
+
+impl WatchValue {
+  /// The value as a number for conditions. Heap bytes are read as a little-endian signed
+  /// integer of up to 8 bytes.
+  pub fn as_number(&self) -> Option<f64> {
+    match self {
+      WatchValue::Bytes(bytes) if !bytes.is_empty() && bytes.len() <= 8 => {
+        let mut buffer = [0; 8];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        let shift = 64 - 8 * bytes.len() as u32;
+        Some(((i64::from_le_bytes(buffer) << shift) >> shift) as f64)
+      }
+      WatchValue::Bytes(_) | WatchValue::Missing => None,
+      WatchValue::Integer(value) => Some(*value as f64),
+      WatchValue::Float(value) => Some(*value),
+      WatchValue::Bool(value) => Some(*value as u8 as f64),
+    }
+  }
+}
+
+impl fmt::Display for WatchValue {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      WatchValue::Bytes(bytes) => {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        write!(f, "[{}]", hex.join(" "))
+      }
+      WatchValue::Integer(value) => write!(f, "{}", value),
+      WatchValue::Float(value) => write!(f, "{:?}", value),
+      WatchValue::Bool(value) => write!(f, "{}", value),
+      WatchValue::Missing => write!(f, "<none>"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+  Equal,
+  NotEqual,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+}
+
+/// Only report a change when the new value satisfies `value <comparison> operand`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchCondition {
+  pub comparison: Comparison,
+  pub operand: f64,
+}
+
+impl WatchCondition {
+  /// Parse a condition like `value > 10` or `!= 0`.
+  pub fn parse(text: &str) -> Option<WatchCondition> {
+    let text = text.trim();
+    let text = text.strip_prefix("value").unwrap_or(text).trim_start();
+    let (comparison, operand) = [
+      ("==", Comparison::Equal),
+      ("!=", Comparison::NotEqual),
+      ("<=", Comparison::LessOrEqual),
+      (">=", Comparison::GreaterOrEqual),
+      ("<", Comparison::Less),
+      (">", Comparison::Greater),
+    ]
+    .into_iter()
+    .find_map(|(symbol, comparison)| Some((comparison, text.strip_prefix(symbol)?)))?;
+    let operand = operand.trim().parse().ok()?;
+    Some(WatchCondition { comparison, operand })
+  }
+
+  pub fn holds(&self, value: &WatchValue) -> bool {
+    let Some(value) = value.as_number() else {
+      return false;
+    };
+    match self.comparison {
+      Comparison::Equal => value == self.operand,
+      Comparison::NotEqual => value != self.operand,
+      Comparison::Less => value < self.operand,
+      Comparison::LessOrEqual => value <= self.operand,
+      Comparison::Greater => value > self.operand,
+      Comparison::GreaterOrEqual => value >= self.operand,
+    }
+  }
+}
+
+impl fmt::Display for WatchCondition {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let symbol = match self.comparison {
+      Comparison::Equal => "==",
+      Comparison::NotEqual => "!=",
+      Comparison::Less => "<",
+      Comparison::LessOrEqual => "<=",
+      Comparison::Greater => ">",
+      Comparison::GreaterOrEqual => ">=",
+    };
+    write!(f, "value {} {}", symbol, self.operand)
+  }
+}
+
+/// What a watchpoint does when its location changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+  /// Stop `run_for` with `RunOutcome::Watchpoint`.
+  Break,
+  /// Log the change and keep running.
+  Log,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+  pub id: usize,
+  pub target: WatchTarget,
+  pub condition: Option<WatchCondition>,
+  pub mode: WatchMode,
+  /// The value after the last instruction that was checked.
+  pub value: WatchValue,
+}
+
+impl VirtualMachine {
+  /// Watch a location from the next instruction on and return the watchpoint's id, `None` for
+  /// a heap range that runs past the end of the address space.
+  pub fn add_watchpoint(&mut self, target: WatchTarget, condition: Option<WatchCondition>, mode: WatchMode) -> Option<usize> {
+    if let WatchTarget::Heap { addr, len } = target {
+      addr.checked_add(len)?;
+    }
+    let id = self.watchpoints.last().map_or(1, |watchpoint| watchpoint.id + 1);
+    let value = self.watch_value(target);
+    self.watchpoints.push(Watchpoint { id, target, condition, mode, value });
+    Some(id)
+  }
+
+  /// Remove a watchpoint, returns whether it existed.
+  pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+    let count = self.watchpoints.len();
+    self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+    self.watchpoints.len() != count
+  }
+
+  pub fn watch_value(&self, target: WatchTarget) -> WatchValue {
+    match target {
+      WatchTarget::Heap { addr, len } => match self.heap_bytes(addr, len) {
+        Some(bytes) => WatchValue::Bytes(bytes.to_vec()),
+        None => WatchValue::Missing,
+      },
+      WatchTarget::Register(index) => self.registers.get(index).map_or(WatchValue::Missing, |value| WatchValue::Integer(*value)),
+      WatchTarget::FloatRegister(index) => {
+        self.float_registers.get(index).map_or(WatchValue::Missing, |value| WatchValue::Float(*value))
+      }
+      WatchTarget::Stack(index) => self.stack.get(index).map_or(WatchValue::Missing, |value| WatchValue::Integer(*value)),
+      WatchTarget::EqualFlag => WatchValue::Bool(self.equal_flag),
+    }
+  }
+
+  fn heap_bytes(&self, addr: usize, len: usize) -> Option<&[u8]> {
+    self.heap.get(addr..addr.checked_add(len)?)
+  }
+
+  /// Take the current value of every watched location without reporting changes.
+  pub(crate) fn refresh_watchpoints(&mut self) {
+    for index in 0..self.watchpoints.len() {
+      self.watchpoints[index].value = self.watch_value(self.watchpoints[index].target);
+    }
+  }
+
+  /// Compare every watched location with its last value after the instruction at `pc` ran.
+  /// Changes are logged, the id of the first breaking watchpoint that changed is returned.
+  pub(crate) fn check_watchpoints(&mut self, pc: usize) -> Option<usize> {
+    let mut hit = None;
+    for index in 0..self.watchpoints.len() {
+      let watchpoint = &self.watchpoints[index];
+      // compare heap ranges in place, they are only copied when they change
+      let unchanged = match (watchpoint.target, &watchpoint.value) {
+        (WatchTarget::Heap { addr, len }, WatchValue::Bytes(last)) => self.heap_bytes(addr, len) == Some(last.as_slice()),
+        (target, last) => self.watch_value(target) == *last,
+      };
+      if unchanged {
+        continue;
+      }
+
+      let value = self.watch_value(watchpoint.target);
+      let watchpoint = &mut self.watchpoints[index];
+      let old = std::mem::replace(&mut watchpoint.value, value);
+      if watchpoint.condition.is_none_or(|condition| condition.holds(&watchpoint.value)) {
+        let (id, target, mode) = (watchpoint.id, watchpoint.target, watchpoint.mode);
+        info!("Watchpoint {} at {}: {} changed from {} to {}", id, self.describe_pc(pc), target, old, self.watchpoints[index].value);
+        if mode == WatchMode::Break && hit.is_none() {
+          hit = Some(id);
+        }
+      }
+    }
+    hit
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_and_evaluate_conditions() {
+    let condition = WatchCondition::parse("value > 10").unwrap();
+    assert_eq!(condition, WatchCondition { comparison: Comparison::Greater, operand: 10.0 });
+    assert!(condition.holds(&WatchValue::Integer(11)));
+    assert!(!condition.holds(&WatchValue::Float(10.0)));
+    assert!(!condition.holds(&WatchValue::Missing));
+    assert_eq!(condition.to_string(), "value > 10");
+
+    assert_eq!(WatchCondition::parse("<= -2.5").unwrap().comparison, Comparison::LessOrEqual);
+    assert!(WatchCondition::parse("!=0").unwrap().holds(&WatchValue::Bool(true)));
+    assert_eq!(WatchCondition::parse("value ~ 3"), None);
+    assert_eq!(WatchCondition::parse("> ten"), None);
+  }
+
+  #[test]
+  fn test_heap_bytes_are_signed_little_endian() {
+    assert_eq!(WatchValue::Bytes(vec![0xff, 0xff]).as_number(), Some(-1.0));
+    assert_eq!(WatchValue::Bytes(vec![0x10, 0x01, 0, 0]).as_number(), Some(272.0));
+    assert_eq!(WatchValue::Bytes(vec![0; 9]).as_number(), None);
+  }
+
+  #[test]
+  fn test_reject_heap_ranges_that_overflow() {
+    let mut vm = VirtualMachine::new();
+    let target = WatchTarget::Heap { addr: usize::MAX, len: 2 };
+    assert_eq!(vm.add_watchpoint(target, None, WatchMode::Break), None);
+    assert!(vm.watchpoints.is_empty());
+    assert_eq!(target.to_string(), format!("heap 0x{:x}..0x{:x}", usize::MAX, usize::MAX));
+  }
+}